@@ -0,0 +1,56 @@
+//! Time how long the compiler takes to check a large generated program
+//!
+//! Run with `cargo bench`. The program is written to a temporary directory and checked a few times, and the fastest and median runs are reported
+
+use std::fs;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+const FUNCTIONS: usize = 5_000;
+const RUNS: usize = 5;
+
+/// Lots of small functions, so that the lexer and parser do most of the work
+fn generate_program(functions: usize) -> String {
+    (0..functions)
+        .map(|i| {
+            format!(
+                "fn step_{i}(a: Int, b: Int) -> Int {{\n    let c: Int = a + b * {i};\n    if c > 10 {{\n        return c - 1;\n    }}\n    return c;\n}}\n\n"
+            )
+        })
+        .collect()
+}
+
+fn main() {
+    let dir = std::env::temp_dir().join(format!("iona_bench_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("bench.iona");
+    let program = generate_program(FUNCTIONS);
+    fs::write(&file, &program).unwrap();
+
+    let mut times = (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            let output = Command::new(env!("CARGO_BIN_EXE_iona"))
+                .current_dir(&dir)
+                .args(["check", "bench.iona", "--lib"])
+                .output()
+                .expect("unable to run the compiler");
+            let elapsed = start.elapsed();
+            assert!(
+                output.status.success(),
+                "{}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+            elapsed
+        })
+        .collect::<Vec<Duration>>();
+    times.sort();
+    println!(
+        "check {} functions ({} KB): fastest {:?}, median {:?}",
+        FUNCTIONS,
+        program.len() / 1024,
+        times[0],
+        times[RUNS / 2]
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
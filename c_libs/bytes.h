@@ -1,3 +1,5 @@
+#pragma once
+
 /// Raw byte handling 
 
 #include <stdint.h>
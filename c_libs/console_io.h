@@ -1,14 +1,17 @@
+#pragma once
+
 #include <stdio.h>
 #include "gen_strings.h"
 
 /// @brief Print a raw C string to the console
 /// @param string the input string 
-void print_raw(char* string) {
+static inline void print_raw(char* string) {
     printf("%s\n", string);
 }
 
 /// @brief Print an Iona string to the console
-/// @param string 
-void print(String string) {
-    
-}
\ No newline at end of file
+/// @param string the input string, which doesn't need a null terminator
+static inline void print(String string) {
+    fwrite(string.data.data, 1, string.data.len, stdout);
+    putchar('\n');
+}
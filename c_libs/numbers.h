@@ -1,3 +1,5 @@
+#pragma once
+
 #include <float.h>
 #include <math.h>
 #include <inttypes.h>
@@ -19,7 +21,7 @@ typedef struct {
 /// @brief Integer value from C integer
 /// @param value the C int64
 /// @return an Integer instance
-Integer integer_from(int64_t value) {
+static inline Integer integer_from(int64_t value) {
     Integer i = {
         .value = value
     };
@@ -29,7 +31,7 @@ Integer integer_from(int64_t value) {
 /// @brief Convert C double to Iona Float
 /// @param value the C double
 /// @return an Iona Float instance
-Float float_from(double value) {
+static inline Float float_from(double value) {
     Float f = {
         .value = value
     };
@@ -38,11 +40,11 @@ Float float_from(double value) {
 
 // -------------------- Traits --------------------
 
-const size_t INTEGER_BUFFER_SIZE = 21;
-const size_t FLOAT_BUFFER_SIZE = 64;
+static const size_t INTEGER_BUFFER_SIZE = 21;
+static const size_t FLOAT_BUFFER_SIZE = 64;
 
 //! Convert Integer to a C string
-char* integer_show(Integer num) {
+static inline char* integer_show(Integer num) {
     char* str = (char*)malloc(INTEGER_BUFFER_SIZE);
     if (str == NULL) {
         perror("Fatal runtime error\nFailed to allocate memory\nTried to represent an integer with a string\nCalling code: `integer_show`");
@@ -53,7 +55,7 @@ char* integer_show(Integer num) {
 }
 
 //! Convert Float to a C string
-char* float_show(Float num) {
+static inline char* float_show(Float num) {
     char* str = (char*)malloc(FLOAT_BUFFER_SIZE);
     if (str == NULL) {
         perror("Fatal runtime error\nFailed to allocate memory\nTried to represent an integer with a string\nCalling code: `integer_show`");
@@ -64,14 +66,14 @@ char* float_show(Float num) {
 }
 
 //! Checks if two integers are equal
-bool integer_equals(Integer a, Integer b) {
+static inline bool integer_equals(Integer a, Integer b) {
     return a.value == b.value;
 }
 
 //! Checks if two floats are equal
 //! 
 //! TODO: improve
-bool float_equals(Float a, Float b) {
+static inline bool float_equals(Float a, Float b) {
     if (a.value == b.value) {
         return true;
     } else {
@@ -82,7 +84,7 @@ bool float_equals(Float a, Float b) {
 // -------------------- Basic Arithmetic --------------------
 
 //! Saturating addition for Integer
-Integer saturating_add(Integer a, Integer b) {
+static inline Integer saturating_add(Integer a, Integer b) {
     if (a.value > 0) {
         if (b.value > INT64_MAX - a.value) {
             return integer_from(INT64_MAX);
@@ -94,7 +96,7 @@ Integer saturating_add(Integer a, Integer b) {
 }
 
 //! Saturating subtraction for Integer
-Integer saturating_sub(Integer a, Integer b) {
+static inline Integer saturating_sub(Integer a, Integer b) {
     if (b.value < 0) {
         if (a.value > INT64_MAX + b.value) {
             return integer_from(INT64_MAX);
@@ -106,7 +108,7 @@ Integer saturating_sub(Integer a, Integer b) {
 }
 
 //! Saturating multiplication for Integer
-Integer saturating_mul(Integer a, Integer b) {
+static inline Integer saturating_mul(Integer a, Integer b) {
     if (a.value > 0) {
         if (b.value > 0 && a.value > INT64_MAX / b.value) {
             return integer_from(INT64_MAX);
@@ -124,7 +126,7 @@ Integer saturating_mul(Integer a, Integer b) {
 }
 
 //! Saturating division for Integer
-Integer saturating_div(Integer a, Integer b) {
+static inline Integer saturating_div(Integer a, Integer b) {
     if (b.value == 0) {
         // Division by zero is undefined; return the maximum value as a fallback.
         return integer_from(a.value > 0 ? INT64_MAX : INT64_MIN);
@@ -137,32 +139,32 @@ Integer saturating_div(Integer a, Integer b) {
 }
 
 //! Clamp helper function for Float
-static double clamp(double value, double min, double max) {
+static inline double clamp(double value, double min, double max) {
     if (value < min) return min;
     if (value > max) return max;
     return value;
 }
 
 //! Saturating addition for Float
-Float saturating_add_float(Float a, Float b) {
+static inline Float saturating_add_float(Float a, Float b) {
     double result = a.value + b.value;
     return float_from(clamp(result, -DBL_MAX, DBL_MAX));
 }
 
 //! Saturating subtraction for Float
-Float saturating_sub_float(Float a, Float b) {
+static inline Float saturating_sub_float(Float a, Float b) {
     double result = a.value - b.value;
     return float_from(clamp(result, -DBL_MAX, DBL_MAX));
 }
 
 //! Saturating multiplication for Float
-Float saturating_mul_float(Float a, Float b) {
+static inline Float saturating_mul_float(Float a, Float b) {
     double result = a.value * b.value;
     return float_from(clamp(result, -DBL_MAX, DBL_MAX));
 }
 
 //! Saturating division for Float
-Float saturating_div_float(Float a, Float b) {
+static inline Float saturating_div_float(Float a, Float b) {
     if (b.value == 0.0) {
         // Division by zero; saturate to maximum value
         return float_from(a.value > 0 ? DBL_MAX : -DBL_MAX);
@@ -0,0 +1,16 @@
+#pragma once
+
+/// String handling that can't be written in Iona
+
+#include "gen_strings.h"
+
+/// @brief Free the bytes a string owns, and leave it empty
+/// @param string the string to free
+static inline void string_free(String* string) {
+    // A capacity of 0 marks bytes in static storage (like a string literal's), which were never allocated
+    if (string->data.capacity > 0) {
+        byte_array_free(&string->data);
+    }
+    string->data.data = NULL;
+    string->data.len = 0;
+}
@@ -1,3 +1,5 @@
+#pragma once
+
 // Template parameters to be replaced by compiler:
 // ARRAY_NAME -> concrete type name (e.g., StringArray, IntArray)
 // ELEM_TYPE -> concrete element type (e.g., char, int)
@@ -16,7 +18,7 @@ typedef struct {
 } ARRAY_NAME;
 
 // Create a new empty array with default capacity
-ARRAY_NAME PREFIX_new(void) {
+static inline ARRAY_NAME PREFIX_new(void) {
     const size_t initial_capacity = 8;
     ARRAY_NAME arr = {
         .data = malloc(sizeof(ELEM_TYPE) * initial_capacity),
@@ -27,7 +29,7 @@ ARRAY_NAME PREFIX_new(void) {
 }
 
 // Create array with specific capacity
-ARRAY_NAME PREFIX_with_capacity(size_t capacity) {
+static inline ARRAY_NAME PREFIX_with_capacity(size_t capacity) {
     ARRAY_NAME arr = {
         .data = malloc(sizeof(ELEM_TYPE) * capacity),
         .len = 0,
@@ -37,7 +39,7 @@ ARRAY_NAME PREFIX_with_capacity(size_t capacity) {
 }
 
 // Free the array's memory
-void PREFIX_free(ARRAY_NAME* arr) {
+static inline void PREFIX_free(ARRAY_NAME* arr) {
     free(arr->data);
     arr->data = NULL;
     arr->len = 0;
@@ -45,7 +47,7 @@ void PREFIX_free(ARRAY_NAME* arr) {
 }
 
 // Ensure the array has enough capacity for additional elements
-void PREFIX_reserve(ARRAY_NAME* arr, size_t additional) {
+static inline void PREFIX_reserve(ARRAY_NAME* arr, size_t additional) {
     size_t required = arr->len + additional;
     if (required <= arr->capacity) return;
     
@@ -59,13 +61,13 @@ void PREFIX_reserve(ARRAY_NAME* arr, size_t additional) {
 }
 
 // Push an element to the end
-void PREFIX_push(ARRAY_NAME* arr, ELEM_TYPE elem) {
+static inline void PREFIX_push(ARRAY_NAME* arr, ELEM_TYPE elem) {
     PREFIX_reserve(arr, 1);
     arr->data[arr->len++] = elem;
 }
 
 // Pop an element from the end
-ELEM_TYPE PREFIX_pop(ARRAY_NAME* arr) {
+static inline ELEM_TYPE PREFIX_pop(ARRAY_NAME* arr) {
     if (arr->len == 0) {
         // TODO: use Result type
         ELEM_TYPE zero = {0};  // Zero initialization works for most types
@@ -75,7 +77,7 @@ ELEM_TYPE PREFIX_pop(ARRAY_NAME* arr) {
 }
 
 // Get a slice of the array (returns new array)
-ARRAY_NAME PREFIX_slice(const ARRAY_NAME* arr, size_t start, size_t end) {
+static inline ARRAY_NAME PREFIX_slice(const ARRAY_NAME* arr, size_t start, size_t end) {
     if (end > arr->len) end = arr->len;
     if (start > end) start = end;
     
@@ -88,8 +90,13 @@ ARRAY_NAME PREFIX_slice(const ARRAY_NAME* arr, size_t start, size_t end) {
     return result;
 }
 
+// Number of elements in the array
+static inline size_t PREFIX_len(const ARRAY_NAME* arr) {
+    return arr->len;
+}
+
 // Get element at index (bounds checking optional based on your language's semantics)
-ELEM_TYPE PREFIX_get(const ARRAY_NAME* arr, size_t index) {
+static inline ELEM_TYPE PREFIX_get(const ARRAY_NAME* arr, size_t index) {
     if (index >= arr->len) {
         // Handle out of bounds - TODO: use Result type
         ELEM_TYPE zero = {0};
@@ -99,7 +106,7 @@ ELEM_TYPE PREFIX_get(const ARRAY_NAME* arr, size_t index) {
 }
 
 // Set element at index (bounds checking optional based on your language's semantics)
-bool PREFIX_set(ARRAY_NAME* arr, size_t index, ELEM_TYPE elem) {
+static inline bool PREFIX_set(ARRAY_NAME* arr, size_t index, ELEM_TYPE elem) {
     if (index >= arr->len) {
         return false;  // Or handle error based on your language's semantics
     }
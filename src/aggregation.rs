@@ -1,13 +1,23 @@
 //! State/Tables for the compiler
 
 use std::collections::hash_map::Entry;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
-use crate::parser::{ASTNode, DataProperties, Enum, FunctionProperties, Statement, Struct, Type};
+use crate::analysis;
+use crate::diagnostics::{did_you_mean, Diagnostic, DiagnosticKind, IssueLevel};
+use crate::expression_parser::Expr;
+use crate::lexer::SourcePosition;
+use crate::parser::{
+    ASTNode, Attribute, DataProperties, DataTraits, Enum, Field, Function, FunctionPermissions,
+    FunctionProperties, Import, Statement, Struct, Type,
+};
 
+#[derive(Debug)]
 pub struct ParsingTables {
     pub modules: ModuleTable,
     pub types: TypeTable,
+    pub functions: FunctionTable,
+    pub calls: CallGraph,
 }
 
 impl ParsingTables {
@@ -15,12 +25,48 @@ impl ParsingTables {
         ParsingTables {
             modules: ModuleTable::new(),
             types: TypeTable::new(),
+            functions: FunctionTable::new(),
+            calls: CallGraph::new(),
         }
     }
 
     pub fn update(&mut self, nodes: &Vec<ASTNode>, module_name: &str) {
         self.modules.update(nodes, module_name);
-        self.types.update(nodes, module_name);
+        // Functions go first, so the type table can see what calls return
+        self.functions.update(nodes, module_name);
+        self.calls.update(nodes, module_name);
+        self.types
+            .update_with_functions(nodes, module_name, &self.functions);
+    }
+
+    /// Run the checks that need every module in the tables, like whether imports match what the other modules make visible
+    pub fn check(&self) -> SemanticOutput {
+        let mut out = SemanticOutput::new();
+        out.extend(self.modules.validate());
+        out
+    }
+}
+
+/// Everything the semantic checks found, in the order the checks ran
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SemanticOutput {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl SemanticOutput {
+    pub fn new() -> SemanticOutput {
+        SemanticOutput::default()
+    }
+
+    pub fn extend(&mut self, diagnostics: Vec<Diagnostic>) {
+        self.diagnostics.extend(diagnostics);
+    }
+
+    /// Should compilation stop? Warnings and notes alone don't stop it
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics
+            .iter()
+            .any(|d| *d.level() == IssueLevel::Error)
     }
 }
 
@@ -28,37 +74,109 @@ impl ParsingTables {
 ///
 /// Each key in the HashMaps corresponds to a filename
 ///
-/// - `parsing_status` lets us track if we need to load and parse a new module
+/// - `parsing_status` lets us track if we need to load and parse a new module. It's sorted, so modules are always loaded in the same order
 ///
 /// - `imported_items` tracks everything that *any* module has tried to bring in from a certain file (functions, structs, enums, etc.)
 ///
-/// - `exported_items` tracks all things marked as Export within a file
+/// - `public_items` tracks all things visible outside of a file (anything marked as Public or Export)
+///
+/// - `exported_items` tracks all things marked as Export within a file, and everything it re-exports with `export import`
+///
+/// - `declared_items` tracks everything declared within a file, visible or not. A module only has an entry here once it has been parsed
+///
+/// - `imports` keeps each import statement alongside the module that wrote it, so mismatches can be reported at the import
+///
+/// - `declarations` keeps every top level declaration in source order, so duplicates can be reported at both definitions
+///
+/// If the `imported_items` and the `exported_items` don't align, then we've got a problem! `validate` reports those problems.
 ///
-/// If the `imported_items` and the `exported_items` don't align, then we've got a problem!
+/// Updating a module that's already in the table replaces what it declared and imported before, rather than adding to it
 ///
 /// TODO: module table seems to fail on functions, my guess is that the parser itself chokes. Look into later.
 #[derive(Debug, Clone, PartialEq)]
 pub struct ModuleTable {
-    pub parsing_status: HashMap<String, bool>,
+    pub parsing_status: BTreeMap<String, bool>,
     imported_items: HashMap<String, HashSet<String>>,
     public_items: HashMap<String, HashSet<String>>,
     exported_items: HashMap<String, HashSet<String>>,
+    declared_items: HashMap<String, HashSet<String>>,
+    imports: Vec<(String, Import)>,
+    declarations: Vec<Declaration>,
+}
+
+/// The kinds of item that can be declared at the top level of a module
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ItemKind {
+    Struct,
+    Enum,
+    Function,
+}
+
+impl ItemKind {
+    fn describe(&self) -> &'static str {
+        match self {
+            ItemKind::Struct => "struct",
+            ItemKind::Enum => "enum",
+            ItemKind::Function => "function",
+        }
+    }
+}
+
+/// Where a top level item was declared
+#[derive(Debug, Clone, PartialEq)]
+pub struct Declaration {
+    pub module: String,
+    pub name: String,
+    pub kind: ItemKind,
+    pub attributes: Vec<Attribute>,
+    pub pos: SourcePosition,
 }
 
 impl ModuleTable {
     pub fn new() -> ModuleTable {
         ModuleTable {
-            parsing_status: HashMap::new(),
+            parsing_status: BTreeMap::new(),
             imported_items: HashMap::new(),
             public_items: HashMap::new(),
             exported_items: HashMap::new(),
+            declared_items: HashMap::new(),
+            imports: Vec::new(),
+            declarations: Vec::new(),
+        }
+    }
+
+    /// Forget everything a module declared and imported
+    ///
+    /// A module that something still imports keeps its `parsing_status`, but modules only this one wanted to load are dropped
+    pub fn remove_module(&mut self, module_name: &str) {
+        self.declared_items.remove(module_name);
+        self.public_items.remove(module_name);
+        self.exported_items.remove(module_name);
+        self.declarations.retain(|d| d.module != module_name);
+        self.imports.retain(|(importer, _)| importer != module_name);
+        self.imported_items.clear();
+        for (_, import) in self.imports.iter() {
+            self.imported_items
+                .entry(import.file.clone())
+                .or_default()
+                .extend(import.items.iter().cloned());
         }
+        let imports = &self.imports;
+        self.parsing_status.retain(|module, is_parsed| {
+            *is_parsed || imports.iter().any(|(_, import)| &import.file == module)
+        });
     }
 
     pub fn update(&mut self, ast: &Vec<ASTNode>, module_name: &str) {
+        self.remove_module(module_name);
+        self.declared_items
+            .entry(module_name.to_string())
+            .or_default();
         for node in ast {
             match node {
                 ASTNode::ImportStatement(i) => {
+                    self.imports.push((module_name.to_string(), i.clone()));
+
                     // Mark this file as needing to be parsed if we haven't seen it before
                     self.parsing_status.entry(i.file.clone()).or_insert(false);
 
@@ -74,50 +192,780 @@ impl ModuleTable {
                             entry.insert(items_set);
                         }
                     }
+
+                    // Re-exported items can be imported from this module as if it declared them
+                    if i.reexport {
+                        for item in i.items.iter() {
+                            self.record_visibility(module_name, item, true, true);
+                        }
+                    }
                 }
                 ASTNode::EnumDeclaration(e) => {
-                    if e.properties.contains(&DataProperties::Export) {
-                        self.exported_items
-                            .entry(module_name.to_string())
-                            .or_insert_with(HashSet::new)
-                            .insert(e.name.clone());
-                    }
-                    if e.properties.contains(&DataProperties::Public) {
-                        self.public_items
-                            .entry(module_name.to_string())
-                            .or_insert_with(HashSet::new)
-                            .insert(e.name.clone());
-                    }
+                    self.record_declaration(
+                        module_name,
+                        ItemKind::Enum,
+                        &e.name,
+                        &e.attributes,
+                        &e.pos,
+                    );
+                    self.record_visibility(
+                        module_name,
+                        &e.name,
+                        e.properties.contains(&DataProperties::Public),
+                        e.properties.contains(&DataProperties::Export),
+                    )
                 }
                 ASTNode::StructDeclaration(s) => {
-                    if s.properties.contains(&DataProperties::Export) {
-                        self.exported_items
-                            .entry(module_name.to_string())
-                            .or_insert_with(HashSet::new)
-                            .insert(s.name.clone());
+                    self.record_declaration(
+                        module_name,
+                        ItemKind::Struct,
+                        &s.name,
+                        &s.attributes,
+                        &s.pos,
+                    );
+                    self.record_visibility(
+                        module_name,
+                        &s.name,
+                        s.properties.contains(&DataProperties::Public),
+                        s.properties.contains(&DataProperties::Export),
+                    )
+                }
+                ASTNode::FunctionDeclaration(f) => {
+                    self.record_declaration(
+                        module_name,
+                        ItemKind::Function,
+                        &f.name,
+                        &f.attributes,
+                        &f.pos,
+                    );
+                    self.record_visibility(
+                        module_name,
+                        &f.name,
+                        f.properties.contains(&FunctionProperties::Public),
+                        f.properties.contains(&FunctionProperties::Export),
+                    )
+                }
+                ASTNode::ModuleMetadata(_) => {}
+            }
+        }
+    }
+
+    fn record_declaration(
+        &mut self,
+        module_name: &str,
+        kind: ItemKind,
+        name: &str,
+        attributes: &[Attribute],
+        pos: &SourcePosition,
+    ) {
+        self.declarations.push(Declaration {
+            module: module_name.to_string(),
+            name: name.to_string(),
+            kind,
+            attributes: attributes.to_vec(),
+            pos: pos.clone(),
+        });
+    }
+
+    /// Every top level declaration with this name, across all modules
+    pub fn declarations_named(&self, name: &str) -> Vec<&Declaration> {
+        self.declarations
+            .iter()
+            .filter(|d| d.name == name)
+            .collect()
+    }
+
+    /// The modules each module imports from, sorted by name so the output is stable
+    pub fn dependencies(&self) -> BTreeMap<String, BTreeSet<String>> {
+        let mut dependencies: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        for (module, import) in self.imports.iter() {
+            dependencies
+                .entry(module.clone())
+                .or_default()
+                .insert(import.file.clone());
+        }
+        dependencies
+    }
+
+    /// The items each module imports from each other module, keyed by `(importer, imported)` and sorted so the output is stable
+    pub fn import_edges(&self) -> BTreeMap<(String, String), BTreeSet<String>> {
+        let mut edges: BTreeMap<(String, String), BTreeSet<String>> = BTreeMap::new();
+        for (module, import) in self.imports.iter() {
+            edges
+                .entry((module.clone(), import.file.clone()))
+                .or_default()
+                .extend(import.items.iter().cloned());
+        }
+        edges
+    }
+
+    /// The modules an entry point needs, each one after everything it imports and the entry point last
+    ///
+    /// Modules the entry point doesn't (transitively) import are left out. Cycles are reported by `validate`, here the import that closes one is skipped
+    pub fn build_order(&self, entry: &str) -> Vec<String> {
+        fn visit(
+            module: &str,
+            dependencies: &BTreeMap<String, BTreeSet<String>>,
+            visiting: &mut BTreeSet<String>,
+            order: &mut Vec<String>,
+        ) {
+            if order.iter().any(|m| m == module) || !visiting.insert(module.to_string()) {
+                return;
+            }
+            for dependency in dependencies.get(module).into_iter().flatten() {
+                visit(dependency, dependencies, visiting, order);
+            }
+            order.push(module.to_string());
+        }
+        let mut order = Vec::new();
+        visit(
+            entry,
+            &self.dependencies(),
+            &mut BTreeSet::new(),
+            &mut order,
+        );
+        order
+    }
+
+    /// Can other Iona modules import this item? True for both `Public` and `Export` items
+    pub fn is_public(&self, module: &str, item: &str) -> bool {
+        self.public_items
+            .get(module)
+            .is_some_and(|items| items.contains(item))
+    }
+
+    /// Is this item part of the module's C interface as well?
+    ///
+    /// `Public` only reaches other Iona modules, `Export` additionally keeps the item visible to C code linking against the generated library
+    pub fn is_exported(&self, module: &str, item: &str) -> bool {
+        self.exported_items
+            .get(module)
+            .is_some_and(|items| items.contains(item))
+    }
+
+    /// The modules that import anything from this one
+    pub fn importers_of(&self, module: &str) -> BTreeSet<String> {
+        self.imports
+            .iter()
+            .filter(|(_, import)| import.file == module)
+            .map(|(importer, _)| importer.clone())
+            .collect()
+    }
+
+    /// Every top level name usable inside a module: its own declarations, plus whatever it imports that the other module has made public
+    pub fn visible_items(&self, module: &str) -> BTreeSet<String> {
+        let mut visible: BTreeSet<String> = self
+            .declared_items
+            .get(module)
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect();
+        for (_, import) in self
+            .imports
+            .iter()
+            .filter(|(importer, _)| importer == module)
+        {
+            visible.extend(
+                import
+                    .items
+                    .iter()
+                    .filter(|item| self.is_public(&import.file, item))
+                    .cloned(),
+            );
+        }
+        visible
+    }
+
+    /// Report names declared more than once in a module
+    ///
+    /// Declarations under different `@cfg` attributes are alternatives for different targets, so they don't collide
+    pub fn check_duplicates(&self, module_name: &str) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        let mut seen: HashMap<&str, Vec<&Declaration>> = HashMap::new();
+        for declaration in self.declarations.iter().filter(|d| d.module == module_name) {
+            let earlier = seen.entry(&declaration.name).or_default();
+            if let Some(first) = earlier
+                .iter()
+                .find(|d| cfg_attributes(&d.attributes) == cfg_attributes(&declaration.attributes))
+            {
+                let message = if first.kind == declaration.kind {
+                    format!(
+                        "{} `{}` is defined more than once in module `{}`",
+                        declaration.kind.describe(),
+                        declaration.name,
+                        module_name
+                    )
+                } else {
+                    format!(
+                        "{} `{}` has the same name as {} `{}`",
+                        declaration.kind.describe(),
+                        declaration.name,
+                        first.kind.describe(),
+                        first.name
+                    )
+                };
+                diagnostics.push(Diagnostic::new_error_with_references(
+                    DiagnosticKind::DuplicateDefinition,
+                    &message,
+                    &declaration.pos,
+                    vec![first.pos.clone()],
+                ));
+            }
+            earlier.push(declaration);
+        }
+        diagnostics
+    }
+
+    /// Record whether an item is visible outside of its module
+    ///
+    /// `Export` makes an item available to the module's consumers, so anything exported is treated as `Public` too
+    fn record_visibility(
+        &mut self,
+        module_name: &str,
+        item_name: &str,
+        is_public: bool,
+        is_export: bool,
+    ) {
+        self.declared_items
+            .entry(module_name.to_string())
+            .or_default()
+            .insert(item_name.to_string());
+        if is_export {
+            self.exported_items
+                .entry(module_name.to_string())
+                .or_default()
+                .insert(item_name.to_string());
+        }
+        if is_public || is_export {
+            self.public_items
+                .entry(module_name.to_string())
+                .or_default()
+                .insert(item_name.to_string());
+        }
+    }
+
+    /// Check every import against what the target module actually makes visible
+    ///
+    /// Reports imports of names the target doesn't declare, of names that aren't `Public` or `Export`, from modules that were never parsed, of the same name from two different modules, and modules that import each other in a loop
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        // Which module (and import statement) each consumer got each name from
+        let mut sources: HashMap<(&str, &str), &Import> = HashMap::new();
+        for (importer, import) in &self.imports {
+            for item in &import.items {
+                match sources.entry((importer, item)) {
+                    Entry::Occupied(entry) if entry.get().file != import.file => {
+                        diagnostics.push(Diagnostic::new_error_with_references(
+                            DiagnosticKind::UnresolvedImport,
+                            &format!(
+                                "`{}` is imported from both `{}` and `{}`",
+                                item,
+                                entry.get().file,
+                                import.file
+                            ),
+                            &import.pos,
+                            vec![entry.get().pos.clone()],
+                        ));
+                    }
+                    Entry::Occupied(_) => {}
+                    Entry::Vacant(entry) => {
+                        entry.insert(import);
                     }
-                    if s.properties.contains(&DataProperties::Public) {
-                        self.public_items
-                            .entry(module_name.to_string())
-                            .or_insert_with(HashSet::new)
-                            .insert(s.name.clone());
+                }
+            }
+            let declared = match self.declared_items.get(&import.file) {
+                Some(declared) => declared,
+                None => {
+                    diagnostics.push(Diagnostic::new_error_simple(
+                        DiagnosticKind::UnresolvedImport,
+                        &format!(
+                            "module `{}` imports from `{}`, but `{}` was never parsed",
+                            importer, import.file, import.file
+                        ),
+                        &import.pos,
+                    ));
+                    continue;
+                }
+            };
+            let public = self.public_items.get(&import.file);
+            for item in &import.items {
+                if !declared.contains(item) {
+                    let mut candidates: Vec<&str> = declared.iter().map(|d| d.as_str()).collect();
+                    candidates.sort();
+                    let message = match did_you_mean(item, candidates) {
+                        Some(suggestion) => format!(
+                            "`{}` is not defined in module `{}` (did you mean `{}`?)",
+                            item, import.file, suggestion
+                        ),
+                        None => format!("`{}` is not defined in module `{}`", item, import.file),
+                    };
+                    diagnostics.push(Diagnostic::new_error_simple(
+                        DiagnosticKind::UnresolvedImport,
+                        &message,
+                        &import.pos,
+                    ));
+                } else if !public.is_some_and(|p| p.contains(item)) {
+                    diagnostics.push(Diagnostic::new_error_simple(
+                        DiagnosticKind::PrivateImport,
+                        &format!(
+                            "`{}` is private to module `{}`, mark it as `Public` or `Export` to import it",
+                            item, import.file
+                        ),
+                        &import.pos,
+                    ));
+                }
+            }
+        }
+        diagnostics.extend(self.check_cycles());
+        diagnostics
+    }
+
+    /// Report modules that (eventually) import themselves, since their C headers can't include each other in a loop
+    ///
+    /// Each cycle is reported once, at the import that closes it
+    fn check_cycles(&self) -> Vec<Diagnostic> {
+        let mut graph: BTreeMap<&str, Vec<&Import>> = BTreeMap::new();
+        for (importer, import) in &self.imports {
+            let edges = graph.entry(importer.as_str()).or_default();
+            if !edges.iter().any(|edge| edge.file == import.file) {
+                edges.push(import);
+            }
+        }
+        let mut finished = HashSet::new();
+        let mut diagnostics = Vec::new();
+        for module in graph.keys() {
+            find_cycles(
+                module,
+                &graph,
+                &mut Vec::new(),
+                &mut finished,
+                &mut diagnostics,
+            );
+        }
+        diagnostics
+    }
+}
+
+/// The `@cfg` attributes in a list, which decide whether two declarations are alternatives to each other
+fn cfg_attributes(attributes: &[Attribute]) -> Vec<&Attribute> {
+    attributes
+        .iter()
+        .filter(|attribute| matches!(attribute, Attribute::Cfg { .. }))
+        .collect()
+}
+
+/// Depth first search through the import graph
+///
+/// `path` holds the modules we're in the middle of visiting, each with the import we followed out of it
+fn find_cycles<'a>(
+    module: &'a str,
+    graph: &BTreeMap<&'a str, Vec<&'a Import>>,
+    path: &mut Vec<(&'a str, &'a Import)>,
+    finished: &mut HashSet<&'a str>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    if finished.contains(module) {
+        return;
+    }
+    for import in graph.get(module).into_iter().flatten() {
+        let target = import.file.as_str();
+        if target == module {
+            diagnostics.push(Diagnostic::new_error_simple(
+                DiagnosticKind::ImportCycle,
+                &format!("module `{}` imports itself", module),
+                &import.pos,
+            ));
+        } else if let Some(start) = path.iter().position(|(m, _)| *m == target) {
+            let cycle = path[start..]
+                .iter()
+                .map(|(m, _)| *m)
+                .chain([module, target])
+                .collect::<Vec<&str>>();
+            diagnostics.push(Diagnostic::new_error_with_references(
+                DiagnosticKind::ImportCycle,
+                &format!("import cycle between modules: {}", cycle.join(" -> ")),
+                &import.pos,
+                path[start..].iter().map(|(_, i)| i.pos.clone()).collect(),
+            ));
+        } else {
+            path.push((module, import));
+            find_cycles(target, graph, path, finished, diagnostics);
+            path.pop();
+        }
+    }
+    finished.insert(module);
+}
+
+/// The signature of a function, without its body
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionSig {
+    pub module: String,
+    pub name: String,
+    pub args: Vec<Field>,
+    pub returns: Type,
+    pub properties: Vec<FunctionProperties>,
+    pub permissions: Vec<FunctionPermissions>,
+}
+
+impl FunctionSig {
+    /// `defaults` are the module's permissions (from `@module`), which the function gets if it doesn't declare its own
+    fn from_function(
+        f: &Function,
+        module_name: &str,
+        defaults: &[FunctionPermissions],
+    ) -> FunctionSig {
+        FunctionSig {
+            module: module_name.to_string(),
+            name: f.name.clone(),
+            args: f.args.clone(),
+            returns: f.returns.clone(),
+            properties: f.properties.clone(),
+            permissions: analysis::effective_permissions(f, defaults).to_vec(),
+        }
+    }
+}
+
+/// Track the signature of every function declared in the program
+///
+/// Signatures are keyed by `(module, name)`. If a module declares the same name twice, the first declaration wins and the later ones are kept in `duplicates` so they can be diagnosed.
+///
+/// Each module's imports are kept too, so a call can be resolved the way the module sees it (see `resolve`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionTable {
+    signatures: HashMap<(String, String), FunctionSig>,
+    duplicates: Vec<FunctionSig>,
+    /// The module each imported name comes from, keyed by `(importing module, name)`
+    imports: HashMap<(String, String), String>,
+}
+
+impl FunctionTable {
+    pub fn new() -> FunctionTable {
+        FunctionTable {
+            signatures: HashMap::new(),
+            duplicates: Vec::new(),
+            imports: HashMap::new(),
+        }
+    }
+
+    /// Forget every function a module declared and everything it imported, duplicates included
+    pub fn remove_module(&mut self, module_name: &str) {
+        self.signatures
+            .retain(|(module, _), _| module != module_name);
+        self.duplicates.retain(|sig| sig.module != module_name);
+        self.imports.retain(|(module, _), _| module != module_name);
+    }
+
+    pub fn update(&mut self, ast: &Vec<ASTNode>, module_name: &str) {
+        self.remove_module(module_name);
+        let defaults = analysis::module_permissions(ast);
+        for node in ast {
+            if let ASTNode::ImportStatement(i) = node {
+                for item in i.items.iter() {
+                    self.imports
+                        .entry((module_name.to_string(), item.clone()))
+                        .or_insert_with(|| i.file.clone());
+                }
+            }
+            if let ASTNode::FunctionDeclaration(f) = node {
+                let signature = FunctionSig::from_function(f, module_name, &defaults);
+                match self
+                    .signatures
+                    .entry((module_name.to_string(), f.name.clone()))
+                {
+                    Entry::Occupied(_) => self.duplicates.push(signature),
+                    Entry::Vacant(entry) => {
+                        entry.insert(signature);
                     }
                 }
+            }
+        }
+    }
+
+    /// Find a function by name in a specific module
+    pub fn lookup_in(&self, module_name: &str, name: &str) -> Option<&FunctionSig> {
+        self.signatures
+            .get(&(module_name.to_string(), name.to_string()))
+    }
+
+    /// Find the function a call to `name` from `module_name` reaches, like the call graph does
+    ///
+    /// The module's own functions win, then the ones it imports by that name. Imports are followed through `export import`s to the module that declares the function
+    pub fn resolve(&self, module_name: &str, name: &str) -> Option<&FunctionSig> {
+        let mut module = module_name;
+        let mut seen = HashSet::new();
+        // An import cycle never reaches a declaration, so stop once it comes back around
+        while seen.insert(module) {
+            if let Some(sig) = self.lookup_in(module, name) {
+                return Some(sig);
+            }
+            module = self.imports.get(&(module.to_string(), name.to_string()))?;
+        }
+        None
+    }
+
+    /// All functions declared in a module, sorted by name
+    pub fn all_in_module(&self, module_name: &str) -> Vec<&FunctionSig> {
+        let mut found = self
+            .signatures
+            .values()
+            .filter(|sig| sig.module == module_name)
+            .collect::<Vec<&FunctionSig>>();
+        found.sort_by(|a, b| a.name.cmp(&b.name));
+        found
+    }
+
+    /// Declarations which reused a name already declared in the same module
+    pub fn duplicates(&self) -> &[FunctionSig] {
+        &self.duplicates
+    }
+}
+
+/// A function qualified by the module that declares it, methods are named like `Animal.speak`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FunctionId {
+    pub module: String,
+    pub name: String,
+}
+
+impl FunctionId {
+    pub fn new(module: &str, name: &str) -> FunctionId {
+        FunctionId {
+            module: module.to_string(),
+            name: name.to_string(),
+        }
+    }
+}
+
+/// Track who calls whom across the whole program
+///
+/// A call resolves to a function declared in the same module, and then to one imported by name. Method calls resolve to every method with that name in the same module, since we don't know the type of the receiver yet. Built in functions aren't recorded.
+///
+/// Calls that don't resolve (like a misspelled name) are kept in `unresolved`, so "calls nothing" can be told apart from "calls something we couldn't find"
+///
+/// A name re-exported with `export import` is recorded as calling the function it came from, so calls through the re-export still reach it
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallGraph {
+    calls: BTreeMap<FunctionId, BTreeSet<FunctionId>>,
+    unresolved: BTreeMap<FunctionId, BTreeSet<String>>,
+    /// `Export` functions and the methods of `Export` types, which C code outside the program can call
+    exported: BTreeSet<FunctionId>,
+}
+
+impl CallGraph {
+    pub fn new() -> CallGraph {
+        CallGraph {
+            calls: BTreeMap::new(),
+            unresolved: BTreeMap::new(),
+            exported: BTreeSet::new(),
+        }
+    }
+
+    /// Forget every function a module declared, calls into it from other modules are kept
+    pub fn remove_module(&mut self, module_name: &str) {
+        self.calls.retain(|id, _| id.module != module_name);
+        self.unresolved.retain(|id, _| id.module != module_name);
+        self.exported.retain(|id| id.module != module_name);
+    }
+
+    pub fn update(&mut self, ast: &Vec<ASTNode>, module_name: &str) {
+        self.remove_module(module_name);
+        let mut local: HashSet<&str> = HashSet::new();
+        let mut imported: HashMap<&str, &str> = HashMap::new();
+        // (the method's own name, the function it is) for every method in the module
+        let mut methods: Vec<(&str, FunctionId)> = Vec::new();
+        // (the function, whether it's exported) for every function and method in the module
+        let mut functions: Vec<(FunctionId, &Function, bool)> = Vec::new();
+        for node in ast {
+            match node {
                 ASTNode::FunctionDeclaration(f) => {
-                    if f.properties.contains(&FunctionProperties::Export) {
-                        self.exported_items
-                            .entry(module_name.to_string())
-                            .or_insert_with(HashSet::new)
-                            .insert(f.name.clone());
+                    local.insert(&f.name);
+                    let exported = f.properties.contains(&FunctionProperties::Export);
+                    functions.push((FunctionId::new(module_name, &f.name), f, exported));
+                }
+                ASTNode::StructDeclaration(Struct {
+                    name,
+                    methods: declared,
+                    properties,
+                    ..
+                })
+                | ASTNode::EnumDeclaration(Enum {
+                    name,
+                    methods: declared,
+                    properties,
+                    ..
+                }) => {
+                    let exported = properties.contains(&DataProperties::Export);
+                    for method in declared.iter() {
+                        let id = FunctionId::new(module_name, &format!("{}.{}", name, method.name));
+                        methods.push((&method.name, id.clone()));
+                        functions.push((id, method, exported));
+                    }
+                }
+                ASTNode::ImportStatement(i) => {
+                    for item in i.items.iter() {
+                        imported.insert(item, &i.file);
+                        if i.reexport {
+                            self.calls.insert(
+                                FunctionId::new(module_name, item),
+                                BTreeSet::from([FunctionId::new(&i.file, item)]),
+                            );
+                        }
+                    }
+                }
+                ASTNode::ModuleMetadata(_) => {}
+            }
+        }
+
+        for (id, f, exported) in functions {
+            if exported {
+                self.exported.insert(id.clone());
+            }
+            let mut callees = BTreeSet::new();
+            let mut unresolved = BTreeSet::new();
+            let mut record = |expr: &Expr, _: &SourcePosition| match expr {
+                Expr::FunctionCall { name, .. } => {
+                    if local.contains(name.as_str()) {
+                        callees.insert(FunctionId::new(module_name, name));
+                    } else if let Some(module) = imported.get(name.as_str()) {
+                        callees.insert(FunctionId::new(module, name));
+                    } else if !analysis::BUILTIN_FUNCTIONS.contains(&name.as_str()) {
+                        unresolved.insert(name.clone());
                     }
-                    if f.properties.contains(&FunctionProperties::Public) {
-                        self.public_items
-                            .entry(module_name.to_string())
-                            .or_insert_with(HashSet::new)
-                            .insert(f.name.clone());
+                }
+                Expr::MethodCall { method, .. } => {
+                    let targets = methods
+                        .iter()
+                        .filter(|(name, _)| name == method)
+                        .map(|(_, id)| id.clone())
+                        .collect::<Vec<FunctionId>>();
+                    if targets.is_empty() {
+                        unresolved.insert(format!(".{}", method));
                     }
+                    callees.extend(targets);
+                }
+                _ => {}
+            };
+            for contract in f.contracts.iter() {
+                analysis::visit_expr(&contract.condition, &contract.pos, &mut record);
+            }
+            analysis::visit_statements(&f.statements, &mut record);
+            if !unresolved.is_empty() {
+                self.unresolved.insert(id.clone(), unresolved);
+            }
+            self.calls.insert(id, callees);
+        }
+    }
+
+    /// The functions `f` calls directly, sorted
+    pub fn callees(&self, f: &FunctionId) -> Vec<&FunctionId> {
+        self.calls
+            .get(f)
+            .map(|callees| callees.iter().collect())
+            .unwrap_or_default()
+    }
+
+    /// The functions that call `f` directly, sorted
+    pub fn callers(&self, f: &FunctionId) -> Vec<&FunctionId> {
+        self.calls
+            .iter()
+            .filter(|(_, callees)| callees.contains(f))
+            .map(|(caller, _)| caller)
+            .collect()
+    }
+
+    /// Calls made by `f` that couldn't be resolved, method calls are written like `.speak`
+    pub fn unresolved_calls(&self, f: &FunctionId) -> Vec<&str> {
+        self.unresolved
+            .get(f)
+            .map(|names| names.iter().map(|name| name.as_str()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Every function `entry` can end up calling, including itself
+    pub fn reachable_from(&self, entry: &FunctionId) -> BTreeSet<&FunctionId> {
+        let mut reached = BTreeSet::new();
+        let mut stack = self
+            .calls
+            .get_key_value(entry)
+            .map(|(id, _)| id)
+            .into_iter()
+            .collect::<Vec<&FunctionId>>();
+        while let Some(f) = stack.pop() {
+            if reached.insert(f) {
+                stack.extend(self.callees(f));
+            }
+        }
+        reached
+    }
+
+    /// Where the program can be entered from: every `main`, and everything exported
+    pub fn entry_points(&self) -> Vec<&FunctionId> {
+        self.calls
+            .keys()
+            .filter(|id| id.name == "main" || self.exported.contains(*id))
+            .collect()
+    }
+
+    /// Group the functions into strongly connected components (sets of mutually recursive functions)
+    ///
+    /// Components come out callees first, so a function is only visited after everything it calls outside of its own cycle
+    pub fn components(&self) -> Vec<Vec<&FunctionId>> {
+        let mut tarjan = Tarjan {
+            graph: self,
+            index: HashMap::new(),
+            low_link: HashMap::new(),
+            stack: Vec::new(),
+            components: Vec::new(),
+        };
+        for f in self.calls.keys() {
+            if !tarjan.index.contains_key(f) {
+                tarjan.visit(f);
+            }
+        }
+        tarjan.components
+    }
+}
+
+/// Bookkeeping for Tarjan's strongly connected components algorithm
+struct Tarjan<'a> {
+    graph: &'a CallGraph,
+    index: HashMap<&'a FunctionId, usize>,
+    low_link: HashMap<&'a FunctionId, usize>,
+    stack: Vec<&'a FunctionId>,
+    components: Vec<Vec<&'a FunctionId>>,
+}
+
+impl<'a> Tarjan<'a> {
+    fn visit(&mut self, f: &'a FunctionId) {
+        let index = self.index.len();
+        self.index.insert(f, index);
+        self.low_link.insert(f, index);
+        self.stack.push(f);
+        // Callees from other modules that haven't been parsed yet have no entry, so they're skipped
+        for callee in self.graph.callees(f) {
+            if !self.graph.calls.contains_key(callee) {
+                continue;
+            }
+            if !self.index.contains_key(callee) {
+                self.visit(callee);
+                let low = self.low_link[f].min(self.low_link[callee]);
+                self.low_link.insert(f, low);
+            } else if self.stack.contains(&callee) {
+                let low = self.low_link[f].min(self.index[callee]);
+                self.low_link.insert(f, low);
+            }
+        }
+        if self.low_link[f] == self.index[f] {
+            let mut component = Vec::new();
+            while let Some(member) = self.stack.pop() {
+                component.push(member);
+                if member == f {
+                    break;
                 }
             }
+            component.sort();
+            self.components.push(component);
         }
     }
 }
@@ -127,12 +975,32 @@ impl ModuleTable {
 /// All fields except `types_used_by_module` are "global" across the program
 ///
 /// `types_used_by_module` tracks which types are *external* to a module so we know what that module has to import
+///
+/// `instantiations` tracks the concrete types each generic function is called with, one type per generic parameter (in the order they first appear in the signature)
+///
+/// Updating a module that's already in the table replaces everything it contributed before, so the global fields are rebuilt from `contributions`
 #[derive(Debug, Clone, PartialEq)]
 pub struct TypeTable {
     pub type_list: HashSet<Type>,
     pub types_used_by_module: HashMap<String, HashSet<Type>>,
     new_structs: HashMap<String, Struct>,
     new_enums: HashMap<String, Enum>,
+    instantiations: HashMap<String, HashSet<Vec<Type>>>,
+    contributions: HashMap<String, ModuleTypes>,
+    /// The module each struct and enum was declared in
+    declared_in: HashMap<String, String>,
+    /// The parameter and return types of every function, so codegen can tell what an argument or a call's result is stored as
+    function_types: HashMap<String, (Vec<Type>, Type)>,
+    /// The modules that call `print` or `print_raw`, which need the console runtime
+    console_users: HashSet<String>,
+}
+
+/// What a single module added to the global parts of the `TypeTable`
+#[derive(Debug, Clone, PartialEq, Default)]
+struct ModuleTypes {
+    types: HashSet<Type>,
+    instantiations: Vec<(String, Vec<Type>)>,
+    functions: Vec<(String, Vec<Type>, Type)>,
 }
 
 impl TypeTable {
@@ -142,107 +1010,501 @@ impl TypeTable {
             types_used_by_module: HashMap::new(),
             new_structs: HashMap::new(),
             new_enums: HashMap::new(),
+            instantiations: HashMap::new(),
+            contributions: HashMap::new(),
+            declared_in: HashMap::new(),
+            function_types: HashMap::new(),
+            console_users: HashSet::new(),
         }
     }
 
-    // Helper method to process individual statements
-    fn process_statement(
+    /// Forget everything a module contributed, as if it had never been added
+    pub fn remove_module(&mut self, module_name: &str) {
+        self.types_used_by_module.remove(module_name);
+        self.contributions.remove(module_name);
+        self.console_users.remove(module_name);
+        let declared = self
+            .declared_in
+            .iter()
+            .filter(|(_, module)| *module == module_name)
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<String>>();
+        for name in declared {
+            self.new_structs.remove(&name);
+            self.new_enums.remove(&name);
+            self.declared_in.remove(&name);
+        }
+        // Other modules may have contributed the same types, so rebuild from what's left
+        self.type_list = self
+            .contributions
+            .values()
+            .flat_map(|contribution| contribution.types.iter().cloned())
+            .collect();
+        self.instantiations.clear();
+        for contribution in self.contributions.values() {
+            for (name, concrete) in contribution.instantiations.iter() {
+                self.instantiations
+                    .entry(name.clone())
+                    .or_default()
+                    .insert(concrete.clone());
+            }
+        }
+        self.function_types = self
+            .contributions
+            .values()
+            .flat_map(|contribution| contribution.functions.iter())
+            .map(|(name, args, returns)| (name.clone(), (args.clone(), returns.clone())))
+            .collect();
+    }
+
+    /// Collect the types used by a function: declared types, and the types of expressions where we know them
+    ///
+    /// Expressions are walked by `analysis::visit_statements`, so every kind of statement is covered
+    fn process_function(
         &mut self,
-        statement: &Statement,
+        f: &Function,
+        module_name: &str,
+        functions: Option<&FunctionTable>,
         external_type_tracker: &mut HashSet<Type>,
+        contribution: &mut ModuleTypes,
     ) {
-        match statement {
-            Statement::VariableDeclaration { type_, .. } => {
-                self.type_list.insert(type_.clone());
-                external_type_tracker.insert(type_.clone());
-            }
-            Statement::Conditional(branches) => {
-                for branch in branches {
-                    for inner_statement in &branch.computations {
-                        self.process_statement(inner_statement, external_type_tracker);
+        let mut declarations = Vec::new();
+        collect_declarations(&f.statements, &mut declarations);
+        let mut found = declarations
+            .iter()
+            .map(|(_, type_)| (*type_).clone())
+            .collect::<Vec<Type>>();
+        // Good enough to type the arguments of generic calls, even though it ignores shadowing
+        let locals = f
+            .args
+            .iter()
+            .map(|arg| (arg.name.as_str(), &arg.field_type))
+            .chain(declarations.iter().copied())
+            .collect::<HashMap<&str, &Type>>();
+        let mut calls = Vec::new();
+        let mut console = false;
+        analysis::visit_statements(&f.statements, &mut |expr, _| match expr {
+            Expr::IntegerLiteral(_) => found.push(Type::Integer),
+            Expr::FloatLiteral(_) => found.push(Type::Float),
+            Expr::StringLiteral(_) => found.push(Type::String),
+            Expr::FunctionCall { name, arguments } => {
+                console |= analysis::CONSOLE_FUNCTIONS.contains(&name.as_str());
+                if let Some(functions) = functions {
+                    if let Some(returns) = expr_type(expr, &locals, module_name, functions) {
+                        found.push(returns);
+                    }
+                    let generic = functions
+                        .resolve(module_name, name)
+                        .filter(|sig| sig.args.iter().any(|arg| contains_generic(&arg.field_type)));
+                    if let Some(sig) = generic {
+                        if let Some(bindings) =
+                            generic_bindings(sig, arguments, &locals, module_name, functions)
+                        {
+                            calls.push((
+                                name.clone(),
+                                bindings.into_iter().map(|(_, t)| t).collect::<Vec<Type>>(),
+                            ));
+                        }
                     }
                 }
             }
-            // Add other statement types as needed
             _ => {}
+        });
+        if console {
+            self.console_users.insert(module_name.to_string());
+        }
+        for type_ in found {
+            self.type_list.insert(type_.clone());
+            contribution.types.insert(type_.clone());
+            external_type_tracker.insert(type_);
+        }
+        for (name, concrete) in calls {
+            self.instantiations
+                .entry(name.clone())
+                .or_default()
+                .insert(concrete.clone());
+            contribution.instantiations.push((name, concrete));
         }
     }
 
+    /// Add a type to the global list on behalf of a module
+    fn add_type(&mut self, type_: &Type, contribution: &mut ModuleTypes) {
+        self.type_list.insert(type_.clone());
+        contribution.types.insert(type_.clone());
+    }
+
     /// Walk an AST and build a set of all of the types used
+    ///
+    /// Calls only contribute their return types through `update_with_functions`
     pub fn update(&mut self, ast: &Vec<ASTNode>, module_name: &str) {
+        self.update_inner(ast, module_name, None);
+    }
+
+    /// Walk an AST like `update`, also recording the return type of each call to a function in `functions`
+    pub fn update_with_functions(
+        &mut self,
+        ast: &Vec<ASTNode>,
+        module_name: &str,
+        functions: &FunctionTable,
+    ) {
+        self.update_inner(ast, module_name, Some(functions));
+    }
+
+    fn update_inner(
+        &mut self,
+        ast: &Vec<ASTNode>,
+        module_name: &str,
+        functions: Option<&FunctionTable>,
+    ) {
+        self.remove_module(module_name);
         let mut types_used_by_module: HashSet<Type> = HashSet::new();
+        let mut contribution = ModuleTypes::default();
         for node in ast {
             match node {
                 ASTNode::StructDeclaration(s) => {
                     // store new struct
                     self.new_structs.insert(s.name.clone(), s.clone());
+                    self.declared_in
+                        .insert(s.name.clone(), module_name.to_string());
                     // Add all used types to the type list
-                    self.type_list.insert(Type::Custom(s.name.clone()));
+                    self.add_type(&Type::Custom(s.name.clone()), &mut contribution);
                     for field in s.fields.iter() {
-                        self.type_list.insert(field.field_type.clone());
+                        self.add_type(&field.field_type, &mut contribution);
                         types_used_by_module.insert(field.field_type.clone());
                     }
                 }
                 ASTNode::EnumDeclaration(e) => {
                     self.new_enums.insert(e.name.clone(), e.clone());
+                    self.declared_in
+                        .insert(e.name.clone(), module_name.to_string());
                     // Add all used types to the type list
-                    self.type_list.insert(Type::Custom(e.name.clone()));
+                    self.add_type(&Type::Custom(e.name.clone()), &mut contribution);
                     for field in e.fields.iter() {
-                        self.type_list.insert(field.field_type.clone());
+                        self.add_type(&field.field_type, &mut contribution);
                         types_used_by_module.insert(field.field_type.clone());
                     }
                 }
                 ASTNode::FunctionDeclaration(f) => {
-                    self.type_list.insert(f.returns.clone());
+                    let args = f
+                        .args
+                        .iter()
+                        .map(|arg| arg.field_type.clone())
+                        .collect::<Vec<Type>>();
+                    self.function_types
+                        .insert(f.name.clone(), (args.clone(), f.returns.clone()));
+                    contribution
+                        .functions
+                        .push((f.name.clone(), args, f.returns.clone()));
+                    self.add_type(&f.returns, &mut contribution);
+                    types_used_by_module.insert(f.returns.clone());
                     for arg in f.args.iter() {
-                        self.type_list.insert(arg.field_type.clone());
+                        self.add_type(&arg.field_type, &mut contribution);
                         types_used_by_module.insert(arg.field_type.clone());
                     }
-                    for st in f.statements.iter() {
-                        self.process_statement(st, &mut types_used_by_module);
-                    }
+                    self.process_function(
+                        f,
+                        module_name,
+                        functions,
+                        &mut types_used_by_module,
+                        &mut contribution,
+                    );
                 }
-                ASTNode::ImportStatement(_) => {}
+                ASTNode::ImportStatement(_) | ASTNode::ModuleMetadata(_) => {}
             }
         }
         self.types_used_by_module
             .insert(module_name.to_string(), types_used_by_module);
+        self.contributions
+            .insert(module_name.to_string(), contribution);
     }
-}
 
-// -------------------- Unit Tests --------------------
+    /// Was this name declared as a struct anywhere in the program?
+    pub fn is_struct(&self, name: &str) -> bool {
+        self.new_structs.contains_key(name)
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::lexer::Lexer;
-    use crate::parser::Parser;
+    /// Was this name declared as an enum anywhere in the program?
+    pub fn is_enum(&self, name: &str) -> bool {
+        self.new_enums.contains_key(name)
+    }
 
-    const PROGRAM: &'static str = r#"import npc with Creature;
+    /// Look up an enum declared anywhere in the program
+    pub fn get_enum(&self, name: &str) -> Option<&Enum> {
+        self.new_enums.get(name)
+    }
 
-        struct Animal {
-            legs: Int,
-            hair: Bool,
-            feathers: Bool
-            
-            @metadata {
-                Is: Public, Export;
-                Derives: Eq, Show;
+    /// Where a struct or enum was declared
+    pub fn declared_at(&self, name: &str) -> Option<&SourcePosition> {
+        self.new_structs
+            .get(name)
+            .map(|s| &s.pos)
+            .or_else(|| self.new_enums.get(name).map(|e| &e.pos))
+    }
+
+    /// The part of a type that stops it from deriving `trait_`, if there is one
+    ///
+    /// Primitives can derive anything, containers can if their elements can, and structs and enums must derive it themselves. Generics and C types never can, since we can't see inside them
+    pub fn underivable_part<'a>(&self, type_: &'a Type, trait_: &DataTraits) -> Option<&'a Type> {
+        match type_ {
+            Type::Array(inner)
+            | Type::Map(inner)
+            | Type::Shared(inner)
+            | Type::Optional(inner)
+            | Type::FixedArray(inner, _) => self.underivable_part(inner, trait_),
+            Type::Tuple(items) => items
+                .iter()
+                .find_map(|item| self.underivable_part(item, trait_)),
+            Type::Custom(name) => {
+                let traits = match (self.new_structs.get(name), self.new_enums.get(name)) {
+                    (Some(s), _) => &s.traits,
+                    (None, Some(e)) => &e.traits,
+                    // Unknown types are reported by `analysis::check_types`
+                    (None, None) => return None,
+                };
+                (!traits.contains(trait_)).then_some(type_)
             }
+            Type::Generic(_) | Type::CType => Some(type_),
+            _ => None,
         }
+    }
 
-        enum Status {
-            Alive,
-            Dead,
+    /// The types of a function's parameters, if it was declared anywhere in the program
+    pub fn parameters_of(&self, name: &str) -> Option<&[Type]> {
+        self.function_types
+            .get(name)
+            .map(|(args, _)| args.as_slice())
+    }
 
-            @metadata {
-                Is: Export;
-            }
-        }
-    "#;
+    /// What a function returns, if it was declared anywhere in the program
+    pub fn returns_of(&self, name: &str) -> Option<&Type> {
+        self.function_types.get(name).map(|(_, returns)| returns)
+    }
 
-    #[test]
-    fn construct_module_table() {
+    /// Does a module call any of the console builtins?
+    pub fn uses_console(&self, module_name: &str) -> bool {
+        self.console_users.contains(module_name)
+    }
+
+    /// Every set of concrete types a generic function has been called with
+    pub fn instantiations_of(&self, name: &str) -> Option<&HashSet<Vec<Type>>> {
+        self.instantiations.get(name)
+    }
+
+    /// Render the table for debugging, with every list sorted so the output is stable between runs
+    pub fn dump(&self) -> String {
+        fn sorted_names<'a, I: Iterator<Item = &'a Type>>(types: I) -> Vec<String> {
+            let mut names = types.map(|t| format!("{:?}", t)).collect::<Vec<String>>();
+            names.sort();
+            names
+        }
+
+        let mut buffer = "type_list:\n".to_string();
+        for name in sorted_names(self.type_list.iter()) {
+            buffer.push_str(&format!("    {}\n", name));
+        }
+        buffer.push_str("types_used_by_module:\n");
+        let mut modules = self.types_used_by_module.keys().collect::<Vec<&String>>();
+        modules.sort();
+        for module in modules {
+            buffer.push_str(&format!("    {}:\n", module));
+            for name in sorted_names(self.types_used_by_module[module].iter()) {
+                buffer.push_str(&format!("        {}\n", name));
+            }
+        }
+        buffer
+    }
+}
+
+/// The explicitly declared variables in a block (with their types), including nested blocks
+///
+/// `Auto` isn't a real type, it's resolved before codegen
+fn collect_declarations<'a>(statements: &'a [Statement], found: &mut Vec<(&'a str, &'a Type)>) {
+    for statement in statements {
+        match statement {
+            Statement::VariableDeclaration { name, type_, .. } => {
+                if *type_ != Type::Auto {
+                    found.push((name, type_));
+                }
+            }
+            Statement::Conditional { branches, .. } | Statement::Match { branches, .. } => {
+                for branch in branches {
+                    collect_declarations(&branch.computations, found);
+                }
+            }
+            Statement::WhileLoop { body, .. } => collect_declarations(body, found),
+            Statement::FunctionCall { .. }
+            | Statement::VariableMutation { .. }
+            | Statement::Return { .. }
+            | Statement::Assert { .. } => {}
+        }
+    }
+}
+
+/// The type of an expression in `module_name`, as far as we can tell without a full type check
+fn expr_type(
+    expr: &Expr,
+    locals: &HashMap<&str, &Type>,
+    module_name: &str,
+    functions: &FunctionTable,
+) -> Option<Type> {
+    match expr {
+        Expr::IntegerLiteral(_) => Some(Type::Integer),
+        Expr::FloatLiteral(_) => Some(Type::Float),
+        Expr::StringLiteral(_) => Some(Type::String),
+        Expr::Variable(name) => locals.get(name.as_str()).map(|t| (*t).clone()),
+        Expr::FunctionCall { name, arguments } => {
+            let sig = functions.resolve(module_name, name)?;
+            if !contains_generic(&sig.returns) {
+                return Some(sig.returns.clone());
+            }
+            let bindings = generic_bindings(sig, arguments, locals, module_name, functions)?;
+            Some(substitute_generics(&sig.returns, &bindings))
+        }
+        _ => None,
+    }
+}
+
+/// Work out the concrete type behind each generic parameter of a call, in the order they first appear
+///
+/// Returns `None` unless every generic parameter can be pinned down
+fn generic_bindings(
+    sig: &FunctionSig,
+    arguments: &[Expr],
+    locals: &HashMap<&str, &Type>,
+    module_name: &str,
+    functions: &FunctionTable,
+) -> Option<Vec<(String, Type)>> {
+    let mut bindings = Vec::new();
+    for (param, arg) in sig.args.iter().zip(arguments.iter()) {
+        if contains_generic(&param.field_type) {
+            let arg_type = expr_type(arg, locals, module_name, functions)?;
+            bind_generics(&param.field_type, &arg_type, &mut bindings)?;
+        }
+    }
+    let all_bound = sig.args.iter().all(|param| {
+        generic_names(&param.field_type)
+            .iter()
+            .all(|name| bindings.iter().any(|(bound, _)| bound == name))
+    });
+    all_bound.then_some(bindings)
+}
+
+/// Match a parameter type against an argument type, recording what each generic stands for
+///
+/// Fails if a generic would stand for two different types, or for something that isn't concrete. Any other mismatch is left for `typecheck`
+fn bind_generics(param: &Type, arg: &Type, bindings: &mut Vec<(String, Type)>) -> Option<()> {
+    match (param, arg) {
+        (Type::Generic(name), concrete) => {
+            if contains_generic(concrete) || *concrete == Type::Auto {
+                return None;
+            }
+            match bindings.iter().find(|(bound, _)| bound == name) {
+                Some((_, existing)) => (existing == concrete).then_some(()),
+                None => {
+                    bindings.push((name.clone(), concrete.clone()));
+                    Some(())
+                }
+            }
+        }
+        (Type::Array(p), Type::Array(a))
+        | (Type::Map(p), Type::Map(a))
+        | (Type::Shared(p), Type::Shared(a))
+        | (Type::Optional(p), Type::Optional(a)) => bind_generics(p, a, bindings),
+        (Type::FixedArray(p, n), Type::FixedArray(a, m)) if n == m => bind_generics(p, a, bindings),
+        (Type::Tuple(ps), Type::Tuple(args)) if ps.len() == args.len() => {
+            for (p, a) in ps.iter().zip(args.iter()) {
+                bind_generics(p, a, bindings)?;
+            }
+            Some(())
+        }
+        _ => Some(()),
+    }
+}
+
+/// Replace every generic in a type with what it's bound to
+fn substitute_generics(type_: &Type, bindings: &[(String, Type)]) -> Type {
+    match type_ {
+        Type::Generic(name) => bindings
+            .iter()
+            .find(|(bound, _)| bound == name)
+            .map(|(_, concrete)| concrete.clone())
+            .unwrap_or_else(|| type_.clone()),
+        Type::Array(inner) => Type::Array(Box::new(substitute_generics(inner, bindings))),
+        Type::Map(inner) => Type::Map(Box::new(substitute_generics(inner, bindings))),
+        Type::Shared(inner) => Type::Shared(Box::new(substitute_generics(inner, bindings))),
+        Type::Optional(inner) => Type::Optional(Box::new(substitute_generics(inner, bindings))),
+        Type::FixedArray(inner, size) => {
+            Type::FixedArray(Box::new(substitute_generics(inner, bindings)), *size)
+        }
+        Type::Tuple(items) => Type::Tuple(
+            items
+                .iter()
+                .map(|item| substitute_generics(item, bindings))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+/// The names of the generics in a type, like `T` in `Array<Generic<T>>`
+pub fn generic_names(type_: &Type) -> Vec<&str> {
+    match type_ {
+        Type::Generic(name) => vec![name.as_str()],
+        Type::Array(inner)
+        | Type::Map(inner)
+        | Type::Shared(inner)
+        | Type::Optional(inner)
+        | Type::FixedArray(inner, _) => generic_names(inner),
+        Type::Tuple(items) => items.iter().flat_map(generic_names).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn contains_generic(type_: &Type) -> bool {
+    !generic_names(type_).is_empty()
+}
+
+// -------------------- Unit Tests --------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    const PROGRAM: &str = r#"import npc with Creature;
+
+        struct Animal {
+            legs: Int,
+            hair: Bool,
+            feathers: Bool
+            
+            @metadata {
+                Is: Public, Export;
+                Derives: Eq, Show;
+            }
+        }
+
+        enum Status {
+            Alive,
+            Dead,
+
+            @metadata {
+                Is: Export;
+            }
+        }
+
+        fn feed(animal: Animal, amount: Int) -> Bool {
+            @metadata {
+                Is: Public;
+                Uses: WriteConsole;
+            }
+            return true;
+        }
+    "#;
+
+    #[test]
+    fn construct_module_table() {
         let mut lexer = Lexer::new("test.iona");
         lexer.lex(PROGRAM);
         let mut parser = Parser::new(lexer.token_stream);
@@ -255,7 +1517,7 @@ mod tests {
 
         // Test import tracking
         assert!(module_table.parsing_status.contains_key("npc"));
-        assert_eq!(*module_table.parsing_status.get("npc").unwrap(), false);
+        assert!(!*module_table.parsing_status.get("npc").unwrap());
         let imported = module_table.imported_items.get("npc").unwrap();
         assert!(imported.contains("Creature"));
         assert_eq!(imported.len(), 1);
@@ -266,9 +1528,774 @@ mod tests {
         assert!(exported.contains("Status"));
         assert_eq!(exported.len(), 2);
 
-        // Test public tracking
+        // Test public tracking (`Status` is only marked Export, which implies Public)
         let public = module_table.public_items.get("test.iona").unwrap();
         assert!(public.contains("Animal"));
-        assert_eq!(public.len(), 1);
+        assert!(public.contains("Status"));
+        assert!(public.contains("feed"));
+        assert_eq!(public.len(), 3);
+    }
+
+    #[test]
+    fn construct_function_table() {
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(PROGRAM);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.output.is_some());
+        let mut function_table = FunctionTable::new();
+        function_table.update(&out.output.unwrap(), "test.iona");
+
+        println!("{:#?}", function_table);
+
+        let feed = function_table.resolve("test.iona", "feed").unwrap();
+        assert_eq!(feed.module, "test.iona");
+        assert_eq!(
+            feed.args,
+            vec![
+                Field {
+                    name: "animal".to_string(),
+                    field_type: Type::Custom("Animal".to_string()),
+                    pos: SourcePosition {
+                        filename: "test.iona".to_string(),
+                        line: 22,
+                        column: 16,
+                    },
+                },
+                Field {
+                    name: "amount".to_string(),
+                    field_type: Type::Integer,
+                    pos: SourcePosition {
+                        filename: "test.iona".to_string(),
+                        line: 22,
+                        column: 32,
+                    },
+                }
+            ]
+        );
+        assert_eq!(feed.returns, Type::Boolean);
+        assert_eq!(feed.properties, vec![FunctionProperties::Public]);
+        assert_eq!(feed.permissions, vec![FunctionPermissions::WriteConsole]);
+        assert_eq!(function_table.lookup_in("test.iona", "feed"), Some(feed));
+
+        assert!(function_table.resolve("test.iona", "starve").is_none());
+        assert!(function_table.lookup_in("npc", "feed").is_none());
+        assert_eq!(function_table.all_in_module("test.iona").len(), 1);
+        assert!(function_table.duplicates().is_empty());
+    }
+
+    #[test]
+    fn function_table_records_duplicates() {
+        const DUPLICATED: &str = r#"
+        fn feed(amount: Int) -> Int {
+            return amount;
+        }
+
+        fn feed(amount: Float) -> Float {
+            return amount;
+        }
+    "#;
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(DUPLICATED);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.output.is_some());
+        let mut function_table = FunctionTable::new();
+        function_table.update(&out.output.unwrap(), "test.iona");
+
+        // The first declaration wins
+        assert_eq!(
+            function_table
+                .lookup_in("test.iona", "feed")
+                .unwrap()
+                .returns,
+            Type::Integer
+        );
+        assert_eq!(function_table.duplicates().len(), 1);
+        assert_eq!(function_table.duplicates()[0].returns, Type::Float);
+    }
+
+    #[test]
+    fn resolve_functions_from_the_calling_module() {
+        let mut function_table = FunctionTable::new();
+        for (name, program) in [
+            (
+                "main",
+                "import a with helper;\nimport b with area;\n\nfn helper(name: String) -> Int {\n    return 0;\n}\n",
+            ),
+            (
+                "a",
+                "fn helper(x: Int, y: Int) -> Int {\n    return x;\n}\n",
+            ),
+            ("b", "export import c with area;\n"),
+            ("c", "fn area(side: Int) -> Int {\n    return side;\n}\n"),
+            ("other", "import a with helper;\n"),
+        ] {
+            let mut lexer = Lexer::new(&format!("{}.iona", name));
+            lexer.lex(program);
+            let mut parser = Parser::new(lexer.token_stream);
+            let out = parser.parse_all();
+            assert!(out.output.is_some());
+            function_table.update(&out.output.unwrap(), name);
+        }
+        // The module's own function wins over the import with the same name
+        assert_eq!(
+            function_table.resolve("main", "helper").unwrap().module,
+            "main"
+        );
+        assert_eq!(
+            function_table.resolve("other", "helper").unwrap().module,
+            "a"
+        );
+        // Re-exports are followed to the module that declares the function
+        assert_eq!(function_table.resolve("main", "area").unwrap().module, "c");
+        // Nothing is found in a module that neither declares nor imports the name
+        assert!(function_table.resolve("b", "helper").is_none());
+        assert!(function_table.resolve("other", "area").is_none());
+    }
+
+    #[test]
+    fn export_implies_public() {
+        const EXPORT_ONLY: &str = r#"
+        struct Point {
+            x: Int,
+            y: Int
+
+            @metadata {
+                Is: Export;
+            }
+        }
+    "#;
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(EXPORT_ONLY);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.output.is_some());
+        let mut module_table = ModuleTable::new();
+        module_table.update(&out.output.unwrap(), "test.iona");
+
+        let exported = module_table.exported_items.get("test.iona").unwrap();
+        assert!(exported.contains("Point"));
+        let public = module_table.public_items.get("test.iona").unwrap();
+        assert!(public.contains("Point"));
+    }
+
+    fn module_table_from(modules: &[(&str, &str)]) -> ModuleTable {
+        let mut module_table = ModuleTable::new();
+        for (name, program) in modules {
+            let mut lexer = Lexer::new(&format!("{}.iona", name));
+            lexer.lex(program);
+            let mut parser = Parser::new(lexer.token_stream);
+            let out = parser.parse_all();
+            assert!(out.output.is_some());
+            module_table.update(&out.output.unwrap(), name);
+        }
+        module_table
+    }
+
+    const GEOMETRY: &str = r#"
+        struct Point {
+            x: Int,
+            y: Int
+
+            @metadata {
+                Is: Public;
+            }
+        }
+
+        struct Cache {
+            size: Int
+
+            @metadata {
+                Is: Export;
+            }
+        }
+
+        struct Scratch {
+            size: Int
+
+            @metadata {
+                Derives: Eq;
+            }
+        }
+    "#;
+
+    #[test]
+    fn validate_matching_imports() {
+        let module_table = module_table_from(&[
+            ("main", "import geometry with Point, Cache;"),
+            ("geometry", GEOMETRY),
+        ]);
+        assert!(module_table.validate().is_empty());
+    }
+
+    #[test]
+    fn reexported_items_are_exported() {
+        let module_table = module_table_from(&[
+            ("main", "import shapes with Point;"),
+            ("shapes", "export import geometry with Point;"),
+            ("geometry", GEOMETRY),
+        ]);
+        assert!(module_table.validate().is_empty());
+        assert!(module_table.exported_items["shapes"].contains("Point"));
+        assert!(module_table.is_public("shapes", "Point"));
+        assert!(module_table.imported_items["geometry"].contains("Point"));
+        assert!(module_table.imported_items["shapes"].contains("Point"));
+        // Plain imports stay private to the module that wrote them
+        let module_table = module_table_from(&[
+            ("main", "import shapes with Point;"),
+            ("shapes", "import geometry with Point;"),
+            ("geometry", GEOMETRY),
+        ]);
+        assert!(!module_table.exported_items.contains_key("shapes"));
+        assert_eq!(module_table.validate().len(), 1);
+    }
+
+    #[test]
+    fn validate_import_undefined_name() {
+        let module_table = module_table_from(&[
+            ("main", "import geometry with Pint;"),
+            ("geometry", GEOMETRY),
+        ]);
+        let diagnostics = module_table.validate();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message(),
+            "`Pint` is not defined in module `geometry` (did you mean `Point`?)"
+        );
+        assert_eq!(diagnostics[0].position().filename, "main.iona");
+    }
+
+    #[test]
+    fn validate_import_private_name() {
+        let module_table = module_table_from(&[
+            ("main", "import geometry with Point, Scratch;"),
+            ("geometry", GEOMETRY),
+        ]);
+        let diagnostics = module_table.validate();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message(),
+            "`Scratch` is private to module `geometry`, mark it as `Public` or `Export` to import it"
+        );
+        assert_eq!(diagnostics[0].kind(), DiagnosticKind::PrivateImport);
+        assert_eq!(diagnostics[0].position().filename, "main.iona");
+    }
+
+    #[test]
+    fn build_order_puts_imports_first() {
+        let module_table = module_table_from(&[
+            ("main", "import shapes with area;\nimport text with label;"),
+            ("shapes", "import text with label;"),
+            ("text", ""),
+            ("unused", "import text with label;"),
+        ]);
+        assert_eq!(
+            module_table.build_order("main"),
+            vec!["text", "shapes", "main"]
+        );
+        assert_eq!(module_table.build_order("text"), vec!["text"]);
+    }
+
+    #[test]
+    fn visibility_queries() {
+        let module_table = module_table_from(&[
+            ("main", "import geometry with Point, Scratch;"),
+            ("shapes", "import geometry with Cache;"),
+            ("geometry", GEOMETRY),
+        ]);
+        assert!(module_table.is_public("geometry", "Point"));
+        assert!(!module_table.is_exported("geometry", "Point"));
+        // Export implies Public
+        assert!(module_table.is_public("geometry", "Cache"));
+        assert!(module_table.is_exported("geometry", "Cache"));
+        assert!(!module_table.is_public("geometry", "Scratch"));
+        assert!(!module_table.is_public("nowhere", "Point"));
+        assert_eq!(
+            module_table.importers_of("geometry"),
+            BTreeSet::from(["main".to_string(), "shapes".to_string()])
+        );
+        assert!(module_table.importers_of("main").is_empty());
+        // A private import doesn't make the item visible
+        assert_eq!(
+            module_table.visible_items("main"),
+            BTreeSet::from(["Point".to_string()])
+        );
+        assert_eq!(
+            module_table.visible_items("geometry"),
+            BTreeSet::from([
+                "Cache".to_string(),
+                "Point".to_string(),
+                "Scratch".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn validate_import_unparsed_module() {
+        let module_table = module_table_from(&[("main", "import geometry with Point;")]);
+        let diagnostics = module_table.validate();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message(),
+            "module `main` imports from `geometry`, but `geometry` was never parsed"
+        );
+    }
+
+    #[test]
+    fn duplicate_struct_in_module() {
+        const DUPLICATE: &str = r#"
+        struct Animal {
+            legs: Int
+
+            @metadata {
+                Is: Public;
+            }
+        }
+
+        struct Animal {
+            wings: Int
+
+            @metadata {
+                Is: Public;
+            }
+        }
+    "#;
+        let module_table = module_table_from(&[("zoo", DUPLICATE)]);
+        let diagnostics = module_table.check_duplicates("zoo");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message(),
+            "struct `Animal` is defined more than once in module `zoo`"
+        );
+        assert_eq!(diagnostics[0].position().line, 9);
+        assert_eq!(diagnostics[0].references().len(), 1);
+        assert_eq!(diagnostics[0].references()[0].line, 1);
+    }
+
+    #[test]
+    fn function_named_like_struct() {
+        const COLLISION: &str = r#"
+        struct Animal {
+            legs: Int
+
+            @metadata {
+                Is: Public;
+            }
+        }
+
+        fn Animal(legs: Int) -> Int {
+            return legs;
+        }
+    "#;
+        let module_table = module_table_from(&[("zoo", COLLISION)]);
+        let diagnostics = module_table.check_duplicates("zoo");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message(),
+            "function `Animal` has the same name as struct `Animal`"
+        );
+    }
+
+    #[test]
+    fn duplicates_across_modules_are_allowed() {
+        let module_table = module_table_from(&[("first", GEOMETRY), ("second", GEOMETRY)]);
+        assert!(module_table.check_duplicates("first").is_empty());
+        assert!(module_table.check_duplicates("second").is_empty());
+    }
+
+    #[test]
+    fn validate_name_imported_twice() {
+        let module_table = module_table_from(&[
+            (
+                "main",
+                "import first with Point;\nimport second with Point;",
+            ),
+            ("first", GEOMETRY),
+            ("second", GEOMETRY),
+        ]);
+        let diagnostics = module_table.validate();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message(),
+            "`Point` is imported from both `first` and `second`"
+        );
+        assert_eq!(diagnostics[0].position().line, 1);
+        assert_eq!(diagnostics[0].references()[0].line, 0);
+    }
+
+    #[test]
+    fn dump_type_table() {
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(PROGRAM);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.output.is_some());
+        let mut type_table = TypeTable::new();
+        type_table.update(&out.output.unwrap(), "test.iona");
+
+        let dump = type_table.dump();
+        println!("{}", dump);
+        assert!(dump.starts_with("type_list:\n"));
+        assert!(dump.contains("    Custom(\"Animal\")\n"));
+        // `Int` is only used in the signature of `feed`
+        assert!(dump.contains("types_used_by_module:\n    test.iona:\n"));
+        let module_section = dump.split("    test.iona:\n").nth(1).unwrap();
+        assert!(module_section.contains("        Integer\n"));
+    }
+
+    #[test]
+    fn dependencies_by_module() {
+        let module_table = module_table_from(&[
+            (
+                "main",
+                "import geometry with Point;\nimport npc with Creature;",
+            ),
+            ("npc", "import geometry with Cache;"),
+            ("geometry", GEOMETRY),
+        ]);
+        let dependencies = module_table.dependencies();
+        assert_eq!(dependencies.len(), 2);
+        assert_eq!(
+            dependencies["main"].iter().collect::<Vec<&String>>(),
+            vec!["geometry", "npc"]
+        );
+        assert_eq!(
+            dependencies["npc"].iter().collect::<Vec<&String>>(),
+            vec!["geometry"]
+        );
+    }
+
+    #[test]
+    fn import_edges_collect_items() {
+        let module_table = module_table_from(&[
+            (
+                "main",
+                "import geometry with Point;\nimport geometry with Cache;\nimport npc with Creature;",
+            ),
+            ("npc", "import geometry with Cache;"),
+            ("geometry", GEOMETRY),
+        ]);
+        let edges = module_table.import_edges();
+        let edge = |from: &str, to: &str| {
+            edges[&(from.to_string(), to.to_string())]
+                .iter()
+                .cloned()
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(edges.len(), 3);
+        assert_eq!(edge("main", "geometry"), vec!["Cache", "Point"]);
+        assert_eq!(edge("main", "npc"), vec!["Creature"]);
+        assert_eq!(edge("npc", "geometry"), vec!["Cache"]);
+    }
+
+    #[test]
+    fn types_from_call_return_values() {
+        let mut tables = ParsingTables::new();
+        for (module_name, program) in [
+            (
+                "lists",
+                "fn make_list() -> Array<Int> {\n    @metadata {\n        Is: Public;\n    }\n    return make_list();\n}\n",
+            ),
+            (
+                "main",
+                "import lists with make_list;\n\nfn count() -> Int {\n    return length(make_list());\n}\n",
+            ),
+        ] {
+            let mut lexer = Lexer::new(module_name);
+            lexer.lex(program);
+            let mut parser = Parser::new(lexer.token_stream);
+            let out = parser.parse_all();
+            assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+            tables.update(&out.output.unwrap(), module_name);
+        }
+        // `main` never spells out the array type, but it still needs the array header
+        let used = &tables.types.types_used_by_module["main"];
+        assert!(used.contains(&Type::Array(Box::new(Type::Integer))));
+    }
+
+    #[test]
+    fn generic_instantiations() {
+        const PROGRAM: &str = r#"
+fn max(a: Generic<T>, b: Generic<T>) -> Generic<T> {
+    return a;
+}
+
+fn make_list() -> Array<Int> {
+    return make_list();
+}
+
+fn main() -> Int {
+    let x: Int = max(1, 2);
+    let y: Float = max(1.5, 2.5);
+    let biggest: Array<Int> = max(make_list(), make_list());
+    let z: Int = max(max(x, 3), 4);
+    return x;
+}
+"#;
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(PROGRAM);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+        let mut tables = ParsingTables::new();
+        tables.update(&out.output.unwrap(), "test");
+
+        let max = tables.types.instantiations_of("max").unwrap();
+        assert_eq!(max.len(), 3);
+        assert!(max.contains(&vec![Type::Integer]));
+        assert!(max.contains(&vec![Type::Float]));
+        assert!(max.contains(&vec![Type::Array(Box::new(Type::Integer))]));
+        // Only generic functions are tracked
+        assert!(tables.types.instantiations_of("make_list").is_none());
+    }
+
+    #[test]
+    fn generic_calls_resolve_in_the_calling_module() {
+        let mut tables = ParsingTables::new();
+        for (name, program) in [
+            (
+                "lib",
+                "fn max(a: Generic<T>, b: Generic<T>) -> Generic<T> {\n    return a;\n}\n",
+            ),
+            (
+                "other",
+                "fn max(a: Float, b: Float) -> Float {\n    return a;\n}\n\nfn main() -> Float {\n    return max(1.5, 2.5);\n}\n",
+            ),
+            (
+                "main",
+                "import lib with max;\n\nfn main() -> Int {\n    return max(1, 2);\n}\n",
+            ),
+        ] {
+            let mut lexer = Lexer::new(&format!("{}.iona", name));
+            lexer.lex(program);
+            let mut parser = Parser::new(lexer.token_stream);
+            let out = parser.parse_all();
+            assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+            tables.update(&out.output.unwrap(), name);
+        }
+
+        // `other` calls its own `max`, which isn't generic
+        let max = tables.types.instantiations_of("max").unwrap();
+        assert_eq!(max.len(), 1);
+        assert!(max.contains(&vec![Type::Integer]));
+    }
+
+    const CALLS: &str = r#"
+fn main(argc: Int) -> Int {
+    return count(argc);
+}
+
+fn count(n: Int) -> Int {
+    if n > 0 {
+        return count(n - 1) + cont(n);
+    }
+    return 0;
+}
+
+fn orphan(n: Int) -> Int {
+    print(n);
+    return count(n);
+}
+"#;
+
+    fn call_graph(program: &str) -> CallGraph {
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(program);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+        let mut graph = CallGraph::new();
+        graph.update(&out.output.unwrap(), "test");
+        graph
+    }
+
+    #[test]
+    fn call_graph_edges() {
+        let graph = call_graph(CALLS);
+        let id = |name: &str| FunctionId::new("test", name);
+        assert_eq!(graph.callees(&id("main")), vec![&id("count")]);
+        assert_eq!(graph.callees(&id("count")), vec![&id("count")]);
+        // Built in functions aren't recorded
+        assert_eq!(graph.callees(&id("orphan")), vec![&id("count")]);
+        assert_eq!(
+            graph.callers(&id("count")),
+            vec![&id("count"), &id("main"), &id("orphan")]
+        );
+        assert!(graph.callers(&id("orphan")).is_empty());
+        assert_eq!(graph.unresolved_calls(&id("count")), vec!["cont"]);
+        assert!(graph.unresolved_calls(&id("orphan")).is_empty());
+    }
+
+    #[test]
+    fn call_graph_reachability() {
+        let graph = call_graph(CALLS);
+        let id = |name: &str| FunctionId::new("test", name);
+        let reachable = graph.reachable_from(&id("main"));
+        assert_eq!(
+            reachable.into_iter().collect::<Vec<&FunctionId>>(),
+            vec![&id("count"), &id("main")]
+        );
+        assert_eq!(graph.entry_points(), vec![&id("main")]);
+        // Callees come before their callers
+        assert_eq!(
+            graph.components(),
+            vec![vec![&id("count")], vec![&id("main")], vec![&id("orphan")]]
+        );
+    }
+
+    #[test]
+    fn call_graph_follows_reexports() {
+        let mut graph = CallGraph::new();
+        for (module, program) in [
+            (
+                "main",
+                "import shapes with area;\n\nfn main() -> Int {\n    return area(2);\n}\n",
+            ),
+            ("shapes", "export import geometry with area;\n"),
+            (
+                "geometry",
+                "fn area(side: Int) -> Int {\n    return side * side;\n}\n",
+            ),
+        ] {
+            let mut lexer = Lexer::new(&format!("{}.iona", module));
+            lexer.lex(program);
+            let mut parser = Parser::new(lexer.token_stream);
+            graph.update(&parser.parse_all().output.unwrap(), module);
+        }
+        let reachable = graph.reachable_from(&FunctionId::new("main", "main"));
+        assert!(reachable.contains(&FunctionId::new("geometry", "area")));
+    }
+
+    #[test]
+    fn call_graph_cycles_and_methods() {
+        let graph = call_graph(
+            r#"
+import zoo with feed;
+
+struct Keeper {
+    name: String
+
+    @metadata {
+        Is: Export;
+    }
+
+    fn visit(self) -> Int {
+        return ping(1);
+    }
+}
+
+fn ping(n: Int) -> Int {
+    return pong(n) + feed(n);
+}
+
+fn pong(n: Int) -> Int {
+    return ping(n) + n.bark();
+}
+"#,
+        );
+        let id = |name: &str| FunctionId::new("test", name);
+        assert_eq!(
+            graph.callees(&id("ping")),
+            vec![&id("pong"), &FunctionId::new("zoo", "feed")]
+        );
+        assert_eq!(graph.unresolved_calls(&id("pong")), vec![".bark"]);
+        assert_eq!(graph.entry_points(), vec![&id("Keeper.visit")]);
+        assert_eq!(
+            graph.components(),
+            vec![vec![&id("ping"), &id("pong")], vec![&id("Keeper.visit")]]
+        );
+    }
+
+    fn parse(program: &str) -> Vec<ASTNode> {
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(program);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+        out.output.unwrap()
+    }
+
+    const BEFORE: &str = r#"
+import npc with Creature;
+
+struct Animal {
+    legs: Int
+
+    @metadata {
+        Is: Export;
+    }
+}
+
+fn feed(animal: Animal) -> Int {
+    return animal.legs;
+}
+"#;
+
+    const AFTER: &str = r#"
+struct Beast {
+    legs: Float
+
+    @metadata {
+        Is: Public;
+    }
+}
+
+fn feed(animal: Beast) -> Float {
+    return animal.legs;
+}
+"#;
+
+    #[test]
+    fn update_replaces_module() {
+        let mut tables = ParsingTables::new();
+        tables.update(&parse(BEFORE), "test");
+        tables.update(
+            &parse("struct Kept { size: String @metadata { Is: Public; } }"),
+            "other",
+        );
+        tables.update(&parse(AFTER), "test");
+
+        // Types
+        assert!(!tables.types.is_struct("Animal"));
+        assert!(tables.types.is_struct("Beast"));
+        assert!(tables.types.is_struct("Kept"));
+        assert!(!tables.types.type_list.contains(&Type::Integer));
+        assert!(!tables
+            .types
+            .type_list
+            .contains(&Type::Custom("Animal".to_string())));
+        assert!(tables.types.type_list.contains(&Type::Float));
+        assert!(tables.types.type_list.contains(&Type::String));
+        assert!(!tables.types.types_used_by_module["test"].contains(&Type::Integer));
+
+        // Modules
+        assert!(!tables.modules.is_exported("test", "Animal"));
+        assert!(tables.modules.is_public("test", "Beast"));
+        assert!(tables.modules.declarations_named("Animal").is_empty());
+        assert!(!tables.modules.dependencies().contains_key("test"));
+        assert!(!tables.modules.parsing_status.contains_key("npc"));
+        assert!(tables.modules.imported_items.is_empty());
+
+        // Functions, re-declaring `feed` isn't a duplicate
+        assert_eq!(
+            tables.functions.lookup_in("test", "feed").unwrap().returns,
+            Type::Float
+        );
+        assert!(tables.functions.duplicates().is_empty());
+    }
+
+    #[test]
+    fn update_twice_is_idempotent() {
+        let ast = parse(BEFORE);
+        let mut once = ParsingTables::new();
+        once.update(&ast, "test");
+        let mut twice = ParsingTables::new();
+        twice.update(&ast, "test");
+        twice.update(&ast, "test");
+        assert_eq!(once.modules, twice.modules);
+        assert_eq!(once.types, twice.types);
+        assert_eq!(once.functions, twice.functions);
+        assert_eq!(once.calls, twice.calls);
     }
 }
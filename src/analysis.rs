@@ -0,0 +1,3236 @@
+//! Scope analysis: make sure every name a function uses actually refers to something
+
+use std::collections::HashSet;
+
+use crate::aggregation::{generic_names, FunctionId, FunctionSig, ItemKind, ParsingTables};
+use crate::diagnostics::{did_you_mean, Diagnostic, DiagnosticKind};
+use crate::expression_parser::{BinaryOperator, Expr, UnaryOperator};
+use crate::lexer::SourcePosition;
+use crate::parser::{
+    ASTNode, Attribute, Branch, ContractType, DataTraits, Field, Function, FunctionContract,
+    FunctionPermissions, Pattern, Statement, Type,
+};
+use crate::scopes::{variables_in, walk_function, Binding, ScopeVisitor, SymbolTable};
+
+/// Check the scoping of every function (including struct methods) in a module
+///
+/// Lints named in an `@allow(..)` on the function, or on the struct or enum a method belongs to, aren't reported
+pub fn check_scopes(ast: &[ASTNode]) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for node in ast.iter() {
+        match node {
+            ASTNode::FunctionDeclaration(f) => {
+                diagnostics.extend(without_allowed(check_function_scopes(f), &[&f.attributes]))
+            }
+            ASTNode::StructDeclaration(s) => {
+                for method in s.methods.iter() {
+                    diagnostics.extend(without_allowed(
+                        check_function_scopes(method),
+                        &[&s.attributes, &method.attributes],
+                    ));
+                }
+            }
+            ASTNode::EnumDeclaration(e) => {
+                for method in e.methods.iter() {
+                    diagnostics.extend(without_allowed(
+                        check_function_scopes(method),
+                        &[&e.attributes, &method.attributes],
+                    ));
+                }
+            }
+            ASTNode::ImportStatement(_) | ASTNode::ModuleMetadata(_) => {}
+        }
+    }
+    diagnostics
+}
+
+/// Drop the lints that any of these attribute lists allow
+fn without_allowed(diagnostics: Vec<Diagnostic>, attributes: &[&[Attribute]]) -> Vec<Diagnostic> {
+    let allowed = attributes
+        .iter()
+        .flat_map(|list| list.iter())
+        .filter_map(|attribute| match attribute {
+            Attribute::Allow(lints) => Some(lints),
+            Attribute::Cfg { .. } => None,
+        })
+        .flatten()
+        .collect::<Vec<&String>>();
+    diagnostics
+        .into_iter()
+        .filter(|d| {
+            d.kind()
+                .lint_name()
+                .is_none_or(|name| !allowed.iter().any(|lint| *lint == name))
+        })
+        .collect()
+}
+
+/// Walk a function's contracts and statements in order, tracking which names are visible at each point
+pub fn check_function_scopes(f: &Function) -> Vec<Diagnostic> {
+    let mut checker = ScopeChecker::default();
+    walk_function(f, &mut checker);
+    checker.diagnostics
+}
+
+/// Functions provided by the C runtime (see `c_libs/`) or the compiler (`some`, which wraps a value in an optional), which are always in scope
+pub const BUILTIN_FUNCTIONS: [&str; 3] = ["print", "print_raw", "some"];
+
+/// The builtins that come from `c_libs/console_io.h`, which has to be included wherever they're called
+pub const CONSOLE_FUNCTIONS: [&str; 2] = ["print", "print_raw"];
+
+/// Check that every function call refers to a function defined in this module, imported by name, or built in
+///
+/// TODO: method calls are skipped until we can resolve methods
+pub fn check_calls(ast: &[ASTNode], module_name: &str, tables: &ParsingTables) -> Vec<Diagnostic> {
+    let mut known: Vec<&str> = BUILTIN_FUNCTIONS.to_vec();
+    known.extend(
+        tables
+            .functions
+            .all_in_module(module_name)
+            .iter()
+            .map(|sig| sig.name.as_str()),
+    );
+    for node in ast.iter() {
+        if let ASTNode::ImportStatement(i) = node {
+            known.extend(i.items.iter().map(|item| item.as_str()));
+        }
+    }
+    known.sort();
+    known.dedup();
+
+    let mut diagnostics = Vec::new();
+    let mut check_call = |expr: &Expr, pos: &SourcePosition| {
+        if let Expr::FunctionCall { name, .. } = expr {
+            if known.contains(&name.as_str()) {
+                return;
+            }
+            let message = if tables.types.is_struct(name) {
+                format!("`{}` is a struct, not a function", name)
+            } else if tables.types.is_enum(name) {
+                format!("`{}` is an enum, not a function", name)
+            } else {
+                match did_you_mean(name, known.iter().copied()) {
+                    Some(suggestion) => format!(
+                        "`{}` is not a known function (did you mean `{}`?)",
+                        name, suggestion
+                    ),
+                    None => format!(
+                        "`{}` is not a known function, it must be defined in this module or imported",
+                        name
+                    ),
+                }
+            };
+            diagnostics.push(Diagnostic::new_error_simple(
+                DiagnosticKind::UndefinedFunction,
+                &message,
+                pos,
+            ));
+        }
+    };
+    for f in functions_in(ast) {
+        for contract in f.contracts.iter() {
+            visit_expr(&contract.condition, &contract.pos, &mut check_call);
+        }
+        visit_statements(&f.statements, &mut check_call);
+    }
+    diagnostics
+}
+
+/// Check that every named type refers to a struct or enum defined in this module or imported by name
+///
+/// A type that exists in another module but wasn't imported gets its own message, since the fix is different
+pub fn check_types(ast: &[ASTNode], module_name: &str, tables: &ParsingTables) -> Vec<Diagnostic> {
+    let mut visible: Vec<&str> = Vec::new();
+    let mut uses: Vec<(&Type, &SourcePosition)> = Vec::new();
+    for node in ast.iter() {
+        match node {
+            ASTNode::StructDeclaration(s) => {
+                visible.push(&s.name);
+                uses.extend(s.fields.iter().map(|field| (&field.field_type, &s.pos)));
+                for method in s.methods.iter() {
+                    collect_function_types(method, &mut uses);
+                }
+            }
+            ASTNode::EnumDeclaration(e) => {
+                visible.push(&e.name);
+                uses.extend(e.fields.iter().map(|field| (&field.field_type, &e.pos)));
+                for method in e.methods.iter() {
+                    collect_function_types(method, &mut uses);
+                }
+            }
+            ASTNode::FunctionDeclaration(f) => collect_function_types(f, &mut uses),
+            ASTNode::ImportStatement(i) => visible.extend(i.items.iter().map(|item| item.as_str())),
+            ASTNode::ModuleMetadata(_) => {}
+        }
+    }
+    visible.sort();
+    visible.dedup();
+
+    let mut diagnostics = Vec::new();
+    for (type_, pos) in uses {
+        let mut names = HashSet::new();
+        collect_type_names(type_, &mut names);
+        let mut unknown = names
+            .into_iter()
+            .filter(|name| !visible.contains(&name.as_str()))
+            .collect::<Vec<String>>();
+        unknown.sort();
+        for name in unknown {
+            let elsewhere = tables
+                .modules
+                .declarations_named(&name)
+                .into_iter()
+                .find(|d| d.module != module_name && d.kind != ItemKind::Function);
+            let message = match (elsewhere, did_you_mean(&name, visible.iter().copied())) {
+                (Some(declaration), _) => format!(
+                    "`{}` is defined in module `{}` but not imported here (add `import {} with {};`)",
+                    name, declaration.module, declaration.module, name
+                ),
+                (None, Some(suggestion)) => format!(
+                    "`{}` is not a known type (did you mean `{}`?)",
+                    name, suggestion
+                ),
+                (None, None) => format!(
+                    "`{}` is not a known type, it must be a struct or enum defined in this module or imported",
+                    name
+                ),
+            };
+            diagnostics.push(Diagnostic::new_error_simple(
+                DiagnosticKind::UndefinedType,
+                &message,
+                pos,
+            ));
+        }
+    }
+    diagnostics
+}
+
+/// The types a function names: its signature at the function, and each declared local at its statement
+fn collect_function_types<'a>(f: &'a Function, uses: &mut Vec<(&'a Type, &'a SourcePosition)>) {
+    for arg in f.args.iter() {
+        uses.push((&arg.field_type, &f.pos));
+    }
+    uses.push((&f.returns, &f.pos));
+    collect_declared_types(&f.statements, uses);
+}
+
+fn collect_declared_types<'a>(
+    statements: &'a [Statement],
+    uses: &mut Vec<(&'a Type, &'a SourcePosition)>,
+) {
+    for statement in statements.iter() {
+        match statement {
+            Statement::VariableDeclaration { type_, pos, .. } => uses.push((type_, pos)),
+            Statement::Conditional { branches, .. } | Statement::Match { branches, .. } => {
+                for branch in branches.iter() {
+                    collect_declared_types(&branch.computations, uses);
+                }
+            }
+            Statement::WhileLoop { body, .. } => collect_declared_types(body, uses),
+            _ => {}
+        }
+    }
+}
+
+/// Check that `Void` and `Auto` only appear where they mean something
+///
+/// `Void` can be returned, and a payload-less enum variant stores it internally, but nothing can hold a `Void` value. Struct fields and payloads also need a concrete type, since there's nothing to infer `Auto` from
+pub fn check_type_positions(ast: &[ASTNode]) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for node in ast.iter() {
+        match node {
+            ASTNode::StructDeclaration(s) => {
+                for field in s.fields.iter() {
+                    let message = match field.field_type {
+                        Type::Void => Some(format!(
+                            "struct field `{}` of `{}` can't have type Void",
+                            field.name, s.name
+                        )),
+                        Type::Auto => Some(format!(
+                            "struct field `{}` of `{}` needs an explicit type, it can't be Auto",
+                            field.name, s.name
+                        )),
+                        _ => nested_void(&field.field_type),
+                    };
+                    if let Some(message) = message {
+                        diagnostics.push(Diagnostic::new_error_simple(
+                            DiagnosticKind::InvalidType,
+                            &message,
+                            &s.pos,
+                        ));
+                    }
+                }
+                for method in s.methods.iter() {
+                    check_function_type_positions(method, &mut diagnostics);
+                }
+            }
+            ASTNode::EnumDeclaration(e) => {
+                for field in e.fields.iter() {
+                    let message = match field.field_type {
+                        // This is how a variant without a payload is stored
+                        Type::Void => None,
+                        Type::Auto => Some(format!(
+                            "variant `{}` of `{}` needs an explicit payload type, it can't be Auto",
+                            field.name, e.name
+                        )),
+                        _ => nested_void(&field.field_type),
+                    };
+                    if let Some(message) = message {
+                        diagnostics.push(Diagnostic::new_error_simple(
+                            DiagnosticKind::InvalidType,
+                            &message,
+                            &e.pos,
+                        ));
+                    }
+                }
+                for method in e.methods.iter() {
+                    check_function_type_positions(method, &mut diagnostics);
+                }
+            }
+            ASTNode::FunctionDeclaration(f) => check_function_type_positions(f, &mut diagnostics),
+            ASTNode::ImportStatement(_) | ASTNode::ModuleMetadata(_) => {}
+        }
+    }
+    diagnostics
+}
+
+/// Report struct fields, enum variants, and parameters that reuse a name from earlier in the same list
+///
+/// C won't accept two members (or parameters) with the same name, and the error it gives is far from the Iona source
+pub fn check_field_names(ast: &[ASTNode]) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for node in ast.iter() {
+        match node {
+            ASTNode::StructDeclaration(s) => {
+                duplicate_fields(
+                    &s.fields,
+                    "field",
+                    &format!("struct `{}`", s.name),
+                    &mut diagnostics,
+                );
+                for method in s.methods.iter() {
+                    duplicate_parameters(method, &mut diagnostics);
+                }
+            }
+            ASTNode::EnumDeclaration(e) => {
+                duplicate_fields(
+                    &e.fields,
+                    "variant",
+                    &format!("enum `{}`", e.name),
+                    &mut diagnostics,
+                );
+                for method in e.methods.iter() {
+                    duplicate_parameters(method, &mut diagnostics);
+                }
+            }
+            ASTNode::FunctionDeclaration(f) => duplicate_parameters(f, &mut diagnostics),
+            ASTNode::ImportStatement(_) | ASTNode::ModuleMetadata(_) => {}
+        }
+    }
+    diagnostics
+}
+
+fn duplicate_parameters(f: &Function, diagnostics: &mut Vec<Diagnostic>) {
+    duplicate_fields(&f.args, "parameter", &format!("`{}`", f.name), diagnostics);
+}
+
+fn duplicate_fields(
+    fields: &[Field],
+    describe: &str,
+    owner: &str,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    for (i, field) in fields.iter().enumerate() {
+        if let Some(first) = fields[..i].iter().find(|f| f.name == field.name) {
+            diagnostics.push(Diagnostic::new_error_with_references(
+                DiagnosticKind::DuplicateDefinition,
+                &format!(
+                    "{} `{}` is declared more than once in {}",
+                    describe, field.name, owner
+                ),
+                &field.pos,
+                vec![first.pos.clone()],
+            ));
+        }
+    }
+}
+
+fn check_function_type_positions(f: &Function, diagnostics: &mut Vec<Diagnostic>) {
+    for arg in f.args.iter() {
+        let message = match arg.field_type {
+            Type::Void => Some(format!(
+                "parameter `{}` of `{}` can't have type Void",
+                arg.name, f.name
+            )),
+            _ => nested_void(&arg.field_type),
+        };
+        if let Some(message) = message {
+            diagnostics.push(Diagnostic::new_error_simple(
+                DiagnosticKind::InvalidType,
+                &message,
+                &f.pos,
+            ));
+        }
+    }
+    if let Some(message) = nested_void(&f.returns) {
+        diagnostics.push(Diagnostic::new_error_simple(
+            DiagnosticKind::InvalidType,
+            &message,
+            &f.pos,
+        ));
+    }
+    let mut locals = Vec::new();
+    collect_declared_types(&f.statements, &mut locals);
+    for (type_, pos) in locals {
+        let message = match type_ {
+            Type::Void => Some("a variable can't have type Void".to_string()),
+            _ => nested_void(type_),
+        };
+        if let Some(message) = message {
+            diagnostics.push(Diagnostic::new_error_simple(
+                DiagnosticKind::InvalidType,
+                &message,
+                pos,
+            ));
+        }
+    }
+}
+
+/// Check that every trait a struct or enum derives is supported by all of its fields
+pub fn check_derives(ast: &[ASTNode], tables: &ParsingTables) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for node in ast.iter() {
+        let (name, fields, traits, pos) = match node {
+            ASTNode::StructDeclaration(s) => (&s.name, &s.fields, &s.traits, &s.pos),
+            ASTNode::EnumDeclaration(e) => (&e.name, &e.fields, &e.traits, &e.pos),
+            ASTNode::FunctionDeclaration(_)
+            | ASTNode::ImportStatement(_)
+            | ASTNode::ModuleMetadata(_) => continue,
+        };
+        for trait_ in traits.iter() {
+            for field in fields.iter() {
+                if let Some(diagnostic) = underivable_field(name, field, trait_, pos, tables) {
+                    diagnostics.push(diagnostic);
+                }
+            }
+        }
+    }
+    diagnostics
+}
+
+fn underivable_field(
+    owner: &str,
+    field: &Field,
+    trait_: &DataTraits,
+    pos: &SourcePosition,
+    tables: &ParsingTables,
+) -> Option<Diagnostic> {
+    let culprit = tables.types.underivable_part(&field.field_type, trait_)?;
+    let reason = match culprit {
+        Type::Custom(_) => format!("doesn't derive {:?}", trait_),
+        Type::Generic(_) => format!("might not support {:?}", trait_),
+        _ => "can't derive anything".to_string(),
+    };
+    let message = format!(
+        "`{}` can't derive {:?}: field `{}` uses `{}`, which {}",
+        owner, trait_, field.name, culprit, reason
+    );
+    let declared_at = match culprit {
+        Type::Custom(name) => tables.types.declared_at(name),
+        _ => None,
+    };
+    Some(match declared_at {
+        Some(declared_at) => Diagnostic::new_error_with_references(
+            DiagnosticKind::UnderivableTrait,
+            &message,
+            pos,
+            vec![declared_at.clone()],
+        ),
+        None => Diagnostic::new_error_simple(DiagnosticKind::UnderivableTrait, &message, pos),
+    })
+}
+
+/// Check that every `Generic<T>` names a type parameter its declaration lists, and that every listed parameter gets used
+///
+/// Methods can use the parameters of the struct or enum they belong to as well as their own
+pub fn check_generics(ast: &[ASTNode]) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for node in ast.iter() {
+        let (name, type_params, fields, methods, attributes, pos) = match node {
+            ASTNode::StructDeclaration(s) => (
+                &s.name,
+                &s.type_params,
+                &s.fields,
+                &s.methods,
+                &s.attributes,
+                &s.pos,
+            ),
+            ASTNode::EnumDeclaration(e) => (
+                &e.name,
+                &e.type_params,
+                &e.fields,
+                &e.methods,
+                &e.attributes,
+                &e.pos,
+            ),
+            ASTNode::FunctionDeclaration(f) => {
+                let mut found = Vec::new();
+                check_function_generics(f, &[], &mut Vec::new(), &mut found);
+                diagnostics.extend(without_allowed(found, &[&f.attributes]));
+                continue;
+            }
+            ASTNode::ImportStatement(_) | ASTNode::ModuleMetadata(_) => continue,
+        };
+        let mut found = Vec::new();
+        let mut used = Vec::new();
+        for field in fields.iter() {
+            check_generic_uses(
+                &field.field_type,
+                name,
+                type_params,
+                pos,
+                &mut used,
+                &mut found,
+            );
+        }
+        for method in methods.iter() {
+            let mut method_found = Vec::new();
+            check_function_generics(method, type_params, &mut used, &mut method_found);
+            found.extend(without_allowed(method_found, &[&method.attributes]));
+        }
+        report_unused_generics(name, type_params, &used, pos, &mut found);
+        diagnostics.extend(without_allowed(found, &[attributes]));
+    }
+    diagnostics
+}
+
+/// Check a function's signature and locals, recording which of the outer parameters it uses
+fn check_function_generics(
+    f: &Function,
+    outer: &[String],
+    outer_used: &mut Vec<String>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let declared = f
+        .type_params
+        .iter()
+        .chain(outer.iter())
+        .cloned()
+        .collect::<Vec<String>>();
+    let mut uses = Vec::new();
+    collect_function_types(f, &mut uses);
+    let mut used = Vec::new();
+    for (type_, pos) in uses {
+        check_generic_uses(type_, &f.name, &declared, pos, &mut used, diagnostics);
+    }
+    report_unused_generics(&f.name, &f.type_params, &used, &f.pos, diagnostics);
+    // A function's own parameter shadows an outer one with the same name
+    outer_used.extend(
+        used.into_iter()
+            .filter(|name| !f.type_params.contains(name)),
+    );
+}
+
+/// Report each undeclared generic in a type (once per declaration), and record the declared ones
+fn check_generic_uses(
+    type_: &Type,
+    owner: &str,
+    declared: &[String],
+    pos: &SourcePosition,
+    used: &mut Vec<String>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    for name in generic_names(type_) {
+        if used.iter().any(|seen| seen == name) {
+            continue;
+        }
+        used.push(name.to_string());
+        if declared.iter().any(|param| param == name) {
+            continue;
+        }
+        let message = match did_you_mean(name, declared.iter().map(|param| param.as_str())) {
+            Some(suggestion) => format!(
+                "`{}` isn't a type parameter of `{}` (did you mean `{}`?)",
+                name, owner, suggestion
+            ),
+            None => format!(
+                "`{}` isn't a type parameter of `{}`, declare it like `{}<{}>`",
+                name, owner, owner, name
+            ),
+        };
+        diagnostics.push(Diagnostic::new_error_simple(
+            DiagnosticKind::UndeclaredTypeParameter,
+            &message,
+            pos,
+        ));
+    }
+}
+
+fn report_unused_generics(
+    owner: &str,
+    type_params: &[String],
+    used: &[String],
+    pos: &SourcePosition,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    for param in type_params.iter().filter(|param| !used.contains(param)) {
+        diagnostics.push(Diagnostic::new_warning_simple(
+            DiagnosticKind::UnusedTypeParameter,
+            &format!("type parameter `{}` of `{}` is never used", param, owner),
+            pos,
+        ));
+    }
+}
+
+/// Describe a `Void` used as the element of a container type, if there is one
+fn nested_void(type_: &Type) -> Option<String> {
+    let inner: Vec<&Type> = match type_ {
+        // A map's key and value are stored as a pair
+        Type::Map(pair) => match pair.as_ref() {
+            Type::Tuple(types) => types.iter().collect(),
+            other => vec![other],
+        },
+        Type::Array(inner)
+        | Type::Shared(inner)
+        | Type::Optional(inner)
+        | Type::FixedArray(inner, _) => vec![inner],
+        Type::Tuple(types) => types.iter().collect(),
+        _ => return None,
+    };
+    if inner.contains(&&Type::Void) {
+        return Some(format!("{} can't contain Void", type_));
+    }
+    inner.into_iter().find_map(nested_void)
+}
+
+/// The permissions a module's `@module` block gives to every function in it
+pub fn module_permissions(ast: &[ASTNode]) -> Vec<FunctionPermissions> {
+    ast.iter()
+        .filter_map(|node| match node {
+            ASTNode::ModuleMetadata(m) => Some(m.permissions.iter().cloned()),
+            _ => None,
+        })
+        .flatten()
+        .collect()
+}
+
+/// What a function is allowed to do: its own `Uses` line if it has one, otherwise the module's defaults
+pub fn effective_permissions<'a>(
+    f: &'a Function,
+    defaults: &'a [FunctionPermissions],
+) -> &'a [FunctionPermissions] {
+    if f.permissions.is_empty() {
+        defaults
+    } else {
+        &f.permissions
+    }
+}
+
+/// How permissions flow through the calls between a module's functions (methods included)
+struct PermissionFlow<'a> {
+    /// The module the functions are in, calls to other functions resolve from here
+    module: &'a str,
+    functions: Vec<&'a Function>,
+    /// The first call to each callee, in order, for every function
+    calls: Vec<Vec<(String, SourcePosition)>>,
+    /// Calls whose permissions we can't know: built in functions, names that don't resolve, and methods
+    opaque: Vec<bool>,
+    /// What each function is allowed to do, see `effective_permissions`
+    declared: Vec<&'a [FunctionPermissions]>,
+    /// What each function is allowed to do plus everything the functions it calls need, directly or not
+    needs: Vec<Vec<FunctionPermissions>>,
+}
+
+impl<'a> PermissionFlow<'a> {
+    fn new(
+        ast: &'a [ASTNode],
+        module: &'a str,
+        defaults: &'a [FunctionPermissions],
+        tables: &ParsingTables,
+    ) -> Self {
+        let functions = functions_in(ast);
+        let mut opaque = vec![false; functions.len()];
+        let calls: Vec<Vec<(String, SourcePosition)>> = functions
+            .iter()
+            .enumerate()
+            .map(|(i, f)| {
+                let mut callees: Vec<(String, SourcePosition)> = Vec::new();
+                visit_statements(
+                    &f.statements,
+                    &mut |expr: &Expr, pos: &SourcePosition| match expr {
+                        Expr::FunctionCall { name, .. }
+                            if !callees.iter().any(|(callee, _)| callee == name) =>
+                        {
+                            callees.push((name.clone(), pos.clone()));
+                        }
+                        Expr::MethodCall { .. } => opaque[i] = true,
+                        _ => {}
+                    },
+                );
+                callees
+            })
+            .collect();
+        let declared = functions
+            .iter()
+            .map(|f| effective_permissions(f, defaults))
+            .collect::<Vec<&[FunctionPermissions]>>();
+        let mut flow = PermissionFlow {
+            module,
+            needs: declared.iter().map(|d| d.to_vec()).collect(),
+            functions,
+            calls,
+            opaque,
+            declared,
+        };
+        for (i, callees) in flow.calls.iter().enumerate() {
+            let unknown = callees.iter().any(|(callee, _)| {
+                flow.index_of(callee).is_none()
+                    && tables.functions.resolve(module, callee).is_none()
+            });
+            flow.opaque[i] |= unknown;
+        }
+
+        // Grow each function's requirements until nothing changes, which also settles recursive calls
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for i in 0..flow.functions.len() {
+                for (callee, _) in flow.calls[i].iter() {
+                    for permission in flow.callee_needs(callee, tables) {
+                        if !flow.needs[i].contains(&permission) {
+                            flow.needs[i].push(permission);
+                            changed = true;
+                        }
+                    }
+                }
+            }
+        }
+        flow
+    }
+
+    fn index_of(&self, name: &str) -> Option<usize> {
+        self.functions.iter().position(|f| f.name == name)
+    }
+
+    /// Functions from other modules are trusted to need exactly what they declare
+    fn callee_needs(&self, callee: &str, tables: &ParsingTables) -> Vec<FunctionPermissions> {
+        match self.index_of(callee) {
+            Some(j) => self.needs[j].clone(),
+            None => tables
+                .functions
+                .resolve(self.module, callee)
+                .map(|sig| sig.permissions.clone())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Check that every function declares (in `Uses:`, or through `@module`) all of the permissions needed by the functions it calls, directly or not
+///
+/// Functions from other modules are trusted to need exactly what they declare, since their own module checks them
+pub fn check_permissions(
+    ast: &[ASTNode],
+    module_name: &str,
+    tables: &ParsingTables,
+) -> Vec<Diagnostic> {
+    let defaults = module_permissions(ast);
+    let flow = PermissionFlow::new(ast, module_name, &defaults, tables);
+    let mut diagnostics = Vec::new();
+    for (i, f) in flow.functions.iter().enumerate() {
+        for permission in flow.needs[i].iter() {
+            if covers(flow.declared[i], permission) {
+                continue;
+            }
+            // Blame the first callee that needs it
+            let culprit = flow.calls[i]
+                .iter()
+                .find(|(callee, _)| flow.callee_needs(callee, tables).contains(permission));
+            if let Some((callee, pos)) = culprit {
+                diagnostics.push(Diagnostic::new_error_simple(
+                    DiagnosticKind::MissingPermission,
+                    &format!(
+                        "`{}` calls `{}`, which needs the {} permission, but `{}` doesn't declare it in `Uses`",
+                        f.name,
+                        callee,
+                        describe_permission(permission),
+                        f.name
+                    ),
+                    pos,
+                ));
+            }
+        }
+    }
+    diagnostics
+}
+
+/// Lint permissions a function declares in `Uses` that nothing it calls needs
+///
+/// Defaults from `@module` aren't linted, since they're shared by the whole file. Extern functions are where permissions come from, and a function that calls a built in or a method might need anything, so neither of those is checked
+pub fn check_unused_permissions(
+    ast: &[ASTNode],
+    module_name: &str,
+    tables: &ParsingTables,
+) -> Vec<Diagnostic> {
+    let defaults = module_permissions(ast);
+    let flow = PermissionFlow::new(ast, module_name, &defaults, tables);
+    let mut diagnostics = Vec::new();
+    for (i, f) in flow.functions.iter().enumerate() {
+        if f.is_extern || flow.opaque[i] {
+            continue;
+        }
+        let required = flow.calls[i]
+            .iter()
+            .flat_map(|(callee, _)| flow.callee_needs(callee, tables))
+            .collect::<Vec<FunctionPermissions>>();
+        let found = f
+            .permissions
+            .iter()
+            .filter(|declared| {
+                !required
+                    .iter()
+                    .any(|needed| covers(std::slice::from_ref(*declared), needed))
+            })
+            .map(|declared| {
+                Diagnostic::new_lint_simple(
+                    DiagnosticKind::UnusedPermission,
+                    &format!(
+                        "`{}` declares the {} permission in `Uses`, but nothing it calls needs it",
+                        f.name,
+                        describe_permission(declared)
+                    ),
+                    &f.pos,
+                )
+            })
+            .collect();
+        diagnostics.extend(without_allowed(found, &[&f.attributes]));
+    }
+    diagnostics
+}
+
+/// Check that the module a program starts from has a `main` that C can call
+///
+/// `main` takes nothing or a single `Array<String>`, and returns `Void` or `Int`. Declaring it twice is already reported by `check_duplicates`
+pub fn check_entry_point(ast: &[ASTNode], filename: &str) -> Vec<Diagnostic> {
+    let mains = ast
+        .iter()
+        .filter_map(|node| match node {
+            ASTNode::FunctionDeclaration(f) if f.name == "main" => Some(f),
+            _ => None,
+        })
+        .collect::<Vec<&Function>>();
+    if mains.is_empty() {
+        return vec![Diagnostic::new_error_simple(
+            DiagnosticKind::InvalidEntryPoint,
+            "no `main` function found, the file a program is built from needs one like `fn main() -> Int`",
+            &SourcePosition {
+                filename: filename.to_string(),
+                line: 0,
+                column: 0,
+            },
+        )];
+    }
+    let mut diagnostics = Vec::new();
+    for f in mains {
+        let takes_arguments = match f.args.as_slice() {
+            [] => true,
+            [arg] => arg.field_type == Type::Array(Box::new(Type::String)),
+            _ => false,
+        };
+        if !takes_arguments {
+            let found = f
+                .args
+                .iter()
+                .map(|arg| format!("{}: {}", arg.name, arg.field_type))
+                .collect::<Vec<String>>();
+            diagnostics.push(Diagnostic::new_error_simple(
+                DiagnosticKind::InvalidEntryPoint,
+                &format!(
+                    "`main` takes ({}), but it should take nothing or a single Array<String>",
+                    found.join(", ")
+                ),
+                &f.pos,
+            ));
+        }
+        if !matches!(f.returns, Type::Void | Type::Integer) {
+            diagnostics.push(Diagnostic::new_error_simple(
+                DiagnosticKind::InvalidEntryPoint,
+                &format!(
+                    "`main` returns {}, but it should return Void or Int",
+                    f.returns
+                ),
+                &f.pos,
+            ));
+        }
+    }
+    diagnostics
+}
+
+/// Warn about functions that can't be reached from `main` or anything exported
+///
+/// A program without a `main` is a library, so there's nothing to measure reachability from and nothing is reported
+pub fn check_dead_functions(
+    ast: &[ASTNode],
+    module_name: &str,
+    tables: &ParsingTables,
+) -> Vec<Diagnostic> {
+    let entry_points = tables.calls.entry_points();
+    if !entry_points.iter().any(|id| id.name == "main") {
+        return Vec::new();
+    }
+    let reachable = entry_points
+        .into_iter()
+        .flat_map(|entry| tables.calls.reachable_from(entry))
+        .collect::<HashSet<&FunctionId>>();
+    let mut diagnostics = Vec::new();
+    for node in ast.iter() {
+        if let ASTNode::FunctionDeclaration(f) = node {
+            if f.is_extern || reachable.contains(&FunctionId::new(module_name, &f.name)) {
+                continue;
+            }
+            let warning = Diagnostic::new_warning_simple(
+                DiagnosticKind::DeadCode,
+                &format!(
+                    "function `{}` is never called from `main` or an exported function",
+                    f.name
+                ),
+                &f.pos,
+            );
+            diagnostics.extend(without_allowed(vec![warning], &[&f.attributes]));
+        }
+    }
+    diagnostics
+}
+
+/// Contracts are checked at runtime, so they must not have side effects: flag any call to a function that isn't pure
+///
+/// Which names a contract can see is handled by `check_scopes`
+pub fn check_contracts(
+    ast: &[ASTNode],
+    module_name: &str,
+    tables: &ParsingTables,
+) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for f in functions_in(ast) {
+        for contract in f.contracts.iter() {
+            visit_expr(&contract.condition, &contract.pos, &mut |expr, pos| {
+                if let Expr::FunctionCall { name, .. } = expr {
+                    let Some(sig) = tables.functions.resolve(module_name, name) else {
+                        return;
+                    };
+                    let Some((culprit, permissions)) =
+                        impurity(&FunctionId::new(&sig.module, &sig.name), tables)
+                    else {
+                        return;
+                    };
+                    let names = permissions
+                        .iter()
+                        .map(describe_permission)
+                        .collect::<Vec<String>>();
+                    let message = if culprit.name == *name {
+                        format!(
+                            "contracts must be pure, but `{}` needs the {} permission(s)",
+                            name,
+                            names.join(", ")
+                        )
+                    } else {
+                        format!(
+                            "contracts must be pure, but `{}` calls `{}`, which needs the {} permission(s)",
+                            name,
+                            culprit.name,
+                            names.join(", ")
+                        )
+                    };
+                    diagnostics.push(Diagnostic::new_error_simple(
+                        DiagnosticKind::MissingPermission,
+                        &message,
+                        pos,
+                    ));
+                }
+            });
+        }
+    }
+    diagnostics
+}
+
+/// Why a function isn't pure: the first function it can end up calling (itself included) that needs a permission, along with those permissions
+///
+/// `None` means the function is pure. The function itself is checked first, so a direct permission is blamed before an inherited one
+pub fn impurity<'a>(
+    id: &FunctionId,
+    tables: &'a ParsingTables,
+) -> Option<(&'a FunctionSig, &'a [FunctionPermissions])> {
+    let permissions_of = |f: &FunctionId| {
+        tables
+            .functions
+            .lookup_in(&f.module, &f.name)
+            .filter(|sig| !sig.permissions.is_empty())
+            .map(|sig| (sig, sig.permissions.as_slice()))
+    };
+    permissions_of(id).or_else(|| {
+        tables
+            .calls
+            .reachable_from(id)
+            .into_iter()
+            .find_map(permissions_of)
+    })
+}
+
+/// `HTTPAny` grants every kind of HTTP request
+fn covers(declared: &[FunctionPermissions], needed: &FunctionPermissions) -> bool {
+    declared.contains(needed)
+        || (matches!(
+            needed,
+            FunctionPermissions::HTTPGet | FunctionPermissions::HTTPPost
+        ) && declared.contains(&FunctionPermissions::HTTPAny))
+}
+
+fn describe_permission(permission: &FunctionPermissions) -> String {
+    match permission {
+        FunctionPermissions::Custom(name) => name.clone(),
+        other => format!("{:?}", other),
+    }
+}
+
+/// Warn about imported names that the module never refers to
+pub fn check_unused_imports(ast: &[ASTNode]) -> Vec<Diagnostic> {
+    let referenced = referenced_names(ast);
+    let mut diagnostics = Vec::new();
+    for node in ast.iter() {
+        if let ASTNode::ImportStatement(i) = node {
+            // Re-exports are there for other modules, so this one doesn't have to use them
+            if i.reexport {
+                continue;
+            }
+            let unused = i
+                .items
+                .iter()
+                .filter(|item| !referenced.contains(*item))
+                .map(|item| format!("`{}`", item))
+                .collect::<Vec<String>>();
+            if !unused.is_empty() {
+                diagnostics.push(Diagnostic::new_warning_simple(
+                    DiagnosticKind::UnusedImport,
+                    &format!("unused import from `{}`: {}", i.file, unused.join(", ")),
+                    &i.pos,
+                ));
+            }
+        }
+    }
+    diagnostics
+}
+
+/// Every name a module refers to: types, called functions, and variables
+///
+/// Variables are included so that enum variant qualifiers (`Status.Alive`) count as uses of the enum
+pub fn referenced_names<'a, I>(ast: I) -> HashSet<String>
+where
+    I: IntoIterator<Item = &'a ASTNode>,
+{
+    let mut names = HashSet::new();
+    let mut functions: Vec<&Function> = Vec::new();
+    for node in ast {
+        match node {
+            ASTNode::StructDeclaration(s) => {
+                for field in s.fields.iter() {
+                    collect_type_names(&field.field_type, &mut names);
+                }
+                functions.extend(s.methods.iter());
+            }
+            ASTNode::EnumDeclaration(e) => {
+                for field in e.fields.iter() {
+                    collect_type_names(&field.field_type, &mut names);
+                }
+                functions.extend(e.methods.iter());
+            }
+            ASTNode::FunctionDeclaration(f) => functions.push(f),
+            ASTNode::ImportStatement(_) | ASTNode::ModuleMetadata(_) => {}
+        }
+    }
+    for f in functions {
+        for arg in f.args.iter() {
+            collect_type_names(&arg.field_type, &mut names);
+        }
+        collect_type_names(&f.returns, &mut names);
+        let mut record = |expr: &Expr, _: &SourcePosition| match expr {
+            Expr::Variable(name) | Expr::FunctionCall { name, .. } => {
+                names.insert(name.clone());
+            }
+            _ => {}
+        };
+        for contract in f.contracts.iter() {
+            visit_expr(&contract.condition, &contract.pos, &mut record);
+        }
+        visit_statements(&f.statements, &mut record);
+        collect_statement_names(&f.statements, &mut names);
+    }
+    names
+}
+
+/// The names in statements that aren't expressions: declared types and patterns
+fn collect_statement_names(statements: &[Statement], names: &mut HashSet<String>) {
+    for statement in statements.iter() {
+        match statement {
+            Statement::VariableDeclaration { type_, .. } => collect_type_names(type_, names),
+            Statement::Conditional { branches, .. } | Statement::Match { branches, .. } => {
+                for branch in branches.iter() {
+                    collect_statement_names(&branch.computations, names);
+                }
+            }
+            Statement::WhileLoop { binding, body, .. } => {
+                if let Some(Pattern::Variant { name, .. }) = binding {
+                    names.insert(name.clone());
+                }
+                collect_statement_names(body, names);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn collect_type_names(type_: &Type, names: &mut HashSet<String>) {
+    match type_ {
+        Type::Custom(name) => {
+            names.insert(name.clone());
+        }
+        Type::Array(inner)
+        | Type::Map(inner)
+        | Type::Shared(inner)
+        | Type::Optional(inner)
+        | Type::FixedArray(inner, _) => collect_type_names(inner, names),
+        Type::Tuple(members) => {
+            for member in members.iter() {
+                collect_type_names(member, names);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Report reads of variables declared without a value (`let x: Int;`) that happen before every path has assigned them
+pub fn check_initialization(ast: &[ASTNode]) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for f in functions_in(ast) {
+        initialize_block(&f.statements, &mut Vec::new(), &mut diagnostics);
+    }
+    diagnostics
+}
+
+/// Whether each local declared so far (innermost last) has definitely been assigned
+type Initialized = Vec<(String, bool)>;
+
+/// Track assignments through a block, returning true if every path through it returns
+fn initialize_block(
+    statements: &[Statement],
+    locals: &mut Initialized,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> bool {
+    let depth = locals.len();
+    let mut returns = false;
+    for statement in statements.iter() {
+        match statement {
+            Statement::FunctionCall { call, pos } => read_locals(call, pos, locals, diagnostics),
+            Statement::VariableDeclaration {
+                name, value, pos, ..
+            } => {
+                if let Some(value) = value {
+                    read_locals(value, pos, locals, diagnostics);
+                }
+                locals.push((name.clone(), value.is_some()));
+            }
+            Statement::VariableMutation { name, value, pos } => {
+                read_locals(value, pos, locals, diagnostics);
+                if let Some(local) = locals.iter_mut().rev().find(|(n, _)| n == name) {
+                    local.1 = true;
+                }
+            }
+            Statement::Conditional { branches, pos } => {
+                for condition in branches.iter().filter_map(|b| b.condition.as_ref()) {
+                    read_locals(condition, pos, locals, diagnostics);
+                }
+                let has_else = branches.iter().any(|b| b.condition.is_none());
+                returns = initialize_branches(branches, has_else, locals, diagnostics);
+            }
+            // Matches are assumed to be exhaustive, since `typecheck` reports the ones that aren't
+            Statement::Match {
+                scrutinee,
+                branches,
+                pos,
+            } => {
+                read_locals(scrutinee, pos, locals, diagnostics);
+                returns = initialize_branches(branches, true, locals, diagnostics);
+            }
+            // The body might not run, so nothing it assigns counts afterwards
+            Statement::WhileLoop {
+                binding,
+                condition,
+                body,
+                pos,
+            } => {
+                read_locals(condition, pos, locals, diagnostics);
+                let mut inner = locals.clone();
+                if let Some(Pattern::Variant {
+                    binding: Some(name),
+                    ..
+                }) = binding
+                {
+                    inner.push((name.clone(), true));
+                }
+                initialize_block(body, &mut inner, diagnostics);
+            }
+            Statement::Return { value, pos } => {
+                read_locals(value, pos, locals, diagnostics);
+                returns = true;
+            }
+            Statement::Assert { cond, pos, .. } => read_locals(cond, pos, locals, diagnostics),
+        }
+        // Anything after this is unreachable, `check_returns` reports it
+        if returns {
+            break;
+        }
+    }
+    locals.truncate(depth);
+    returns
+}
+
+/// A variable is assigned after the branches if it's assigned on every path that doesn't return
+///
+/// Without a catch all, skipping every branch is a path too
+fn initialize_branches(
+    branches: &[Branch],
+    exhaustive: bool,
+    locals: &mut Initialized,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> bool {
+    let mut outcomes = Vec::new();
+    for branch in branches.iter() {
+        let mut inner = locals.clone();
+        if !initialize_block(&branch.computations, &mut inner, diagnostics) {
+            outcomes.push(inner);
+        }
+    }
+    if !exhaustive {
+        outcomes.push(locals.clone());
+    }
+    if outcomes.is_empty() {
+        return true;
+    }
+    for (i, local) in locals.iter_mut().enumerate() {
+        local.1 = outcomes.iter().all(|outcome| outcome[i].1);
+    }
+    false
+}
+
+/// Report each unassigned local an expression reads
+///
+/// Once reported, a variable counts as assigned so the same mistake isn't reported at every later read
+fn read_locals(
+    expr: &Expr,
+    pos: &SourcePosition,
+    locals: &mut Initialized,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    variables_in(expr, &mut |name| {
+        if let Some(local) = locals.iter_mut().rev().find(|(n, _)| n == name) {
+            if !local.1 {
+                diagnostics.push(Diagnostic::new_error_simple(
+                    DiagnosticKind::UninitializedVariable,
+                    &format!(
+                        "`{}` is read before it's been assigned a value on every path",
+                        name
+                    ),
+                    pos,
+                ));
+                local.1 = true;
+            }
+        }
+    });
+}
+
+/// Flag match arms that come after a catch all `_`, since they can never be reached
+pub fn check_match_arms(ast: &[ASTNode]) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for f in functions_in(ast) {
+        unreachable_arms(&f.statements, &mut diagnostics);
+    }
+    diagnostics
+}
+
+fn unreachable_arms(statements: &[Statement], diagnostics: &mut Vec<Diagnostic>) {
+    for statement in statements.iter() {
+        match statement {
+            Statement::Match { branches, .. } => {
+                if let Some(catch_all) = branches.iter().position(|b| b.condition.is_none()) {
+                    for branch in branches[catch_all + 1..].iter() {
+                        diagnostics.push(Diagnostic::new_lint_simple(
+                            DiagnosticKind::UnreachableCode,
+                            "unreachable match arm, the catch all `_` above already matches everything",
+                            &branch.pos,
+                        ));
+                    }
+                }
+                for branch in branches.iter() {
+                    unreachable_arms(&branch.computations, diagnostics);
+                }
+            }
+            Statement::Conditional { branches, .. } => {
+                for branch in branches.iter() {
+                    unreachable_arms(&branch.computations, diagnostics);
+                }
+            }
+            Statement::WhileLoop { body, .. } => unreachable_arms(body, diagnostics),
+            _ => {}
+        }
+    }
+}
+
+/// Check that non-Void functions return a value on every path, and flag code that comes after a return
+///
+/// Extern functions have no body to check
+pub fn check_returns(ast: &[ASTNode]) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for f in functions_in(ast).into_iter().filter(|f| !f.is_extern) {
+        let returns = always_returns(&f.statements, &mut diagnostics);
+        if !returns && f.returns != Type::Void {
+            diagnostics.push(Diagnostic::new_error_simple(
+                DiagnosticKind::MissingReturn,
+                &format!(
+                    "`{}` returns {}, but not every path through it ends in a `return`",
+                    f.name, f.returns
+                ),
+                &f.pos,
+            ));
+        }
+    }
+    diagnostics
+}
+
+/// Does every path through these statements end in a `return`?
+///
+/// A conditional only counts if it has an `else`, and a loop never counts since its body might not run. Matches are assumed to be exhaustive, since `typecheck` reports the ones that aren't.
+fn always_returns(statements: &[Statement], diagnostics: &mut Vec<Diagnostic>) -> bool {
+    for (i, statement) in statements.iter().enumerate() {
+        let returns = match statement {
+            Statement::Return { .. } => true,
+            Statement::Conditional { branches, .. } => {
+                let has_else = branches.iter().any(|b| b.condition.is_none());
+                branches_return(branches, diagnostics) && has_else
+            }
+            Statement::Match { branches, .. } => {
+                branches_return(branches, diagnostics) && !branches.is_empty()
+            }
+            Statement::WhileLoop { body, .. } => {
+                always_returns(body, diagnostics);
+                false
+            }
+            _ => false,
+        };
+        if returns {
+            if let Some(next) = statements.get(i + 1) {
+                diagnostics.push(Diagnostic::new_warning_simple(
+                    DiagnosticKind::UnreachableCode,
+                    "unreachable code, every path above has already returned",
+                    next.pos(),
+                ));
+            }
+            return true;
+        }
+    }
+    false
+}
+
+/// Do all of these branches return? Every branch is checked, so that dead code inside each of them is reported
+fn branches_return(branches: &[Branch], diagnostics: &mut Vec<Diagnostic>) -> bool {
+    let returns = branches
+        .iter()
+        .map(|b| always_returns(&b.computations, diagnostics))
+        .collect::<Vec<bool>>();
+    returns.into_iter().all(|r| r)
+}
+
+/// Flag array indices that can go out of bounds
+///
+/// An index that folds to a negative constant is an error, since the generated `data[index]` would read before the array. Any other index that isn't a non-negative constant gets a lint suggesting the bounds checked `get`
+pub fn check_indices(ast: &[ASTNode]) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for node in ast.iter() {
+        let (functions, attributes): (Vec<&Function>, &[Attribute]) = match node {
+            ASTNode::FunctionDeclaration(f) => (vec![f], &[]),
+            ASTNode::StructDeclaration(s) => (s.methods.iter().collect(), &s.attributes),
+            ASTNode::EnumDeclaration(e) => (e.methods.iter().collect(), &e.attributes),
+            ASTNode::ImportStatement(_) | ASTNode::ModuleMetadata(_) => continue,
+        };
+        for f in functions {
+            let mut found = Vec::new();
+            visit_statements(&f.statements, &mut |expr, pos| {
+                if let Expr::IndexAccess { object, index } = expr {
+                    if let Some(diagnostic) = check_index(object, index, pos) {
+                        found.push(diagnostic);
+                    }
+                }
+            });
+            diagnostics.extend(without_allowed(found, &[attributes, &f.attributes]));
+        }
+    }
+    diagnostics
+}
+
+fn check_index(object: &Expr, index: &Expr, pos: &SourcePosition) -> Option<Diagnostic> {
+    match constant_integer(index) {
+        Some(value) if value >= 0 => None,
+        Some(value) => {
+            let message = if index.to_string() == value.to_string() {
+                format!("index `{}` is negative, arrays start at 0", index)
+            } else {
+                format!(
+                    "index `{}` is always {}, arrays start at 0",
+                    index, value
+                )
+            };
+            Some(Diagnostic::new_error_simple(
+                DiagnosticKind::NegativeIndex,
+                &message,
+                pos,
+            ))
+        }
+        None => Some(Diagnostic::new_lint_simple(
+            DiagnosticKind::UncheckedIndex,
+            &format!(
+                "index `{}` isn't checked against the length of `{}`, consider `{}.get({})` instead",
+                index, object, object, index
+            ),
+            pos,
+        )),
+    }
+}
+
+/// The value of an integer expression, if it can be worked out without running the program
+///
+/// Anything that overflows or divides by zero isn't folded
+pub fn constant_integer(expr: &Expr) -> Option<i64> {
+    match expr {
+        Expr::IntegerLiteral(n) => Some(*n),
+        Expr::UnaryOp {
+            operator: UnaryOperator::Negate,
+            operand,
+        } => constant_integer(operand)?.checked_neg(),
+        Expr::BinaryOp {
+            left,
+            operator,
+            right,
+        } => {
+            let (left, right) = (constant_integer(left)?, constant_integer(right)?);
+            match operator {
+                BinaryOperator::Add => left.checked_add(right),
+                BinaryOperator::Subtract => left.checked_sub(right),
+                BinaryOperator::Multiply => left.checked_mul(right),
+                BinaryOperator::Divide => left.checked_div(right),
+                BinaryOperator::Modulo => left.checked_rem(right),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Every function in a module, including struct and enum methods
+fn functions_in(ast: &[ASTNode]) -> Vec<&Function> {
+    let mut functions = Vec::new();
+    for node in ast.iter() {
+        match node {
+            ASTNode::FunctionDeclaration(f) => functions.push(f),
+            ASTNode::StructDeclaration(s) => functions.extend(s.methods.iter()),
+            ASTNode::EnumDeclaration(e) => functions.extend(e.methods.iter()),
+            ASTNode::ImportStatement(_) | ASTNode::ModuleMetadata(_) => {}
+        }
+    }
+    functions
+}
+
+/// Call `visit` on every expression (and sub-expression) in a list of statements, along with the position of the statement it's in
+///
+/// Match patterns aren't visited, since they aren't evaluated as expressions
+pub fn visit_statements<F>(statements: &[Statement], visit: &mut F)
+where
+    F: FnMut(&Expr, &SourcePosition),
+{
+    for statement in statements.iter() {
+        match statement {
+            Statement::FunctionCall { call: expr, pos }
+            | Statement::VariableDeclaration {
+                value: Some(expr),
+                pos,
+                ..
+            }
+            | Statement::VariableMutation {
+                value: expr, pos, ..
+            }
+            | Statement::Return { value: expr, pos }
+            | Statement::Assert {
+                cond: expr, pos, ..
+            } => visit_expr(expr, pos, visit),
+            Statement::VariableDeclaration { value: None, .. } => {}
+            Statement::Conditional { branches, pos } => {
+                for branch in branches.iter() {
+                    if let Some(condition) = &branch.condition {
+                        visit_expr(condition, pos, visit);
+                    }
+                    visit_statements(&branch.computations, visit);
+                }
+            }
+            Statement::Match {
+                scrutinee,
+                branches,
+                pos,
+            } => {
+                visit_expr(scrutinee, pos, visit);
+                for branch in branches.iter() {
+                    visit_statements(&branch.computations, visit);
+                }
+            }
+            Statement::WhileLoop {
+                condition,
+                body,
+                pos,
+                ..
+            } => {
+                visit_expr(condition, pos, visit);
+                visit_statements(body, visit);
+            }
+        }
+    }
+}
+
+/// Call `visit` on an expression and every sub-expression in it
+pub fn visit_expr<F>(expr: &Expr, pos: &SourcePosition, visit: &mut F)
+where
+    F: FnMut(&Expr, &SourcePosition),
+{
+    visit(expr, pos);
+    match expr {
+        Expr::IntegerLiteral(_)
+        | Expr::FloatLiteral(_)
+        | Expr::StringLiteral(_)
+        | Expr::Variable(_)
+        | Expr::EnumVariant { .. } => {}
+        Expr::PropertyAccess { object, .. } => visit_expr(object, pos, visit),
+        Expr::FunctionCall { arguments, .. } => {
+            for arg in arguments.iter() {
+                visit_expr(arg, pos, visit);
+            }
+        }
+        Expr::MethodCall {
+            object, arguments, ..
+        } => {
+            visit_expr(object, pos, visit);
+            for arg in arguments.iter() {
+                visit_expr(arg, pos, visit);
+            }
+        }
+        Expr::BinaryOp { left, right, .. } => {
+            visit_expr(left, pos, visit);
+            visit_expr(right, pos, visit);
+        }
+        Expr::UnaryOp { operand, .. } => visit_expr(operand, pos, visit),
+        Expr::IndexAccess { object, index } => {
+            visit_expr(object, pos, visit);
+            visit_expr(index, pos, visit);
+        }
+        Expr::IfExpr { cond, then, els } => {
+            visit_expr(cond, pos, visit);
+            visit_expr(then, pos, visit);
+            visit_expr(els, pos, visit);
+        }
+    }
+}
+
+/// Reports names that aren't in scope, and locals that are never read
+#[derive(Default)]
+struct ScopeChecker {
+    diagnostics: Vec<Diagnostic>,
+    /// Preconditions run before the body, so they can't see `result`
+    in_precondition: bool,
+}
+
+impl ScopeChecker {
+    fn undeclared(
+        &mut self,
+        message: String,
+        name: &str,
+        pos: &SourcePosition,
+        symbols: &SymbolTable,
+    ) {
+        let message = match did_you_mean(name, symbols.visible_names()) {
+            Some(suggestion) => format!("{} (did you mean `{}`?)", message, suggestion),
+            None => message,
+        };
+        self.diagnostics.push(Diagnostic::new_error_simple(
+            DiagnosticKind::UndefinedVariable,
+            &message,
+            pos,
+        ));
+    }
+
+    /// Report any variables in an expression that aren't in scope
+    fn check_expr(&mut self, expr: &Expr, pos: &SourcePosition, symbols: &mut SymbolTable) {
+        variables_in(expr, &mut |name| {
+            if symbols.mark_read(name) {
+                return;
+            }
+            // The lexer doesn't have boolean or `none` literals, so they show up as variables
+            if name == "result" && self.in_precondition {
+                self.diagnostics.push(Diagnostic::new_error_simple(
+                    DiagnosticKind::UndefinedVariable,
+                    "`result` is not defined in an `In` contract, only `Out` contracts can see the return value",
+                    pos,
+                ));
+            } else if !["true", "false", "none"].contains(&name) {
+                self.undeclared(format!("`{}` is not defined", name), name, pos, symbols);
+            }
+        });
+    }
+}
+
+impl ScopeVisitor for ScopeChecker {
+    fn visit_contract(&mut self, contract: &FunctionContract, symbols: &mut SymbolTable) {
+        self.in_precondition = contract.type_ == ContractType::Input;
+        self.check_expr(&contract.condition, &contract.pos, symbols);
+        self.in_precondition = false;
+    }
+
+    fn visit_statement(&mut self, statement: &Statement, symbols: &mut SymbolTable) {
+        match statement {
+            // The initializer is checked before the name is declared, so `let x: Int = x;` only works if it shadows an outer `x`
+            Statement::VariableDeclaration {
+                value: Some(value),
+                pos,
+                ..
+            } => self.check_expr(value, pos, symbols),
+            Statement::VariableDeclaration { value: None, .. } => {}
+            Statement::VariableMutation { name, value, pos } => {
+                match symbols.lookup(name).map(|binding| &binding.parameter_of) {
+                    None => self.undeclared(
+                        format!(
+                            "cannot assign to `{}` because it was never declared (use `let` to create a new variable)",
+                            name
+                        ),
+                        name,
+                        pos,
+                        symbols,
+                    ),
+                    // A local that shadows the parameter is fine to reassign
+                    Some(Some(function_pos)) => {
+                        self.diagnostics
+                            .push(Diagnostic::new_lint_with_references(
+                                DiagnosticKind::ParameterAssignment,
+                                &format!(
+                                    "assignment to parameter `{}`, copy it into a local with `let` and change that instead",
+                                    name
+                                ),
+                                pos,
+                                vec![function_pos.clone()],
+                            ))
+                    }
+                    Some(None) => {}
+                }
+                self.check_expr(value, pos, symbols);
+            }
+            // Match patterns name enum variants or literals, not variables
+            Statement::Conditional { branches, pos } => {
+                for condition in branches.iter().filter_map(|b| b.condition.as_ref()) {
+                    self.check_expr(condition, pos, symbols);
+                }
+            }
+            Statement::Match { scrutinee, pos, .. } => self.check_expr(scrutinee, pos, symbols),
+            Statement::WhileLoop { condition, pos, .. } => self.check_expr(condition, pos, symbols),
+            Statement::FunctionCall { call, pos } => self.check_expr(call, pos, symbols),
+            Statement::Return { value, pos } => self.check_expr(value, pos, symbols),
+            Statement::Assert { cond, pos, .. } => self.check_expr(cond, pos, symbols),
+        }
+    }
+
+    fn scope_closed(&mut self, unused: Vec<(String, Binding)>) {
+        for (name, binding) in unused {
+            if let Some(pos) = binding.declared_at {
+                self.diagnostics.push(Diagnostic::new_warning_simple(
+                    DiagnosticKind::UnusedVariable,
+                    &format!(
+                        "unused variable `{}`, prefix it with an underscore if this is intentional",
+                        name
+                    ),
+                    &pos,
+                ));
+            }
+        }
+    }
+}
+
+// -------------------- Unit Tests --------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics::IssueLevel;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn check(program: &str) -> Vec<Diagnostic> {
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(program);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+        check_scopes(&out.output.unwrap())
+    }
+
+    #[test]
+    fn mutation_before_declaration() {
+        let diagnostics = check(
+            r#"
+fn main(argc: Int) -> Int {
+    total = 5;
+    let total: Int = 0;
+    return total;
+}
+"#,
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0]
+            .message()
+            .starts_with("cannot assign to `total` because it was never declared"));
+        assert_eq!(diagnostics[0].position().line, 2);
+    }
+
+    #[test]
+    fn mutate_parameter() {
+        let diagnostics = check(
+            r#"
+fn main(argc: Int) -> Int {
+    argc = 5;
+    return argc;
+}
+
+@allow(parameter_assignment)
+fn reset(argc: Int) -> Int {
+    argc = 0;
+    return argc;
+}
+"#,
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].kind(), DiagnosticKind::ParameterAssignment);
+        assert_eq!(*diagnostics[0].level(), IssueLevel::Lint);
+        assert_eq!(
+            diagnostics[0].message(),
+            "assignment to parameter `argc`, copy it into a local with `let` and change that instead"
+        );
+        assert_eq!(diagnostics[0].position().line, 2);
+        assert_eq!(diagnostics[0].references()[0].line, 1);
+    }
+
+    #[test]
+    fn mutate_local_or_shadowed_parameter() {
+        let diagnostics = check(
+            r#"
+fn main(argc: Int) -> Int {
+    let total: Int = argc;
+    total = total + 1;
+    let argc: Int = total;
+    argc = argc * 2;
+    return argc;
+}
+"#,
+        );
+        assert!(diagnostics.is_empty(), "{:#?}", diagnostics);
+    }
+
+    #[test]
+    fn suggest_for_typo() {
+        let diagnostics = check(
+            r#"
+fn main(argc: Int) -> Int {
+    let count: Int = 0;
+    coutn = 1;
+    return count;
+}
+"#,
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0]
+            .message()
+            .ends_with("(did you mean `count`?)"));
+    }
+
+    #[test]
+    fn branch_scopes_end_with_the_branch() {
+        let diagnostics = check(
+            r#"
+fn main(argc: Int) -> Int {
+    if argc > 0 {
+        let inner: Int = 1;
+        inner = inner + 2;
+    }
+    inner = 3;
+    return argc;
+}
+"#,
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].position().line, 6);
+    }
+
+    #[test]
+    fn undefined_variable() {
+        let diagnostics = check(
+            r#"
+fn main(argc: Int) -> Int {
+    let count: Int = argc;
+    return coutn + count;
+}
+"#,
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message(),
+            "`coutn` is not defined (did you mean `count`?)"
+        );
+        assert_eq!(diagnostics[0].position().line, 3);
+    }
+
+    #[test]
+    fn use_before_declaration() {
+        let diagnostics = check(
+            r#"
+fn main(argc: Int) -> Int {
+    let doubled: Int = total * 2;
+    let total: Int = argc;
+    return doubled + total;
+}
+"#,
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0]
+            .message()
+            .starts_with("`total` is not defined"));
+    }
+
+    #[test]
+    fn shadowing_uses_the_outer_binding() {
+        let diagnostics = check(
+            r#"
+fn main(argc: Int) -> Int {
+    let total: Int = argc;
+    if total > 0 {
+        let total: Int = total + 1;
+        print(total);
+    }
+    return total;
+}
+"#,
+        );
+        assert!(diagnostics.is_empty(), "{:#?}", diagnostics);
+    }
+
+    #[test]
+    fn result_only_in_postconditions() {
+        let diagnostics = check(
+            r#"
+fn double(a: Int) -> Int {
+    @contracts {
+        In: (result > 0, "result is not visible yet")
+        Out: (result > a, "output must grow")
+    }
+    return a * 2;
+}
+"#,
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0]
+            .message()
+            .starts_with("`result` is not defined"));
+        assert_eq!(diagnostics[0].position().line, 3);
+    }
+
+    fn check_program_calls(program: &str) -> Vec<Diagnostic> {
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(program);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+        let ast = out.output.unwrap();
+        let mut tables = ParsingTables::new();
+        tables.update(&ast, "test");
+        check_calls(&ast, "test", &tables)
+    }
+
+    #[test]
+    fn call_defined_function() {
+        let diagnostics = check_program_calls(
+            r#"
+fn add(a: Int, b: Int) -> Int {
+    return a + b;
+}
+
+fn main(argc: Int) -> Int {
+    print("adding");
+    return add(argc, 5);
+}
+"#,
+        );
+        assert!(diagnostics.is_empty(), "{:#?}", diagnostics);
+    }
+
+    #[test]
+    fn call_imported_function() {
+        let diagnostics = check_program_calls(
+            r#"
+import math with add;
+
+fn main(argc: Int) -> Int {
+    return add(argc, 5);
+}
+"#,
+        );
+        assert!(diagnostics.is_empty(), "{:#?}", diagnostics);
+    }
+
+    #[test]
+    fn call_unknown_function() {
+        let diagnostics = check_program_calls(
+            r#"
+fn add(a: Int, b: Int) -> Int {
+    return a + b;
+}
+
+fn main(argc: Int) -> Int {
+    return ad(argc, 5);
+}
+"#,
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message(),
+            "`ad` is not a known function (did you mean `add`?)"
+        );
+        assert_eq!(diagnostics[0].position().line, 6);
+    }
+
+    #[test]
+    fn call_struct_name() {
+        let diagnostics = check_program_calls(
+            r#"
+struct Animal {
+    legs: Int
+
+    @metadata {
+        Is: Public;
+    }
+}
+
+fn main(argc: Int) -> Int {
+    let a: Animal = Animal(argc);
+    return argc;
+}
+"#,
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message(),
+            "`Animal` is a struct, not a function"
+        );
+    }
+
+    #[test]
+    fn arm_after_catch_all() {
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(
+            r#"
+fn main(argc: Int) -> Int {
+    match argc {
+        0 => 1,
+        _ => 2,
+        3 => 4
+    }
+}
+"#,
+        );
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+        let diagnostics = check_match_arms(&out.output.unwrap());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(*diagnostics[0].level(), IssueLevel::Lint);
+        assert!(diagnostics[0]
+            .message()
+            .starts_with("unreachable match arm"));
+        assert_eq!(diagnostics[0].position().line, 5);
+        assert_eq!(diagnostics[0].position().column, 8);
+    }
+
+    #[test]
+    fn unused_import_item() {
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(
+            r#"import npc with Creature, Villager;
+
+fn greet(c: Creature) -> Int {
+    return 0;
+}
+"#,
+        );
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+        let diagnostics = check_unused_imports(&out.output.unwrap());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(*diagnostics[0].level(), IssueLevel::Warning);
+        assert_eq!(
+            diagnostics[0].message(),
+            "unused import from `npc`: `Villager`"
+        );
+        assert_eq!(diagnostics[0].position().line, 0);
+    }
+
+    /// Parse every module into one set of tables, then check the types used by the first
+    fn check_module_types(modules: &[(&str, &str)]) -> Vec<Diagnostic> {
+        let mut tables = ParsingTables::new();
+        let mut asts = Vec::new();
+        for (name, program) in modules {
+            let mut lexer = Lexer::new(&format!("{}.iona", name));
+            lexer.lex(program);
+            let mut parser = Parser::new(lexer.token_stream);
+            let out = parser.parse_all();
+            assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+            let ast = out.output.unwrap();
+            tables.update(&ast, name);
+            asts.push(ast);
+        }
+        check_types(&asts[0], modules[0].0, &tables)
+    }
+
+    const ANIMAL: &str = r#"
+struct Animal {
+    legs: Int
+
+    @metadata {
+        Is: Public;
+    }
+}
+"#;
+
+    #[test]
+    fn resolved_types() {
+        let diagnostics = check_module_types(&[
+            (
+                "main",
+                r#"
+import zoo with Animal;
+
+struct Pen {
+    animals: Array<Animal>,
+    size: Int
+
+    @metadata {
+        Is: Public;
+    }
+}
+
+fn count(pen: Pen) -> Int {
+    let first: Animal = pen.animals[0];
+    return pen.size;
+}
+"#,
+            ),
+            ("zoo", ANIMAL),
+        ]);
+        assert!(diagnostics.is_empty(), "{:#?}", diagnostics);
+    }
+
+    #[test]
+    fn misspelled_type() {
+        let diagnostics = check_module_types(&[(
+            "main",
+            &format!(
+                "{}{}",
+                ANIMAL,
+                r#"
+fn legs(a: Aminal) -> Int {
+    return 4;
+}
+"#
+            ),
+        )]);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message(),
+            "`Aminal` is not a known type (did you mean `Animal`?)"
+        );
+        assert_eq!(diagnostics[0].kind(), DiagnosticKind::UndefinedType);
+        assert_eq!(diagnostics[0].position().line, 9);
+    }
+
+    #[test]
+    fn type_not_imported() {
+        let diagnostics = check_module_types(&[
+            (
+                "main",
+                r#"
+fn main(argc: Int) -> Int {
+    let pets: Array<Animal> = adopt(argc);
+    return argc;
+}
+"#,
+            ),
+            ("zoo", ANIMAL),
+        ]);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message(),
+            "`Animal` is defined in module `zoo` but not imported here (add `import zoo with Animal;`)"
+        );
+        assert_eq!(diagnostics[0].position().line, 2);
+    }
+
+    fn unused_variables(program: &str) -> Vec<String> {
+        check(program)
+            .into_iter()
+            .filter(|d| *d.level() == IssueLevel::Warning)
+            .map(|d| d.message().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn unused_variable() {
+        let diagnostics = check(
+            r#"
+fn main(argc: Int) -> Int {
+    let tmp: Int = compute(argc);
+    return argc;
+}
+"#,
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(*diagnostics[0].level(), IssueLevel::Warning);
+        assert_eq!(
+            diagnostics[0].message(),
+            "unused variable `tmp`, prefix it with an underscore if this is intentional"
+        );
+        assert_eq!(diagnostics[0].position().line, 2);
+    }
+
+    #[test]
+    fn variable_used_only_in_branch() {
+        let warnings = unused_variables(
+            r#"
+fn main(argc: Int) -> Int {
+    let limit: Int = 10;
+    if argc > 0 {
+        return limit;
+    }
+    return argc;
+}
+"#,
+        );
+        assert!(warnings.is_empty(), "{:#?}", warnings);
+    }
+
+    #[test]
+    fn variable_mutated_but_never_read() {
+        let warnings = unused_variables(
+            r#"
+fn main(argc: Int) -> Int {
+    let total: Int = 0;
+    total = 5;
+    return argc;
+}
+"#,
+        );
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("unused variable `total`"));
+    }
+
+    #[test]
+    fn underscore_suppresses_unused_warning() {
+        let warnings = unused_variables(
+            r#"
+fn main(argc: Int) -> Int {
+    let _ignored: Int = compute(argc);
+    return argc;
+}
+"#,
+        );
+        assert!(warnings.is_empty(), "{:#?}", warnings);
+    }
+
+    #[test]
+    fn allow_attribute_suppresses_unused_warning() {
+        // Parameters are never reported, so the unused names here are locals
+        let warnings = unused_variables(
+            r#"
+@allow(unused)
+fn quiet(argc: Int) -> Int {
+    let tmp: Int = compute(argc);
+    return argc;
+}
+
+fn noisy(argc: Int) -> Int {
+    let tmp: Int = compute(argc);
+    return argc;
+}
+"#,
+        );
+        assert_eq!(
+            warnings,
+            vec!["unused variable `tmp`, prefix it with an underscore if this is intentional"]
+        );
+    }
+
+    fn check_init(program: &str) -> Vec<Diagnostic> {
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(program);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+        check_initialization(&out.output.unwrap())
+    }
+
+    #[test]
+    fn read_before_assignment() {
+        let diagnostics = check_init(
+            r#"
+fn main(argc: Int) -> Int {
+    let total: Int;
+    let doubled: Int = total * 2;
+    return total;
+}
+"#,
+        );
+        // Only the first read is reported
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message(),
+            "`total` is read before it's been assigned a value on every path"
+        );
+        assert_eq!(diagnostics[0].position().line, 3);
+    }
+
+    #[test]
+    fn assigned_then_read() {
+        let diagnostics = check_init(
+            r#"
+fn main(argc: Int) -> Int {
+    let total: Int;
+    let sign: Int;
+    total = argc;
+    if argc > 0 {
+        sign = 1;
+    } else {
+        if argc < 0 {
+            return 0;
+        }
+        sign = 0;
+    }
+    return total * sign;
+}
+"#,
+        );
+        assert!(diagnostics.is_empty(), "{:#?}", diagnostics);
+    }
+
+    #[test]
+    fn assigned_on_some_paths() {
+        let diagnostics = check_init(
+            r#"
+fn main(argc: Int) -> Int {
+    let sign: Int;
+    let count: Int;
+    if argc > 0 {
+        sign = 1;
+    }
+    while argc > 0 {
+        count = 1;
+    }
+    return sign + count;
+}
+"#,
+        );
+        let messages = diagnostics
+            .iter()
+            .map(|d| d.message())
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            messages,
+            vec![
+                "`sign` is read before it's been assigned a value on every path",
+                "`count` is read before it's been assigned a value on every path"
+            ]
+        );
+    }
+
+    fn check_program_permissions(program: &str) -> Vec<Diagnostic> {
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(program);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+        let ast = out.output.unwrap();
+        let mut tables = ParsingTables::new();
+        tables.update(&ast, "test");
+        check_permissions(&ast, "test", &tables)
+    }
+
+    const SAVE: &str = r#"
+fn save(data: Int) -> Int {
+    @metadata {
+        Uses: WriteFile;
+    }
+    return data;
+}
+"#;
+
+    #[test]
+    fn direct_permission_violation() {
+        let diagnostics = check_program_permissions(&format!(
+            "{}{}",
+            SAVE,
+            r#"
+fn main(argc: Int) -> Int {
+    return save(argc);
+}
+"#
+        ));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message(),
+            "`main` calls `save`, which needs the WriteFile permission, but `main` doesn't declare it in `Uses`"
+        );
+        assert_eq!(diagnostics[0].position().line, 9);
+    }
+
+    #[test]
+    fn unused_permission() {
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(
+            r#"
+@allow(unused)
+fn read(path: Int) -> Int {
+    @metadata {
+        Uses: ReadFile;
+    }
+    return path;
+}
+
+fn summarize(path: Int) -> Int {
+    @metadata {
+        Uses: ReadFile, WriteFile;
+    }
+    return read(path);
+}
+"#,
+        );
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+        let ast = out.output.unwrap();
+        let mut tables = ParsingTables::new();
+        tables.update(&ast, "test");
+        assert!(check_permissions(&ast, "test", &tables).is_empty());
+        let diagnostics = check_unused_permissions(&ast, "test", &tables);
+        assert_eq!(diagnostics.len(), 1, "{:#?}", diagnostics);
+        assert_eq!(*diagnostics[0].level(), IssueLevel::Lint);
+        assert_eq!(
+            diagnostics[0].message(),
+            "`summarize` declares the WriteFile permission in `Uses`, but nothing it calls needs it"
+        );
+        assert_eq!(diagnostics[0].position().line, 9);
+    }
+
+    #[test]
+    fn module_permissions_are_inherited() {
+        let diagnostics = check_program_permissions(&format!(
+            "{}{}",
+            SAVE,
+            r#"
+@module {
+    Uses: WriteFile;
+}
+
+fn main(argc: Int) -> Int {
+    return save(argc);
+}
+
+fn report(argc: Int) -> Int {
+    @metadata {
+        Uses: ReadConsole;
+    }
+    return save(argc);
+}
+"#
+        ));
+        // `main` inherits WriteFile, `report` overrides it
+        assert_eq!(diagnostics.len(), 1, "{:#?}", diagnostics);
+        assert_eq!(
+            diagnostics[0].message(),
+            "`report` calls `save`, which needs the WriteFile permission, but `report` doesn't declare it in `Uses`"
+        );
+    }
+
+    #[test]
+    fn transitive_permission_violation() {
+        let diagnostics = check_program_permissions(&format!(
+            "{}{}",
+            SAVE,
+            r#"
+fn backup(data: Int) -> Int {
+    return save(data);
+}
+
+fn main(argc: Int) -> Int {
+    return backup(argc);
+}
+"#
+        ));
+        let messages = diagnostics
+            .iter()
+            .map(|d| d.message())
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            messages,
+            vec![
+                "`backup` calls `save`, which needs the WriteFile permission, but `backup` doesn't declare it in `Uses`",
+                "`main` calls `backup`, which needs the WriteFile permission, but `main` doesn't declare it in `Uses`",
+            ]
+        );
+    }
+
+    #[test]
+    fn declared_permission_chain() {
+        let diagnostics = check_program_permissions(&format!(
+            "{}{}",
+            SAVE,
+            r#"
+fn backup(data: Int) -> Int {
+    @metadata {
+        Uses: WriteFile, Audit;
+    }
+    return save(data);
+}
+
+fn main(argc: Int) -> Int {
+    @metadata {
+        Uses: WriteFile, Audit;
+    }
+    return backup(main(argc));
+}
+"#
+        ));
+        assert!(diagnostics.is_empty(), "{:#?}", diagnostics);
+    }
+
+    #[test]
+    fn custom_permissions_propagate() {
+        let diagnostics = check_program_permissions(
+            r#"
+fn audit(data: Int) -> Int {
+    @metadata {
+        Uses: Audit;
+    }
+    return data;
+}
+
+fn main(argc: Int) -> Int {
+    return audit(argc);
+}
+"#,
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0]
+            .message()
+            .contains("needs the Audit permission"));
+    }
+
+    #[test]
+    fn contract_with_unknown_parameter() {
+        let diagnostics = check(
+            r#"
+fn double(a: Int) -> Int {
+    @contracts {
+        In: (q > 0, "q must be positive")
+    }
+    return a * 2;
+}
+"#,
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].message().starts_with("`q` is not defined"));
+        assert_eq!(diagnostics[0].position().line, 3);
+    }
+
+    fn check_program_contracts(source: &str) -> Vec<Diagnostic> {
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(source);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+        let ast = out.output.unwrap();
+        let mut tables = ParsingTables::new();
+        tables.update(&ast, "test");
+        check_contracts(&ast, "test", &tables)
+    }
+
+    #[test]
+    fn contract_calls_impure_function() {
+        let diagnostics = check_program_contracts(&format!(
+            "{}{}",
+            SAVE,
+            r#"
+fn double(a: Int) -> Int {
+    @contracts {
+        In: (save(a) > 0, "saved")
+        Out: (result > a, "output must grow")
+    }
+    return a * 2;
+}
+"#
+        ));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message(),
+            "contracts must be pure, but `save` needs the WriteFile permission(s)"
+        );
+        assert_eq!(diagnostics[0].position().line, 10);
+    }
+
+    #[test]
+    fn contract_calls_pure_helper() {
+        let diagnostics = check_program_contracts(
+            r#"
+fn positive(x: Int) -> Bool {
+    return x > 0;
+}
+
+fn double(a: Int) -> Int {
+    @contracts {
+        In: (positive(a), "a must be positive")
+    }
+    return a * 2;
+}
+"#,
+        );
+        assert!(diagnostics.is_empty(), "{:#?}", diagnostics);
+    }
+
+    #[test]
+    fn contract_calls_impure_function_through_wrapper() {
+        let diagnostics = check_program_contracts(&format!(
+            "{}{}",
+            SAVE,
+            r#"
+fn checked(x: Int) -> Bool {
+    return save(x) > 0;
+}
+
+fn double(a: Int) -> Int {
+    @contracts {
+        In: (checked(a), "saved")
+    }
+    return a * 2;
+}
+"#
+        ));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message(),
+            "contracts must be pure, but `checked` calls `save`, which needs the WriteFile permission(s)"
+        );
+        assert_eq!(diagnostics[0].position().line, 14);
+    }
+
+    #[test]
+    fn valid_contracts() {
+        let diagnostics = check(
+            r#"
+fn double(a: Int, b: Int) -> Int {
+    @contracts {
+        In: (a > 0, "a must be positive")
+        In: (b > a, "b must be bigger than a")
+        Out: (result > a, "output must grow")
+    }
+    return a * b;
+}
+"#,
+        );
+        assert!(diagnostics.is_empty(), "{:#?}", diagnostics);
+    }
+
+    fn check_program_returns(program: &str) -> Vec<Diagnostic> {
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(program);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+        check_returns(&out.output.unwrap())
+    }
+
+    #[test]
+    fn missing_return_in_branch() {
+        let diagnostics = check_program_returns(
+            r#"
+fn sign(a: Int) -> Int {
+    if a > 0 {
+        return 1;
+    } elif a < 0 {
+        return 0 - 1;
+    }
+}
+"#,
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(*diagnostics[0].level(), IssueLevel::Error);
+        assert_eq!(
+            diagnostics[0].message(),
+            "`sign` returns Int, but not every path through it ends in a `return`"
+        );
+        assert_eq!(diagnostics[0].position().line, 1);
+    }
+
+    #[test]
+    fn if_else_returns_on_every_path() {
+        let diagnostics = check_program_returns(
+            r#"
+fn sign(a: Int) -> Int {
+    if a > 0 {
+        return 1;
+    } else {
+        return 0;
+    }
+}
+
+fn log(a: Int) -> Void {
+    if a > 0 {
+        print(a);
+    }
+}
+"#,
+        );
+        assert!(diagnostics.is_empty(), "{:#?}", diagnostics);
+    }
+
+    #[test]
+    fn code_after_return() {
+        let diagnostics = check_program_returns(
+            r#"
+fn double(a: Int) -> Int {
+    return a * 2;
+    print(a);
+}
+
+fn log(a: Int) -> Void {
+    if a > 0 {
+        return print(a);
+        print(a);
+    }
+}
+"#,
+        );
+        assert_eq!(diagnostics.len(), 2);
+        for diagnostic in diagnostics.iter() {
+            assert_eq!(*diagnostic.level(), IssueLevel::Warning);
+            assert_eq!(
+                diagnostic.message(),
+                "unreachable code, every path above has already returned"
+            );
+        }
+        assert_eq!(diagnostics[0].position().line, 3);
+        assert_eq!(diagnostics[1].position().line, 9);
+    }
+
+    fn check_positions(program: &str) -> Vec<Diagnostic> {
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(program);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+        check_type_positions(&out.output.unwrap())
+    }
+
+    #[test]
+    fn invalid_struct_fields() {
+        let diagnostics = check_positions(
+            r#"
+struct Pen {
+    nothing: Void,
+    anything: Auto,
+    boxes: Array<Void>
+
+    @metadata {
+        Is: Public;
+    }
+}
+"#,
+        );
+        let messages = diagnostics
+            .iter()
+            .map(|d| d.message())
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            messages,
+            vec![
+                "struct field `nothing` of `Pen` can't have type Void",
+                "struct field `anything` of `Pen` needs an explicit type, it can't be Auto",
+                "Array<Void> can't contain Void",
+            ]
+        );
+        for diagnostic in diagnostics.iter() {
+            assert_eq!(diagnostic.kind(), DiagnosticKind::InvalidType);
+            assert_eq!(diagnostic.position().line, 1);
+        }
+    }
+
+    #[test]
+    fn invalid_function_types() {
+        let diagnostics = check_positions(
+            r#"
+fn empty(a: Void) -> Array<Void> {
+    let b: Void;
+    let c: Map<String, Shared<Void>> = a;
+    return a;
+}
+"#,
+        );
+        let found = diagnostics
+            .iter()
+            .map(|d| (d.message(), d.position().line))
+            .collect::<Vec<(&str, usize)>>();
+        assert_eq!(
+            found,
+            vec![
+                ("parameter `a` of `empty` can't have type Void", 1),
+                ("Array<Void> can't contain Void", 1),
+                ("a variable can't have type Void", 2),
+                ("Shared<Void> can't contain Void", 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_enum_payloads() {
+        let diagnostics = check_positions(
+            r#"
+enum Status {
+    Alive,
+    Dead,
+    Haunting: Array<Void>
+
+    @metadata {
+        Is: Public;
+    }
+}
+"#,
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message(), "Array<Void> can't contain Void");
+    }
+
+    #[test]
+    fn valid_type_positions() {
+        let diagnostics = check_positions(
+            r#"
+enum Status {
+    Alive,
+    Dead,
+    Aging: Int
+
+    @metadata {
+        Is: Public;
+    }
+}
+
+fn log(a: Array<Int>) -> Void {
+    let b: Auto = a;
+    print(b);
+}
+"#,
+        );
+        assert!(diagnostics.is_empty(), "{:#?}", diagnostics);
+    }
+
+    fn check_program_generics(program: &str) -> Vec<Diagnostic> {
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(program);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+        check_generics(&out.output.unwrap())
+    }
+
+    #[test]
+    fn undeclared_type_parameters() {
+        let diagnostics = check_program_generics(
+            r#"
+struct Pair<T> {
+    left: Generic<T>,
+    right: Generic<U>
+
+    @metadata {
+        Is: Public;
+    }
+}
+
+fn first(items: Array<Generic<T>>) -> Generic<T> {
+    return items[0];
+}
+"#,
+        );
+        let found = diagnostics
+            .iter()
+            .map(|d| (d.message(), d.position().line))
+            .collect::<Vec<(&str, usize)>>();
+        assert_eq!(
+            found,
+            vec![
+                (
+                    "`U` isn't a type parameter of `Pair` (did you mean `T`?)",
+                    1
+                ),
+                (
+                    "`T` isn't a type parameter of `first`, declare it like `first<T>`",
+                    10
+                ),
+            ]
+        );
+        for diagnostic in diagnostics.iter() {
+            assert_eq!(diagnostic.kind(), DiagnosticKind::UndeclaredTypeParameter);
+        }
+    }
+
+    #[test]
+    fn unused_type_parameters() {
+        let diagnostics = check_program_generics(
+            r#"
+struct Crate<T, W> {
+    contents: Generic<T>
+
+    @metadata {
+        Is: Public;
+    }
+}
+
+fn count<T>(items: Array<Int>) -> Int {
+    return 0;
+}
+"#,
+        );
+        let messages = diagnostics
+            .iter()
+            .map(|d| d.message())
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            messages,
+            vec![
+                "type parameter `W` of `Crate` is never used",
+                "type parameter `T` of `count` is never used",
+            ]
+        );
+        for diagnostic in diagnostics.iter() {
+            assert_eq!(*diagnostic.level(), IssueLevel::Warning);
+        }
+    }
+
+    #[test]
+    fn declared_type_parameters() {
+        let diagnostics = check_program_generics(
+            r#"
+struct Wrapper<T> {
+    inner: Int
+
+    @metadata {
+        Is: Public;
+    }
+
+    fn wrap(self, value: Generic<T>) -> Int {
+        return 0;
+    }
+}
+
+fn largest<T>(items: Array<Generic<T>>) -> Int {
+    let best: Generic<T> = items[0];
+    return 0;
+}
+"#,
+        );
+        assert!(diagnostics.is_empty(), "{:#?}", diagnostics);
+    }
+
+    fn check_program_derives(program: &str) -> Vec<Diagnostic> {
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(program);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+        let ast = out.output.unwrap();
+        let mut tables = ParsingTables::new();
+        tables.update(&ast, "test");
+        check_derives(&ast, &tables)
+    }
+
+    #[test]
+    fn nested_derives() {
+        let diagnostics = check_program_derives(
+            r#"
+struct Animal {
+    legs: Int
+
+    @metadata {
+        Is: Public;
+        Derives: Eq, Show;
+    }
+}
+
+struct Pen {
+    animals: Array<Animal>,
+    names: Map<String, Int>,
+    size: Int
+
+    @metadata {
+        Is: Public;
+        Derives: Eq, Show;
+    }
+}
+"#,
+        );
+        assert!(diagnostics.is_empty(), "{:#?}", diagnostics);
+    }
+
+    #[test]
+    fn derive_missing_inner_trait() {
+        let diagnostics = check_program_derives(
+            r#"
+struct Animal {
+    legs: Int
+
+    @metadata {
+        Is: Public;
+        Derives: Eq;
+    }
+}
+
+struct Pen {
+    animals: Array<Animal>
+
+    @metadata {
+        Is: Public;
+        Derives: Eq, Show;
+    }
+}
+"#,
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].kind(), DiagnosticKind::UnderivableTrait);
+        assert_eq!(
+            diagnostics[0].message(),
+            "`Pen` can't derive Show: field `animals` uses `Animal`, which doesn't derive Show"
+        );
+        assert_eq!(diagnostics[0].position().line, 10);
+        assert_eq!(diagnostics[0].references()[0].line, 1);
+    }
+
+    #[test]
+    fn derive_eq_with_non_eq_field() {
+        let diagnostics = check_program_derives(
+            r#"
+struct Animal {
+    legs: Int
+
+    @metadata {
+        Is: Public;
+        Derives: Show;
+    }
+}
+
+struct Pen {
+    size: Int,
+    resident: Animal
+
+    @metadata {
+        Is: Public;
+        Derives: Eq;
+    }
+}
+"#,
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(*diagnostics[0].level(), IssueLevel::Error);
+        assert_eq!(
+            diagnostics[0].message(),
+            "`Pen` can't derive Eq: field `resident` uses `Animal`, which doesn't derive Eq"
+        );
+    }
+
+    #[test]
+    fn derive_with_generic_field() {
+        let diagnostics = check_program_derives(
+            r#"
+enum Maybe<T> {
+    Some: Generic<T>,
+    None,
+
+    @metadata {
+        Is: Public;
+        Derives: Eq;
+    }
+}
+"#,
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message(),
+            "`Maybe` can't derive Eq: field `Some` uses `Generic<T>`, which might not support Eq"
+        );
+        assert!(diagnostics[0].references().is_empty());
+    }
+
+    #[test]
+    fn unreachable_function() {
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(
+            r#"
+fn main(argc: Int) -> Int {
+    return helper(argc);
+}
+
+fn helper(n: Int) -> Int {
+    return n;
+}
+
+fn orphan(n: Int) -> Int {
+    return helper(n);
+}
+
+@allow(unused)
+fn spare(n: Int) -> Int {
+    return n;
+}
+"#,
+        );
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+        let ast = out.output.unwrap();
+        let mut tables = ParsingTables::new();
+        tables.update(&ast, "test");
+        let diagnostics = check_dead_functions(&ast, "test", &tables);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].kind(), DiagnosticKind::DeadCode);
+        assert_eq!(
+            diagnostics[0].message(),
+            "function `orphan` is never called from `main` or an exported function"
+        );
+        assert_eq!(diagnostics[0].position().line, 9);
+    }
+
+    #[test]
+    fn negative_index() {
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(
+            r#"
+fn main(xs: Array<Int>, i: Int) -> Int {
+    let first: Int = xs[0];
+    let last: Int = xs[-1];
+    let before: Int = xs[2 - 3];
+    return xs[i];
+}
+
+@allow(unchecked_index)
+fn nth(xs: Array<Int>, i: Int) -> Int {
+    return xs[i];
+}
+"#,
+        );
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+        let diagnostics = check_indices(&out.output.unwrap());
+        assert_eq!(diagnostics.len(), 3, "{:#?}", diagnostics);
+        assert_eq!(diagnostics[0].kind(), DiagnosticKind::NegativeIndex);
+        assert_eq!(*diagnostics[0].level(), IssueLevel::Error);
+        assert_eq!(
+            diagnostics[0].message(),
+            "index `-1` is negative, arrays start at 0"
+        );
+        assert_eq!(diagnostics[0].position().line, 3);
+        assert_eq!(
+            diagnostics[1].message(),
+            "index `2 - 3` is always -1, arrays start at 0"
+        );
+        assert_eq!(diagnostics[2].kind(), DiagnosticKind::UncheckedIndex);
+        assert_eq!(*diagnostics[2].level(), IssueLevel::Lint);
+        assert_eq!(
+            diagnostics[2].message(),
+            "index `i` isn't checked against the length of `xs`, consider `xs.get(i)` instead"
+        );
+    }
+
+    fn field_names(program: &str) -> Vec<Diagnostic> {
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(program);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+        check_field_names(&out.output.unwrap())
+    }
+
+    #[test]
+    fn duplicate_struct_fields() {
+        let diagnostics = field_names(
+            r#"
+struct Point {
+    x: Int,
+    y: Int,
+    x: Float
+
+    @metadata {
+        Is: Public;
+    }
+}
+"#,
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].kind(), DiagnosticKind::DuplicateDefinition);
+        assert_eq!(
+            diagnostics[0].message(),
+            "field `x` is declared more than once in struct `Point`"
+        );
+        assert_eq!(diagnostics[0].position().line, 4);
+        assert_eq!(diagnostics[0].references()[0].line, 2);
+    }
+
+    #[test]
+    fn duplicate_enum_variants() {
+        let diagnostics = field_names(
+            r#"
+enum Shape {
+    Circle: Float,
+    Square,
+    Circle,
+
+    @metadata {
+        Is: Public;
+    }
+}
+"#,
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message(),
+            "variant `Circle` is declared more than once in enum `Shape`"
+        );
+        assert_eq!(diagnostics[0].position().line, 4);
+        assert_eq!(diagnostics[0].references()[0].line, 2);
+    }
+
+    #[test]
+    fn duplicate_parameters() {
+        let diagnostics = field_names(
+            r#"
+fn add(a: Int, b: Int, a: Int) -> Int {
+    return a + b;
+}
+"#,
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message(),
+            "parameter `a` is declared more than once in `add`"
+        );
+        assert_eq!(diagnostics[0].position().column, 23);
+        assert_eq!(diagnostics[0].references()[0].column, 7);
+    }
+
+    #[test]
+    fn unique_field_names() {
+        let diagnostics = field_names(
+            r#"
+struct Point {
+    x: Int,
+    y: Int
+
+    @metadata {
+        Is: Public;
+    }
+}
+
+fn add(x: Int, y: Int) -> Int {
+    return x + y;
+}
+"#,
+        );
+        assert!(diagnostics.is_empty(), "{:#?}", diagnostics);
+    }
+
+    fn entry_point(program: &str) -> Vec<Diagnostic> {
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(program);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+        check_entry_point(&out.output.unwrap(), "test.iona")
+    }
+
+    #[test]
+    fn missing_main() {
+        let diagnostics = entry_point(
+            r#"
+fn helper(a: Int) -> Int {
+    return a;
+}
+"#,
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].kind(), DiagnosticKind::InvalidEntryPoint);
+        assert!(diagnostics[0]
+            .message()
+            .starts_with("no `main` function found"));
+        assert_eq!(diagnostics[0].position().filename, "test.iona");
+    }
+
+    #[test]
+    fn main_with_wrong_signature() {
+        let diagnostics = entry_point(
+            r#"
+fn main(a: Int, b: Int, c: Int) -> Float {
+    return 1.0;
+}
+"#,
+        );
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(
+            diagnostics[0].message(),
+            "`main` takes (a: Int, b: Int, c: Int), but it should take nothing or a single Array<String>"
+        );
+        assert_eq!(
+            diagnostics[1].message(),
+            "`main` returns Float, but it should return Void or Int"
+        );
+        assert_eq!(diagnostics[0].position().line, 1);
+    }
+
+    #[test]
+    fn accepted_main_signatures() {
+        let diagnostics = entry_point(
+            r#"
+fn main() -> Int {
+    return 0;
+}
+"#,
+        );
+        assert!(diagnostics.is_empty(), "{:#?}", diagnostics);
+        let diagnostics = entry_point(
+            r#"
+fn main(args: Array<String>) -> Void {
+    print(args);
+}
+"#,
+        );
+        assert!(diagnostics.is_empty(), "{:#?}", diagnostics);
+    }
+}
@@ -0,0 +1,1095 @@
+//! Keep parsed ASTs on disk, so files that haven't changed skip lexing and parsing on the next run
+//!
+//! Each file gets one entry in the cache directory, named after a hash of its path. The entry starts with a header (the format version, the compiler version and a hash of the source it was parsed from), followed by the AST in a small binary format
+//!
+//! Anything wrong with an entry (missing, stale, truncated, written by another version) is treated as a miss, and the file is parsed as normal
+
+use std::cell::Cell;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::expression_parser::{BinaryOperator, Expr, UnaryOperator};
+use crate::lexer::SourcePosition;
+use crate::parser::*;
+
+/// Where the cache lives, relative to where the compiler is run
+pub const CACHE_DIR: &str = ".iona-cache";
+
+/// Bump this whenever the encoding (or the shape of the AST) changes, so old entries are ignored
+const FORMAT_VERSION: u32 = 3;
+
+const MAGIC: &[u8; 8] = b"IONA-AST";
+
+/// 64-bit FNV-1a, which is stable across runs and Rust versions (unlike `DefaultHasher`)
+fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// The on-disk AST cache, counting hits and misses so callers (and tests) can see whether it's doing anything
+#[derive(Debug)]
+pub struct AstCache {
+    pub dir: PathBuf,
+    hits: Cell<usize>,
+    misses: Cell<usize>,
+}
+
+impl AstCache {
+    pub fn new(dir: &Path) -> AstCache {
+        AstCache {
+            dir: dir.to_path_buf(),
+            hits: Cell::new(0),
+            misses: Cell::new(0),
+        }
+    }
+
+    pub fn hits(&self) -> usize {
+        self.hits.get()
+    }
+
+    pub fn misses(&self) -> usize {
+        self.misses.get()
+    }
+
+    /// The entry for a file, the contents don't matter since the header records which version it holds
+    fn entry(&self, filepath: &Path) -> PathBuf {
+        self.dir.join(format!(
+            "{:016x}.ast",
+            hash(filepath.to_string_lossy().as_bytes())
+        ))
+    }
+
+    fn header(source: &str) -> Vec<u8> {
+        let mut header = MAGIC.to_vec();
+        FORMAT_VERSION.encode(&mut header);
+        env!("CARGO_PKG_VERSION").to_string().encode(&mut header);
+        hash(source.as_bytes()).encode(&mut header);
+        header
+    }
+
+    /// The cached AST for `source`, if there's a usable one
+    pub fn load(&self, filepath: &Path, source: &str) -> Option<Vec<ASTNode>> {
+        let ast = fs::read(self.entry(filepath)).ok().and_then(|bytes| {
+            let mut input = bytes.strip_prefix(Self::header(source).as_slice())?;
+            let ast = Vec::<ASTNode>::decode(&mut input)?;
+            input.is_empty().then_some(ast)
+        });
+        let counter = if ast.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.set(counter.get() + 1);
+        ast
+    }
+
+    /// Save the AST parsed from `source`
+    ///
+    /// The cache is only ever an optimization, so failing to write it isn't an error
+    pub fn store(&self, filepath: &Path, source: &str, ast: &[ASTNode]) {
+        let mut bytes = Self::header(source);
+        ast.encode(&mut bytes);
+        let _ = fs::create_dir_all(&self.dir).and_then(|_| fs::write(self.entry(filepath), bytes));
+    }
+}
+
+// -------------------- Encoding --------------------
+
+/// Write a value as bytes
+///
+/// Enums write the index of their variant as a byte, then the variant's fields in order. Lengths and integers are little-endian
+trait Encode {
+    fn encode(&self, out: &mut Vec<u8>);
+}
+
+/// Read a value written by `Encode`, or `None` if the bytes don't hold one
+trait Decode: Sized {
+    fn decode(input: &mut &[u8]) -> Option<Self>;
+}
+
+/// Split `n` bytes off the front of the input
+fn take<'a>(input: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+    if input.len() < n {
+        return None;
+    }
+    let (front, rest) = input.split_at(n);
+    *input = rest;
+    Some(front)
+}
+
+fn tag(input: &mut &[u8]) -> Option<u8> {
+    take(input, 1).map(|bytes| bytes[0])
+}
+
+impl Encode for u8 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(*self);
+    }
+}
+
+impl Encode for u32 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Encode for u64 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Decode for u64 {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        Some(u64::from_le_bytes(take(input, 8)?.try_into().ok()?))
+    }
+}
+
+impl Encode for usize {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (*self as u64).encode(out);
+    }
+}
+
+impl Decode for usize {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        u64::decode(input)?.try_into().ok()
+    }
+}
+
+impl Encode for i64 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Decode for i64 {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        Some(i64::from_le_bytes(take(input, 8)?.try_into().ok()?))
+    }
+}
+
+impl Encode for f64 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.to_bits().encode(out);
+    }
+}
+
+impl Decode for f64 {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        u64::decode(input).map(f64::from_bits)
+    }
+}
+
+impl Encode for bool {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (*self as u8).encode(out);
+    }
+}
+
+impl Decode for bool {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        match tag(input)? {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.len().encode(out);
+        out.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl Decode for String {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        let len = usize::decode(input)?;
+        String::from_utf8(take(input, len)?.to_vec()).ok()
+    }
+}
+
+impl<T: Encode> Encode for [T] {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.len().encode(out);
+        for item in self {
+            item.encode(out);
+        }
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.as_slice().encode(out);
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        let len = usize::decode(input)?;
+        // Every item takes at least a byte, so a corrupt length can't make us allocate more than the input
+        let mut items = Vec::with_capacity(len.min(input.len()));
+        for _ in 0..len {
+            items.push(T::decode(input)?);
+        }
+        Some(items)
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            None => 0u8.encode(out),
+            Some(value) => {
+                1u8.encode(out);
+                value.encode(out);
+            }
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        match tag(input)? {
+            0 => Some(None),
+            1 => Some(Some(T::decode(input)?)),
+            _ => None,
+        }
+    }
+}
+
+impl<T: Encode> Encode for Box<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.as_ref().encode(out);
+    }
+}
+
+impl<T: Decode> Decode for Box<T> {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        T::decode(input).map(Box::new)
+    }
+}
+
+impl Encode for SourcePosition {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.filename.encode(out);
+        self.line.encode(out);
+        self.column.encode(out);
+    }
+}
+
+impl Decode for SourcePosition {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        Some(SourcePosition {
+            filename: String::decode(input)?,
+            line: usize::decode(input)?,
+            column: usize::decode(input)?,
+        })
+    }
+}
+
+impl Encode for Type {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Type::Void => 0u8.encode(out),
+            Type::Self_ => 1u8.encode(out),
+            Type::Integer => 2u8.encode(out),
+            Type::Float => 3u8.encode(out),
+            Type::String => 4u8.encode(out),
+            Type::Boolean => 5u8.encode(out),
+            Type::Size => 6u8.encode(out),
+            Type::Byte => 7u8.encode(out),
+            Type::Auto => 8u8.encode(out),
+            Type::CType => 9u8.encode(out),
+            Type::Array(inner) => {
+                10u8.encode(out);
+                inner.encode(out);
+            }
+            Type::Map(inner) => {
+                11u8.encode(out);
+                inner.encode(out);
+            }
+            Type::Shared(inner) => {
+                12u8.encode(out);
+                inner.encode(out);
+            }
+            Type::Tuple(members) => {
+                13u8.encode(out);
+                members.encode(out);
+            }
+            Type::FixedArray(inner, len) => {
+                14u8.encode(out);
+                inner.encode(out);
+                len.encode(out);
+            }
+            Type::Generic(name) => {
+                15u8.encode(out);
+                name.encode(out);
+            }
+            Type::Custom(name) => {
+                16u8.encode(out);
+                name.encode(out);
+            }
+            Type::Optional(inner) => {
+                17u8.encode(out);
+                inner.encode(out);
+            }
+        }
+    }
+}
+
+impl Decode for Type {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        Some(match tag(input)? {
+            0 => Type::Void,
+            1 => Type::Self_,
+            2 => Type::Integer,
+            3 => Type::Float,
+            4 => Type::String,
+            5 => Type::Boolean,
+            6 => Type::Size,
+            7 => Type::Byte,
+            8 => Type::Auto,
+            9 => Type::CType,
+            10 => Type::Array(Box::decode(input)?),
+            11 => Type::Map(Box::decode(input)?),
+            12 => Type::Shared(Box::decode(input)?),
+            13 => Type::Tuple(Vec::decode(input)?),
+            14 => Type::FixedArray(Box::decode(input)?, usize::decode(input)?),
+            15 => Type::Generic(String::decode(input)?),
+            16 => Type::Custom(String::decode(input)?),
+            17 => Type::Optional(Box::decode(input)?),
+            _ => return None,
+        })
+    }
+}
+
+impl Encode for DataProperties {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            DataProperties::Public => 0u8.encode(out),
+            DataProperties::Export => 1u8.encode(out),
+        }
+    }
+}
+
+impl Decode for DataProperties {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        match tag(input)? {
+            0 => Some(DataProperties::Public),
+            1 => Some(DataProperties::Export),
+            _ => None,
+        }
+    }
+}
+
+impl Encode for DataTraits {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            DataTraits::Eq => 0u8.encode(out),
+            DataTraits::Show => 1u8.encode(out),
+        }
+    }
+}
+
+impl Decode for DataTraits {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        match tag(input)? {
+            0 => Some(DataTraits::Eq),
+            1 => Some(DataTraits::Show),
+            _ => None,
+        }
+    }
+}
+
+impl Encode for Field {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.name.encode(out);
+        self.field_type.encode(out);
+        self.pos.encode(out);
+    }
+}
+
+impl Decode for Field {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        Some(Field {
+            name: String::decode(input)?,
+            field_type: Type::decode(input)?,
+            pos: SourcePosition::decode(input)?,
+        })
+    }
+}
+
+impl Encode for Attribute {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Attribute::Cfg { key, value } => {
+                0u8.encode(out);
+                key.encode(out);
+                value.encode(out);
+            }
+            Attribute::Allow(lints) => {
+                1u8.encode(out);
+                lints.encode(out);
+            }
+        }
+    }
+}
+
+impl Decode for Attribute {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        match tag(input)? {
+            0 => Some(Attribute::Cfg {
+                key: String::decode(input)?,
+                value: String::decode(input)?,
+            }),
+            1 => Some(Attribute::Allow(Vec::decode(input)?)),
+            _ => None,
+        }
+    }
+}
+
+impl Encode for Struct {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.name.encode(out);
+        self.type_params.encode(out);
+        self.fields.encode(out);
+        self.properties.encode(out);
+        self.traits.encode(out);
+        self.methods.encode(out);
+        self.attributes.encode(out);
+        self.pos.encode(out);
+    }
+}
+
+impl Decode for Struct {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        Some(Struct {
+            name: String::decode(input)?,
+            type_params: Vec::decode(input)?,
+            fields: Vec::decode(input)?,
+            properties: Vec::decode(input)?,
+            traits: Vec::decode(input)?,
+            methods: Vec::decode(input)?,
+            attributes: Vec::decode(input)?,
+            pos: SourcePosition::decode(input)?,
+        })
+    }
+}
+
+impl Encode for Enum {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.name.encode(out);
+        self.type_params.encode(out);
+        self.fields.encode(out);
+        self.properties.encode(out);
+        self.traits.encode(out);
+        self.methods.encode(out);
+        self.attributes.encode(out);
+        self.pos.encode(out);
+    }
+}
+
+impl Decode for Enum {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        Some(Enum {
+            name: String::decode(input)?,
+            type_params: Vec::decode(input)?,
+            fields: Vec::decode(input)?,
+            properties: Vec::decode(input)?,
+            traits: Vec::decode(input)?,
+            methods: Vec::decode(input)?,
+            attributes: Vec::decode(input)?,
+            pos: SourcePosition::decode(input)?,
+        })
+    }
+}
+
+impl Encode for Import {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.file.encode(out);
+        self.items.encode(out);
+        self.pos.encode(out);
+        self.reexport.encode(out);
+    }
+}
+
+impl Decode for Import {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        Some(Import {
+            file: String::decode(input)?,
+            items: Vec::decode(input)?,
+            pos: SourcePosition::decode(input)?,
+            reexport: bool::decode(input)?,
+        })
+    }
+}
+
+impl Encode for FunctionProperties {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            FunctionProperties::Public => 0u8.encode(out),
+            FunctionProperties::Export => 1u8.encode(out),
+        }
+    }
+}
+
+impl Decode for FunctionProperties {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        match tag(input)? {
+            0 => Some(FunctionProperties::Public),
+            1 => Some(FunctionProperties::Export),
+            _ => None,
+        }
+    }
+}
+
+impl Encode for FunctionPermissions {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            FunctionPermissions::ReadFile => 0u8.encode(out),
+            FunctionPermissions::WriteFile => 1u8.encode(out),
+            FunctionPermissions::ReadConsole => 2u8.encode(out),
+            FunctionPermissions::WriteConsole => 3u8.encode(out),
+            FunctionPermissions::HTTPAny => 4u8.encode(out),
+            FunctionPermissions::HTTPGet => 5u8.encode(out),
+            FunctionPermissions::HTTPPost => 6u8.encode(out),
+            FunctionPermissions::Custom(name) => {
+                7u8.encode(out);
+                name.encode(out);
+            }
+        }
+    }
+}
+
+impl Decode for FunctionPermissions {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        Some(match tag(input)? {
+            0 => FunctionPermissions::ReadFile,
+            1 => FunctionPermissions::WriteFile,
+            2 => FunctionPermissions::ReadConsole,
+            3 => FunctionPermissions::WriteConsole,
+            4 => FunctionPermissions::HTTPAny,
+            5 => FunctionPermissions::HTTPGet,
+            6 => FunctionPermissions::HTTPPost,
+            7 => FunctionPermissions::Custom(String::decode(input)?),
+            _ => return None,
+        })
+    }
+}
+
+impl Encode for ContractType {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            ContractType::Input => 0u8.encode(out),
+            ContractType::Output => 1u8.encode(out),
+        }
+    }
+}
+
+impl Decode for ContractType {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        match tag(input)? {
+            0 => Some(ContractType::Input),
+            1 => Some(ContractType::Output),
+            _ => None,
+        }
+    }
+}
+
+impl Encode for FunctionContract {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.type_.encode(out);
+        self.condition.encode(out);
+        self.message.encode(out);
+        self.pos.encode(out);
+    }
+}
+
+impl Decode for FunctionContract {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        Some(FunctionContract {
+            type_: ContractType::decode(input)?,
+            condition: Expr::decode(input)?,
+            message: String::decode(input)?,
+            pos: SourcePosition::decode(input)?,
+        })
+    }
+}
+
+impl Encode for Function {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.name.encode(out);
+        self.pos.encode(out);
+        self.type_params.encode(out);
+        self.args.encode(out);
+        self.returns.encode(out);
+        self.properties.encode(out);
+        self.permissions.encode(out);
+        self.contracts.encode(out);
+        self.statements.encode(out);
+        self.attributes.encode(out);
+        self.is_extern.encode(out);
+    }
+}
+
+impl Decode for Function {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        Some(Function {
+            name: String::decode(input)?,
+            pos: SourcePosition::decode(input)?,
+            type_params: Vec::decode(input)?,
+            args: Vec::decode(input)?,
+            returns: Type::decode(input)?,
+            properties: Vec::decode(input)?,
+            permissions: Vec::decode(input)?,
+            contracts: Vec::decode(input)?,
+            statements: Vec::decode(input)?,
+            attributes: Vec::decode(input)?,
+            is_extern: bool::decode(input)?,
+        })
+    }
+}
+
+impl Encode for ModuleMetadata {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.permissions.encode(out);
+        self.pos.encode(out);
+    }
+}
+
+impl Decode for ModuleMetadata {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        Some(ModuleMetadata {
+            permissions: Vec::decode(input)?,
+            pos: SourcePosition::decode(input)?,
+        })
+    }
+}
+
+impl Encode for ASTNode {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            ASTNode::StructDeclaration(s) => {
+                0u8.encode(out);
+                s.encode(out);
+            }
+            ASTNode::EnumDeclaration(e) => {
+                1u8.encode(out);
+                e.encode(out);
+            }
+            ASTNode::ImportStatement(i) => {
+                2u8.encode(out);
+                i.encode(out);
+            }
+            ASTNode::FunctionDeclaration(f) => {
+                3u8.encode(out);
+                f.encode(out);
+            }
+            ASTNode::ModuleMetadata(m) => {
+                4u8.encode(out);
+                m.encode(out);
+            }
+        }
+    }
+}
+
+impl Decode for ASTNode {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        Some(match tag(input)? {
+            0 => ASTNode::StructDeclaration(Struct::decode(input)?),
+            1 => ASTNode::EnumDeclaration(Enum::decode(input)?),
+            2 => ASTNode::ImportStatement(Import::decode(input)?),
+            3 => ASTNode::FunctionDeclaration(Function::decode(input)?),
+            4 => ASTNode::ModuleMetadata(ModuleMetadata::decode(input)?),
+            _ => return None,
+        })
+    }
+}
+
+impl Encode for Branch {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.condition.encode(out);
+        self.computations.encode(out);
+        self.pos.encode(out);
+    }
+}
+
+impl Decode for Branch {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        Some(Branch {
+            condition: Option::decode(input)?,
+            computations: Vec::decode(input)?,
+            pos: SourcePosition::decode(input)?,
+        })
+    }
+}
+
+impl Encode for Pattern {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Pattern::Variant { name, binding } => {
+                0u8.encode(out);
+                name.encode(out);
+                binding.encode(out);
+            }
+        }
+    }
+}
+
+impl Decode for Pattern {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        match tag(input)? {
+            0 => Some(Pattern::Variant {
+                name: String::decode(input)?,
+                binding: Option::decode(input)?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl Encode for Statement {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Statement::FunctionCall { call, pos } => {
+                0u8.encode(out);
+                call.encode(out);
+                pos.encode(out);
+            }
+            Statement::VariableDeclaration {
+                name,
+                type_,
+                value,
+                pos,
+            } => {
+                1u8.encode(out);
+                name.encode(out);
+                type_.encode(out);
+                value.encode(out);
+                pos.encode(out);
+            }
+            Statement::VariableMutation { name, value, pos } => {
+                2u8.encode(out);
+                name.encode(out);
+                value.encode(out);
+                pos.encode(out);
+            }
+            Statement::Conditional { branches, pos } => {
+                3u8.encode(out);
+                branches.encode(out);
+                pos.encode(out);
+            }
+            Statement::Match {
+                scrutinee,
+                branches,
+                pos,
+            } => {
+                4u8.encode(out);
+                scrutinee.encode(out);
+                branches.encode(out);
+                pos.encode(out);
+            }
+            Statement::WhileLoop {
+                binding,
+                condition,
+                body,
+                pos,
+            } => {
+                5u8.encode(out);
+                binding.encode(out);
+                condition.encode(out);
+                body.encode(out);
+                pos.encode(out);
+            }
+            Statement::Return { value, pos } => {
+                6u8.encode(out);
+                value.encode(out);
+                pos.encode(out);
+            }
+            Statement::Assert { cond, message, pos } => {
+                7u8.encode(out);
+                cond.encode(out);
+                message.encode(out);
+                pos.encode(out);
+            }
+        }
+    }
+}
+
+impl Decode for Statement {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        Some(match tag(input)? {
+            0 => Statement::FunctionCall {
+                call: Expr::decode(input)?,
+                pos: SourcePosition::decode(input)?,
+            },
+            1 => Statement::VariableDeclaration {
+                name: String::decode(input)?,
+                type_: Type::decode(input)?,
+                value: Option::decode(input)?,
+                pos: SourcePosition::decode(input)?,
+            },
+            2 => Statement::VariableMutation {
+                name: String::decode(input)?,
+                value: Expr::decode(input)?,
+                pos: SourcePosition::decode(input)?,
+            },
+            3 => Statement::Conditional {
+                branches: Vec::decode(input)?,
+                pos: SourcePosition::decode(input)?,
+            },
+            4 => Statement::Match {
+                scrutinee: Expr::decode(input)?,
+                branches: Vec::decode(input)?,
+                pos: SourcePosition::decode(input)?,
+            },
+            5 => Statement::WhileLoop {
+                binding: Option::decode(input)?,
+                condition: Expr::decode(input)?,
+                body: Vec::decode(input)?,
+                pos: SourcePosition::decode(input)?,
+            },
+            6 => Statement::Return {
+                value: Expr::decode(input)?,
+                pos: SourcePosition::decode(input)?,
+            },
+            7 => Statement::Assert {
+                cond: Expr::decode(input)?,
+                message: Option::decode(input)?,
+                pos: SourcePosition::decode(input)?,
+            },
+            _ => return None,
+        })
+    }
+}
+
+impl Encode for BinaryOperator {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let index: u8 = match self {
+            BinaryOperator::Add => 0,
+            BinaryOperator::Subtract => 1,
+            BinaryOperator::Multiply => 2,
+            BinaryOperator::Divide => 3,
+            BinaryOperator::Modulo => 4,
+            BinaryOperator::LessThan => 5,
+            BinaryOperator::GreaterThan => 6,
+            BinaryOperator::And => 7,
+            BinaryOperator::Or => 8,
+        };
+        index.encode(out);
+    }
+}
+
+impl Decode for BinaryOperator {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        Some(match tag(input)? {
+            0 => BinaryOperator::Add,
+            1 => BinaryOperator::Subtract,
+            2 => BinaryOperator::Multiply,
+            3 => BinaryOperator::Divide,
+            4 => BinaryOperator::Modulo,
+            5 => BinaryOperator::LessThan,
+            6 => BinaryOperator::GreaterThan,
+            7 => BinaryOperator::And,
+            8 => BinaryOperator::Or,
+            _ => return None,
+        })
+    }
+}
+
+impl Encode for UnaryOperator {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            UnaryOperator::Negate => 0u8.encode(out),
+        }
+    }
+}
+
+impl Decode for UnaryOperator {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        match tag(input)? {
+            0 => Some(UnaryOperator::Negate),
+            _ => None,
+        }
+    }
+}
+
+impl Encode for Expr {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Expr::IntegerLiteral(value) => {
+                0u8.encode(out);
+                value.encode(out);
+            }
+            Expr::FloatLiteral(value) => {
+                1u8.encode(out);
+                value.encode(out);
+            }
+            Expr::StringLiteral(value) => {
+                2u8.encode(out);
+                value.encode(out);
+            }
+            Expr::Variable(name) => {
+                3u8.encode(out);
+                name.encode(out);
+            }
+            Expr::PropertyAccess { object, property } => {
+                4u8.encode(out);
+                object.encode(out);
+                property.encode(out);
+            }
+            Expr::EnumVariant { enum_name, variant } => {
+                5u8.encode(out);
+                enum_name.encode(out);
+                variant.encode(out);
+            }
+            Expr::FunctionCall { name, arguments } => {
+                6u8.encode(out);
+                name.encode(out);
+                arguments.encode(out);
+            }
+            Expr::MethodCall {
+                object,
+                method,
+                arguments,
+            } => {
+                7u8.encode(out);
+                object.encode(out);
+                method.encode(out);
+                arguments.encode(out);
+            }
+            Expr::BinaryOp {
+                left,
+                operator,
+                right,
+            } => {
+                8u8.encode(out);
+                left.encode(out);
+                operator.encode(out);
+                right.encode(out);
+            }
+            Expr::UnaryOp { operator, operand } => {
+                9u8.encode(out);
+                operator.encode(out);
+                operand.encode(out);
+            }
+            Expr::IndexAccess { object, index } => {
+                10u8.encode(out);
+                object.encode(out);
+                index.encode(out);
+            }
+            Expr::IfExpr { cond, then, els } => {
+                11u8.encode(out);
+                cond.encode(out);
+                then.encode(out);
+                els.encode(out);
+            }
+        }
+    }
+}
+
+impl Decode for Expr {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        Some(match tag(input)? {
+            0 => Expr::IntegerLiteral(i64::decode(input)?),
+            1 => Expr::FloatLiteral(f64::decode(input)?),
+            2 => Expr::StringLiteral(String::decode(input)?),
+            3 => Expr::Variable(String::decode(input)?),
+            4 => Expr::PropertyAccess {
+                object: Box::decode(input)?,
+                property: String::decode(input)?,
+            },
+            5 => Expr::EnumVariant {
+                enum_name: String::decode(input)?,
+                variant: String::decode(input)?,
+            },
+            6 => Expr::FunctionCall {
+                name: String::decode(input)?,
+                arguments: Vec::decode(input)?,
+            },
+            7 => Expr::MethodCall {
+                object: Box::decode(input)?,
+                method: String::decode(input)?,
+                arguments: Vec::decode(input)?,
+            },
+            8 => Expr::BinaryOp {
+                left: Box::decode(input)?,
+                operator: BinaryOperator::decode(input)?,
+                right: Box::decode(input)?,
+            },
+            9 => Expr::UnaryOp {
+                operator: UnaryOperator::decode(input)?,
+                operand: Box::decode(input)?,
+            },
+            10 => Expr::IndexAccess {
+                object: Box::decode(input)?,
+                index: Box::decode(input)?,
+            },
+            11 => Expr::IfExpr {
+                cond: Box::decode(input)?,
+                then: Box::decode(input)?,
+                els: Box::decode(input)?,
+            },
+            _ => return None,
+        })
+    }
+}
+
+// -------------------- Unit Tests --------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn parse(filename: &str, source: &str) -> Vec<ASTNode> {
+        let mut lexer = Lexer::new(filename);
+        lexer.lex(source);
+        let mut parser = Parser::new(lexer.token_stream);
+        parser.parse_all().output.unwrap()
+    }
+
+    #[test]
+    fn round_trip_test_programs() {
+        for entry in fs::read_dir("test_programs").unwrap() {
+            let path = entry.unwrap().path();
+            let source = fs::read_to_string(&path).unwrap();
+            let ast = parse(&path.to_string_lossy(), &source);
+            let mut bytes = Vec::new();
+            ast.encode(&mut bytes);
+            let mut input = bytes.as_slice();
+            assert_eq!(Vec::<ASTNode>::decode(&mut input), Some(ast), "{:?}", path);
+            assert!(input.is_empty());
+        }
+    }
+
+    #[test]
+    fn reject_bad_entries() {
+        let dir = std::env::temp_dir().join(format!("iona_cache_{}", std::process::id()));
+        let cache = AstCache::new(&dir);
+        let file = Path::new("main.iona");
+        let source = "fn main() -> Int {\n    return 0;\n}\n";
+        cache.store(file, source, &parse("main.iona", source));
+        assert!(cache.load(file, source).is_some());
+        // A different source is stale
+        assert!(cache
+            .load(file, "fn main() -> Int {\n    return 1;\n}\n")
+            .is_none());
+        // So is one cut short or written by another format version
+        let entry = cache.entry(file);
+        let bytes = fs::read(&entry).unwrap();
+        fs::write(&entry, &bytes[..bytes.len() - 3]).unwrap();
+        assert!(cache.load(file, source).is_none());
+        let mut other_version = bytes.clone();
+        other_version[MAGIC.len()] += 1;
+        fs::write(&entry, other_version).unwrap();
+        assert!(cache.load(file, source).is_none());
+        assert_eq!((cache.hits(), cache.misses()), (1, 3));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
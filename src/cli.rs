@@ -1,7 +1,11 @@
 //! Command line interface for the compiler
 
+use std::env;
 use std::error::Error;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::diagnostics::{LintLevel, LintLevels, LINT_NAMES};
 
 /// What mode should the compiler be run on?
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -23,40 +27,173 @@ pub enum Target {
 pub enum Flags {
     SingleFile,
     Verbose,
+    DumpTypeTable,
+    EmitDeps,
+    /// Print the generated C to stdout instead of writing `gen/test_case.c`
+    EmitCFragment,
+    /// Compile the entrypoint and everything it imports into one C file
+    SingleUnit,
+    /// The entrypoint is a library, so it doesn't need a `main`
+    Library,
+    /// Load the ASTs of files that haven't changed from `.iona-cache/`, instead of parsing them again
+    Incremental,
+    /// Print every module's AST as JSON instead of compiling, for editors and other tools
+    EmitAst,
+    /// Print the entrypoint's tokens and stop before parsing, for debugging the lexer
+    EmitTokens,
+    /// Print `--emit=tokens` as JSON instead of one token per line
+    Json,
+    /// Print `--emit=deps` as a Graphviz DOT graph instead of Makefile rules
+    Dot,
+    /// Keep running, and check or build again whenever a watched file changes
+    Watch,
 }
 
 /// Encapsulate the various options into a single command
+///
+/// `platform` is the operating system we're building for (set with `--target=<os>`), which defaults to the host
+///
+/// `lints` holds the levels set with `--allow=<lint>`, `--lint=<lint>`, `--warn=<lint>` and `--deny=<lint>`, where a later flag for the same lint wins
+///
+/// `include_dirs` are extra directories to look for imported modules in (set with `--include=<dir>`), searched in the order they were given
+///
+/// `output` is where `--emit=ast` and `--emit=tokens` write to instead of stdout (set with `-o <file>`)
+///
+/// `contracts` is whether the generated C checks contracts at runtime (set with `--contracts=on|off`). They're on for `check` and `test`, but off for `build`
+///
+/// `watch_interval` is how often `--watch` looks for changes (set in milliseconds with `--watch-interval=<ms>`)
 pub struct Command {
     pub mode: Mode,
     pub target: Target,
     pub flags: Vec<Flags>,
+    pub platform: String,
+    pub lints: LintLevels,
+    pub include_dirs: Vec<PathBuf>,
+    pub output: Option<PathBuf>,
+    pub contracts: bool,
+    pub watch_interval: Duration,
+}
+
+/// How often `--watch` looks for changes, unless `--watch-interval` says otherwise
+const DEFAULT_WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Shorter spellings accepted for some lints, and the lint each one stands for
+const LINT_ALIASES: [(&str, &str); 1] = [("unused_vars", "unused_variables")];
+
+/// Parse the value of a lint level flag, like the `unused-imports,dead-code` in `--deny=unused-imports,dead-code`
+///
+/// Dashes and underscores are interchangeable, so `unused-imports` is the lint `unused_imports`
+fn parse_lint_names(names: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    names
+        .split(',')
+        .map(|name| {
+            let name = name.replace('-', "_");
+            let name = match LINT_ALIASES.iter().find(|(alias, _)| *alias == name) {
+                Some((_, lint)) => lint.to_string(),
+                None => name,
+            };
+            if LINT_NAMES.contains(&name.as_str()) {
+                Ok(name)
+            } else {
+                Err(format!(
+                    "unknown lint `{}`, the lints are {}",
+                    name,
+                    LINT_NAMES.join(", ")
+                )
+                .into())
+            }
+        })
+        .collect()
 }
 
 /// Parse the command line string into a single command
-pub fn parse_args(args: &Vec<String>) -> Result<Command, Box<dyn Error>> {
+pub fn parse_args(args: &[String]) -> Result<Command, Box<dyn Error>> {
     if args.len() < 2 {
         return Err("you must pass at least 1 argument to the compiler".into());
     }
     // Arg 1 is compiler mode
-    let mode: Mode;
-    match args[1].as_str() {
-        "build" => mode = Mode::Build,
-        "check" => mode = Mode::Check,
-        "test" => mode = Mode::Test,
+    let mode: Mode = match args[1].as_str() {
+        "build" => Mode::Build,
+        "check" => Mode::Check,
+        "test" => Mode::Test,
         _ => unreachable!("compiler must be invoked in 'build', 'check', or 'test' mode"),
-    }
+    };
     // Args 2+ is flags and target
     if args.len() >= 2 {
         let mut flags: Vec<Flags> = Vec::new();
         let mut maybe_target: Option<Target> = None;
-        for arg in args.iter().skip(1) {
-            if arg.starts_with("-") {
+        let mut platform = env::consts::OS.to_string();
+        let mut lints = LintLevels::new();
+        let mut include_dirs: Vec<PathBuf> = Vec::new();
+        let mut output: Option<PathBuf> = None;
+        let mut contracts = mode != Mode::Build;
+        let mut watch_interval = DEFAULT_WATCH_INTERVAL;
+        let mut remaining = args.iter().skip(1);
+        while let Some(arg) = remaining.next() {
+            let lint_flag = [
+                ("--allow=", LintLevel::Allow),
+                ("--lint=", LintLevel::Lint),
+                ("--warn=", LintLevel::Warn),
+                ("--deny=", LintLevel::Deny),
+            ]
+            .into_iter()
+            .find_map(|(prefix, level)| arg.strip_prefix(prefix).map(|names| (names, level)));
+            if let Some(os) = arg.strip_prefix("--target=") {
+                platform = os.to_string();
+            } else if let Some(setting) = arg.strip_prefix("--contracts=") {
+                contracts = match setting {
+                    "on" => true,
+                    "off" => false,
+                    _ => {
+                        return Err(format!(
+                            "`--contracts` is either `on` or `off`, not `{}`",
+                            setting
+                        )
+                        .into())
+                    }
+                };
+            } else if let Some(ms) = arg.strip_prefix("--watch-interval=") {
+                watch_interval = match ms.parse() {
+                    Ok(ms) => Duration::from_millis(ms),
+                    Err(_) => {
+                        return Err(format!(
+                            "`--watch-interval` is a number of milliseconds, not `{}`",
+                            ms
+                        )
+                        .into())
+                    }
+                };
+            } else if let Some(dir) = arg.strip_prefix("--include=") {
+                include_dirs.push(PathBuf::from(dir));
+            } else if arg == "-o" {
+                match remaining.next() {
+                    Some(file) => output = Some(PathBuf::from(file)),
+                    None => return Err("`-o` needs a file to write to, like `-o ast.json`".into()),
+                }
+            } else if let Some((names, level)) = lint_flag {
+                for name in parse_lint_names(names)? {
+                    lints.insert(name, level);
+                }
+            } else if arg.starts_with("-") {
                 flags.push(match arg.as_str() {
                     "-v" => Flags::Verbose,
                     "--verbose" => Flags::Verbose,
                     "-f" => Flags::SingleFile,
                     "--file" => Flags::SingleFile,
-                    _ => unreachable!("the only supported compiler flags are -v and -f"),
+                    "--dump-type-table" => Flags::DumpTypeTable,
+                    "--emit=deps" => Flags::EmitDeps,
+                    "--emit=c-fragment" => Flags::EmitCFragment,
+                    "--emit=ast" => Flags::EmitAst,
+                    "--emit=tokens" => Flags::EmitTokens,
+                    "--json" => Flags::Json,
+                    "--dot" => Flags::Dot,
+                    "--single-unit" => Flags::SingleUnit,
+                    "--lib" => Flags::Library,
+                    "--incremental" => Flags::Incremental,
+                    "--watch" => Flags::Watch,
+                    _ => unreachable!(
+                        "the only supported compiler flags are -v, -f, --dump-type-table, --emit=deps, --emit=c-fragment, --emit=ast, --emit=tokens, --json, --dot, --single-unit, --lib, --incremental, --watch, --watch-interval=<ms>, --allow=<lint>, --lint=<lint>, --warn=<lint>, --deny=<lint>, --include=<dir>, --contracts=on|off, -o <file>, and --target=<os>"
+                    ),
                 });
             } else {
                 if arg.ends_with(".iona") {
@@ -66,17 +203,128 @@ pub fn parse_args(args: &Vec<String>) -> Result<Command, Box<dyn Error>> {
                 }
             }
         }
-        return Ok(Command {
+        Ok(Command {
             mode,
             target: maybe_target.unwrap_or(Target::Entrypoint(Path::new("main.iona").into())),
             flags,
-        });
+            platform,
+            lints,
+            include_dirs,
+            output,
+            contracts,
+            watch_interval,
+        })
     } else {
         let target: Target = Target::Entrypoint(Path::new("main.iona").into());
-        return Ok(Command {
+        Ok(Command {
             mode,
             target,
             flags: Vec::new(),
-        });
+            platform: env::consts::OS.to_string(),
+            lints: LintLevels::new(),
+            include_dirs: Vec::new(),
+            output: None,
+            contracts: mode != Mode::Build,
+            watch_interval: DEFAULT_WATCH_INTERVAL,
+        })
+    }
+}
+
+// -------------------- Unit Tests --------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split(' ').map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_lint_levels() {
+        let command = parse_args(&args(
+            "iona build main.iona --deny=unused-imports --allow=unused_variables,dead-code --warn=unused --deny=unused-vars --lint=unchecked-index",
+        ))
+        .unwrap();
+        assert_eq!(
+            command.lints,
+            LintLevels::from([
+                ("unused_imports".to_string(), LintLevel::Deny),
+                // The later flag wins, even when it uses the `unused-vars` alias
+                ("unused_variables".to_string(), LintLevel::Deny),
+                ("dead_code".to_string(), LintLevel::Allow),
+                ("unused".to_string(), LintLevel::Warn),
+                ("unchecked_index".to_string(), LintLevel::Lint),
+            ])
+        );
+        assert!(command.flags.is_empty());
+    }
+
+    #[test]
+    fn reject_unknown_lint() {
+        let error = parse_args(&args("iona build main.iona --deny=unused-vals"))
+            .err()
+            .unwrap();
+        assert!(error
+            .to_string()
+            .starts_with("unknown lint `unused_vals`, the lints are unused, "));
+    }
+
+    #[test]
+    fn parse_include_dirs() {
+        let command = parse_args(&args(
+            "iona build game/main.iona --include=vendor --include=../shared -v",
+        ))
+        .unwrap();
+        assert_eq!(
+            command.include_dirs,
+            vec![PathBuf::from("vendor"), PathBuf::from("../shared")]
+        );
+        assert_eq!(command.flags, vec![Flags::Verbose]);
+        assert_eq!(
+            command.target,
+            Target::Entrypoint(Path::new("game/main.iona").into())
+        );
+    }
+
+    #[test]
+    fn parse_output_file() {
+        let command =
+            parse_args(&args("iona check main.iona --emit=ast -o out/main.iona")).unwrap();
+        assert_eq!(command.flags, vec![Flags::EmitAst]);
+        // The file after `-o` isn't mistaken for the entrypoint, even though it looks like one
+        assert_eq!(command.output, Some(PathBuf::from("out/main.iona")));
+        assert_eq!(
+            command.target,
+            Target::Entrypoint(Path::new("main.iona").into())
+        );
+        assert!(parse_args(&args("iona check main.iona --emit=ast -o")).is_err());
+    }
+
+    #[test]
+    fn parse_contracts() {
+        assert!(!parse_args(&args("iona build main.iona")).unwrap().contracts);
+        assert!(parse_args(&args("iona check main.iona")).unwrap().contracts);
+        assert!(parse_args(&args("iona test main.iona")).unwrap().contracts);
+        let command = parse_args(&args("iona build main.iona --contracts=on")).unwrap();
+        assert!(command.contracts);
+        assert!(command.flags.is_empty());
+        assert!(
+            !parse_args(&args("iona test main.iona --contracts=off"))
+                .unwrap()
+                .contracts
+        );
+        assert!(parse_args(&args("iona build main.iona --contracts=maybe")).is_err());
+    }
+
+    #[test]
+    fn parse_watch() {
+        let command = parse_args(&args("iona check main.iona --watch")).unwrap();
+        assert_eq!(command.flags, vec![Flags::Watch]);
+        assert_eq!(command.watch_interval, DEFAULT_WATCH_INTERVAL);
+        let command =
+            parse_args(&args("iona check main.iona --watch --watch-interval=50")).unwrap();
+        assert_eq!(command.watch_interval, Duration::from_millis(50));
+        assert!(parse_args(&args("iona check main.iona --watch-interval=soon")).is_err());
     }
 }
@@ -3,21 +3,50 @@
 //! Note: we don't lift the type writing into a function because it's somewhat context dependent (ex. strings cannot have Void types but Enums can)
 
 use std::borrow::Cow;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::iter::zip;
 
 use crate::aggregation::TypeTable;
+use crate::analysis;
+use crate::expression_parser::{BinaryOperator, Expr, UnaryOperator};
+use crate::lexer::SourcePosition;
 use crate::parser::*;
 
 // -------------------- Monomorphization Templates --------------------
 
 /// Load a C header template for monomorphization
 pub fn load_c_template(template_name: &str) -> String {
-    fs::read_to_string(format!("c_libs/templates/{}", template_name)).expect(&format!(
-        "could not find template for {}, are the c_libs missing? (check for /c_libs/templates/{})",
-        template_name, template_name
-    ))
+    fs::read_to_string(format!("c_libs/templates/{}", template_name)).unwrap_or_else(|_| {
+        panic!(
+            "could not find template for {}, are the c_libs missing? (check for /c_libs/templates/{})",
+            template_name, template_name
+        )
+    })
+}
+
+/// Templates read so far, so each one only comes off the disk once per run
+///
+/// `load` is normally `load_c_template`, but can be swapped out in tests
+pub struct TemplateCache<F: FnMut(&str) -> String> {
+    load: F,
+    templates: HashMap<String, String>,
+}
+
+impl<F: FnMut(&str) -> String> TemplateCache<F> {
+    pub fn new(load: F) -> TemplateCache<F> {
+        TemplateCache {
+            load,
+            templates: HashMap::new(),
+        }
+    }
+
+    fn get(&mut self, template_name: &str) -> &str {
+        let load = &mut self.load;
+        self.templates
+            .entry(template_name.to_string())
+            .or_insert_with(|| load(template_name))
+    }
 }
 
 /// A concrete, monomorphized type
@@ -56,12 +85,12 @@ fn monomorphize_array_template(
     let elem_type = c_type;
     let prefix = type_method_prefix;
     // TODO: support nested types, this will require a loop and/or recursion
-    let imports = match type_to_std_lib(&inner_type) {
+    let imports = match type_to_std_lib(inner_type) {
         Some(t) => &format!("#include \"{}\"\n", t),
         None => "",
     };
     template
-        .replace("ARRAY_NAME", &array_type_name)
+        .replace("ARRAY_NAME", array_type_name)
         .replace("ELEM_TYPE", elem_type)
         .replace("PREFIX", prefix)
         .replace("<OTHER_IMPORTS>", imports)
@@ -71,25 +100,41 @@ fn monomorphize_array_template(
 fn boxed_type_name(type_: &Type) -> String {
     match type_ {
         Type::Array(inner) => format!("{}Array", boxed_type_name(inner)),
+        Type::Optional(inner) => format!("Optional_{}", boxed_type_name(inner)),
+        // Tuples are named by their members, like `Tuple_Integer_String`
+        Type::Tuple(members) => format!(
+            "Tuple_{}",
+            members
+                .iter()
+                .map(boxed_type_name)
+                .collect::<Vec<String>>()
+                .join("_")
+        ),
+        // Fixed arrays are named by their length too, like `IntegerArray4`
+        Type::FixedArray(inner, size) => format!("{}Array{}", boxed_type_name(inner), size),
         _ => write_fn_arg_type(type_).to_string(),
     }
 }
 
+/// The prefix of the functions generated for an array of `inner`, like `integer_array` in `integer_array_get`
+fn array_method_prefix(inner: &Type) -> String {
+    format!("{}_array", write_fn_arg_type(inner).to_lowercase())
+}
+
 impl MonomorphizedArray {
-    fn new(type_: &Type) -> MonomorphizedArray {
-        let template = load_c_template("array.h");
+    fn new(type_: &Type, template: &str) -> MonomorphizedArray {
         let header_file = monomorphize_array_template(
             type_,
-            &template,
+            template,
             &format!("{}Array", write_fn_arg_type(type_)),
-            &format!("{}_array", write_fn_arg_type(type_).to_lowercase()),
+            &array_method_prefix(type_),
             &write_fn_arg_type(type_),
         );
         let header_name: String =
             format!("gen_{}_array.h", write_fn_arg_type(type_).to_lowercase());
         MonomorphizedArray {
             type_: type_.clone(),
-            name: write_fn_arg_type(&type_).to_string(),
+            name: write_fn_arg_type(type_).to_string(),
             header_file,
             header_name,
         }
@@ -114,15 +159,216 @@ impl TemplateInstance for MonomorphizedArray {
     }
 }
 
+/// A C struct standing in for a tuple type
+///
+/// Each distinct tuple shape gets its own header so it's emitted once per program, no matter how many modules use it
+struct TupleTypedef {
+    type_: Type,
+    name: String,
+    header_file: String,
+    header_name: String,
+}
+
+impl TupleTypedef {
+    fn new(type_: &Type) -> TupleTypedef {
+        let members = match type_ {
+            Type::Tuple(members) => members,
+            other => panic!("cannot create a tuple typedef for {:?}", other),
+        };
+        let name = boxed_type_name(type_);
+        // Tuple headers can be included by several other headers, so they need a guard
+        let mut header_file = "#pragma once\n\n".to_string();
+        let mut imports = HashSet::new();
+        for member in members.iter() {
+            if let Some(lib) = type_to_std_lib(member) {
+                if imports.insert(lib.clone()) {
+                    if lib.starts_with('<') {
+                        header_file.push_str(&format!("#include {}\n", lib));
+                    } else {
+                        header_file.push_str(&format!("#include \"{}\"\n", lib));
+                    }
+                }
+            }
+        }
+        header_file.push_str("\ntypedef struct {\n");
+        for (i, member) in members.iter().enumerate() {
+            header_file.push_str(&format!("\t{} _{};\n", write_fn_arg_type(member), i));
+        }
+        header_file.push_str(&format!("}} {};\n", name));
+        TupleTypedef {
+            type_: type_.clone(),
+            header_name: format!("gen_{}.h", name.to_lowercase()),
+            name,
+            header_file,
+        }
+    }
+}
+
+/// An optional is a flag saying whether there's a value, next to where the value would be
+struct OptionalTypedef {
+    type_: Type,
+    name: String,
+    header_file: String,
+    header_name: String,
+}
+
+impl OptionalTypedef {
+    fn new(type_: &Type) -> OptionalTypedef {
+        let inner = match type_ {
+            Type::Optional(inner) => inner,
+            other => panic!("cannot create an optional typedef for {:?}", other),
+        };
+        let name = boxed_type_name(type_);
+        let mut header_file = "#pragma once\n\n#include <stdbool.h>\n".to_string();
+        match type_to_std_lib(inner).as_deref() {
+            None | Some("<stdbool.h>") => {}
+            Some(lib) if lib.starts_with('<') => {
+                header_file.push_str(&format!("#include {}\n", lib))
+            }
+            Some(lib) => header_file.push_str(&format!("#include \"{}\"\n", lib)),
+        }
+        header_file.push_str(&format!(
+            "\ntypedef struct {{\n\tbool has_value;\n\t{} value;\n}} {};\n",
+            write_fn_arg_type(inner),
+            name
+        ));
+        OptionalTypedef {
+            type_: type_.clone(),
+            header_name: format!("gen_{}.h", name.to_lowercase()),
+            name,
+            header_file,
+        }
+    }
+}
+
+impl TemplateInstance for OptionalTypedef {
+    fn get_type(&self) -> &Type {
+        &self.type_
+    }
+
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    fn get_header_file(&self) -> &str {
+        &self.header_file
+    }
+
+    fn get_header_name(&self) -> &str {
+        &self.header_name
+    }
+}
+
+impl TemplateInstance for TupleTypedef {
+    fn get_type(&self) -> &Type {
+        &self.type_
+    }
+
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    fn get_header_file(&self) -> &str {
+        &self.header_file
+    }
+
+    fn get_header_name(&self) -> &str {
+        &self.header_name
+    }
+}
+
+/// A fixed array wrapped in a struct, so it can be passed to and returned from functions by value
+///
+/// Indexing goes through `.data`, the same as for a heap-backed array
+struct FixedArrayTypedef {
+    type_: Type,
+    name: String,
+    header_file: String,
+    header_name: String,
+}
+
+impl FixedArrayTypedef {
+    fn new(type_: &Type) -> FixedArrayTypedef {
+        let (inner, size) = match type_ {
+            Type::FixedArray(inner, size) => (inner, size),
+            other => panic!("cannot create a fixed array typedef for {:?}", other),
+        };
+        let name = boxed_type_name(type_);
+        let mut header_file = "#pragma once\n\n".to_string();
+        match type_to_std_lib(inner) {
+            None => {}
+            Some(lib) if lib.starts_with('<') => {
+                header_file.push_str(&format!("#include {}\n", lib))
+            }
+            Some(lib) => header_file.push_str(&format!("#include \"{}\"\n", lib)),
+        }
+        header_file.push_str(&format!(
+            "\ntypedef struct {{\n\t{} data[{}];\n}} {};\n",
+            write_fn_arg_type(inner),
+            size,
+            name
+        ));
+        FixedArrayTypedef {
+            type_: type_.clone(),
+            header_name: format!("gen_{}.h", name.to_lowercase()),
+            name,
+            header_file,
+        }
+    }
+}
+
+impl TemplateInstance for FixedArrayTypedef {
+    fn get_type(&self) -> &Type {
+        &self.type_
+    }
+
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    fn get_header_file(&self) -> &str {
+        &self.header_file
+    }
+
+    fn get_header_name(&self) -> &str {
+        &self.header_name
+    }
+}
+
 // -------------------- Programmatic C Code --------------------
 
 pub fn generate_templated_libs(type_table: &TypeTable) -> Vec<Box<dyn TemplateInstance>> {
+    generate_templated_libs_with(type_table, &mut TemplateCache::new(load_c_template))
+}
+
+/// Generate the monomorphized libraries, reading templates through `templates`
+pub fn generate_templated_libs_with<F: FnMut(&str) -> String>(
+    type_table: &TypeTable,
+    templates: &mut TemplateCache<F>,
+) -> Vec<Box<dyn TemplateInstance>> {
     let mut generated_libs: Vec<Box<dyn TemplateInstance>> = Vec::new();
 
     fn collect_array_types(t: &Type, set: &mut HashSet<Type>) {
-        if let Type::Array(inner) = t {
-            set.insert(t.clone());
-            collect_array_types(inner, set);
+        match t {
+            Type::Array(inner) => {
+                set.insert(t.clone());
+                collect_array_types(inner, set);
+            }
+            Type::Tuple(members) => {
+                set.insert(t.clone());
+                for member in members.iter() {
+                    collect_array_types(member, set);
+                }
+            }
+            Type::Optional(inner) => {
+                set.insert(t.clone());
+                collect_array_types(inner, set);
+            }
+            Type::FixedArray(inner, _) => {
+                set.insert(t.clone());
+                collect_array_types(inner, set);
+            }
+            _ => {}
         }
     }
 
@@ -132,11 +378,28 @@ pub fn generate_templated_libs(type_table: &TypeTable) -> Vec<Box<dyn TemplateIn
     }
 
     for t in all_array_types {
-        if let Type::Array(inner) = t {
-            let data = MonomorphizedArray::new(&inner);
-            generated_libs.push(Box::new(data));
+        match t {
+            Type::Array(inner) => {
+                let data = MonomorphizedArray::new(&inner, templates.get("array.h"));
+                generated_libs.push(Box::new(data));
+            }
+            Type::Tuple(_) => {
+                let data = TupleTypedef::new(&t);
+                generated_libs.push(Box::new(data));
+            }
+            Type::Optional(_) => {
+                let data = OptionalTypedef::new(&t);
+                generated_libs.push(Box::new(data));
+            }
+            Type::FixedArray(..) => {
+                let data = FixedArrayTypedef::new(&t);
+                generated_libs.push(Box::new(data));
+            }
+            _ => {}
         }
     }
+    // The types came out of a `HashSet`, sort them so the files are always generated in the same order
+    generated_libs.sort_by(|a, b| a.get_header_name().cmp(b.get_header_name()));
 
     generated_libs
 }
@@ -147,17 +410,19 @@ pub fn emit_templated_stdlib_files(generated_libs: &Vec<Box<dyn TemplateInstance
             format!("c_libs/{}", lib.get_header_name()),
             lib.get_header_file(),
         )
-        .expect(&format!(
-            "Unable to write generated header file: {}",
-            lib.get_header_name()
-        ));
+        .unwrap_or_else(|_| {
+            panic!(
+                "Unable to write generated header file: {}",
+                lib.get_header_name()
+            )
+        });
     }
 }
 
 /// Input a type and receive the name of the header file which implements it
 fn type_to_std_lib(type_: &Type) -> Option<String> {
     match type_ {
-        Type::String => Some("gen_strings.h".to_string()),
+        Type::String => Some("strings.h".to_string()),
         Type::Integer | Type::Float => Some("numbers.h".to_string()),
         Type::Byte => Some("bytes.h".to_string()),
         Type::Boolean => Some("<stdbool.h>".to_string()),
@@ -165,58 +430,63 @@ fn type_to_std_lib(type_: &Type) -> Option<String> {
             "gen_{}_array.h",
             write_fn_arg_type(inner).to_lowercase()
         )),
+        Type::Tuple(_) | Type::Optional(_) | Type::FixedArray(..) => {
+            Some(format!("gen_{}.h", boxed_type_name(type_).to_lowercase()))
+        }
         _ => None,
     }
 }
 
 /// Check the Type Table to see which standard libraries we need
+///
+/// Sorted and without duplicates (`Integer` and `Float` share a header), so the output is stable between runs
 fn identify_std_libs(type_table: &TypeTable, filename: &str) -> Vec<String> {
-    let mut pre_existing_lib_names: Vec<String> = Vec::new();
     let relevant_types = type_table
         .types_used_by_module
         .get(filename)
-        .expect(&format!(
-            "creating imports failed for {}, could not find file name in type table\nTable:\n{:?}",
-            filename, type_table.types_used_by_module
-        ));
-    for t in relevant_types.iter() {
-        if let Some(h) = type_to_std_lib(t) {
-            pre_existing_lib_names.push(h);
-        }
+        .unwrap_or_else(|| {
+            panic!(
+                "creating imports failed for {}, could not find file name in type table\nTable:\n{:?}",
+                filename, type_table.types_used_by_module
+            )
+        });
+    let mut pre_existing_lib_names = relevant_types
+        .iter()
+        .filter_map(type_to_std_lib)
+        .collect::<Vec<String>>();
+    if type_table.uses_console(filename) {
+        pre_existing_lib_names.push("console_io.h".to_string());
     }
+    pre_existing_lib_names.sort();
+    pre_existing_lib_names.dedup();
     pre_existing_lib_names
 }
 
 /// Handles import for core libraries
+///
+/// User defined types come from their own modules, see `write_import`
 fn write_header(type_table: &TypeTable, filename: &str, is_stdlib: bool) -> String {
-    let relevant_types = type_table
-        .types_used_by_module
-        .get(filename)
-        .expect(&format!(
-            "creating imports failed for {}, could not find file name in type table\nTable:\n{:?}",
-            filename, type_table.types_used_by_module
-        ));
     let mut buffer = format!("// source: {}\n\n", filename);
-    for (t, i) in zip(relevant_types, identify_std_libs(type_table, filename)) {
-        // If we're creating a stdlib file, then we're all in the same folder
-        if is_stdlib {
+    buffer.push_str(&write_includes(
+        identify_std_libs(type_table, filename),
+        is_stdlib,
+    ));
+    buffer
+}
+
+/// Include each core library once, either from the C standard library or from the generated headers
+fn write_includes(libs: Vec<String>, is_stdlib: bool) -> String {
+    let mut buffer = String::new();
+    for i in libs {
+        if i.starts_with('<') && i.ends_with('>') {
+            // Actual C stdlib
+            buffer.push_str(&format!("#include {}", i));
+        } else if is_stdlib {
+            // If we're creating a stdlib file, then we're all in the same folder
             buffer.push_str(&format!("#include \"{}\"", i));
         } else {
-            // If we're creating a user file, then stdlib files are in a parallel folder and custom files are in this directory
-            match t {
-                Type::Custom(_) => {
-                    buffer.push_str(&format!("#include \"{}\"\n", i));
-                }
-                _ => {
-                    // Actual C stdlib
-                    if i.starts_with('<') && i.ends_with('>') {
-                        buffer.push_str(&format!("#include {}", i));
-                    } else {
-                        // Some C file we wrote
-                        buffer.push_str(&format!("#include \"../c_libs/{}\"", i));
-                    }
-                }
-            }
+            // If we're creating a user file, then stdlib files are in a parallel folder
+            buffer.push_str(&format!("#include \"../c_libs/{}\"", i));
         }
         buffer += "\n";
     }
@@ -238,15 +508,32 @@ fn write_import(input: &Import) -> String {
 fn write_struct(input: &Struct) -> String {
     let mut buffer: String = format!("struct {} {{\n", input.name);
     for field in input.fields.iter() {
+        // `check_type_positions` rejects these before code generation
+        debug_assert!(
+            field.field_type != Type::Void,
+            "struct field {} has type Void",
+            field.name
+        );
         match &field.field_type {
             Type::String => buffer.push_str("\tString"),
             Type::Byte => buffer.push_str("\tByte"),
             Type::Integer => buffer.push_str("\tInteger"),
             Type::Boolean => buffer.push_str("\tbool"),
-            Type::Custom(name) => buffer.push_str(&format!("\t {}", name)),
+            Type::Custom(name) => buffer.push_str(&format!("\t{}", name)),
             Type::Generic(_) => buffer.push_str("\tvoid*"),
-            Type::Array(_) => buffer.push_str(&format!("\t{}", boxed_type_name(&field.field_type))),
-            Type::Void => panic!("A struct cannot have type Void. This error indicates that there is a compiler issue, it should have been caught before code generation."), // this should not be possible
+            Type::Array(_) | Type::Optional(_) => {
+                buffer.push_str(&format!("\t{}", boxed_type_name(&field.field_type)))
+            }
+            // Fixed arrays are stored inline, and C puts the size after the name
+            Type::FixedArray(inner, size) => {
+                buffer.push_str(&format!(
+                    "\t{} {}[{}];\n",
+                    write_fn_arg_type(inner),
+                    field.name,
+                    size
+                ));
+                continue;
+            }
             _ => {
                 println!("WARNING: cannot emit type {:?} yet", &field.field_type);
                 buffer.push_str("\tNOT_IMPLEMENTED");
@@ -265,6 +552,11 @@ fn write_struct(input: &Struct) -> String {
 ///
 /// TODO! Replace generic's use of void pointer with Monomorphization (need a table to track this from call sites)
 fn write_enum(input: &Enum) -> String {
+    write_enum_with_linkage(input, "")
+}
+
+/// Write an enum with its constructors marked by `linkage`, which is `static inline ` in a header so every file including it gets its own copy
+fn write_enum_with_linkage(input: &Enum, linkage: &str) -> String {
     // Create the enum for states
     let mut buffer: String = "typedef enum {\n".to_string();
     for field in input.fields.iter() {
@@ -281,8 +573,10 @@ fn write_enum(input: &Enum) -> String {
             Type::Integer => buffer.push_str("\tInteger"),
             Type::Boolean => buffer.push_str("\tbool"),
             Type::Generic(_) => buffer.push_str("\tvoid*"),
-            Type::Array(_) => buffer.push_str(&format!("\t{}", boxed_type_name(&field.field_type))),
-            Type::Custom(name) => buffer.push_str(&format!("\t {}", name)),
+            Type::Array(_) | Type::Optional(_) => {
+                buffer.push_str(&format!("\t{}", boxed_type_name(&field.field_type)))
+            }
+            Type::Custom(name) => buffer.push_str(&format!("\t{}", name)),
             Type::Void => continue,
             _ => {
                 println!("WARNING: cannot emit type {:#?} yet", &field.field_type);
@@ -299,9 +593,36 @@ fn write_enum(input: &Enum) -> String {
     ));
     // C doesn't mark a struct as a type by default
     buffer.push_str(&format!("typedef struct {} {};", input.name, input.name));
+    // Give each variant a constructor so the tag and data can't get out of sync
+    for field in input.fields.iter() {
+        buffer.push_str("\n\n");
+        buffer.push_str(linkage);
+        buffer.push_str(&write_enum_constructor(&input.name, field));
+    }
     buffer
 }
 
+/// Write a constructor for a single enum variant, like `Pets Pets_Cat(Integer value)`
+fn write_enum_constructor(enum_name: &str, variant: &Field) -> String {
+    let tag = variant.name.to_uppercase();
+    match &variant.field_type {
+        Type::Void => format!(
+            "{} {}_{}(void) {{\n\treturn ({}){{ .tag = {} }};\n}}",
+            enum_name, enum_name, variant.name, enum_name, tag
+        ),
+        payload => format!(
+            "{} {}_{}({} value) {{\n\treturn ({}){{ .tag = {}, .data.{} = value }};\n}}",
+            enum_name,
+            enum_name,
+            variant.name,
+            write_fn_arg_type(payload),
+            enum_name,
+            tag,
+            variant.name
+        ),
+    }
+}
+
 // -------------------- Functions --------------------
 
 fn write_fn_arg_type(input: &Type) -> Cow<'static, str> {
@@ -311,114 +632,2239 @@ fn write_fn_arg_type(input: &Type) -> Cow<'static, str> {
         Type::Integer => Cow::Borrowed("Integer"),
         Type::Float => Cow::Borrowed("Float"),
         Type::Boolean => Cow::Borrowed("bool"),
-        Type::Custom(name) => Cow::Owned(format!("{}", name)),
+        Type::Size => Cow::Borrowed("size_t"),
+        Type::CType => Cow::Borrowed("void*"),
+        Type::Custom(name) => Cow::Owned(name.to_string()),
         Type::Generic(_) => Cow::Borrowed("void*"),
-        Type::Array(_) => Cow::Owned(boxed_type_name(input)),
+        Type::Array(_) | Type::Tuple(_) | Type::Optional(_) | Type::FixedArray(..) => {
+            Cow::Owned(boxed_type_name(input))
+        }
         Type::Void => Cow::Borrowed("void"),
         _ => todo!(),
     }
 }
 
-fn write_fn_declare(input: &Function) -> String {
-    let mut buffer: String = format!("{} {}(", write_fn_arg_type(&input.returns), input.name);
-    for arg in &input.args {
-        buffer += &format!("{} {}, ", write_fn_arg_type(&arg.field_type), arg.name);
-    }
-    // Remove the trailing `, `
-    buffer.pop(); // pop comma
-    buffer.pop(); // pop space
-    buffer.push(')');
-    buffer.push(';');
+/// Write the signature of a function, like `Integer add(Integer a, Integer b)`
+fn write_fn_signature(input: &Function) -> String {
+    let args = input
+        .args
+        .iter()
+        .map(|arg| format!("{} {}", write_fn_arg_type(&arg.field_type), arg.name))
+        .collect::<Vec<String>>();
+    // C wants an explicit `void` for an empty parameter list
+    let args = if args.is_empty() {
+        "void".to_string()
+    } else {
+        args.join(", ")
+    };
+    format!(
+        "{} {}({})",
+        write_fn_arg_type(&input.returns),
+        c_function_name(&input.name),
+        args
+    )
+}
+
+/// The name a function has in C
+///
+/// C's `main` has to take and return plain C types, so the Iona `main` is renamed and called from one that does (see `write_entry_point`)
+fn c_function_name(name: &str) -> &str {
+    match name {
+        "main" => "iona_main",
+        other => other,
+    }
+}
+
+/// Write the C `main`, which hands the command line arguments to the Iona `main` (if it takes them) and returns its exit code
+fn write_entry_point(input: &Function) -> String {
+    let mut buffer = "int main(int argc, char** argv) {\n".to_string();
+    let call = if input.args.is_empty() {
+        buffer.push_str("\t(void)argc;\n\t(void)argv;\n");
+        "iona_main()"
+    } else {
+        // The arguments are borrowed straight from `argv`, so like string literals they have no capacity to free
+        let strings = Type::Array(Box::new(Type::String));
+        let prefix = array_method_prefix(&Type::String);
+        buffer.push_str(&format!(
+            "\t{} args = {}_with_capacity((size_t)argc);\n",
+            write_fn_arg_type(&strings),
+            prefix
+        ));
+        buffer.push_str(&format!(
+            "\tfor (int i = 0; i < argc; i++) {{\n\t\t{}_push(&args, (String){{ .data = {{ .data = (Byte*)argv[i], .len = strlen(argv[i]), .capacity = 0 }} }});\n\t}}\n",
+            prefix
+        ));
+        "iona_main(args)"
+    };
+    match input.returns {
+        Type::Void => buffer.push_str(&format!("\t{};\n", call)),
+        _ => buffer.push_str(&format!(
+            "\t{} status = {};\n",
+            write_fn_arg_type(&input.returns),
+            call
+        )),
+    }
+    if !input.args.is_empty() {
+        buffer.push_str(&format!(
+            "\t{}_free(&args);\n",
+            array_method_prefix(&Type::String)
+        ));
+    }
+    match input.returns {
+        Type::Void => buffer.push_str("\treturn 0;\n}"),
+        _ => buffer.push_str("\treturn (int)status.value;\n}"),
+    }
     buffer
 }
 
-// -------------------- All Together --------------------
+fn write_fn_declare(input: &Function) -> String {
+    format!("{};", write_fn_signature(input))
+}
 
-/// Write an AST to a string
-pub fn write_all<'ast, I>(ast: I, type_table: &TypeTable, filename: &str, is_stdlib: bool) -> String
-where
-    I: Iterator<Item = &'ast ASTNode>,
-{
-    let mut buffer = write_header(type_table, filename, is_stdlib);
-    for node in ast {
-        match node {
-            ASTNode::EnumDeclaration(e) => {
-                buffer.push_str(&write_enum(e));
-                buffer.push_str("\n\n");
-            }
-            ASTNode::StructDeclaration(s) => {
-                buffer.push_str(&write_struct(s));
-                buffer.push_str("\n\n");
-            }
-            ASTNode::ImportStatement(i) => {
-                buffer.push_str(&write_import(i));
-                buffer.push_str("\n\n");
-            }
-            ASTNode::FunctionDeclaration(f) => {
-                buffer.push_str(&write_fn_declare(f));
-            }
+/// Escape text so it can be embedded in a C string literal
+fn escape_c_string(input: &str) -> String {
+    let mut buffer = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '\\' => buffer.push_str("\\\\"),
+            '"' => buffer.push_str("\\\""),
+            '\n' => buffer.push_str("\\n"),
+            '\t' => buffer.push_str("\\t"),
+            other => buffer.push(other),
         }
     }
     buffer
 }
 
-// -------------------- Unit Tests --------------------
+/// Write the check for a single contract
+///
+/// The failure message embeds the source location and the condition as it was written, like
+///
+/// `contract violated at rect.iona:14: "width must be positive" (width > 0)`
+fn write_contract_check(contract: &FunctionContract, indent: usize, types: &ExprTypes) -> String {
+    write_runtime_check(
+        "contract violated",
+        &contract.condition,
+        Some(&contract.message),
+        &contract.pos,
+        indent,
+        types,
+    )
+}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::aggregation::TypeTable;
-    use crate::lexer::Lexer;
-    use crate::parser::Parser;
+/// Write the checks for a group of contracts, which a C compiler drops when `NDEBUG` is defined, like `assert`
+fn write_contract_checks(
+    contracts: Vec<&FunctionContract>,
+    indent: usize,
+    types: &ExprTypes,
+) -> String {
+    if contracts.is_empty() {
+        return String::new();
+    }
+    let mut buffer = "#ifndef NDEBUG\n".to_string();
+    for contract in contracts {
+        buffer.push_str(&write_contract_check(contract, indent, types));
+    }
+    buffer.push_str("#endif\n");
+    buffer
+}
 
-    #[test]
-    fn monomorphize_nested_arrays() {
-        const PROGRAM: &'static str = r#"
-fn main() -> Void {
-    let x: Array<Int>;
-    let y: Array<Array<String>>;
-    let z: Array<Array<Array<Bool>>>;
+fn has_assert(statements: &[Statement]) -> bool {
+    statements.iter().any(|statement| match statement {
+        Statement::Assert { .. } => true,
+        Statement::Conditional { branches, .. } | Statement::Match { branches, .. } => branches
+            .iter()
+            .any(|branch| has_assert(&branch.computations)),
+        Statement::WhileLoop { body, .. } => has_assert(body),
+        _ => false,
+    })
 }
-"#;
-        let mut lexer = Lexer::new("test.iona");
-        lexer.lex(PROGRAM);
-        let mut parser = Parser::new(lexer.token_stream);
-        let out = parser.parse_all();
-        assert!(out.output.is_some());
-        let ast = out.output.unwrap();
 
-        let mut type_table = TypeTable::new();
-        type_table.update(&ast, "test.iona");
+/// Write a check that prints where it failed (and why, if there's a message) to stderr and exits
+fn write_runtime_check(
+    failure: &str,
+    condition: &Expr,
+    message: Option<&str>,
+    pos: &SourcePosition,
+    indent: usize,
+    types: &ExprTypes,
+) -> String {
+    let tabs = "\t".repeat(indent);
+    let reason = match message {
+        Some(message) => format!(
+            "\\\"{}\\\" ({})",
+            escape_c_string(message),
+            escape_c_string(&condition.to_string())
+        ),
+        None => format!("({})", escape_c_string(&condition.to_string())),
+    };
+    // The message is used as a format string, so any literal `%` has to be doubled
+    let message = format!(
+        "{} at {}:{}: {}\\n",
+        failure,
+        escape_c_string(&pos.filename),
+        pos.line + 1,
+        reason
+    )
+    .replace('%', "%%");
+    format!(
+        "{tabs}if (!({})) {{\n{tabs}\tfprintf(stderr, \"{}\");\n{tabs}\texit(EXIT_FAILURE);\n{tabs}}}\n",
+        write_expr(condition, types),
+        message
+    )
+}
 
-        println!("{:#?}", type_table);
+// -------------------- Expressions --------------------
 
-        let generated_libs = generate_templated_libs(&type_table);
+/// What codegen knows about the types of the names in an expression
+///
+/// TODO: locals declared as `Auto` aren't tracked, this should use the type checker's inference instead
+struct ExprTypes<'a> {
+    locals: HashMap<String, Type>,
+    type_table: &'a TypeTable,
+}
 
-        assert_eq!(generated_libs.len(), 6);
-        let names: HashSet<String> = generated_libs
-            .iter()
-            .map(|lib| lib.get_header_name().to_string())
-            .collect();
-        // Check for all expected monomorphizations
-        assert!(names.contains("gen_integer_array.h"));
-        assert!(names.contains("gen_string_array.h"));
-        assert!(names.contains("gen_stringarray_array.h"));
-        assert!(names.contains("gen_bool_array.h"));
-        assert!(names.contains("gen_boolarray_array.h"));
-        assert!(names.contains("gen_boolarrayarray_array.h"));
+impl<'a> ExprTypes<'a> {
+    fn new(type_table: &'a TypeTable) -> ExprTypes<'a> {
+        ExprTypes {
+            locals: HashMap::new(),
+            type_table,
+        }
     }
 
-    #[test]
-    fn boxed_type_naming() {
-        let t1 = Type::Array(Box::new(Type::Integer));
-        assert_eq!(boxed_type_name(&t1), "IntegerArray");
+    /// The element type of an array local, if the expression is one
+    fn array_element(&self, expr: &Expr) -> Option<&Type> {
+        match expr {
+            Expr::Variable(name) => match self.locals.get(name) {
+                Some(Type::Array(inner)) => Some(inner),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
 
-        let t2 = Type::Array(Box::new(Type::Array(Box::new(Type::String))));
-        assert_eq!(boxed_type_name(&t2), "StringArrayArray");
+    /// Is this expression a raw `size_t`, rather than an `Integer`?
+    fn is_size_value(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::Variable(name) => self.locals.get(name) == Some(&Type::Size),
+            Expr::MethodCall {
+                object,
+                method,
+                arguments,
+            } => method == "len" && arguments.is_empty() && self.array_element(object).is_some(),
+            // Arithmetic on a Size stays a Size, comparisons give a Bool
+            Expr::BinaryOp {
+                left,
+                operator,
+                right,
+            } => {
+                !matches!(
+                    operator,
+                    BinaryOperator::LessThan
+                        | BinaryOperator::GreaterThan
+                        | BinaryOperator::And
+                        | BinaryOperator::Or
+                ) && (self.is_size_value(left) || self.is_size_value(right))
+            }
+            _ => false,
+        }
+    }
 
-        let t3 = Type::Array(Box::new(Type::Array(Box::new(Type::Array(Box::new(
+    /// The type inside an optional, if the expression is a local or a call that gives one
+    fn optional_inner(&self, expr: &Expr) -> Option<&Type> {
+        let type_ = match expr {
+            Expr::Variable(name) => self.locals.get(name),
+            Expr::FunctionCall { name, .. } => self.type_table.returns_of(name),
+            _ => None,
+        };
+        match type_ {
+            Some(Type::Optional(inner)) => Some(inner),
+            _ => None,
+        }
+    }
+
+    /// Is this expression a value of an enum type?
+    fn is_enum_value(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::Variable(name) => match self.locals.get(name) {
+                Some(Type::Custom(type_name)) => self.type_table.is_enum(type_name),
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+}
+
+/// Write the raw C number inside of an `Integer` or `Float`, or a `Size` which already is one
+fn write_numeric_value(input: &Expr, types: &ExprTypes) -> String {
+    match input {
+        Expr::IntegerLiteral(n) => n.to_string(),
+        other if types.is_size_value(other) => write_expr(other, types),
+        Expr::FloatLiteral(x) => format!("{:?}", x),
+        Expr::Variable(name) => format!("{}.value", name),
+        other => format!("({}).value", write_expr(other, types)),
+    }
+}
+
+/// Write an expression that's stored somewhere of type `expected`
+///
+/// `none` and `some(x)` don't say which optional they make, so they take it from where they're stored. The same goes for integer literals stored in a `Size` or `Byte`, which are plain C integers
+fn write_value(input: &Expr, expected: &Type, types: &ExprTypes) -> String {
+    match (input, expected) {
+        (Expr::Variable(name), Type::Optional(_)) if name == "none" => {
+            format!("({}){{ .has_value = false }}", write_fn_arg_type(expected))
+        }
+        (Expr::FunctionCall { name, arguments }, Type::Optional(inner))
+            if name == "some" && arguments.len() == 1 =>
+        {
+            format!(
+                "({}){{ .has_value = true, .value = {} }}",
+                write_fn_arg_type(expected),
+                write_value(&arguments[0], inner, types)
+            )
+        }
+        (Expr::IntegerLiteral(n), Type::Size | Type::Byte) => n.to_string(),
+        _ => write_expr(input, types),
+    }
+}
+
+/// Write a string literal as an Iona `String`, with its length worked out now instead of by `strlen` at runtime
+///
+/// The length counts the bytes of the UTF-8 encoding, so non-ASCII characters take more than one. The bytes live in static storage rather than on the heap, which a `capacity` of 0 marks, so nothing may grow or free them in place
+fn write_string_literal(input: &str) -> String {
+    format!(
+        "(String){{ .data = {{ .data = (Byte*)\"{}\", .len = {}, .capacity = 0 }} }}",
+        escape_c_string(input),
+        input.len()
+    )
+}
+
+/// Write an expression to C
+///
+/// TODO: this doesn't know the types of its operands yet, so arithmetic always uses the `Integer` helpers from `numbers.h`
+fn write_expr(input: &Expr, types: &ExprTypes) -> String {
+    match input {
+        Expr::IntegerLiteral(n) => format!("integer_from({})", n),
+        Expr::FloatLiteral(x) => format!("float_from({:?})", x),
+        Expr::StringLiteral(s) => write_string_literal(s),
+        Expr::Variable(name) => name.clone(),
+        // Enum payloads live in the union inside of the tagged union
+        Expr::PropertyAccess { object, property } if types.is_enum_value(object) => {
+            format!("{}.data.{}", write_expr(object, types), property)
+        }
+        Expr::PropertyAccess { object, property } => {
+            format!("{}.{}", write_expr(object, types), property)
+        }
+        // Every variant has a constructor, see `write_enum_constructor`
+        Expr::EnumVariant { enum_name, variant } => format!("{}_{}()", enum_name, variant),
+        // Arguments are stored in the parameters, so they're written like any other value with a known destination
+        Expr::FunctionCall { name, arguments } => {
+            let parameters = types.type_table.parameters_of(name).unwrap_or_default();
+            format!(
+                "{}({})",
+                c_function_name(name),
+                arguments
+                    .iter()
+                    .enumerate()
+                    .map(|(i, arg)| match parameters.get(i) {
+                        Some(parameter) => write_value(arg, parameter, types),
+                        None => write_expr(arg, types),
+                    })
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            )
+        }
+        // The length of an array comes from its monomorphized accessor
+        Expr::MethodCall {
+            object,
+            method,
+            arguments,
+        } if method == "len" && arguments.is_empty() && types.array_element(object).is_some() => {
+            format!(
+                "{}_len(&{})",
+                array_method_prefix(types.array_element(object).unwrap()),
+                write_expr(object, types)
+            )
+        }
+        // TODO: qualify the method name with the type of the object once we have type inference
+        Expr::MethodCall {
+            object,
+            method,
+            arguments,
+        } => {
+            let mut args = vec![write_expr(object, types)];
+            args.extend(arguments.iter().map(|arg| write_expr(arg, types)));
+            format!("{}({})", method, args.join(", "))
+        }
+        Expr::IfExpr { cond, then, els } => format!(
+            "({} ? {} : {})",
+            write_expr(cond, types),
+            write_expr(then, types),
+            write_expr(els, types)
+        ),
+        // A Size is a plain `size_t`, so it uses C's own operators instead of the `Integer` helpers
+        Expr::BinaryOp {
+            left,
+            operator,
+            right,
+        } if !matches!(operator, BinaryOperator::And | BinaryOperator::Or)
+            && (types.is_size_value(left) || types.is_size_value(right)) =>
+        {
+            format!(
+                "({} {} {})",
+                write_numeric_value(left, types),
+                operator,
+                write_numeric_value(right, types)
+            )
+        }
+        Expr::BinaryOp {
+            left,
+            operator,
+            right,
+        } => match operator {
+            BinaryOperator::Add => {
+                format!(
+                    "saturating_add({}, {})",
+                    write_expr(left, types),
+                    write_expr(right, types)
+                )
+            }
+            BinaryOperator::Subtract => {
+                format!(
+                    "saturating_sub({}, {})",
+                    write_expr(left, types),
+                    write_expr(right, types)
+                )
+            }
+            BinaryOperator::Multiply => {
+                format!(
+                    "saturating_mul({}, {})",
+                    write_expr(left, types),
+                    write_expr(right, types)
+                )
+            }
+            BinaryOperator::Divide => {
+                format!(
+                    "saturating_div({}, {})",
+                    write_expr(left, types),
+                    write_expr(right, types)
+                )
+            }
+            BinaryOperator::Modulo => format!(
+                "integer_from({} % {})",
+                write_numeric_value(left, types),
+                write_numeric_value(right, types)
+            ),
+            BinaryOperator::LessThan => format!(
+                "({} < {})",
+                write_numeric_value(left, types),
+                write_numeric_value(right, types)
+            ),
+            BinaryOperator::GreaterThan => format!(
+                "({} > {})",
+                write_numeric_value(left, types),
+                write_numeric_value(right, types)
+            ),
+            BinaryOperator::And => format!(
+                "({} && {})",
+                write_expr(left, types),
+                write_expr(right, types)
+            ),
+            BinaryOperator::Or => format!(
+                "({} || {})",
+                write_expr(left, types),
+                write_expr(right, types)
+            ),
+        },
+        Expr::UnaryOp { operator, operand } => match (operator, operand.as_ref()) {
+            (UnaryOperator::Negate, Expr::IntegerLiteral(n)) => format!("integer_from({})", -n),
+            (UnaryOperator::Negate, Expr::FloatLiteral(x)) => format!("float_from({:?})", -x),
+            (UnaryOperator::Negate, other) => {
+                format!(
+                    "saturating_sub(integer_from(0), {})",
+                    write_expr(other, types)
+                )
+            }
+        },
+        // TODO: use the bounds checked accessor from the array template once we know the array's type
+        Expr::IndexAccess { object, index } if types.is_size_value(index) => format!(
+            "{}.data[{}]",
+            write_expr(object, types),
+            write_expr(index, types)
+        ),
+        Expr::IndexAccess { object, index } => format!(
+            "{}.data[{}]",
+            write_expr(object, types),
+            write_numeric_value(index, types)
+        ),
+    }
+}
+
+// -------------------- Statements --------------------
+
+/// The cleanup function for a heap-backed type, if it needs one
+///
+/// TODO: Map and Shared don't have a C runtime yet, so they aren't freed
+fn free_function(type_: &Type) -> Option<String> {
+    match type_ {
+        Type::String => Some("string_free".to_string()),
+        Type::Array(inner) => Some(format!("{}_free", array_method_prefix(inner))),
+        _ => None,
+    }
+}
+
+/// The types of a function's parameters, plus `result` for its postconditions
+fn fn_arg_types<'a>(function: &Function, type_table: &'a TypeTable) -> ExprTypes<'a> {
+    let mut types = ExprTypes::new(type_table);
+    for arg in function.args.iter() {
+        types
+            .locals
+            .insert(arg.name.clone(), arg.field_type.clone());
+    }
+    types
+        .locals
+        .insert("result".to_string(), function.returns.clone());
+    types
+}
+
+/// A local (or parameter) of the function being written, tracked so each heap-backed value is freed exactly once
+struct Binding {
+    name: String,
+    type_: Type,
+    /// Parameters belong to the caller, so they're never freed here
+    owned: bool,
+    /// The binding whose buffer this one shares, after `let b = a;` or `b = a;`. Only the owner at the end of the chain is freed
+    alias_of: Option<usize>,
+    /// The index in `BodyContext::scopes` of the block it was declared in
+    depth: usize,
+}
+
+/// State threaded through the statements of a single function body
+struct BodyContext<'a> {
+    function: &'a Function,
+    types: ExprTypes<'a>,
+    /// Every binding seen so far, `scopes` refers to them by index
+    bindings: Vec<Binding>,
+    /// The bindings declared in each enclosing block, innermost last. The first holds the parameters
+    scopes: Vec<Vec<usize>>,
+    /// Are the function's contracts checked? See `CodegenOptions`
+    contracts: bool,
+}
+
+impl<'a> BodyContext<'a> {
+    fn new(function: &'a Function, type_table: &'a TypeTable, contracts: bool) -> BodyContext<'a> {
+        let bindings = function
+            .args
+            .iter()
+            .map(|arg| Binding {
+                name: arg.name.clone(),
+                type_: arg.field_type.clone(),
+                owned: false,
+                alias_of: None,
+                depth: 0,
+            })
+            .collect::<Vec<Binding>>();
+        BodyContext {
+            function,
+            types: fn_arg_types(function, type_table),
+            scopes: vec![(0..bindings.len()).collect()],
+            bindings,
+            contracts,
+        }
+    }
+
+    /// Declare a binding in the innermost block, shadowing any earlier one with the same name
+    fn declare(&mut self, name: &str, type_: &Type, alias_of: Option<usize>) {
+        self.bindings.push(Binding {
+            name: name.to_string(),
+            type_: type_.clone(),
+            owned: true,
+            alias_of,
+            depth: self.scopes.len() - 1,
+        });
+        let id = self.bindings.len() - 1;
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push(id);
+        }
+    }
+
+    /// The binding a name refers to at this point, the innermost declaration wins
+    fn lookup(&self, name: &str) -> Option<usize> {
+        self.scopes
+            .iter()
+            .rev()
+            .flat_map(|scope| scope.iter().rev())
+            .copied()
+            .find(|id| self.bindings[*id].name == name)
+    }
+
+    /// The binding that owns the buffer behind `id`, following aliases
+    fn owner(&self, mut id: usize) -> usize {
+        while let Some(next) = self.bindings[id].alias_of {
+            id = next;
+        }
+        id
+    }
+
+    /// The owner of the buffer a heap-backed local holds, if `expr` is just such a local
+    fn heap_owner(&self, expr: &Expr) -> Option<usize> {
+        match expr {
+            Expr::Variable(name) => self
+                .lookup(name)
+                .filter(|id| free_function(&self.bindings[*id].type_).is_some())
+                .map(|id| self.owner(id)),
+            _ => None,
+        }
+    }
+
+    /// Does this binding free its buffer when it goes out of scope?
+    fn frees(&self, id: usize) -> bool {
+        let binding = &self.bindings[id];
+        binding.owned && binding.alias_of.is_none() && free_function(&binding.type_).is_some()
+    }
+
+    /// Write the cleanup calls for the bindings in the given scopes, innermost first
+    ///
+    /// `kept` is the owner of a value that's being returned, which the caller frees instead
+    fn write_frees<'s, I>(&self, scopes: I, kept: Option<usize>, indent: usize) -> String
+    where
+        I: Iterator<Item = &'s Vec<usize>>,
+    {
+        let tabs = "\t".repeat(indent);
+        let mut buffer = String::new();
+        for scope in scopes {
+            for id in scope.iter().rev() {
+                if !self.frees(*id) || kept == Some(*id) {
+                    continue;
+                }
+                let binding = &self.bindings[*id];
+                if let Some(free) = free_function(&binding.type_) {
+                    buffer.push_str(&format!("{}{}(&{});\n", tabs, free, binding.name));
+                }
+            }
+        }
+        buffer
+    }
+
+    /// Assign a new value to a heap-backed local, freeing the value it held unless something else still shares it
+    ///
+    /// Ownership only changes for certain when the assignment is in the same block as the declaration. Inside a nested block it might not run, so nothing is freed that the other path would still need, at the cost of a possible leak
+    fn write_heap_mutation(&mut self, id: usize, value: &Expr, indent: usize) -> String {
+        let tabs = "\t".repeat(indent);
+        let type_ = self.bindings[id].type_.clone();
+        let exact = self.bindings[id].depth == self.scopes.len() - 1;
+        let source = self.heap_owner(value);
+        let written = write_value(value, &type_, &self.types);
+        let name = self.bindings[id].name.clone();
+        let owns = self.frees(id);
+        // Locals that share the old buffer keep it alive
+        let sharing = self
+            .scopes
+            .iter()
+            .flatten()
+            .copied()
+            .filter(|other| *other != id && self.owner(*other) == id)
+            .collect::<Vec<usize>>();
+        let free_old = owns && source != Some(id) && sharing.is_empty();
+        if owns && source != Some(id) && exact {
+            // The first local sharing the old buffer becomes its owner
+            if let Some((first, rest)) = sharing.split_first() {
+                self.bindings[*first].alias_of = None;
+                for other in rest {
+                    self.bindings[*other].alias_of = Some(*first);
+                }
+            }
+        }
+        match source {
+            Some(source) if source != id => self.bindings[id].alias_of = Some(source),
+            Some(_) => {}
+            None if exact && self.bindings[id].owned => self.bindings[id].alias_of = None,
+            None => {}
+        }
+        match free_function(&type_) {
+            // The new value is worked out before the old one is freed, since it might be made from it
+            Some(free) if free_old => format!(
+                "{}{{\n{}\t{} __old = {};\n{}\t{} = {};\n{}\t{}(&__old);\n{}}}\n",
+                tabs,
+                tabs,
+                write_fn_arg_type(&type_),
+                name,
+                tabs,
+                name,
+                written,
+                tabs,
+                free,
+                tabs
+            ),
+            _ => format!("{}{} = {};\n", tabs, name, written),
+        }
+    }
+}
+
+/// Write a block of statements, one per line, at the given indentation level
+///
+/// Heap-backed locals declared in the block are freed when it ends, unless the block already returned
+fn write_block(statements: &[Statement], ctx: &mut BodyContext, indent: usize) -> String {
+    ctx.scopes.push(Vec::new());
+    let mut buffer = statements
+        .iter()
+        .map(|st| write_statement(st, ctx, indent))
+        .collect::<String>();
+    if !matches!(statements.last(), Some(Statement::Return { .. })) {
+        buffer.push_str(&ctx.write_frees(ctx.scopes.last().into_iter(), None, indent));
+    }
+    ctx.scopes.pop();
+    buffer
+}
+
+fn write_statement(input: &Statement, ctx: &mut BodyContext, indent: usize) -> String {
+    let tabs = "\t".repeat(indent);
+    match input {
+        Statement::FunctionCall { call, .. } => {
+            format!("{}{};\n", tabs, write_expr(call, &ctx.types))
+        }
+        Statement::VariableDeclaration {
+            name, type_, value, ..
+        } => {
+            // Let the C compiler work out `Auto` until we do our own inference
+            let c_type = match type_ {
+                Type::Auto => Cow::Borrowed("__auto_type"),
+                other => write_fn_arg_type(other),
+            };
+            // The initializer can't see the variable it's initializing
+            let buffer = match value {
+                Some(value) => format!(
+                    "{}{} {} = {};\n",
+                    tabs,
+                    c_type,
+                    name,
+                    write_value(value, type_, &ctx.types)
+                ),
+                // Heap-backed values are freed at the end of the scope, so they have to start out empty
+                None if free_function(type_).is_some() => {
+                    format!("{}{} {} = {{0}};\n", tabs, c_type, name)
+                }
+                None => format!("{}{} {};\n", tabs, c_type, name),
+            };
+            // `let b = a;` shares `a`'s buffer rather than copying it, so only `a` frees it
+            //
+            // A literal's bytes are static (see `write_string_literal`), but freeing a local holding one is still safe since `string_free` skips them. That way a literal local that's later given a heap string is still freed
+            let alias_of = value.as_ref().and_then(|value| ctx.heap_owner(value));
+            ctx.declare(name, type_, alias_of);
+            ctx.types.locals.insert(name.clone(), type_.clone());
+            buffer
+        }
+        Statement::VariableMutation { name, value, .. } => {
+            if let Some(id) = ctx
+                .lookup(name)
+                .filter(|id| free_function(&ctx.bindings[*id].type_).is_some())
+            {
+                return ctx.write_heap_mutation(id, value, indent);
+            }
+            let value = match ctx.types.locals.get(name) {
+                Some(type_) => write_value(value, type_, &ctx.types),
+                None => write_expr(value, &ctx.types),
+            };
+            format!("{}{} = {};\n", tabs, name, value)
+        }
+        Statement::Conditional { branches, .. } => {
+            let conditions = branches
+                .iter()
+                .map(|b| b.condition.as_ref().map(|c| write_expr(c, &ctx.types)))
+                .collect::<Vec<Option<String>>>();
+            write_if_chain(branches, conditions, ctx, indent)
+        }
+        // TODO: the scrutinee is re-evaluated for every arm, so it should be bound to a temporary once we know its type
+        Statement::Match {
+            scrutinee,
+            branches,
+            ..
+        } => {
+            let conditions = branches
+                .iter()
+                .map(|b| {
+                    b.condition
+                        .as_ref()
+                        .map(|pattern| write_match_pattern(scrutinee, pattern, &ctx.types))
+                })
+                .collect::<Vec<Option<String>>>();
+            write_if_chain(branches, conditions, ctx, indent)
+        }
+        Statement::WhileLoop {
+            binding: None,
+            condition,
+            body,
+            ..
+        } => {
+            let mut buffer = format!("{}while ({}) {{\n", tabs, write_expr(condition, &ctx.types));
+            buffer.push_str(&write_block(body, ctx, indent + 1));
+            buffer.push_str(&format!("{}}}\n", tabs));
+            buffer
+        }
+        // The condition is evaluated once per iteration, and the loop ends as soon as it doesn't match
+        Statement::WhileLoop {
+            binding: Some(Pattern::Variant { name, binding }),
+            condition,
+            body,
+            ..
+        } => {
+            let mut buffer = format!("{}while (1) {{\n", tabs);
+            buffer.push_str(&format!(
+                "{}\t__auto_type __scrutinee = {};\n",
+                tabs,
+                write_expr(condition, &ctx.types)
+            ));
+            // Optionals only say whether they have a value, and `Some` holds it in `value`
+            let (stop, payload, payload_type) = match ctx.types.optional_inner(condition) {
+                Some(_) if name == "None" => (
+                    "__scrutinee.has_value".to_string(),
+                    String::new(),
+                    Type::Auto,
+                ),
+                Some(inner) => (
+                    "!__scrutinee.has_value".to_string(),
+                    "value".to_string(),
+                    inner.clone(),
+                ),
+                None => (
+                    format!("__scrutinee.tag != {}", name.to_uppercase()),
+                    format!("data.{}", name),
+                    Type::Auto,
+                ),
+            };
+            buffer.push_str(&format!(
+                "{}\tif ({}) {{\n{}\t\tbreak;\n{}\t}}\n",
+                tabs, stop, tabs, tabs
+            ));
+            if let Some(binding) = binding {
+                buffer.push_str(&format!(
+                    "{}\t__auto_type {} = __scrutinee.{};\n",
+                    tabs, binding, payload
+                ));
+                ctx.types.locals.insert(binding.clone(), payload_type);
+            }
+            buffer.push_str(&write_block(body, ctx, indent + 1));
+            buffer.push_str(&format!("{}}}\n", tabs));
+            buffer
+        }
+        Statement::Return { value: expr, .. } => {
+            let postconditions = ctx
+                .function
+                .contracts
+                .iter()
+                .filter(|c| ctx.contracts && c.type_ == ContractType::Output)
+                .collect::<Vec<&FunctionContract>>();
+            // Everything still in scope is dropped on the way out, except whatever is being returned
+            let frees = ctx.write_frees(ctx.scopes.iter().rev(), ctx.heap_owner(expr), indent + 1);
+            let types = &ctx.types;
+            let value = write_value(expr, &ctx.function.returns, types);
+            if postconditions.is_empty() && frees.is_empty() {
+                return format!("{}return {};\n", tabs, value);
+            }
+            // There's no value to hold on to, so the call runs first and then everything is cleaned up
+            if ctx.function.returns == Type::Void {
+                let mut buffer = format!("{}{};\n", tabs, value);
+                buffer.push_str(&write_contract_checks(postconditions, indent, types));
+                buffer.push_str(&ctx.write_frees(ctx.scopes.iter().rev(), None, indent));
+                buffer.push_str(&format!("{}return;\n", tabs));
+                return buffer;
+            }
+            // Postconditions refer to the return value as `result`, and it has to be computed before any locals are freed
+            let mut buffer = format!("{}{{\n", tabs);
+            buffer.push_str(&format!(
+                "{}\t{} result = {};\n",
+                tabs,
+                write_fn_arg_type(&ctx.function.returns),
+                value
+            ));
+            buffer.push_str(&write_contract_checks(postconditions, indent + 1, types));
+            buffer.push_str(&frees);
+            buffer.push_str(&format!("{}\treturn result;\n{}}}\n", tabs, tabs));
+            buffer
+        }
+        Statement::Assert { cond, message, pos } => write_runtime_check(
+            "assertion failed",
+            cond,
+            message.as_deref(),
+            pos,
+            indent,
+            &ctx.types,
+        ),
+    }
+}
+
+/// Write a series of branches as an `if`/`else if`/`else` chain
+///
+/// `conditions` holds the C for each branch's condition, already written, with `None` for the catch all
+fn write_if_chain(
+    branches: &[Branch],
+    conditions: Vec<Option<String>>,
+    ctx: &mut BodyContext,
+    indent: usize,
+) -> String {
+    let tabs = "\t".repeat(indent);
+    let mut buffer = String::new();
+    for (i, (branch, condition)) in zip(branches, conditions).enumerate() {
+        match (i, condition) {
+            (0, Some(condition)) => buffer.push_str(&format!("{}if ({}) {{\n", tabs, condition)),
+            (_, Some(condition)) => {
+                buffer.push_str(&format!("{}}} else if ({}) {{\n", tabs, condition))
+            }
+            (_, None) => buffer.push_str(&format!("{}}} else {{\n", tabs)),
+        }
+        buffer.push_str(&write_block(&branch.computations, ctx, indent + 1));
+    }
+    buffer.push_str(&format!("{}}}\n", tabs));
+    buffer
+}
+
+/// Compare the scrutinee of a match against one of its patterns
+///
+/// A bare name is an enum variant, so it checks the tag of the tagged union. Optionals aren't tagged unions, `Some` and `None` check whether they have a value instead
+fn write_match_pattern(scrutinee: &Expr, pattern: &Expr, types: &ExprTypes) -> String {
+    match pattern {
+        Expr::Variable(variant) | Expr::FunctionCall { name: variant, .. }
+            if types.optional_inner(scrutinee).is_some() =>
+        {
+            format!(
+                "{}{}.has_value",
+                if variant == "None" { "!" } else { "" },
+                write_expr(scrutinee, types)
+            )
+        }
+        Expr::Variable(variant) => format!(
+            "{}.tag == {}",
+            write_expr(scrutinee, types),
+            variant.to_uppercase()
+        ),
+        Expr::EnumVariant { variant, .. } => format!(
+            "{}.tag == {}",
+            write_expr(scrutinee, types),
+            variant.to_uppercase()
+        ),
+        Expr::IntegerLiteral(_) | Expr::FloatLiteral(_) => format!(
+            "{} == {}",
+            write_numeric_value(scrutinee, types),
+            write_numeric_value(pattern, types)
+        ),
+        other => format!(
+            "{} == {}",
+            write_expr(scrutinee, types),
+            write_expr(other, types)
+        ),
+    }
+}
+
+/// Write a full function definition, including the runtime checks for its preconditions
+///
+/// Postconditions are checked at each `return`. Contracts are left out entirely unless `options.contracts` is set
+fn write_fn_definition(
+    input: &Function,
+    type_table: &TypeTable,
+    internal: bool,
+    options: &CodegenOptions,
+) -> String {
+    // Functions only the rest of the translation unit can call are `static`
+    let linkage = if internal { "static " } else { "" };
+    let mut buffer = format!("{}{} {{\n", linkage, write_fn_signature(input));
+    let mut ctx = BodyContext::new(input, type_table, options.contracts);
+    let preconditions = input
+        .contracts
+        .iter()
+        .filter(|c| ctx.contracts && c.type_ == ContractType::Input)
+        .collect::<Vec<&FunctionContract>>();
+    buffer.push_str(&write_contract_checks(preconditions, 1, &ctx.types));
+    buffer.push_str(&write_block(&input.statements, &mut ctx, 1));
+    buffer.push('}');
+    buffer
+}
+
+// -------------------- All Together --------------------
+
+/// Settings which apply to a whole run of code generation
+pub struct CodegenOptions {
+    /// The platform we're building for, matched against `@cfg(target = "...")`
+    pub target: String,
+    /// Should functions check their contracts at runtime? On by default
+    pub contracts: bool,
+}
+
+impl CodegenOptions {
+    pub fn new(target: &str) -> CodegenOptions {
+        CodegenOptions {
+            target: target.to_string(),
+            contracts: true,
+        }
+    }
+
+    /// Should a declaration with these attributes be emitted?
+    ///
+    /// Every `@cfg` has to match. We don't know about any other keys, so they never match.
+    fn is_enabled(&self, attributes: &[Attribute]) -> bool {
+        attributes.iter().all(|attribute| match attribute {
+            Attribute::Cfg { key, value } => key == "target" && *value == self.target,
+            Attribute::Allow(_) => true,
+        })
+    }
+}
+
+/// Write an AST to a string
+///
+/// A standard library module is written as a header, which both the runtime in `c_libs/` and the program may include, so it's guarded with `#pragma once`
+pub fn write_all<'ast, I>(
+    ast: I,
+    type_table: &TypeTable,
+    filename: &str,
+    is_stdlib: bool,
+    options: &CodegenOptions,
+) -> String
+where
+    I: Iterator<Item = &'ast ASTNode>,
+{
+    let mut buffer = if is_stdlib {
+        "#pragma once\n\n".to_string()
+    } else {
+        String::new()
+    };
+    buffer.push_str(&write_header(type_table, filename, is_stdlib));
+    buffer.push_str(&write_module(ast, type_table, options, false, is_stdlib));
+    buffer
+}
+
+/// Write several modules into a single C translation unit, in the order given (so each module should come after the ones it imports)
+///
+/// The core library includes are merged at the top. Imports between the modules are dropped, since everything they'd include is already in the file, and functions other than `main` and `Export`s get internal linkage
+pub fn write_unit(
+    modules: &[(&str, &[ASTNode])],
+    type_table: &TypeTable,
+    options: &CodegenOptions,
+) -> String {
+    let names = modules.iter().map(|(name, _)| *name).collect::<Vec<&str>>();
+    let mut buffer = format!("// source: {}\n\n", names.join(", "));
+    let mut libs = names
+        .iter()
+        .flat_map(|name| identify_std_libs(type_table, name))
+        .collect::<Vec<String>>();
+    libs.sort();
+    libs.dedup();
+    buffer.push_str(&write_includes(libs, false));
+    for (name, ast) in modules.iter() {
+        buffer.push_str(&format!("// module: {}\n\n", name));
+        buffer.push_str(&write_module(ast.iter(), type_table, options, true, false));
+    }
+    buffer
+}
+
+/// Write an imported module as a header, with its types and the prototypes of its functions, and a source file with the function definitions
+///
+/// The header is what importing modules include (see `write_import`), so it's named after the module and guarded with `#pragma once`
+pub fn write_module_files(
+    ast: &[ASTNode],
+    type_table: &TypeTable,
+    module_name: &str,
+    options: &CodegenOptions,
+) -> (String, String) {
+    let nodes = enabled_nodes(ast.iter(), options);
+    let referenced = analysis::referenced_names(nodes.iter().copied());
+    let mut header = format!(
+        "#pragma once\n\n{}",
+        write_header(type_table, module_name, false)
+    );
+    let mut source = format!(
+        "// source: {}\n\n#include \"{}.h\"\n\n",
+        module_name, module_name
+    );
+    source.push_str(write_check_includes(&nodes, options));
+    for node in nodes {
+        match node {
+            ASTNode::EnumDeclaration(e) => {
+                header.push_str(&write_enum_with_linkage(e, "static inline "));
+                header.push_str("\n\n");
+            }
+            ASTNode::StructDeclaration(s) => {
+                header.push_str(&write_struct(s));
+                header.push_str("\n\n");
+            }
+            ASTNode::ImportStatement(i) => {
+                // Whatever includes this header expects the re-exported items to come with it
+                if i.reexport || i.items.iter().any(|item| referenced.contains(item)) {
+                    header.push_str(&write_import(i));
+                    header.push_str("\n\n");
+                }
+            }
+            ASTNode::ModuleMetadata(_) => {}
+            ASTNode::FunctionDeclaration(f) => {
+                header.push_str(&write_fn_declare(f));
+                header.push_str("\n\n");
+                if !f.is_extern {
+                    source.push_str(&write_fn_definition(f, type_table, false, options));
+                    source.push_str("\n\n");
+                }
+            }
+        }
+    }
+    (header, source)
+}
+
+/// Drop anything configured out for this target
+fn enabled_nodes<'ast, I>(ast: I, options: &CodegenOptions) -> Vec<&'ast ASTNode>
+where
+    I: Iterator<Item = &'ast ASTNode>,
+{
+    ast.filter(|node| match node {
+        ASTNode::FunctionDeclaration(f) => options.is_enabled(&f.attributes),
+        ASTNode::StructDeclaration(s) => options.is_enabled(&s.attributes),
+        ASTNode::EnumDeclaration(e) => options.is_enabled(&e.attributes),
+        ASTNode::ImportStatement(_) => true,
+        // Module metadata only matters to the checks, there's nothing to write
+        ASTNode::ModuleMetadata(_) => false,
+    })
+    .collect()
+}
+
+/// Failed contracts and asserts report to stderr and exit, so they need the headers for that
+fn write_check_includes(nodes: &[&ASTNode], options: &CodegenOptions) -> &'static str {
+    let has_checks = nodes.iter().any(|node| match node {
+        ASTNode::FunctionDeclaration(f) => {
+            (options.contracts && !f.contracts.is_empty()) || has_assert(&f.statements)
+        }
+        _ => false,
+    });
+    if has_checks {
+        "#include <stdio.h>\n#include <stdlib.h>\n\n"
+    } else {
+        ""
+    }
+}
+
+/// Write the declarations of one module, `in_unit` when it's part of a larger translation unit (see `write_unit`)
+///
+/// `in_header` is set for standard library modules, which are included by every file that uses them, so everything they define gets internal linkage
+fn write_module<'ast, I>(
+    ast: I,
+    type_table: &TypeTable,
+    options: &CodegenOptions,
+    in_unit: bool,
+    in_header: bool,
+) -> String
+where
+    I: Iterator<Item = &'ast ASTNode>,
+{
+    let nodes = enabled_nodes(ast, options);
+    // Imports that nothing refers to don't need their headers
+    let referenced = analysis::referenced_names(nodes.iter().copied());
+    let mut buffer = String::new();
+    buffer.push_str(write_check_includes(&nodes, options));
+    for node in nodes {
+        match node {
+            ASTNode::EnumDeclaration(e) if in_header => {
+                buffer.push_str(&write_enum_with_linkage(e, "static inline "));
+                buffer.push_str("\n\n");
+            }
+            ASTNode::EnumDeclaration(e) => {
+                buffer.push_str(&write_enum(e));
+                buffer.push_str("\n\n");
+            }
+            ASTNode::StructDeclaration(s) => {
+                buffer.push_str(&write_struct(s));
+                buffer.push_str("\n\n");
+            }
+            ASTNode::ImportStatement(i) => {
+                if !in_unit && (i.reexport || i.items.iter().any(|item| referenced.contains(item)))
+                {
+                    buffer.push_str(&write_import(i));
+                    buffer.push_str("\n\n");
+                }
+            }
+            ASTNode::ModuleMetadata(_) => {}
+            ASTNode::FunctionDeclaration(f) if f.is_extern => {
+                buffer.push_str(&write_fn_declare(f));
+                buffer.push_str("\n\n");
+            }
+            ASTNode::FunctionDeclaration(f) => {
+                let internal = in_header
+                    || (in_unit
+                        && f.name != "main"
+                        && !f.properties.contains(&FunctionProperties::Export));
+                buffer.push_str(&write_fn_definition(f, type_table, internal, options));
+                buffer.push_str("\n\n");
+                if f.name == "main" {
+                    buffer.push_str(&write_entry_point(f));
+                    buffer.push_str("\n\n");
+                }
+            }
+        }
+    }
+    buffer
+}
+
+// -------------------- Unit Tests --------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregation::TypeTable;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    #[test]
+    fn monomorphize_nested_arrays() {
+        const PROGRAM: &str = r#"
+fn main() -> Void {
+    let x: Array<Int>;
+    let y: Array<Array<String>>;
+    let z: Array<Array<Array<Bool>>>;
+}
+"#;
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(PROGRAM);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.output.is_some());
+        let ast = out.output.unwrap();
+
+        let mut type_table = TypeTable::new();
+        type_table.update(&ast, "test.iona");
+
+        println!("{:#?}", type_table);
+
+        let generated_libs = generate_templated_libs(&type_table);
+
+        assert_eq!(generated_libs.len(), 6);
+        let names: HashSet<String> = generated_libs
+            .iter()
+            .map(|lib| lib.get_header_name().to_string())
+            .collect();
+        // Check for all expected monomorphizations
+        assert!(names.contains("gen_integer_array.h"));
+        assert!(names.contains("gen_string_array.h"));
+        assert!(names.contains("gen_stringarray_array.h"));
+        assert!(names.contains("gen_bool_array.h"));
+        assert!(names.contains("gen_boolarray_array.h"));
+        assert!(names.contains("gen_boolarrayarray_array.h"));
+    }
+
+    #[test]
+    fn boxed_type_naming() {
+        let t1 = Type::Array(Box::new(Type::Integer));
+        assert_eq!(boxed_type_name(&t1), "IntegerArray");
+
+        let t2 = Type::Array(Box::new(Type::Array(Box::new(Type::String))));
+        assert_eq!(boxed_type_name(&t2), "StringArrayArray");
+
+        let t3 = Type::Array(Box::new(Type::Array(Box::new(Type::Array(Box::new(
             Type::Boolean,
         ))))));
         assert_eq!(boxed_type_name(&t3), "boolArrayArrayArray");
     }
+
+    #[test]
+    fn contract_failure_reports_location() {
+        const PROGRAM: &str = r#"
+fn double(a: Int) -> Int {
+    @contracts {
+        In: (a > 0, "a must be positive")
+        Out: (result > a, "output must grow")
+    }
+    return a * 2;
+}
+"#;
+        let mut lexer = Lexer::new("rect.iona");
+        lexer.lex(PROGRAM);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        println!("{:#?}", out);
+        assert!(out.output.is_some());
+        let ast = out.output.unwrap();
+        let f = match &ast[0] {
+            ASTNode::FunctionDeclaration(f) => f,
+            other => panic!("expected a function, got {:?}", other),
+        };
+
+        let mut type_table = TypeTable::new();
+        type_table.update(&ast, "test.iona");
+        let generated = write_fn_definition(f, &type_table, false, &CodegenOptions::new("linux"));
+        println!("{}", generated);
+        assert!(generated.contains(
+            r#"fprintf(stderr, "contract violated at rect.iona:4: \"a must be positive\" (a > 0)\n");"#
+        ));
+        assert!(generated.contains(
+            r#"fprintf(stderr, "contract violated at rect.iona:5: \"output must grow\" (result > a)\n");"#
+        ));
+        assert!(generated.contains("exit(EXIT_FAILURE);"));
+    }
+
+    #[test]
+    fn contracts_can_be_left_out() {
+        const PROGRAM: &str = r#"
+fn double(a: Int) -> Int {
+    @contracts {
+        In: (a > 0, "a must be positive")
+        Out: (result > a, "output must grow")
+    }
+    return a * 2;
+}
+"#;
+        let mut lexer = Lexer::new("rect.iona");
+        lexer.lex(PROGRAM);
+        let mut parser = Parser::new(lexer.token_stream);
+        let ast = parser.parse_all().output.unwrap();
+        let f = match &ast[0] {
+            ASTNode::FunctionDeclaration(f) => f,
+            other => panic!("expected a function, got {:?}", other),
+        };
+        let mut type_table = TypeTable::new();
+        type_table.update(&ast, "test.iona");
+
+        let mut options = CodegenOptions::new("linux");
+        let generated = write_fn_definition(f, &type_table, false, &options);
+        // Both the precondition and the postcondition are guarded
+        assert_eq!(
+            generated.matches("#ifndef NDEBUG\n").count(),
+            2,
+            "{}",
+            generated
+        );
+        assert_eq!(generated.matches("#endif\n").count(), 2, "{}", generated);
+
+        options.contracts = false;
+        let generated = write_fn_definition(f, &type_table, false, &options);
+        assert!(!generated.contains("contract violated"), "{}", generated);
+        assert!(!generated.contains("NDEBUG"), "{}", generated);
+        assert!(
+            generated.contains("\treturn saturating_mul(a, integer_from(2));\n"),
+            "{}",
+            generated
+        );
+        let nodes = ast.iter().collect::<Vec<&ASTNode>>();
+        assert_eq!(write_check_includes(&nodes, &options), "");
+    }
+
+    #[test]
+    fn assert_failure_reports_location() {
+        const PROGRAM: &str = r#"
+fn halve(a: Int) -> Int {
+    assert(a > 0, "positive");
+    assert(a % 2 < 1);
+    return a / 2;
+}
+"#;
+        let mut lexer = Lexer::new("rect.iona");
+        lexer.lex(PROGRAM);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+        let ast = out.output.unwrap();
+        let mut type_table = TypeTable::new();
+        type_table.update(&ast, "rect");
+        let generated = write_all(
+            ast.iter(),
+            &type_table,
+            "rect",
+            false,
+            &CodegenOptions::new("linux"),
+        );
+        assert!(generated.contains("#include <stdlib.h>"));
+        assert!(generated.contains("\tif (!((a.value > 0))) {\n"));
+        assert!(generated.contains(
+            r#"fprintf(stderr, "assertion failed at rect.iona:3: \"positive\" (a > 0)\n");"#
+        ));
+        // Without a message there's only the condition, with its `%` escaped for fprintf
+        assert!(generated
+            .contains(r#"fprintf(stderr, "assertion failed at rect.iona:4: (a %% 2 < 1)\n");"#));
+    }
+
+    #[test]
+    fn enum_variant_constructors() {
+        const PROGRAM: &str = r#"
+enum Pets {
+    Dog,
+    Cat: Int,
+
+    @metadata {
+        Is: Public;
+    }
+}
+"#;
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(PROGRAM);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.output.is_some());
+        let ast = out.output.unwrap();
+        let e = match &ast[0] {
+            ASTNode::EnumDeclaration(e) => e,
+            other => panic!("expected an enum, got {:?}", other),
+        };
+
+        let generated = write_enum(e);
+        println!("{}", generated);
+        assert!(generated.contains("Pets Pets_Dog(void) {\n\treturn (Pets){ .tag = DOG };\n}"));
+        assert!(generated.contains(
+            "Pets Pets_Cat(Integer value) {\n\treturn (Pets){ .tag = CAT, .data.Cat = value };\n}"
+        ));
+    }
+
+    #[test]
+    fn string_literal_carries_length() {
+        let type_table = TypeTable::new();
+        let types = ExprTypes::new(&type_table);
+        assert_eq!(
+            write_expr(&Expr::StringLiteral("dog".to_string()), &types),
+            r#"(String){ .data = { .data = (Byte*)"dog", .len = 3, .capacity = 0 } }"#
+        );
+        // Escapes don't count towards the length, but every byte of a multi-byte character does
+        assert_eq!(
+            write_expr(&Expr::StringLiteral("\"é\"\n".to_string()), &types),
+            r#"(String){ .data = { .data = (Byte*)"\"é\"\n", .len = 5, .capacity = 0 } }"#
+        );
+    }
+
+    #[test]
+    fn free_heap_locals_at_scope_exit() {
+        const PROGRAM: &str = r#"
+fn build(n: Int) -> Array<Int> {
+    let scratch: Array<Int> = new_array(n);
+    let kept: Array<Int> = new_array(n);
+    return kept;
+}
+"#;
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(PROGRAM);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.output.is_some());
+        let ast = out.output.unwrap();
+        let f = match &ast[0] {
+            ASTNode::FunctionDeclaration(f) => f,
+            other => panic!("expected a function, got {:?}", other),
+        };
+
+        let mut type_table = TypeTable::new();
+        type_table.update(&ast, "test.iona");
+        let generated = write_fn_definition(f, &type_table, false, &CodegenOptions::new("linux"));
+        println!("{}", generated);
+        // The dropped array is freed before returning, the returned one is not
+        assert!(generated.contains("integer_array_free(&scratch);"));
+        assert!(!generated.contains("integer_array_free(&kept);"));
+        assert!(generated.contains("\t\treturn result;"));
+    }
+
+    #[test]
+    fn free_owned_strings() {
+        const PROGRAM: &str = r#"
+fn greet() -> Int {
+    let name: String = read_name();
+    return 0;
+}
+"#;
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(PROGRAM);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.output.is_some());
+        let ast = out.output.unwrap();
+        let f = match &ast[0] {
+            ASTNode::FunctionDeclaration(f) => f,
+            other => panic!("expected a function, got {:?}", other),
+        };
+
+        let mut type_table = TypeTable::new();
+        type_table.update(&ast, "test.iona");
+        let generated = write_fn_definition(f, &type_table, false, &CodegenOptions::new("linux"));
+        println!("{}", generated);
+        assert!(generated.contains("string_free(&name);"));
+        // `string_free` is part of the C runtime, next to the generated `String` struct
+        assert_eq!(
+            type_to_std_lib(&Type::String),
+            Some("strings.h".to_string())
+        );
+        assert!(fs::read_to_string("c_libs/strings.h")
+            .unwrap()
+            .contains("void string_free(String* string)"));
+    }
+
+    #[test]
+    fn string_literal_locals_are_freed_safely() {
+        const PROGRAM: &str = r#"
+fn greet(loud: Bool) -> Int {
+    let name: String = "dog";
+    if loud {
+        let other: String = "cat";
+        name = read_name();
+    }
+    return 0;
+}
+"#;
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(PROGRAM);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+        let ast = out.output.unwrap();
+        let f = match &ast[0] {
+            ASTNode::FunctionDeclaration(f) => f,
+            other => panic!("expected a function, got {:?}", other),
+        };
+
+        let mut type_table = TypeTable::new();
+        type_table.update(&ast, "test.iona");
+        let generated = write_fn_definition(f, &type_table, false, &CodegenOptions::new("linux"));
+        println!("{}", generated);
+        assert!(generated.contains("String name = (String){ .data = { .data = (Byte*)\"dog\""));
+        // The literal's bytes are never released, `string_free` only releases what was allocated
+        assert!(generated.contains("\t\tstring_free(&other);\n"));
+        // `name` may hold a heap string by the time it goes out of scope
+        assert!(generated.contains("\tstring_free(&name);\n"));
+        assert!(fs::read_to_string("c_libs/strings.h")
+            .unwrap()
+            .contains("if (string->data.capacity > 0) {"));
+    }
+
+    #[test]
+    fn heap_locals_are_freed_once_per_binding() {
+        const PROGRAM: &str = r#"
+fn alias() -> String {
+    let a: String = read_name();
+    let b: String = a;
+    return a;
+}
+
+fn shadow(loud: Bool) -> String {
+    let s: String = read_name();
+    if loud {
+        let s: String = read_name();
+    }
+    return s;
+}
+
+fn rename() -> Int {
+    let name: String = read_name();
+    name = read_name();
+    return 0;
+}
+"#;
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(PROGRAM);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+        let ast = out.output.unwrap();
+        let mut type_table = TypeTable::new();
+        type_table.update(&ast, "test.iona");
+        let generated = ast
+            .iter()
+            .filter_map(|node| match node {
+                ASTNode::FunctionDeclaration(f) => Some(write_fn_definition(
+                    f,
+                    &type_table,
+                    false,
+                    &CodegenOptions::new("linux"),
+                )),
+                _ => None,
+            })
+            .collect::<Vec<String>>();
+        println!("{}", generated.join("\n"));
+        // `b` shares `a`'s buffer, which is handed to the caller
+        assert!(!generated[0].contains("string_free"));
+        // The inner `s` is its own string, only the outer one is returned
+        assert!(generated[1].contains("\t\tstring_free(&s);\n\t}"));
+        assert_eq!(generated[1].matches("string_free").count(), 1);
+        // The first name is released once the second replaces it
+        assert!(generated[2]
+            .contains("\tString __old = name;\n\t\tname = read_name();\n\t\tstring_free(&__old);"));
+        assert_eq!(generated[2].matches("string_free(&name)").count(), 1);
+    }
+
+    #[test]
+    fn void_returns_free_locals() {
+        const PROGRAM: &str = r#"
+enum Pets {
+    Dog,
+    Fish,
+
+    @metadata {
+        Is: Public;
+    }
+}
+
+fn greet(pet: Pets) -> Void {
+    let name: String = read_name();
+    match pet {
+        Dog => bark(name),
+        Fish => swim(name)
+    }
+}
+"#;
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(PROGRAM);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+        let ast = out.output.unwrap();
+        let f = match &ast[1] {
+            ASTNode::FunctionDeclaration(f) => f,
+            other => panic!("expected a function, got {:?}", other),
+        };
+
+        let mut type_table = TypeTable::new();
+        type_table.update(&ast, "test.iona");
+        let generated = write_fn_definition(f, &type_table, false, &CodegenOptions::new("linux"));
+        println!("{}", generated);
+        assert!(!generated.contains("void result"));
+        assert!(generated.contains("\t\tbark(name);\n\t\tstring_free(&name);\n\t\treturn;\n"));
+        assert!(generated.contains("\t\tswim(name);\n\t\tstring_free(&name);\n\t\treturn;\n"));
+    }
+
+    #[test]
+    fn fixed_array_arguments() {
+        const PROGRAM: &str = r#"
+fn first(xs: Array<Int, 4>) -> Int {
+    return xs[0];
+}
+"#;
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(PROGRAM);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.output.is_some());
+        let ast = out.output.unwrap();
+        let f = match &ast[0] {
+            ASTNode::FunctionDeclaration(f) => f,
+            other => panic!("expected a function, got {:?}", other),
+        };
+
+        let mut type_table = TypeTable::new();
+        type_table.update(&ast, "test.iona");
+        let generated = write_fn_definition(f, &type_table, false, &CodegenOptions::new("linux"));
+        println!("{}", generated);
+        assert!(generated.starts_with("Integer first(IntegerArray4 xs)"));
+        assert!(generated.contains("xs.data["));
+
+        let generated_libs = generate_templated_libs(&type_table);
+        let header = generated_libs
+            .iter()
+            .find(|lib| lib.get_header_name() == "gen_integerarray4.h")
+            .expect("no header for the fixed array");
+        assert!(header
+            .get_header_file()
+            .contains("typedef struct {\n\tInteger data[4];\n} IntegerArray4;\n"));
+        assert!(identify_std_libs(&type_table, "test.iona")
+            .contains(&"gen_integerarray4.h".to_string()));
+    }
+
+    #[test]
+    fn shared_monomorphizations_generated_once() {
+        const FIRST: &str = r#"
+fn first(a: Int) -> Int {
+    let xs: Array<Int> = new_array(a);
+    return a;
+}
+"#;
+        const SECOND: &str = r#"
+fn second(b: Int) -> Int {
+    let ys: Array<Int> = new_array(b);
+    return b;
+}
+"#;
+        let mut type_table = TypeTable::new();
+        for (module_name, program) in [("first", FIRST), ("second", SECOND)] {
+            let mut lexer = Lexer::new(&format!("{}.iona", module_name));
+            lexer.lex(program);
+            let mut parser = Parser::new(lexer.token_stream);
+            let out = parser.parse_all();
+            assert!(out.output.is_some());
+            type_table.update(&out.output.unwrap(), module_name);
+        }
+        let generated_libs = generate_templated_libs(&type_table);
+        let count = generated_libs
+            .iter()
+            .filter(|lib| lib.get_header_name() == "gen_integer_array.h")
+            .count();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn tuple_typedefs() {
+        const PROGRAM: &str = r#"
+fn pair(a: Int) -> (Int, String) {
+    let points: Array<(Int, Int)> = new_array(a);
+}
+"#;
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(PROGRAM);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.output.is_some());
+        let ast = out.output.unwrap();
+        let f = match &ast[0] {
+            ASTNode::FunctionDeclaration(f) => f,
+            other => panic!("expected a function, got {:?}", other),
+        };
+        assert!(write_fn_signature(f).starts_with("Tuple_Integer_String pair("));
+
+        let mut type_table = TypeTable::new();
+        type_table.update(&ast, "test.iona");
+        let generated_libs = generate_templated_libs(&type_table);
+        let find = |name: &str| {
+            generated_libs
+                .iter()
+                .find(|lib| lib.get_header_name() == name)
+                .unwrap_or_else(|| panic!("{} was not generated", name))
+        };
+        // The returned tuple
+        let returned = find("gen_tuple_integer_string.h");
+        assert!(returned
+            .get_header_file()
+            .contains("typedef struct {\n\tInteger _0;\n\tString _1;\n} Tuple_Integer_String;"));
+        // The nested tuple gets its own typedef, which the array header pulls in
+        let nested = find("gen_tuple_integer_integer.h");
+        assert!(nested
+            .get_header_file()
+            .contains("} Tuple_Integer_Integer;"));
+        let array = find("gen_tuple_integer_integer_array.h");
+        assert!(array
+            .get_header_file()
+            .contains("#include \"gen_tuple_integer_integer.h\""));
+        assert!(array
+            .get_header_file()
+            .contains("} Tuple_Integer_IntegerArray;"));
+        // Each shape is only emitted once
+        assert_eq!(
+            generated_libs
+                .iter()
+                .filter(|lib| lib.get_header_name() == "gen_tuple_integer_integer.h")
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn struct_with_fixed_array() {
+        const PROGRAM: &str = r#"
+struct Board {
+    cells: Array<Int, 8>,
+    name: String
+
+    @metadata {
+        Is: Public;
+    }
+}
+"#;
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(PROGRAM);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.output.is_some());
+        let ast = out.output.unwrap();
+        let s = match &ast[0] {
+            ASTNode::StructDeclaration(s) => s,
+            other => panic!("expected a struct, got {:?}", other),
+        };
+
+        let generated = write_struct(s);
+        println!("{}", generated);
+        assert!(generated.contains("\tInteger cells[8];\n"));
+        assert!(generated.contains("\tString name;\n"));
+    }
+
+    #[test]
+    fn cfg_gated_functions() {
+        const PROGRAM: &str = r#"
+@cfg(target = "windows")
+fn open_windows(path: String) -> Int {
+    return 1;
+}
+
+fn open_anywhere(path: String) -> Int {
+    return 2;
+}
+"#;
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(PROGRAM);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.output.is_some());
+        let ast = out.output.unwrap();
+        let mut type_table = TypeTable::new();
+        type_table.update(&ast, "test.iona");
+
+        let linux = write_all(
+            ast.iter(),
+            &type_table,
+            "test.iona",
+            false,
+            &CodegenOptions::new("linux"),
+        );
+        assert!(!linux.contains("open_windows"));
+        assert!(linux.contains("Integer open_anywhere(String path)"));
+
+        let windows = write_all(
+            ast.iter(),
+            &type_table,
+            "test.iona",
+            false,
+            &CodegenOptions::new("windows"),
+        );
+        assert!(windows.contains("Integer open_windows(String path)"));
+        assert!(windows.contains("Integer open_anywhere(String path)"));
+    }
+
+    #[test]
+    fn enum_payload_access() {
+        const PROGRAM: &str = r#"
+enum Pets {
+    Dog,
+    Cat: Int,
+
+    @metadata {
+        Is: Public;
+    }
+}
+
+struct Owner {
+    age: Int
+
+    @metadata {
+        Is: Public;
+    }
+}
+
+fn lives(pet: Pets, owner: Owner) -> Int {
+    return pet.Cat + owner.age;
+}
+"#;
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(PROGRAM);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.output.is_some());
+        let ast = out.output.unwrap();
+        let f = match &ast[2] {
+            ASTNode::FunctionDeclaration(f) => f,
+            other => panic!("expected a function, got {:?}", other),
+        };
+        let mut type_table = TypeTable::new();
+        type_table.update(&ast, "test.iona");
+
+        let generated = write_fn_definition(f, &type_table, false, &CodegenOptions::new("linux"));
+        println!("{}", generated);
+        // Enums go through the union, structs are accessed directly
+        assert!(generated.contains("return saturating_add(pet.data.Cat, owner.age);"));
+    }
+
+    #[test]
+    fn while_let_loop() {
+        const PROGRAM: &str = r#"
+fn drain(queue: Int) -> Int {
+    while let Some(item) = next(queue) {
+        print(item);
+    }
+    return queue;
+}
+"#;
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(PROGRAM);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.output.is_some());
+        let ast = out.output.unwrap();
+        let f = match &ast[0] {
+            ASTNode::FunctionDeclaration(f) => f,
+            other => panic!("expected a function, got {:?}", other),
+        };
+        let type_table = TypeTable::new();
+
+        let generated = write_fn_definition(f, &type_table, false, &CodegenOptions::new("linux"));
+        println!("{}", generated);
+        assert!(generated.contains(
+            "\twhile (1) {\n\t\t__auto_type __scrutinee = next(queue);\n\t\tif (__scrutinee.tag != SOME) {\n\t\t\tbreak;\n\t\t}\n\t\t__auto_type item = __scrutinee.data.Some;\n\t\tprint(item);\n\t}\n"
+        ));
+    }
+
+    #[test]
+    fn skip_unused_imports() {
+        const PROGRAM: &str = r#"import npc with Creature;
+import math with sqrt;
+
+fn greet(c: Creature) -> Int {
+    return 0;
+}
+"#;
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(PROGRAM);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.output.is_some());
+        let ast = out.output.unwrap();
+        let mut type_table = TypeTable::new();
+        type_table.update(&ast, "test.iona");
+
+        let generated = write_all(
+            ast.iter(),
+            &type_table,
+            "test.iona",
+            false,
+            &CodegenOptions::new("linux"),
+        );
+        assert!(generated.contains("#include \"npc.h\""));
+        assert!(!generated.contains("#include \"math.h\""));
+    }
+
+    #[test]
+    fn extern_functions_only_declared() {
+        const PROGRAM: &str = r#"extern fn c_malloc(n: Size) -> RawCType;
+"#;
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(PROGRAM);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.output.is_some());
+        let ast = out.output.unwrap();
+        let mut type_table = TypeTable::new();
+        type_table.update(&ast, "test.iona");
+
+        let generated = write_all(
+            ast.iter(),
+            &type_table,
+            "test.iona",
+            false,
+            &CodegenOptions::new("linux"),
+        );
+        assert!(generated.contains("void* c_malloc(size_t n);"));
+        assert!(!generated.contains("c_malloc(size_t n) {"));
+    }
+
+    #[test]
+    fn custom_type_fields_have_no_stray_space() {
+        const PROGRAM: &str = r#"
+struct Pen {
+    legs: Animal
+
+    @metadata {
+        Is: Public;
+    }
+}
+
+enum Visitor {
+    Guest: Animal,
+    Nobody,
+
+    @metadata {
+        Is: Public;
+    }
+}
+"#;
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(PROGRAM);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.output.is_some());
+        let ast = out.output.unwrap();
+        let (s, e) = match (&ast[0], &ast[1]) {
+            (ASTNode::StructDeclaration(s), ASTNode::EnumDeclaration(e)) => (s, e),
+            other => panic!("expected a struct and an enum, got {:?}", other),
+        };
+
+        assert!(write_struct(s).contains("\n\tAnimal legs;\n"));
+        assert!(write_enum(e).contains("\n\tAnimal Guest;\n"));
+    }
+
+    #[test]
+    fn array_accessors_use_size() {
+        let header = MonomorphizedArray::new(&Type::Integer, &load_c_template("array.h"))
+            .get_header_file()
+            .to_string();
+        assert!(
+            header.contains("Integer integer_array_get(const IntegerArray* arr, size_t index) {")
+        );
+        assert!(header.contains("size_t integer_array_len(const IntegerArray* arr) {"));
+
+        const PROGRAM: &str = r#"
+fn last(xs: Array<Int>, i: Size) -> Int {
+    let n: Size = xs.len();
+    let start: Size = 0;
+    return xs[i];
+}
+"#;
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(PROGRAM);
+        let mut parser = Parser::new(lexer.token_stream);
+        let ast = parser.parse_all().output.unwrap();
+        let mut type_table = TypeTable::new();
+        type_table.update(&ast, "test");
+        let generated = write_all(
+            ast.iter(),
+            &type_table,
+            "test",
+            false,
+            &CodegenOptions::new("linux"),
+        );
+        assert!(generated.contains("size_t n = integer_array_len(&xs);"));
+        assert!(generated.contains("size_t start = 0;"));
+        // A `Size` is already a C integer, so it doesn't go through `.value`
+        assert!(generated.contains("xs.data[i]"));
+    }
+
+    #[test]
+    fn size_arithmetic_uses_c_operators() {
+        const PROGRAM: &str = r#"
+fn last(xs: Array<Int>) -> Int {
+    let n: Size = xs.len();
+    if xs.len() > 0 {
+        return xs[n - 1];
+    }
+    return 0;
+}
+"#;
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(PROGRAM);
+        let mut parser = Parser::new(lexer.token_stream);
+        let ast = parser.parse_all().output.unwrap();
+        let mut type_table = TypeTable::new();
+        type_table.update(&ast, "test");
+        let generated = write_all(
+            ast.iter(),
+            &type_table,
+            "test",
+            false,
+            &CodegenOptions::new("linux"),
+        );
+        println!("{}", generated);
+        assert!(generated.contains("(integer_array_len(&xs) > 0)"));
+        assert!(generated.contains("xs.data[(n - 1)]"));
+        assert!(!generated.contains("n.value"));
+    }
+
+    #[test]
+    fn optionals_are_tagged_structs() {
+        let header = OptionalTypedef::new(&Type::Optional(Box::new(Type::String)))
+            .get_header_file()
+            .to_string();
+        assert!(header
+            .contains("typedef struct {\n\tbool has_value;\n\tString value;\n} Optional_String;"));
+
+        const PROGRAM: &str = r#"
+fn first() -> Int? {
+    let x: Int? = none;
+    return some(1);
+}
+"#;
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(PROGRAM);
+        let mut parser = Parser::new(lexer.token_stream);
+        let ast = parser.parse_all().output.unwrap();
+        let mut type_table = TypeTable::new();
+        type_table.update(&ast, "test");
+        let generated = write_all(
+            ast.iter(),
+            &type_table,
+            "test",
+            false,
+            &CodegenOptions::new("linux"),
+        );
+        assert!(
+            generated.contains("Optional_Integer x = (Optional_Integer){ .has_value = false };")
+        );
+        assert!(generated
+            .contains("return (Optional_Integer){ .has_value = true, .value = integer_from(1) };"));
+    }
+
+    #[test]
+    fn optionals_outside_of_let_and_return() {
+        const PROGRAM: &str = r#"
+fn get(x: Int?) -> Int {
+    match x {
+        Some => 1,
+        None => 0
+    }
+}
+
+fn next(i: Int) -> Int? {
+    return some(i);
+}
+
+fn drain(opt: Int?) -> Int {
+    let total: Int = get(none) + get(some(2));
+    while let Some(v) = opt {
+        total = total + v;
+        opt = next(v);
+    }
+    while let Some(v) = next(total) {
+        total = v;
+    }
+    return total;
+}
+"#;
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(PROGRAM);
+        let mut parser = Parser::new(lexer.token_stream);
+        let ast = parser.parse_all().output.unwrap();
+        let mut type_table = TypeTable::new();
+        type_table.update(&ast, "test");
+        let generated = write_all(
+            ast.iter(),
+            &type_table,
+            "test",
+            false,
+            &CodegenOptions::new("linux"),
+        );
+        println!("{}", generated);
+        assert!(generated.contains("get((Optional_Integer){ .has_value = false })"));
+        assert!(generated
+            .contains("get((Optional_Integer){ .has_value = true, .value = integer_from(2) })"));
+        assert!(generated.contains("if (x.has_value) {"));
+        assert!(generated.contains("} else if (!x.has_value) {"));
+        assert!(generated.contains("\t\tif (!__scrutinee.has_value) {\n\t\t\tbreak;\n\t\t}\n\t\t__auto_type v = __scrutinee.value;\n"));
+        assert!(generated.contains("__auto_type __scrutinee = next(total);"));
+        assert!(!generated.contains(".tag"));
+        assert!(!generated.contains(".data."));
+    }
+
+    #[test]
+    fn header_for_array_returned_by_call() {
+        let mut tables = crate::aggregation::ParsingTables::new();
+        let mut asts = Vec::new();
+        for (module_name, program) in [
+            (
+                "lists",
+                "fn make_list() -> Array<Int> {\n    @metadata {\n        Is: Public;\n    }\n    return make_list();\n}\n",
+            ),
+            (
+                "main",
+                "import lists with make_list;\n\nfn count() -> Int {\n    return length(make_list());\n}\n",
+            ),
+        ] {
+            let mut lexer = Lexer::new(module_name);
+            lexer.lex(program);
+            let mut parser = Parser::new(lexer.token_stream);
+            let ast = parser.parse_all().output.unwrap();
+            tables.update(&ast, module_name);
+            asts.push(ast);
+        }
+
+        let generated = write_all(
+            asts[1].iter(),
+            &tables.types,
+            "main",
+            false,
+            &CodegenOptions::new("linux"),
+        );
+        assert!(generated.contains("#include \"../c_libs/gen_integer_array.h\"\n"));
+    }
+
+    #[test]
+    fn if_expression_is_a_ternary() {
+        const PROGRAM: &str = r#"
+fn max(a: Int, b: Int) -> Int {
+    return if a > b then a else b;
+}
+"#;
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(PROGRAM);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+        let ast = out.output.unwrap();
+        let f = match &ast[0] {
+            ASTNode::FunctionDeclaration(f) => f,
+            other => panic!("expected a function, got {:?}", other),
+        };
+
+        let generated =
+            write_fn_definition(f, &TypeTable::new(), false, &CodegenOptions::new("linux"));
+        assert!(generated.contains("\treturn ((a.value > b.value) ? a : b);\n"));
+    }
+
+    #[test]
+    fn entry_point_wraps_iona_main() {
+        const PROGRAM: &str = r#"
+fn main(args: Array<String>) -> Void {
+    main(args);
+}
+"#;
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(PROGRAM);
+        let mut parser = Parser::new(lexer.token_stream);
+        let ast = parser.parse_all().output.unwrap();
+        let mut type_table = TypeTable::new();
+        type_table.update(&ast, "test");
+        let generated = write_all(
+            ast.iter(),
+            &type_table,
+            "test",
+            false,
+            &CodegenOptions::new("linux"),
+        );
+        println!("{}", generated);
+        assert!(generated.contains("void iona_main(StringArray args) {\n\tiona_main(args);\n"));
+        assert!(generated.contains("int main(int argc, char** argv) {\n\tStringArray args = string_array_with_capacity((size_t)argc);\n"));
+        assert!(
+            generated.contains("\tiona_main(args);\n\tstring_array_free(&args);\n\treturn 0;\n}")
+        );
+    }
+
+    #[test]
+    fn stdlib_headers_have_internal_linkage() {
+        const PROGRAM: &str = r#"
+enum Maybe {
+    Some: Int,
+    None,
+
+    @metadata {
+        Is: Public;
+    }
+}
+
+fn unwrap_or(x: Maybe, fallback: Int) -> Int {
+    return fallback;
+}
+"#;
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(PROGRAM);
+        let mut parser = Parser::new(lexer.token_stream);
+        let ast = parser.parse_all().output.unwrap();
+        let mut type_table = TypeTable::new();
+        type_table.update(&ast, "errors");
+        let generated = write_all(
+            ast.iter(),
+            &type_table,
+            "errors",
+            true,
+            &CodegenOptions::new("linux"),
+        );
+        println!("{}", generated);
+        assert!(generated.starts_with("#pragma once\n\n// source: errors\n"));
+        assert!(generated.contains("\nstatic inline Maybe Maybe_Some(Integer value) {"));
+        assert!(generated.contains("\nstatic Integer unwrap_or(Maybe x, Integer fallback) {"));
+    }
+
+    #[test]
+    fn templates_read_once() {
+        const PROGRAM: &str = r#"
+fn main(a: Int) -> Int {
+    let xs: Array<Int> = new_array(a);
+    let ys: Array<String> = new_array(a);
+    let zs: Array<Bool> = new_array(a);
+    return a;
+}
+"#;
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(PROGRAM);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.output.is_some());
+        let mut type_table = TypeTable::new();
+        type_table.update(&out.output.unwrap(), "test.iona");
+
+        let mut reads = 0;
+        let mut templates = TemplateCache::new(|_: &str| {
+            reads += 1;
+            "ARRAY_NAME of ELEM_TYPE".to_string()
+        });
+        let generated_libs = generate_templated_libs_with(&type_table, &mut templates);
+        drop(templates);
+        assert_eq!(generated_libs.len(), 3);
+        assert_eq!(reads, 1);
+    }
 }
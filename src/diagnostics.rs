@@ -1,5 +1,7 @@
 //! Compiler Errors, Warnings, and Lints
 
+use std::collections::HashMap;
+
 use crate::lexer::SourcePosition;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -9,45 +11,394 @@ pub enum IssueLevel {
     Error,
 }
 
+impl IssueLevel {
+    pub fn name(&self) -> &'static str {
+        match self {
+            IssueLevel::Lint => "lint",
+            IssueLevel::Warning => "warning",
+            IssueLevel::Error => "error",
+        }
+    }
+}
+
+/// Every distinct kind of problem we report, each with a stable code so users can look it up (or eventually suppress it)
+///
+/// Codes are never reused or renumbered: new kinds go at the end
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    /// The lexer or parser couldn't make sense of the source
+    Syntax,
+    /// A variable that isn't in scope
+    UndefinedVariable,
+    /// A call to something that isn't a known function
+    UndefinedFunction,
+    /// Two declarations with the same name in one module
+    DuplicateDefinition,
+    /// An import that can't be found
+    UnresolvedImport,
+    /// An import of something that isn't `Public` or `Export`
+    PrivateImport,
+    ImportCycle,
+    /// A call needs a permission the caller doesn't declare in `Uses`
+    MissingPermission,
+    MismatchedTypes,
+    CannotInferType,
+    NonExhaustiveMatch,
+    MissingReturn,
+    UnusedVariable,
+    UnusedImport,
+    UnreachableCode,
+    /// A call with too many or too few arguments
+    WrongArgumentCount,
+    /// A pattern or `Enum.Variant` naming a variant the enum doesn't have
+    UndefinedVariant,
+    /// A pattern that binds a payload the variant doesn't carry, or binds it wrongly
+    VariantPayload,
+    /// A named type that isn't a struct or enum in scope
+    UndefinedType,
+    /// A variable declared without a value, read before it's assigned
+    UninitializedVariable,
+    /// `Void` or `Auto` somewhere a value needs a concrete type, like a struct field
+    InvalidType,
+    /// `Generic<T>` where the declaration doesn't list `T` in its type parameters
+    UndeclaredTypeParameter,
+    UnusedTypeParameter,
+    /// `Derives: Eq` or `Show` on a type with a field that can't support it
+    UnderivableTrait,
+    /// A function that nothing reachable from `main` calls
+    DeadCode,
+    /// An index that's known to be below 0 before the program runs
+    NegativeIndex,
+    /// `xs[i]` with an index that isn't checked against the array's length
+    UncheckedIndex,
+    /// Assigning to a function parameter instead of a local copy
+    ParameterAssignment,
+    /// A program without a `main`, or with one C can't call
+    InvalidEntryPoint,
+    /// A permission in `Uses` that nothing the function calls needs
+    UnusedPermission,
+}
+
+impl DiagnosticKind {
+    pub fn code(&self) -> &'static str {
+        match self {
+            DiagnosticKind::Syntax => "E0001",
+            DiagnosticKind::UndefinedVariable => "E0002",
+            DiagnosticKind::UndefinedFunction => "E0003",
+            DiagnosticKind::DuplicateDefinition => "E0004",
+            DiagnosticKind::UnresolvedImport => "E0005",
+            DiagnosticKind::PrivateImport => "E0006",
+            DiagnosticKind::ImportCycle => "E0007",
+            DiagnosticKind::MissingPermission => "E0008",
+            DiagnosticKind::MismatchedTypes => "E0009",
+            DiagnosticKind::CannotInferType => "E0010",
+            DiagnosticKind::NonExhaustiveMatch => "E0011",
+            DiagnosticKind::MissingReturn => "E0012",
+            DiagnosticKind::UnusedVariable => "W0001",
+            DiagnosticKind::UnusedImport => "W0002",
+            DiagnosticKind::UnreachableCode => "W0003",
+            DiagnosticKind::WrongArgumentCount => "E0013",
+            DiagnosticKind::UndefinedVariant => "E0014",
+            DiagnosticKind::VariantPayload => "E0015",
+            DiagnosticKind::UndefinedType => "E0016",
+            DiagnosticKind::UninitializedVariable => "E0017",
+            DiagnosticKind::InvalidType => "E0018",
+            DiagnosticKind::UndeclaredTypeParameter => "E0019",
+            DiagnosticKind::UnusedTypeParameter => "W0004",
+            DiagnosticKind::UnderivableTrait => "E0020",
+            DiagnosticKind::DeadCode => "W0005",
+            DiagnosticKind::NegativeIndex => "E0021",
+            DiagnosticKind::UncheckedIndex => "W0006",
+            DiagnosticKind::ParameterAssignment => "W0007",
+            DiagnosticKind::InvalidEntryPoint => "E0022",
+            DiagnosticKind::UnusedPermission => "W0008",
+        }
+    }
+
+    /// The name used to silence this kind with `@allow(..)`, errors can't be silenced
+    pub fn lint_name(&self) -> Option<&'static str> {
+        match self {
+            DiagnosticKind::UnusedVariable
+            | DiagnosticKind::UnusedImport
+            | DiagnosticKind::UnusedTypeParameter
+            | DiagnosticKind::DeadCode
+            | DiagnosticKind::UnusedPermission => Some("unused"),
+            DiagnosticKind::UnreachableCode => Some("unreachable"),
+            DiagnosticKind::UncheckedIndex => Some("unchecked_index"),
+            DiagnosticKind::ParameterAssignment => Some("parameter_assignment"),
+            _ => None,
+        }
+    }
+
+    /// A narrower name than `lint_name` for kinds that share a group, so `--deny=unused_imports` can leave unused variables alone
+    pub fn specific_lint_name(&self) -> Option<&'static str> {
+        match self {
+            DiagnosticKind::UnusedVariable => Some("unused_variables"),
+            DiagnosticKind::UnusedImport => Some("unused_imports"),
+            DiagnosticKind::UnusedTypeParameter => Some("unused_type_parameters"),
+            DiagnosticKind::DeadCode => Some("dead_code"),
+            DiagnosticKind::UnusedPermission => Some("unused_permissions"),
+            other => other.lint_name(),
+        }
+    }
+}
+
+/// Every name that `--allow`, `--warn` and `--deny` accept, groups first
+pub const LINT_NAMES: [&str; 9] = [
+    "unused",
+    "unused_variables",
+    "unused_imports",
+    "unused_type_parameters",
+    "dead_code",
+    "unused_permissions",
+    "unreachable",
+    "unchecked_index",
+    "parameter_assignment",
+];
+
+/// What to do with a lint when it's found, set per lint from the command line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LintLevel {
+    /// Don't report it at all
+    Allow,
+    /// Report it as a lint, which is only shown and never fails the build
+    Lint,
+    /// Report it as a warning
+    Warn,
+    /// Report it as an error, which stops compilation
+    Deny,
+}
+
+/// Lint names (see `LINT_NAMES`) mapped to the level they should be reported at
+pub type LintLevels = HashMap<String, LintLevel>;
+
+/// Raise, lower, or drop each lint according to `levels`, errors are never touched
+///
+/// A specific name like `unused_imports` wins over its group `unused`
+pub fn apply_lint_levels(diagnostics: Vec<Diagnostic>, levels: &LintLevels) -> Vec<Diagnostic> {
+    diagnostics
+        .into_iter()
+        .filter_map(|d| {
+            let kind = d.kind();
+            let level = [kind.specific_lint_name(), kind.lint_name()]
+                .into_iter()
+                .flatten()
+                .find_map(|name| levels.get(name));
+            match level {
+                None => Some(d),
+                Some(LintLevel::Allow) => None,
+                Some(LintLevel::Lint) => Some(d.with_level(IssueLevel::Lint)),
+                Some(LintLevel::Warn) => Some(d.with_level(IssueLevel::Warning)),
+                Some(LintLevel::Deny) => Some(d.with_level(IssueLevel::Error)),
+            }
+        })
+        .collect()
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Diagnostic {
     level: IssueLevel,
+    kind: DiagnosticKind,
     message: String,
     position: SourcePosition,
     references: Option<Vec<SourcePosition>>,
+    /// Extra explanation shown under the caret, like why a type was expected
+    notes: Vec<String>,
 }
 
 impl Diagnostic {
-    pub fn new_error_simple(message: &str, position: &SourcePosition) -> Self {
+    pub fn new_error_simple(
+        kind: DiagnosticKind,
+        message: &str,
+        position: &SourcePosition,
+    ) -> Self {
         Diagnostic {
+            kind,
             level: IssueLevel::Error,
             message: message.to_string(),
             position: position.clone(),
             references: None,
+            notes: Vec::new(),
         }
     }
 
+    pub fn new_warning_simple(
+        kind: DiagnosticKind,
+        message: &str,
+        position: &SourcePosition,
+    ) -> Self {
+        Diagnostic {
+            kind,
+            level: IssueLevel::Warning,
+            message: message.to_string(),
+            position: position.clone(),
+            references: None,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn new_lint_simple(kind: DiagnosticKind, message: &str, position: &SourcePosition) -> Self {
+        Diagnostic {
+            kind,
+            level: IssueLevel::Lint,
+            message: message.to_string(),
+            position: position.clone(),
+            references: None,
+            notes: Vec::new(),
+        }
+    }
+
+    /// A lint that also points at other relevant places, like where a name was declared
+    pub fn new_lint_with_references(
+        kind: DiagnosticKind,
+        message: &str,
+        position: &SourcePosition,
+        references: Vec<SourcePosition>,
+    ) -> Self {
+        Diagnostic {
+            level: IssueLevel::Lint,
+            message: message.to_string(),
+            position: position.clone(),
+            kind,
+            references: Some(references),
+            notes: Vec::new(),
+        }
+    }
+
+    /// An error that also points at other relevant places, like the first definition of a duplicate
+    pub fn new_error_with_references(
+        kind: DiagnosticKind,
+        message: &str,
+        position: &SourcePosition,
+        references: Vec<SourcePosition>,
+    ) -> Self {
+        Diagnostic {
+            level: IssueLevel::Error,
+            message: message.to_string(),
+            position: position.clone(),
+            kind,
+            references: Some(references),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn level(&self) -> &IssueLevel {
+        &self.level
+    }
+
+    /// The same diagnostic reported at a different level
+    pub fn with_level(self, level: IssueLevel) -> Self {
+        Diagnostic { level, ..self }
+    }
+
+    /// The same diagnostic with another note under the caret
+    pub fn with_note(mut self, note: &str) -> Self {
+        self.notes.push(note.to_string());
+        self
+    }
+
+    pub fn kind(&self) -> DiagnosticKind {
+        self.kind
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.kind.code()
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn position(&self) -> &SourcePosition {
+        &self.position
+    }
+
+    pub fn references(&self) -> &[SourcePosition] {
+        self.references.as_deref().unwrap_or_default()
+    }
+
+    pub fn notes(&self) -> &[String] {
+        &self.notes
+    }
+
     pub fn display(&self, source: &str) -> String {
-        format!(
-            "{:?} in {}:{}:{}\n{}",
-            self.level,
+        let mut buffer = format!(
+            "{}[{}]: {}:{}:{}\n{}",
+            self.level.name(),
+            self.kind.code(),
             self.position.filename,
             self.position.line,
             self.position.column,
-            create_rich_diagnostic_message(&self.position, source, &self.message)
-        )
+            create_rich_diagnostic_message(&self.position, source, &self.message, &self.notes)
+        );
+        for reference in self.references() {
+            buffer.push_str(&format!(
+                "note: see also {}:{}:{}\n\n",
+                reference.filename, reference.line, reference.column
+            ));
+        }
+        buffer
     }
 }
 
+/// Find the candidate closest to `name`, if any are close enough to plausibly be a typo
+///
+/// Ties go to whichever candidate comes first
+pub fn did_you_mean<'a, I>(name: &str, candidates: I) -> Option<&'a str>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    // Short names are easy to mistype into something totally different, so scale the allowance with the length
+    let max_distance = name.chars().count().div_ceil(3).max(1);
+    let mut best: Option<(&str, usize)> = None;
+    for candidate in candidates {
+        let distance = edit_distance(name, candidate);
+        if distance == 0 || distance > max_distance {
+            continue;
+        }
+        match best {
+            Some((_, best_distance)) if best_distance <= distance => {}
+            _ => best = Some((candidate, distance)),
+        }
+    }
+    best.map(|(candidate, _)| candidate)
+}
+
+/// Levenshtein distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b_chars: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b_chars.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b_chars.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            let insertion = current[j] + 1;
+            let deletion = previous[j + 1] + 1;
+            current.push(substitution.min(insertion).min(deletion));
+        }
+        previous = current;
+    }
+    previous[b_chars.len()]
+}
+
 /// Create a nice diagnostic message that includes the source code context
-fn create_rich_diagnostic_message(position: &SourcePosition, input: &str, message: &str) -> String {
+///
+/// Line numbers are right-aligned to the widest one shown, so the `|` separators and the caret line up. Each note gets its own line under the caret
+fn create_rich_diagnostic_message(
+    position: &SourcePosition,
+    input: &str,
+    message: &str,
+    notes: &[String],
+) -> String {
     let mut lines = input.lines();
     let mut buffer = String::new();
+    let width = (position.line + 1).to_string().len();
+    let gutter = |line: usize| format!(" {:>width$} |", line, width = width);
 
     // Get the line before
     if position.line > 0 {
         if let Some(line) = lines.nth(position.line - 1) {
-            buffer.push_str(&format!(" {} |", position.line - 1));
+            buffer.push_str(&gutter(position.line - 1));
             buffer.push_str(line);
             buffer.push('\n'); // Add a newline after the line
         }
@@ -55,20 +406,24 @@ fn create_rich_diagnostic_message(position: &SourcePosition, input: &str, messag
 
     // Get the primary line, and add an error message
     if let Some(line) = lines.next() {
-        let align = format!(" {} |", position.line);
+        let align = gutter(position.line);
         buffer.push_str(&align);
         buffer.push_str(line);
         buffer.push('\n'); // Add a newline after the line
                            // Add spaces until we reach the column, then place a caret (`^`)
-        let caret_position = " ".repeat(position.column + align.len()) + "^";
-        buffer.push_str(&caret_position);
+        let indent = " ".repeat(position.column + align.len());
+        buffer.push_str(&indent);
+        buffer.push('^');
         buffer.push_str(message);
         buffer.push('\n');
+        for note in notes {
+            buffer.push_str(&format!("{}= note: {}\n", indent, note));
+        }
     }
 
     // Get the line after
     if let Some(line) = lines.next() {
-        buffer.push_str(&format!(" {} |", position.line + 1));
+        buffer.push_str(&gutter(position.line + 1));
         buffer.push_str(line);
         buffer.push('\n'); // Add a newline after the line
     }
@@ -76,3 +431,128 @@ fn create_rich_diagnostic_message(position: &SourcePosition, input: &str, messag
 
     buffer
 }
+
+// -------------------- Unit Tests --------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suggest_close_names() {
+        let candidates = ["count", "total", "name"];
+        assert_eq!(did_you_mean("coutn", candidates), Some("count"));
+        assert_eq!(did_you_mean("totl", candidates), Some("total"));
+        assert_eq!(did_you_mean("xyz", candidates), None);
+        // An exact match isn't a suggestion
+        assert_eq!(did_you_mean("name", candidates), None);
+    }
+
+    #[test]
+    fn display_includes_code() {
+        let position = SourcePosition {
+            filename: "test.iona".to_string(),
+            line: 0,
+            column: 4,
+        };
+        let diagnostic =
+            Diagnostic::new_error_simple(DiagnosticKind::Syntax, "expected `;`", &position);
+        assert_eq!(diagnostic.code(), "E0001");
+        assert!(diagnostic
+            .display("let x")
+            .starts_with("error[E0001]: test.iona:0:4\n"));
+        let warning = Diagnostic::new_warning_simple(
+            DiagnosticKind::UnusedVariable,
+            "unused variable `x`",
+            &position,
+        );
+        assert!(warning.display("let x").starts_with("warning[W0001]: "));
+    }
+
+    #[test]
+    fn gutters_are_aligned() {
+        let source = (0..12)
+            .map(|i| format!("line{}", i))
+            .collect::<Vec<String>>()
+            .join("\n");
+        let position = SourcePosition {
+            filename: "test.iona".to_string(),
+            line: 10,
+            column: 2,
+        };
+        let rendered = create_rich_diagnostic_message(&position, &source, "oops", &[]);
+        assert_eq!(
+            rendered,
+            "  9 |line9\n 10 |line10\n       ^oops\n 11 |line11\n\n"
+        );
+    }
+
+    #[test]
+    fn notes_under_the_caret() {
+        let position = SourcePosition {
+            filename: "test.iona".to_string(),
+            line: 1,
+            column: 15,
+        };
+        let diagnostic = Diagnostic::new_error_simple(
+            DiagnosticKind::MismatchedTypes,
+            "mismatched types: expected Integer, found String",
+            &position,
+        )
+        .with_note("because parameter `a` is declared as Integer")
+        .with_note("see test.iona:0:7");
+        assert_eq!(diagnostic.notes().len(), 2);
+        assert_eq!(
+            diagnostic.display("fn add(a: Int) -> Int {\n    return add(\"1\");\n}"),
+            concat!(
+                "error[E0009]: test.iona:1:15\n",
+                " 0 |fn add(a: Int) -> Int {\n",
+                " 1 |    return add(\"1\");\n",
+                "                   ^mismatched types: expected Integer, found String\n",
+                "                   = note: because parameter `a` is declared as Integer\n",
+                "                   = note: see test.iona:0:7\n",
+                " 2 |}\n",
+                "\n",
+            )
+        );
+    }
+
+    #[test]
+    fn lint_levels() {
+        let position = SourcePosition {
+            filename: "test.iona".to_string(),
+            line: 0,
+            column: 0,
+        };
+        let diagnostics = vec![
+            Diagnostic::new_lint_simple(DiagnosticKind::UnusedImport, "unused import", &position),
+            Diagnostic::new_lint_simple(DiagnosticKind::UnusedVariable, "unused var", &position),
+            Diagnostic::new_lint_simple(DiagnosticKind::DeadCode, "never called", &position),
+            Diagnostic::new_warning_simple(
+                DiagnosticKind::UncheckedIndex,
+                "might be out of bounds",
+                &position,
+            ),
+            Diagnostic::new_error_simple(DiagnosticKind::Syntax, "expected `;`", &position),
+        ];
+        let levels = LintLevels::from([
+            ("unused".to_string(), LintLevel::Warn),
+            ("unused_imports".to_string(), LintLevel::Deny),
+            ("dead_code".to_string(), LintLevel::Allow),
+            ("unchecked_index".to_string(), LintLevel::Lint),
+        ]);
+        let levels = apply_lint_levels(diagnostics, &levels)
+            .iter()
+            .map(|d| (d.message().to_string(), d.level().clone()))
+            .collect::<Vec<(String, IssueLevel)>>();
+        assert_eq!(
+            levels,
+            vec![
+                ("unused import".to_string(), IssueLevel::Error),
+                ("unused var".to_string(), IssueLevel::Warning),
+                ("might be out of bounds".to_string(), IssueLevel::Lint),
+                ("expected `;`".to_string(), IssueLevel::Error),
+            ]
+        );
+    }
+}
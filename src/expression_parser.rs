@@ -16,6 +16,8 @@
      └─ parse_index() → IndexAccess (when left is followed by square brackets)
 */
 
+use std::fmt;
+
 use crate::lexer::Symbol;
 use crate::parser::*;
 
@@ -33,6 +35,13 @@ pub enum Expr {
         object: Box<Expr>,
         property: String,
     },
+    /// A variant of a known enum, like `Status.Alive`
+    ///
+    /// The parser can't tell this apart from field access, so `typecheck::resolve_enum_variants` rewrites the matching `PropertyAccess` nodes once the enums are known
+    EnumVariant {
+        enum_name: String,
+        variant: String,
+    },
 
     // Function and method calls
     FunctionCall {
@@ -61,6 +70,14 @@ pub enum Expr {
         object: Box<Expr>,
         index: Box<Expr>,
     },
+
+    // Conditional expression, `if cond then a else b`
+    #[allow(clippy::enum_variant_names)] // `If` alone reads like the statement
+    IfExpr {
+        cond: Box<Expr>,
+        then: Box<Expr>,
+        els: Box<Expr>,
+    },
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -81,6 +98,126 @@ pub enum UnaryOperator {
     Negate, // -
 }
 
+impl BinaryOperator {
+    /// Binding strength of the operator, matching the precedence table used by the parser
+    const fn precedence(&self) -> u8 {
+        match self {
+            BinaryOperator::Or => 1,
+            BinaryOperator::And => 2,
+            BinaryOperator::LessThan | BinaryOperator::GreaterThan => 3,
+            BinaryOperator::Add | BinaryOperator::Subtract => 4,
+            BinaryOperator::Multiply | BinaryOperator::Divide | BinaryOperator::Modulo => 5,
+        }
+    }
+}
+
+impl fmt::Display for BinaryOperator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match self {
+            BinaryOperator::Add => "+",
+            BinaryOperator::Subtract => "-",
+            BinaryOperator::Multiply => "*",
+            BinaryOperator::Divide => "/",
+            BinaryOperator::Modulo => "%",
+            BinaryOperator::LessThan => "<",
+            BinaryOperator::GreaterThan => ">",
+            BinaryOperator::And => "and",
+            BinaryOperator::Or => "or",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+/// Write a comma separated list of expressions
+fn write_expr_list(f: &mut fmt::Formatter, items: &[Expr]) -> fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", item)?;
+    }
+    Ok(())
+}
+
+/// Pretty print an expression back into Iona source code
+///
+/// Parentheses are only added where they are needed to preserve the grouping of the tree
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::IntegerLiteral(n) => write!(f, "{}", n),
+            Expr::FloatLiteral(x) => write!(f, "{:?}", x),
+            Expr::StringLiteral(s) => write!(f, "\"{}\"", s),
+            Expr::Variable(name) => write!(f, "{}", name),
+            Expr::PropertyAccess { object, property } => write!(f, "{}.{}", object, property),
+            Expr::EnumVariant { enum_name, variant } => write!(f, "{}.{}", enum_name, variant),
+            Expr::FunctionCall { name, arguments } => {
+                write!(f, "{}(", name)?;
+                write_expr_list(f, arguments)?;
+                write!(f, ")")
+            }
+            Expr::MethodCall {
+                object,
+                method,
+                arguments,
+            } => {
+                write!(f, "{}.{}(", object, method)?;
+                write_expr_list(f, arguments)?;
+                write!(f, ")")
+            }
+            Expr::BinaryOp {
+                left,
+                operator,
+                right,
+            } => {
+                // Operators are left associative, so a right child of equal precedence needs grouping
+                let needs_parens = |child: &Expr, is_right: bool| match child {
+                    Expr::BinaryOp {
+                        operator: inner, ..
+                    } => {
+                        inner.precedence() < operator.precedence()
+                            || (is_right && inner.precedence() == operator.precedence())
+                    }
+                    // The `else` branch would swallow anything after it
+                    Expr::IfExpr { .. } => true,
+                    _ => false,
+                };
+                if needs_parens(left, false) {
+                    write!(f, "({})", left)?;
+                } else {
+                    write!(f, "{}", left)?;
+                }
+                write!(f, " {} ", operator)?;
+                if needs_parens(right, true) {
+                    write!(f, "({})", right)
+                } else {
+                    write!(f, "{}", right)
+                }
+            }
+            Expr::UnaryOp { operator, operand } => {
+                let symbol = match operator {
+                    UnaryOperator::Negate => "-",
+                };
+                match operand.as_ref() {
+                    Expr::BinaryOp { .. } | Expr::IfExpr { .. } => {
+                        write!(f, "{}({})", symbol, operand)
+                    }
+                    _ => write!(f, "{}{}", symbol, operand),
+                }
+            }
+            Expr::IndexAccess { object, index } => write!(f, "{}[{}]", object, index),
+            Expr::IfExpr { cond, then, els } => {
+                write!(f, "if {} then {} else {}", cond, then, els)
+            }
+        }
+    }
+}
+
+/// The operand of a unary operator is parsed at this precedence, so that only `.` binds tighter than a unary operator
+///
+/// That makes `-2 * 3` into `(-2) * 3`, and `-a.b` into `-(a.b)`
+const UNARY_OPERAND_PRECEDENCE: u8 = precedence(&Symbol::Dot);
+
 // Precedence levels for operators
 const fn precedence(op: &Symbol) -> u8 {
     match op {
@@ -94,13 +231,19 @@ const fn precedence(op: &Symbol) -> u8 {
     }
 }
 
-impl Parser {
+impl Parser<'_> {
     pub fn parse_expr(&mut self, min_precedence: u8) -> ParserOutput<Expr> {
-        // Track our recursion depth
+        // Track our recursion depth, which only counts expressions nested inside of each other
         self.recursion_counter += 1;
         if self.recursion_counter > 30 {
             panic!("maximum recursion depth exceeded while parsing an expression!")
         }
+        let expr = self.parse_nested_expr(min_precedence);
+        self.recursion_counter -= 1;
+        expr
+    }
+
+    fn parse_nested_expr(&mut self, min_precedence: u8) -> ParserOutput<Expr> {
         // First parse a prefix expression
         let mut left = self.parse_prefix();
         if left.output.is_none() {
@@ -109,18 +252,17 @@ impl Parser {
 
         // Keep parsing infix expressions as long as they have higher precedence
         loop {
-            self.skip_whitespace(); // Safe to skip here - we're looking for infix operators
-
-            if let Some(op_precedence) = self.peek_precedence() {
-                if op_precedence < min_precedence {
-                    break;
-                }
-                left = self.parse_infix(left.output.unwrap());
-                if left.output.is_none() {
-                    break;
+            // An operator can carry the expression onto the next line, otherwise the newline is left to end the statement
+            self.skip_spaces();
+            match self.peek_precedence() {
+                Some(op_precedence) if op_precedence >= min_precedence => {
+                    self.skip_whitespace();
+                    left = self.parse_infix(left.output.unwrap());
+                    if left.output.is_none() {
+                        break;
+                    }
                 }
-            } else {
-                break;
+                _ => break,
             }
         }
 
@@ -134,8 +276,7 @@ impl Parser {
             Symbol::Dash => {
                 self.consume();
                 self.skip_whitespace(); // Safe to skip after consuming the unary operator
-                                        // Parse the operand with high precedence to ensure right association
-                let operand = self.parse_expr(6);
+                let operand = self.parse_expr(UNARY_OPERAND_PRECEDENCE);
                 if operand.output.is_none() {
                     return operand;
                 }
@@ -144,6 +285,7 @@ impl Parser {
                     operand: Box::new(operand.output.unwrap()),
                 })
             }
+            Symbol::If => self.parse_if_expr(),
             Symbol::Integer(n) => {
                 self.consume();
                 ParserOutput::okay(Expr::IntegerLiteral(*n))
@@ -166,35 +308,61 @@ impl Parser {
             }
             Symbol::Identifier(name) => {
                 self.consume();
-                self.skip_whitespace(); // Safe to skip after identifier
-                                        // Look ahead to see if this is a function call
-                if self.peek().symbol == Symbol::ParenOpen {
+                // Look ahead to see if this is a function call
+                if self.lookahead().symbol == Symbol::ParenOpen {
+                    self.skip_whitespace();
                     self.parse_function_call(name.clone())
                 } else {
                     ParserOutput::okay(Expr::Variable(name.clone()))
                 }
             }
             other => self.single_error(&format!(
-                "Expected the beginning of an expression, but found {:?}",
+                "Expected the beginning of an expression, but found {}",
                 other
             )),
         }
     }
 
+    /// Parse a conditional expression like `if a > b then a else b`
+    ///
+    /// Both branches are required, since the expression always needs a value. Statement `if`s are handled by the statement parser before we get here
+    fn parse_if_expr(&mut self) -> ParserOutput<Expr> {
+        self.consume(); // consume if
+        self.skip_whitespace();
+        self.parse_expr(0).and_then(|cond| {
+            self.with_whitespace(|p| p.then_ignore(Symbol::Then))
+                .and_then(|_| self.parse_expr(0))
+                .and_then(|then| {
+                    self.with_whitespace(|p| p.then_ignore(Symbol::Else))
+                        .and_then(|_| self.parse_expr(0))
+                        .map(|els| Expr::IfExpr {
+                            cond: Box::new(cond),
+                            then: Box::new(then),
+                            els: Box::new(els),
+                        })
+                })
+        })
+    }
+
     fn parse_function_call(&mut self, name: String) -> ParserOutput<Expr> {
         // Consume opening parenthesis
         // self.then_ignore(Symbol::ParenOpen);
         self.consume();
 
-        // Parse comma-separated arguments
-        self.parse_list_comma_separated(|p| p.parse_expr(0))
-            .and_then(|args| {
-                self.then_ignore(Symbol::ParenClose)
-                    .map(|_| Expr::FunctionCall {
-                        name: name,
-                        arguments: args,
-                    })
-            })
+        // Parse comma-separated arguments, which may be spread over several lines (with comments, which leave their newline behind)
+        self.skip_whitespace();
+        let arguments = if self.peek().symbol == Symbol::ParenClose {
+            ParserOutput::okay(Vec::new())
+        } else {
+            self.parse_list_comma_separated(|p| p.with_whitespace(|p| p.parse_expr(0)))
+        };
+        arguments.and_then(|args| {
+            self.then_ignore(Symbol::ParenClose)
+                .map(|_| Expr::FunctionCall {
+                    name,
+                    arguments: args,
+                })
+        })
     }
 
     fn parse_infix(&mut self, left: Expr) -> ParserOutput<Expr> {
@@ -236,24 +404,21 @@ impl Parser {
                         if self.peek().symbol == Symbol::ParenOpen {
                             // Method call
                             self.consume();
-                            let arguments: Vec<Expr>;
-                            if self.peek().symbol == Symbol::ParenClose {
-                                arguments = vec![]
+                            let arguments: Vec<Expr> = if self.peek().symbol == Symbol::ParenClose {
+                                vec![]
                             } else {
                                 let possible = self.parse_list_comma_separated(|p| p.parse_expr(0));
-                                if possible.output.is_none() {
-                                    return possible.transmute_error::<Expr>();
-                                } else {
-                                    arguments = possible.output.unwrap();
+                                match possible.output {
+                                    Some(args) => args,
+                                    None => return possible.transmute_error::<Expr>(),
                                 }
                             };
-                            self.then_ignore(Symbol::ParenClose);
-
-                            ParserOutput::okay(Expr::MethodCall {
-                                object: Box::new(left),
-                                method: name.clone(),
-                                arguments,
-                            })
+                            self.then_ignore(Symbol::ParenClose)
+                                .map(|_| Expr::MethodCall {
+                                    object: Box::new(left),
+                                    method: name.clone(),
+                                    arguments,
+                                })
                         } else {
                             // Property access
                             ParserOutput::okay(Expr::PropertyAccess {
@@ -298,9 +463,10 @@ impl Parser {
         }
     }
 
+    /// The precedence of the next infix operator, which may be on a later line
     fn peek_precedence(&self) -> Option<u8> {
         // If we have any of these symbols, run the precedence function on it
-        match &self.peek().symbol {
+        match &self.lookahead().symbol {
             Symbol::Plus
             | Symbol::Dash
             | Symbol::Times
@@ -311,7 +477,7 @@ impl Parser {
             | Symbol::And
             | Symbol::Or
             | Symbol::Dot
-            | Symbol::BracketOpen => Some(precedence(&self.peek().symbol)),
+            | Symbol::BracketOpen => Some(precedence(&self.lookahead().symbol)),
             _ => None,
         }
     }
@@ -329,7 +495,7 @@ mod tests {
         let program_text = "5";
         // Lex
         let mut lexer = Lexer::new("test");
-        lexer.lex(&program_text);
+        lexer.lex(program_text);
         // Parse
         let mut parser = Parser::new(lexer.token_stream);
         let out = parser.parse_expr(0);
@@ -342,7 +508,7 @@ mod tests {
         let program_text = "5.39";
         // Lex
         let mut lexer = Lexer::new("test");
-        lexer.lex(&program_text);
+        lexer.lex(program_text);
         // Parse
         let mut parser = Parser::new(lexer.token_stream);
         let out = parser.parse_expr(0);
@@ -355,7 +521,7 @@ mod tests {
         let program_text = "-5";
         // Lex
         let mut lexer = Lexer::new("test");
-        lexer.lex(&program_text);
+        lexer.lex(program_text);
         println!("{:#?}", lexer.token_stream);
         // Parse
         let mut parser = Parser::new(lexer.token_stream);
@@ -373,7 +539,7 @@ mod tests {
         let program_text = "2+5";
         // Lex
         let mut lexer = Lexer::new("test");
-        lexer.lex(&program_text);
+        lexer.lex(program_text);
         println!("{:#?}", lexer.token_stream);
         // Parse
         let mut parser = Parser::new(lexer.token_stream);
@@ -392,7 +558,7 @@ mod tests {
         let program_text = "2 + 5";
         // Lex
         let mut lexer = Lexer::new("test");
-        lexer.lex(&program_text);
+        lexer.lex(program_text);
         println!("{:#?}", lexer.token_stream);
         // Parse
         let mut parser = Parser::new(lexer.token_stream);
@@ -411,7 +577,7 @@ mod tests {
         let program_text = "add(2, 5)";
         // Lex
         let mut lexer = Lexer::new("test");
-        lexer.lex(&program_text);
+        lexer.lex(program_text);
         println!("{:#?}", lexer.token_stream);
         // Parse
         let mut parser = Parser::new(lexer.token_stream);
@@ -429,7 +595,7 @@ mod tests {
         let program_text = "add(2, 5 * a)";
         // Lex
         let mut lexer = Lexer::new("test");
-        lexer.lex(&program_text);
+        lexer.lex(program_text);
         println!("{:#?}", lexer.token_stream);
         // Parse
         let mut parser = Parser::new(lexer.token_stream);
@@ -448,4 +614,145 @@ mod tests {
         };
         assert_eq!(expected, out.output.unwrap());
     }
+
+    fn parse(program_text: &str) -> Expr {
+        let mut lexer = Lexer::new("test");
+        lexer.lex(program_text);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_expr(0);
+        assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+        out.output.unwrap()
+    }
+
+    #[test]
+    fn unary_minus_binds_tighter_than_multiply() {
+        let expected = Expr::BinaryOp {
+            left: Box::new(Expr::UnaryOp {
+                operator: UnaryOperator::Negate,
+                operand: Box::new(Expr::IntegerLiteral(2)),
+            }),
+            operator: BinaryOperator::Multiply,
+            right: Box::new(Expr::IntegerLiteral(3)),
+        };
+        assert_eq!(expected, parse("-2 * 3"));
+    }
+
+    #[test]
+    fn unary_minus_binds_looser_than_dot() {
+        let expected = Expr::UnaryOp {
+            operator: UnaryOperator::Negate,
+            operand: Box::new(Expr::PropertyAccess {
+                object: Box::new(Expr::Variable("a".to_string())),
+                property: "b".to_string(),
+            }),
+        };
+        assert_eq!(expected, parse("-a.b"));
+    }
+
+    #[test]
+    fn unary_minus_after_binary_operator() {
+        let expected = Expr::BinaryOp {
+            left: Box::new(Expr::IntegerLiteral(2)),
+            operator: BinaryOperator::Subtract,
+            right: Box::new(Expr::UnaryOp {
+                operator: UnaryOperator::Negate,
+                operand: Box::new(Expr::IntegerLiteral(3)),
+            }),
+        };
+        assert_eq!(expected, parse("2 - -3"));
+    }
+
+    #[test]
+    fn chained_calls_and_property_access() {
+        // ((foo()).bar()).baz
+        let expected = Expr::PropertyAccess {
+            object: Box::new(Expr::MethodCall {
+                object: Box::new(Expr::FunctionCall {
+                    name: "foo".to_string(),
+                    arguments: vec![],
+                }),
+                method: "bar".to_string(),
+                arguments: vec![],
+            }),
+            property: "baz".to_string(),
+        };
+        assert_eq!(expected, parse("foo().bar().baz"));
+    }
+
+    #[test]
+    fn chained_calls_with_arguments() {
+        let expected = Expr::MethodCall {
+            object: Box::new(Expr::FunctionCall {
+                name: "load".to_string(),
+                arguments: vec![Expr::Variable("path".to_string())],
+            }),
+            method: "get".to_string(),
+            arguments: vec![Expr::IntegerLiteral(1)],
+        };
+        assert_eq!(expected, parse("load(path).get(1)"));
+    }
+
+    #[test]
+    fn unclosed_method_call() {
+        let mut lexer = Lexer::new("test");
+        lexer.lex("foo().bar(1");
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_expr(0);
+        assert!(out.output.is_none());
+        assert!(!out.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn expr_display_round_trip() {
+        let program_text = "(a + b) * -c > foo(1, 2.5)";
+        // Lex
+        let mut lexer = Lexer::new("test");
+        lexer.lex(program_text);
+        // Parse
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_expr(0);
+        assert_eq!(
+            out.output.unwrap().to_string(),
+            "(a + b) * -c > foo(1, 2.5)"
+        );
+    }
+
+    #[test]
+    fn comments_between_arguments() {
+        let out = parse("foo(\n    a, # first\n    # on its own line\n    b # last\n)");
+        let expected = Expr::FunctionCall {
+            name: "foo".to_string(),
+            arguments: vec![
+                Expr::Variable("a".to_string()),
+                Expr::Variable("b".to_string()),
+            ],
+        };
+        assert_eq!(expected, out);
+    }
+
+    #[test]
+    fn calls_without_arguments_as_arguments() {
+        let empty = || Expr::FunctionCall {
+            name: "make_list".to_string(),
+            arguments: vec![],
+        };
+        let expected = Expr::FunctionCall {
+            name: "max".to_string(),
+            arguments: vec![empty(), empty()],
+        };
+        assert_eq!(expected, parse("max(make_list(), make_list())"));
+    }
+
+    #[test]
+    fn depth_limit_only_counts_nesting() {
+        let program_text = "a + 1\n".repeat(50);
+        let mut lexer = Lexer::new("test");
+        lexer.lex(&program_text);
+        let mut parser = Parser::new(lexer.token_stream);
+        for _ in 0..50 {
+            assert!(parser.parse_expr(0).output.is_some());
+            parser.skip_whitespace();
+        }
+        assert_eq!(parser.recursion_counter, 0);
+    }
 }
@@ -0,0 +1,715 @@
+//! Write the AST (`--emit=ast`) or the tokens (`--emit=tokens --json`) as JSON, for editor plugins and other tools
+//!
+//! The layout follows serde's defaults: structs are objects, unit variants are strings, and any other variant is an object with the variant's name as its only key. Object keys keep the order they're written in, so the output is the same from run to run
+
+use std::fmt;
+
+use crate::diagnostics::Diagnostic;
+use crate::expression_parser::{BinaryOperator, Expr, UnaryOperator};
+use crate::lexer::{SourcePosition, Symbol, Token};
+use crate::parser::*;
+
+/// A JSON value, objects keep their keys in order
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+fn object(fields: Vec<(&str, Json)>) -> Json {
+    Json::Object(
+        fields
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect(),
+    )
+}
+
+/// A variant that carries data, like `{"Array": "Integer"}`
+fn variant(name: &str, value: Json) -> Json {
+    object(vec![(name, value)])
+}
+
+/// A variant without any data is just its name
+fn unit(name: &str) -> Json {
+    Json::String(name.to_string())
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Integer(n) => write!(f, "{}", n),
+            // JSON has no infinity, and `{:?}` always keeps a decimal point so the value reads back as a float
+            Json::Float(x) if x.is_finite() => write!(f, "{:?}", x),
+            Json::Float(_) => write!(f, "null"),
+            Json::String(s) => write_string(f, s),
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+/// Convert a value to JSON
+pub trait ToJson {
+    fn to_json(&self) -> Json;
+}
+
+impl ToJson for String {
+    fn to_json(&self) -> Json {
+        Json::String(self.clone())
+    }
+}
+
+impl ToJson for bool {
+    fn to_json(&self) -> Json {
+        Json::Bool(*self)
+    }
+}
+
+impl ToJson for i64 {
+    fn to_json(&self) -> Json {
+        Json::Integer(*self)
+    }
+}
+
+impl ToJson for usize {
+    fn to_json(&self) -> Json {
+        Json::Integer(*self as i64)
+    }
+}
+
+impl ToJson for f64 {
+    fn to_json(&self) -> Json {
+        Json::Float(*self)
+    }
+}
+
+impl<T: ToJson> ToJson for [T] {
+    fn to_json(&self) -> Json {
+        Json::Array(self.iter().map(ToJson::to_json).collect())
+    }
+}
+
+impl<T: ToJson> ToJson for Vec<T> {
+    fn to_json(&self) -> Json {
+        self.as_slice().to_json()
+    }
+}
+
+impl<T: ToJson> ToJson for Option<T> {
+    fn to_json(&self) -> Json {
+        match self {
+            Some(value) => value.to_json(),
+            None => Json::Null,
+        }
+    }
+}
+
+impl<T: ToJson> ToJson for Box<T> {
+    fn to_json(&self) -> Json {
+        self.as_ref().to_json()
+    }
+}
+
+impl ToJson for SourcePosition {
+    fn to_json(&self) -> Json {
+        object(vec![
+            ("filename", self.filename.to_json()),
+            ("line", self.line.to_json()),
+            ("column", self.column.to_json()),
+        ])
+    }
+}
+
+impl ToJson for Symbol {
+    fn to_json(&self) -> Json {
+        match self {
+            Symbol::Identifier(name) => variant("Identifier", name.to_json()),
+            Symbol::StringLiteral(value) => variant("StringLiteral", value.to_json()),
+            Symbol::Integer(value) => variant("Integer", value.to_json()),
+            Symbol::Float(value) => variant("Float", value.to_json()),
+            // The rest don't carry anything, so their debug names are their variant names
+            other => unit(&format!("{:?}", other)),
+        }
+    }
+}
+
+impl ToJson for Token {
+    fn to_json(&self) -> Json {
+        object(vec![
+            ("symbol", self.symbol.to_json()),
+            ("pos", self.pos.to_json()),
+        ])
+    }
+}
+
+impl ToJson for Diagnostic {
+    fn to_json(&self) -> Json {
+        object(vec![
+            ("level", unit(self.level().name())),
+            ("code", Json::String(self.code().to_string())),
+            ("message", Json::String(self.message().to_string())),
+            ("position", self.position().to_json()),
+            ("notes", self.notes().to_json()),
+        ])
+    }
+}
+
+impl ToJson for Type {
+    fn to_json(&self) -> Json {
+        match self {
+            Type::Void => unit("Void"),
+            Type::Self_ => unit("Self_"),
+            Type::Integer => unit("Integer"),
+            Type::Float => unit("Float"),
+            Type::String => unit("String"),
+            Type::Boolean => unit("Boolean"),
+            Type::Size => unit("Size"),
+            Type::Byte => unit("Byte"),
+            Type::Auto => unit("Auto"),
+            Type::CType => unit("CType"),
+            Type::Array(inner) => variant("Array", inner.to_json()),
+            Type::Map(inner) => variant("Map", inner.to_json()),
+            Type::Shared(inner) => variant("Shared", inner.to_json()),
+            Type::Tuple(members) => variant("Tuple", members.to_json()),
+            Type::FixedArray(inner, len) => variant(
+                "FixedArray",
+                Json::Array(vec![inner.to_json(), len.to_json()]),
+            ),
+            Type::Generic(name) => variant("Generic", name.to_json()),
+            Type::Custom(name) => variant("Custom", name.to_json()),
+            Type::Optional(inner) => variant("Optional", inner.to_json()),
+        }
+    }
+}
+
+impl ToJson for DataProperties {
+    fn to_json(&self) -> Json {
+        match self {
+            DataProperties::Public => unit("Public"),
+            DataProperties::Export => unit("Export"),
+        }
+    }
+}
+
+impl ToJson for DataTraits {
+    fn to_json(&self) -> Json {
+        match self {
+            DataTraits::Eq => unit("Eq"),
+            DataTraits::Show => unit("Show"),
+        }
+    }
+}
+
+impl ToJson for Field {
+    fn to_json(&self) -> Json {
+        object(vec![
+            ("name", self.name.to_json()),
+            ("field_type", self.field_type.to_json()),
+            ("pos", self.pos.to_json()),
+        ])
+    }
+}
+
+impl ToJson for Attribute {
+    fn to_json(&self) -> Json {
+        match self {
+            Attribute::Cfg { key, value } => variant(
+                "Cfg",
+                object(vec![("key", key.to_json()), ("value", value.to_json())]),
+            ),
+            Attribute::Allow(lints) => variant("Allow", lints.to_json()),
+        }
+    }
+}
+
+impl ToJson for Struct {
+    fn to_json(&self) -> Json {
+        object(vec![
+            ("name", self.name.to_json()),
+            ("type_params", self.type_params.to_json()),
+            ("fields", self.fields.to_json()),
+            ("properties", self.properties.to_json()),
+            ("traits", self.traits.to_json()),
+            ("methods", self.methods.to_json()),
+            ("attributes", self.attributes.to_json()),
+            ("pos", self.pos.to_json()),
+        ])
+    }
+}
+
+impl ToJson for Enum {
+    fn to_json(&self) -> Json {
+        object(vec![
+            ("name", self.name.to_json()),
+            ("type_params", self.type_params.to_json()),
+            ("fields", self.fields.to_json()),
+            ("properties", self.properties.to_json()),
+            ("traits", self.traits.to_json()),
+            ("methods", self.methods.to_json()),
+            ("attributes", self.attributes.to_json()),
+            ("pos", self.pos.to_json()),
+        ])
+    }
+}
+
+impl ToJson for Import {
+    fn to_json(&self) -> Json {
+        object(vec![
+            ("file", self.file.to_json()),
+            ("items", self.items.to_json()),
+            ("pos", self.pos.to_json()),
+            ("reexport", self.reexport.to_json()),
+        ])
+    }
+}
+
+impl ToJson for FunctionProperties {
+    fn to_json(&self) -> Json {
+        match self {
+            FunctionProperties::Public => unit("Public"),
+            FunctionProperties::Export => unit("Export"),
+        }
+    }
+}
+
+impl ToJson for FunctionPermissions {
+    fn to_json(&self) -> Json {
+        match self {
+            FunctionPermissions::ReadFile => unit("ReadFile"),
+            FunctionPermissions::WriteFile => unit("WriteFile"),
+            FunctionPermissions::ReadConsole => unit("ReadConsole"),
+            FunctionPermissions::WriteConsole => unit("WriteConsole"),
+            FunctionPermissions::HTTPAny => unit("HTTPAny"),
+            FunctionPermissions::HTTPGet => unit("HTTPGet"),
+            FunctionPermissions::HTTPPost => unit("HTTPPost"),
+            FunctionPermissions::Custom(name) => variant("Custom", name.to_json()),
+        }
+    }
+}
+
+impl ToJson for ContractType {
+    fn to_json(&self) -> Json {
+        match self {
+            ContractType::Input => unit("Input"),
+            ContractType::Output => unit("Output"),
+        }
+    }
+}
+
+impl ToJson for FunctionContract {
+    fn to_json(&self) -> Json {
+        object(vec![
+            ("type_", self.type_.to_json()),
+            ("condition", self.condition.to_json()),
+            ("message", self.message.to_json()),
+            ("pos", self.pos.to_json()),
+        ])
+    }
+}
+
+impl ToJson for Function {
+    fn to_json(&self) -> Json {
+        object(vec![
+            ("name", self.name.to_json()),
+            ("pos", self.pos.to_json()),
+            ("type_params", self.type_params.to_json()),
+            ("args", self.args.to_json()),
+            ("returns", self.returns.to_json()),
+            ("properties", self.properties.to_json()),
+            ("permissions", self.permissions.to_json()),
+            ("contracts", self.contracts.to_json()),
+            ("statements", self.statements.to_json()),
+            ("attributes", self.attributes.to_json()),
+            ("is_extern", self.is_extern.to_json()),
+        ])
+    }
+}
+
+impl ToJson for ModuleMetadata {
+    fn to_json(&self) -> Json {
+        object(vec![
+            ("permissions", self.permissions.to_json()),
+            ("pos", self.pos.to_json()),
+        ])
+    }
+}
+
+impl ToJson for ASTNode {
+    fn to_json(&self) -> Json {
+        match self {
+            ASTNode::StructDeclaration(s) => variant("StructDeclaration", s.to_json()),
+            ASTNode::EnumDeclaration(e) => variant("EnumDeclaration", e.to_json()),
+            ASTNode::ImportStatement(i) => variant("ImportStatement", i.to_json()),
+            ASTNode::FunctionDeclaration(f) => variant("FunctionDeclaration", f.to_json()),
+            ASTNode::ModuleMetadata(m) => variant("ModuleMetadata", m.to_json()),
+        }
+    }
+}
+
+impl ToJson for Branch {
+    fn to_json(&self) -> Json {
+        object(vec![
+            ("condition", self.condition.to_json()),
+            ("computations", self.computations.to_json()),
+            ("pos", self.pos.to_json()),
+        ])
+    }
+}
+
+impl ToJson for Pattern {
+    fn to_json(&self) -> Json {
+        match self {
+            Pattern::Variant { name, binding } => variant(
+                "Variant",
+                object(vec![
+                    ("name", name.to_json()),
+                    ("binding", binding.to_json()),
+                ]),
+            ),
+        }
+    }
+}
+
+impl ToJson for Statement {
+    fn to_json(&self) -> Json {
+        match self {
+            Statement::FunctionCall { call, pos } => variant(
+                "FunctionCall",
+                object(vec![("call", call.to_json()), ("pos", pos.to_json())]),
+            ),
+            Statement::VariableDeclaration {
+                name,
+                type_,
+                value,
+                pos,
+            } => variant(
+                "VariableDeclaration",
+                object(vec![
+                    ("name", name.to_json()),
+                    ("type_", type_.to_json()),
+                    ("value", value.to_json()),
+                    ("pos", pos.to_json()),
+                ]),
+            ),
+            Statement::VariableMutation { name, value, pos } => variant(
+                "VariableMutation",
+                object(vec![
+                    ("name", name.to_json()),
+                    ("value", value.to_json()),
+                    ("pos", pos.to_json()),
+                ]),
+            ),
+            Statement::Conditional { branches, pos } => variant(
+                "Conditional",
+                object(vec![
+                    ("branches", branches.to_json()),
+                    ("pos", pos.to_json()),
+                ]),
+            ),
+            Statement::Match {
+                scrutinee,
+                branches,
+                pos,
+            } => variant(
+                "Match",
+                object(vec![
+                    ("scrutinee", scrutinee.to_json()),
+                    ("branches", branches.to_json()),
+                    ("pos", pos.to_json()),
+                ]),
+            ),
+            Statement::WhileLoop {
+                binding,
+                condition,
+                body,
+                pos,
+            } => variant(
+                "WhileLoop",
+                object(vec![
+                    ("binding", binding.to_json()),
+                    ("condition", condition.to_json()),
+                    ("body", body.to_json()),
+                    ("pos", pos.to_json()),
+                ]),
+            ),
+            Statement::Return { value, pos } => variant(
+                "Return",
+                object(vec![("value", value.to_json()), ("pos", pos.to_json())]),
+            ),
+            Statement::Assert { cond, message, pos } => variant(
+                "Assert",
+                object(vec![
+                    ("cond", cond.to_json()),
+                    ("message", message.to_json()),
+                    ("pos", pos.to_json()),
+                ]),
+            ),
+        }
+    }
+}
+
+impl ToJson for BinaryOperator {
+    fn to_json(&self) -> Json {
+        unit(match self {
+            BinaryOperator::Add => "Add",
+            BinaryOperator::Subtract => "Subtract",
+            BinaryOperator::Multiply => "Multiply",
+            BinaryOperator::Divide => "Divide",
+            BinaryOperator::Modulo => "Modulo",
+            BinaryOperator::LessThan => "LessThan",
+            BinaryOperator::GreaterThan => "GreaterThan",
+            BinaryOperator::And => "And",
+            BinaryOperator::Or => "Or",
+        })
+    }
+}
+
+impl ToJson for UnaryOperator {
+    fn to_json(&self) -> Json {
+        match self {
+            UnaryOperator::Negate => unit("Negate"),
+        }
+    }
+}
+
+impl ToJson for Expr {
+    fn to_json(&self) -> Json {
+        match self {
+            Expr::IntegerLiteral(value) => variant("IntegerLiteral", value.to_json()),
+            Expr::FloatLiteral(value) => variant("FloatLiteral", value.to_json()),
+            Expr::StringLiteral(value) => variant("StringLiteral", value.to_json()),
+            Expr::Variable(name) => variant("Variable", name.to_json()),
+            Expr::PropertyAccess {
+                object: o,
+                property,
+            } => variant(
+                "PropertyAccess",
+                object(vec![
+                    ("object", o.to_json()),
+                    ("property", property.to_json()),
+                ]),
+            ),
+            Expr::EnumVariant {
+                enum_name,
+                variant: v,
+            } => variant(
+                "EnumVariant",
+                object(vec![
+                    ("enum_name", enum_name.to_json()),
+                    ("variant", v.to_json()),
+                ]),
+            ),
+            Expr::FunctionCall { name, arguments } => variant(
+                "FunctionCall",
+                object(vec![
+                    ("name", name.to_json()),
+                    ("arguments", arguments.to_json()),
+                ]),
+            ),
+            Expr::MethodCall {
+                object: o,
+                method,
+                arguments,
+            } => variant(
+                "MethodCall",
+                object(vec![
+                    ("object", o.to_json()),
+                    ("method", method.to_json()),
+                    ("arguments", arguments.to_json()),
+                ]),
+            ),
+            Expr::BinaryOp {
+                left,
+                operator,
+                right,
+            } => variant(
+                "BinaryOp",
+                object(vec![
+                    ("left", left.to_json()),
+                    ("operator", operator.to_json()),
+                    ("right", right.to_json()),
+                ]),
+            ),
+            Expr::UnaryOp { operator, operand } => variant(
+                "UnaryOp",
+                object(vec![
+                    ("operator", operator.to_json()),
+                    ("operand", operand.to_json()),
+                ]),
+            ),
+            Expr::IndexAccess { object: o, index } => variant(
+                "IndexAccess",
+                object(vec![("object", o.to_json()), ("index", index.to_json())]),
+            ),
+            Expr::IfExpr { cond, then, els } => variant(
+                "IfExpr",
+                object(vec![
+                    ("cond", cond.to_json()),
+                    ("then", then.to_json()),
+                    ("els", els.to_json()),
+                ]),
+            ),
+        }
+    }
+}
+
+// -------------------- Unit Tests --------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use std::iter::Peekable;
+    use std::str::Chars;
+
+    /// Just enough of a JSON reader to check that the output reads back as the same value
+    fn read(chars: &mut Peekable<Chars>) -> Json {
+        let literal = |chars: &mut Peekable<Chars>, word: &str, value: Json| {
+            for expected in word.chars() {
+                assert_eq!(chars.next(), Some(expected));
+            }
+            value
+        };
+        match *chars.peek().unwrap() {
+            'n' => literal(chars, "null", Json::Null),
+            't' => literal(chars, "true", Json::Bool(true)),
+            'f' => literal(chars, "false", Json::Bool(false)),
+            '"' => Json::String(read_string(chars)),
+            '[' => {
+                chars.next();
+                let mut items = Vec::new();
+                while chars.peek() != Some(&']') {
+                    items.push(read(chars));
+                    if chars.peek() == Some(&',') {
+                        chars.next();
+                    }
+                }
+                chars.next();
+                Json::Array(items)
+            }
+            '{' => {
+                chars.next();
+                let mut fields = Vec::new();
+                while chars.peek() != Some(&'}') {
+                    let key = read_string(chars);
+                    assert_eq!(chars.next(), Some(':'));
+                    fields.push((key, read(chars)));
+                    if chars.peek() == Some(&',') {
+                        chars.next();
+                    }
+                }
+                chars.next();
+                Json::Object(fields)
+            }
+            _ => {
+                let mut number = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_ascii_digit() || "-+.eE".contains(c) {
+                        number.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                if number.contains(['.', 'e', 'E']) {
+                    Json::Float(number.parse().unwrap())
+                } else {
+                    Json::Integer(number.parse().unwrap())
+                }
+            }
+        }
+    }
+
+    fn read_string(chars: &mut Peekable<Chars>) -> String {
+        assert_eq!(chars.next(), Some('"'));
+        let mut s = String::new();
+        loop {
+            match chars.next().unwrap() {
+                '"' => return s,
+                '\\' => match chars.next().unwrap() {
+                    'n' => s.push('\n'),
+                    'r' => s.push('\r'),
+                    't' => s.push('\t'),
+                    'u' => {
+                        let code: String = chars.by_ref().take(4).collect();
+                        s.push(char::from_u32(u32::from_str_radix(&code, 16).unwrap()).unwrap());
+                    }
+                    other => s.push(other),
+                },
+                c => s.push(c),
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip_ast() {
+        const PROGRAM: &str = r#"import util with double;
+
+fn main() -> Int {
+    let path: String = "C:\temp";
+    let ratio: Float = 2.5;
+    if ratio > 1.0 {
+        return double(-1);
+    }
+    return 0;
+}
+"#;
+        let mut lexer = Lexer::new("main.iona");
+        lexer.lex(PROGRAM);
+        let mut parser = Parser::new(lexer.token_stream);
+        let ast = parser.parse_all().output.unwrap();
+        let json = ast.to_json();
+        let text = json.to_string();
+        assert_eq!(read(&mut text.chars().peekable()), json);
+        assert!(text.starts_with(
+            r#"[{"ImportStatement":{"file":"util","items":["double"],"pos":{"filename":"main.iona","line":0,"column":0},"reexport":false}},{"FunctionDeclaration":{"name":"main","#
+        ));
+        assert!(text.contains(r#"{"StringLiteral":"C:\\temp"}"#));
+        assert!(text.contains(r#"{"FloatLiteral":2.5}"#));
+        assert!(text.contains(r#""returns":"Integer""#));
+    }
+}
@@ -1,8 +1,11 @@
 //! Split text stream into tokens
 
-use crate::diagnostics::Diagnostic;
+use crate::diagnostics::{Diagnostic, DiagnosticKind};
 use core::panic;
 use std::fmt;
+use std::iter::Peekable;
+use std::num::IntErrorKind;
+use std::str::Chars;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SourcePosition {
@@ -21,6 +24,7 @@ pub enum Symbol {
     Struct,
     Enum,
     Function,
+    Extern,
     Generic,
     With,
     Colon,
@@ -52,19 +56,85 @@ pub enum Symbol {
     Times,
     Divide,
     Modulo,
+    Question, // ?
     Space,
     NewLine,
     Underscore,
     Let,
     If,
+    Then,
     Elif,
     Else,
     Match,
+    While,
     Return,
+    Assert,
     Equals,
     FatArrow,
 }
 
+/// How a symbol reads in an error message, like `':'` or `an identifier 'foo'`
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            Symbol::Identifier(name) => return write!(f, "an identifier '{}'", name),
+            Symbol::StringLiteral(value) => return write!(f, "the string {:?}", value),
+            Symbol::Integer(value) => return write!(f, "the number {}", value),
+            Symbol::Float(value) => return write!(f, "the number {}", value),
+            Symbol::Space => return write!(f, "a space"),
+            Symbol::NewLine => return write!(f, "the end of the line"),
+            Symbol::Import => "import",
+            Symbol::Struct => "struct",
+            Symbol::Enum => "enum",
+            Symbol::Function => "fn",
+            Symbol::Extern => "extern",
+            Symbol::Generic => "Generic",
+            Symbol::With => "with",
+            Symbol::Colon => ":",
+            Symbol::Comma => ",",
+            Symbol::Tag => "@",
+            Symbol::Metadata => "metadata",
+            Symbol::Contracts => "contracts",
+            Symbol::In => "In",
+            Symbol::Out => "Out",
+            Symbol::Properties => "Is",
+            Symbol::Traits => "Derives",
+            Symbol::Permissions => "Uses",
+            Symbol::Semicolon => ";",
+            Symbol::BraceOpen => "{",
+            Symbol::BraceClose => "}",
+            Symbol::BracketOpen => "[",
+            Symbol::BracketClose => "]",
+            Symbol::LeftAngle | Symbol::LessThan => "<",
+            Symbol::RightAngle | Symbol::GreaterThan => ">",
+            Symbol::ParenOpen => "(",
+            Symbol::ParenClose => ")",
+            Symbol::Dash => "-",
+            Symbol::Dot => ".",
+            Symbol::Or => "or",
+            Symbol::And => "and",
+            Symbol::Plus => "+",
+            Symbol::Times => "*",
+            Symbol::Divide => "/",
+            Symbol::Modulo => "%",
+            Symbol::Question => "?",
+            Symbol::Underscore => "_",
+            Symbol::Let => "let",
+            Symbol::If => "if",
+            Symbol::Then => "then",
+            Symbol::Elif => "elif",
+            Symbol::Else => "else",
+            Symbol::Match => "match",
+            Symbol::While => "while",
+            Symbol::Return => "return",
+            Symbol::Assert => "assert",
+            Symbol::Equals => "=",
+            Symbol::FatArrow => "=>",
+        };
+        write!(f, "'{}'", text)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Token {
     pub symbol: Symbol,
@@ -117,257 +187,467 @@ impl Lexer {
 
     pub fn lex(&mut self, code: &str) {
         let mut chars = code.chars().peekable();
-        while let Some(&c) = chars.peek() {
-            match c {
-                // Consume comments until a line break
-                '#' => {
-                    while let Some(&ch) = chars.peek() {
-                        if ch != '\n' {
-                            chars.next(); // consume the character
-                        } else {
-                            break; // Stop at the end of the line
-                        }
+        while chars.peek().is_some() {
+            self.lex_next(&mut chars);
+        }
+        self.finish();
+    }
+
+    /// Lex tokens lazily instead of all at once, see `TokenStream`
+    pub fn stream<'a>(&'a mut self, code: &'a str) -> TokenStream<'a> {
+        TokenStream {
+            lexer: self,
+            chars: code.chars().peekable(),
+            finished: false,
+        }
+    }
+
+    /// Lex whatever is at the front of the input, which adds at most one token (comments don't add any)
+    fn lex_next(&mut self, chars: &mut Peekable<Chars>) {
+        let Some(&c) = chars.peek() else {
+            return;
+        };
+        match c {
+            // Consume comments until a line break
+            '#' => {
+                // Comments don't produce tokens, but they still take up columns
+                while let Some(&ch) = chars.peek() {
+                    if ch != '\n' {
+                        chars.next(); // consume the character
+                        self.position.column += if ch == '\t' { 4 } else { 1 };
+                    } else {
+                        break; // Stop at the end of the line
                     }
                 }
-                '\n' => {
-                    self.simple_add(Symbol::NewLine, 1);
-                    chars.next();
-                    // Manually set position -- this overwrites/undoes the change in simple_add
-                    self.position.line += 1;
-                    self.position.column = 0;
-                }
-                '\t' => {
-                    self.simple_add(Symbol::Space, 4);
-                    chars.next();
-                }
-                c if c.is_whitespace() => {
-                    self.simple_add(Symbol::Space, c.len_utf8());
-                    chars.next();
-                }
-                ';' => {
-                    self.simple_add(Symbol::Semicolon, 1);
-                    chars.next();
-                }
-                '{' => {
-                    self.simple_add(Symbol::BraceOpen, 1);
-                    chars.next();
-                }
-                '}' => {
-                    self.simple_add(Symbol::BraceClose, 1);
-                    chars.next();
-                }
-                '[' => {
-                    self.simple_add(Symbol::BracketOpen, 1);
-                    chars.next();
-                }
-                ']' => {
-                    self.simple_add(Symbol::BracketClose, 1);
-                    chars.next();
-                }
-                '<' => {
-                    self.simple_add(Symbol::LeftAngle, 1);
-                    chars.next();
-                }
-                '>' => {
-                    self.simple_add(Symbol::RightAngle, 1);
-                    chars.next();
-                }
-                '(' => {
-                    self.simple_add(Symbol::ParenOpen, 1);
-                    chars.next();
-                }
-                ')' => {
-                    self.simple_add(Symbol::ParenClose, 1);
-                    chars.next();
-                }
-                '-' => {
-                    self.simple_add(Symbol::Dash, 1);
-                    chars.next();
-                }
-                '.' => {
-                    self.simple_add(Symbol::Dot, 1);
-                    chars.next();
-                }
-                ':' => {
-                    self.simple_add(Symbol::Colon, 1);
-                    chars.next();
-                }
-                '@' => {
-                    self.simple_add(Symbol::Tag, 1);
-                    chars.next();
-                }
-                ',' => {
-                    self.simple_add(Symbol::Comma, 1);
-                    chars.next();
-                }
-                '+' => {
-                    self.simple_add(Symbol::Plus, 1);
-                    chars.next();
-                }
-                '/' => {
-                    self.simple_add(Symbol::Divide, 1);
-                    chars.next();
-                }
-                '*' => {
-                    self.simple_add(Symbol::Times, 1);
-                    chars.next();
-                }
-                '%' => {
-                    self.simple_add(Symbol::Modulo, 1);
-                    chars.next();
-                }
-                '_' => {
-                    self.simple_add(Symbol::Underscore, 1);
-                    chars.next();
-                }
-                '=' => {
-                    // Check for fat arrow ('=>')
-                    chars.next();
-                    if chars.peek() == Some(&'>') {
-                        self.simple_add(Symbol::FatArrow, 2);
+            }
+            '\n' => {
+                self.simple_add(Symbol::NewLine, 1);
+                chars.next();
+                // Manually set position -- this overwrites/undoes the change in simple_add
+                self.position.line += 1;
+                self.position.column = 0;
+            }
+            '\t' => {
+                self.simple_add(Symbol::Space, 4);
+                chars.next();
+            }
+            c if c.is_whitespace() => {
+                self.simple_add(Symbol::Space, 1);
+                chars.next();
+            }
+            ';' => {
+                self.simple_add(Symbol::Semicolon, 1);
+                chars.next();
+            }
+            '{' => {
+                self.simple_add(Symbol::BraceOpen, 1);
+                chars.next();
+            }
+            '}' => {
+                self.simple_add(Symbol::BraceClose, 1);
+                chars.next();
+            }
+            '[' => {
+                self.simple_add(Symbol::BracketOpen, 1);
+                chars.next();
+            }
+            ']' => {
+                self.simple_add(Symbol::BracketClose, 1);
+                chars.next();
+            }
+            '<' => {
+                self.simple_add(Symbol::LeftAngle, 1);
+                chars.next();
+            }
+            '>' => {
+                self.simple_add(Symbol::RightAngle, 1);
+                chars.next();
+            }
+            '(' => {
+                self.simple_add(Symbol::ParenOpen, 1);
+                chars.next();
+            }
+            ')' => {
+                self.simple_add(Symbol::ParenClose, 1);
+                chars.next();
+            }
+            '-' => {
+                self.simple_add(Symbol::Dash, 1);
+                chars.next();
+            }
+            '.' => {
+                self.simple_add(Symbol::Dot, 1);
+                chars.next();
+            }
+            ':' => {
+                self.simple_add(Symbol::Colon, 1);
+                chars.next();
+            }
+            '@' => {
+                self.simple_add(Symbol::Tag, 1);
+                chars.next();
+            }
+            ',' => {
+                self.simple_add(Symbol::Comma, 1);
+                chars.next();
+            }
+            '+' => {
+                self.simple_add(Symbol::Plus, 1);
+                chars.next();
+            }
+            '/' => {
+                self.simple_add(Symbol::Divide, 1);
+                chars.next();
+            }
+            '*' => {
+                self.simple_add(Symbol::Times, 1);
+                chars.next();
+            }
+            '%' => {
+                self.simple_add(Symbol::Modulo, 1);
+                chars.next();
+            }
+            '?' => {
+                self.simple_add(Symbol::Question, 1);
+                chars.next();
+            }
+            '_' => {
+                chars.next();
+                // A leading underscore can start a name (`_unused`), otherwise it's the wildcard
+                let mut word = String::from("_");
+                while let Some(&ch) = chars.peek() {
+                    if ch.is_alphanumeric() || ch == '_' {
+                        word.push(ch);
                         chars.next();
                     } else {
-                        self.simple_add(Symbol::Equals, 1);
+                        break;
                     }
                 }
-                c if c.is_whitespace() => {
-                    println!("some other space? {}", c);
-                    self.simple_add(Symbol::Space, c.len_utf8());
+                if word.len() == 1 {
+                    self.simple_add(Symbol::Underscore, 1);
+                } else if word
+                    .trim_start_matches('_')
+                    .starts_with(|c: char| c.is_numeric())
+                    && word.chars().all(|c| c.is_numeric() || c == '_')
+                {
+                    // Something like `_1000` is a number with a misplaced separator, not a name
+                    self.add_number(&word);
+                } else {
+                    let word_len = word.chars().count();
+                    self.simple_add(Symbol::Identifier(word), word_len);
+                }
+            }
+            // Raw identifiers, like `match` in backticks, are never keywords
+            '`' => self.lex_raw_identifier(chars),
+            '=' => {
+                // Check for fat arrow ('=>')
+                chars.next();
+                if chars.peek() == Some(&'>') {
+                    self.simple_add(Symbol::FatArrow, 2);
                     chars.next();
+                } else {
+                    self.simple_add(Symbol::Equals, 1);
                 }
-                c if c.is_alphabetic() => {
-                    // We can't use take_while because it's too aggressive with whitespace
-                    let mut word = String::new();
-                    while let Some(&ch) = chars.peek() {
-                        if ch.is_alphanumeric() || ch == '_' {
-                            word.push(ch);
-                            chars.next(); // consume the character
-                        } else {
-                            break; // Stop when the next character isn't alphanumeric
-                        }
-                    }
-                    let word_len = word.len();
-                    match word.as_str() {
-                        "import" => self.simple_add(Symbol::Import, word_len),
-                        "struct" => self.simple_add(Symbol::Struct, word_len),
-                        "enum" => self.simple_add(Symbol::Enum, word_len),
-                        "fn" => self.simple_add(Symbol::Function, word_len),
-                        "with" => self.simple_add(Symbol::With, word_len),
-                        "metadata" => self.simple_add(Symbol::Metadata, word_len),
-                        "contracts" => self.simple_add(Symbol::Contracts, word_len),
-                        "In" => self.simple_add(Symbol::In, word_len),
-                        "Out" => self.simple_add(Symbol::Out, word_len),
-                        "Is" => self.simple_add(Symbol::Properties, word_len),
-                        "Derives" => self.simple_add(Symbol::Traits, word_len),
-                        "Uses" => self.simple_add(Symbol::Permissions, word_len),
-                        "Generic" => self.simple_add(Symbol::Generic, word_len),
-                        "let" => self.simple_add(Symbol::Let, word_len),
-                        "if" => self.simple_add(Symbol::If, word_len),
-                        "match" => self.simple_add(Symbol::Match, word_len),
-                        "return" => self.simple_add(Symbol::Return, word_len),
-                        "elif" => self.simple_add(Symbol::Elif, word_len),
-                        "else" => self.simple_add(Symbol::Else, word_len),
-                        _ => self.simple_add(Symbol::Identifier(word), word_len),
+            }
+            c if c.is_whitespace() => {
+                println!("some other space? {}", c);
+                self.simple_add(Symbol::Space, c.len_utf8());
+                chars.next();
+            }
+            c if c.is_alphabetic() => {
+                // We can't use take_while because it's too aggressive with whitespace
+                let mut word = String::new();
+                while let Some(&ch) = chars.peek() {
+                    if ch.is_alphanumeric() || ch == '_' {
+                        word.push(ch);
+                        chars.next(); // consume the character
+                    } else {
+                        break; // Stop when the next character isn't alphanumeric
                     }
                 }
-                c if c.is_numeric() => {
-                    let mut number: String = c.to_string();
-                    chars.next();
-                    // For some reason `take_while` over consumes
-                    loop {
-                        let nc = chars.peek();
-                        match nc {
-                            Some(c) => {
-                                if c.is_numeric() || *c == '.' {
-                                    number.push(*c);
-                                    chars.next();
-                                } else {
-                                    break;
-                                }
-                            }
-                            None => {
-                                break;
-                            }
-                        }
+                // Columns count characters, not bytes
+                let word_len = word.chars().count();
+                match word.as_str() {
+                    "import" => self.simple_add(Symbol::Import, word_len),
+                    "struct" => self.simple_add(Symbol::Struct, word_len),
+                    "enum" => self.simple_add(Symbol::Enum, word_len),
+                    "fn" => self.simple_add(Symbol::Function, word_len),
+                    "extern" => self.simple_add(Symbol::Extern, word_len),
+                    "with" => self.simple_add(Symbol::With, word_len),
+                    "metadata" => self.simple_add(Symbol::Metadata, word_len),
+                    "contracts" => self.simple_add(Symbol::Contracts, word_len),
+                    "In" => self.simple_add(Symbol::In, word_len),
+                    "Out" => self.simple_add(Symbol::Out, word_len),
+                    "Is" => self.simple_add(Symbol::Properties, word_len),
+                    "Derives" => self.simple_add(Symbol::Traits, word_len),
+                    "Uses" => self.simple_add(Symbol::Permissions, word_len),
+                    "Generic" => self.simple_add(Symbol::Generic, word_len),
+                    "let" => self.simple_add(Symbol::Let, word_len),
+                    "if" => self.simple_add(Symbol::If, word_len),
+                    "then" => self.simple_add(Symbol::Then, word_len),
+                    "match" => self.simple_add(Symbol::Match, word_len),
+                    "while" => self.simple_add(Symbol::While, word_len),
+                    "return" => self.simple_add(Symbol::Return, word_len),
+                    "assert" => self.simple_add(Symbol::Assert, word_len),
+                    "elif" => self.simple_add(Symbol::Elif, word_len),
+                    "else" => self.simple_add(Symbol::Else, word_len),
+                    _ => self.simple_add(Symbol::Identifier(word), word_len),
+                }
+            }
+            c if c.is_numeric() => {
+                // Underscores can separate digits, like `1_000_000`
+                let mut number: String = c.to_string();
+                chars.next();
+                while let Some(&c) = chars.peek() {
+                    if c == '.' && number.contains('.') {
+                        // A second `.` ends the number, so `1.2.3` is still `1.2` and the `.3` lexes as a `Dot` and an `Integer`
+                        let rest = chars
+                            .clone()
+                            .take_while(|c| c.is_numeric() || *c == '.' || *c == '_')
+                            .collect::<String>();
+                        self.invalid_number(
+                            &format!("{}{}", number, rest),
+                            "a number can only have one `.`",
+                        );
+                        break;
                     }
-                    if number.contains('.') {
-                        // For some reason `take_while` over consumes
-                        loop {
-                            let nc = chars.peek();
-                            match nc {
-                                Some(c) => {
-                                    if c.is_numeric() || *c == '.' {
-                                        number.push(*c);
-                                        chars.next();
-                                    } else {
-                                        break;
-                                    }
-                                }
-                                None => {
-                                    break;
-                                }
-                            }
-                        }
-                        if let Ok(f) = number.parse() {
-                            self.simple_add(Symbol::Float(f), number.len());
-                        } else {
-                            // Handle error
-                        }
+                    if c.is_numeric() || c == '.' || c == '_' {
+                        number.push(c);
+                        chars.next();
                     } else {
-                        if let Ok(n) = number.parse() {
-                            self.simple_add(Symbol::Integer(n), number.len());
-                        } else {
-                            // Handle error
-                        }
+                        break;
                     }
                 }
-                c if c == '"' => {
-                    // ~5MB of raw string data
-                    const LEXER_STRING_LEN_LIMIT: usize = 5120;
-                    // Handle string literals
-                    let mut new_string: String = String::new();
-                    chars.next(); // eat opening paren
-                    let mut counter: usize = 0;
-                    loop {
-                        let nc = chars.peek();
-                        match nc {
-                            Some(c) => {
-                                // TODO: handle string escapes
-                                if *c == '"' {
-                                    break;
-                                } else {
-                                    new_string.push(*c);
-                                    chars.next();
-                                }
-                            }
-                            None => {
+                self.add_number(&number);
+            }
+            // Raw strings, `"""like this"""`, keep their content exactly as written (newlines included)
+            '"' if {
+                let mut ahead = chars.clone();
+                ahead.next();
+                ahead.next() == Some('"') && ahead.next() == Some('"')
+            } =>
+            {
+                self.lex_raw_string(chars);
+            }
+            '"' => {
+                // ~5MB of raw string data
+                const LEXER_STRING_LEN_LIMIT: usize = 5120;
+                // Handle string literals
+                //
+                // A literal tab is kept in the value as a tab byte, like every other character, even though outside of a string it's a `Space`. It still takes up 4 columns like it does everywhere else, so the tokens after the string line up with the source
+                let mut new_string: String = String::new();
+                chars.next(); // eat opening paren
+                let mut counter: usize = 0;
+                loop {
+                    let nc = chars.peek();
+                    match nc {
+                        Some(c) => {
+                            // TODO: handle string escapes, a `\t` written out is still a backslash and a `t` until then
+                            if *c == '"' {
                                 break;
+                            } else {
+                                new_string.push(*c);
+                                chars.next();
                             }
                         }
-                        counter += 1;
-                        if counter > LEXER_STRING_LEN_LIMIT {
-                            panic!("Fatal error: string literal length limit exceeded (currently set to 5MB). Consider putting the string in a file instead.");
+                        None => {
+                            break;
                         }
                     }
-                    let string_len = new_string.len();
-                    self.simple_add(Symbol::StringLiteral(new_string), string_len);
-                    chars.next(); // eat closing paren
-                }
-                other => {
-                    // Handle unexpected characters
-                    self.diagnostics.push(Diagnostic::new_error_simple(
-                        &format!("Unexpected symbol in program {}", other),
-                        &self.position,
-                    ));
-                    chars.next();
+                    counter += 1;
+                    if counter > LEXER_STRING_LEN_LIMIT {
+                        panic!("Fatal error: string literal length limit exceeded (currently set to 5MB). Consider putting the string in a file instead.");
+                    }
                 }
+                // Columns count characters (and tabs as 4) plus the quotes, an unterminated string has no closing quote
+                let closed = chars.next().is_some(); // eat closing paren
+                let width = new_string
+                    .chars()
+                    .map(|c| if c == '\t' { 4 } else { 1 })
+                    .sum::<usize>()
+                    + 1
+                    + usize::from(closed);
+                self.simple_add(Symbol::StringLiteral(new_string), width);
+            }
+            other => {
+                // Handle unexpected characters
+                self.diagnostics.push(Diagnostic::new_error_simple(
+                    DiagnosticKind::Syntax,
+                    &format!("Unexpected symbol in program {}", other),
+                    &self.position,
+                ));
+                chars.next();
             }
         }
-        // Add trailing whitespace to avoid over-running the token boundary during parsing
+    }
+
+    /// Add trailing whitespace to avoid over-running the token boundary during parsing
+    fn finish(&mut self) {
         self.position.line += 1;
         self.position.column = 0;
         self.simple_add(Symbol::NewLine, 1);
     }
+
+    /// Add an integer or float token, ignoring separators
+    ///
+    /// A separator has to sit between two digits, so `_1000`, `1000_`, `1__000`, and `1_.5` are all reported
+    fn add_number(&mut self, number: &str) {
+        let chars = number.chars().collect::<Vec<char>>();
+        let misplaced = chars.iter().enumerate().any(|(i, c)| {
+            *c == '_'
+                && !(i > 0
+                    && chars[i - 1].is_numeric()
+                    && chars.get(i + 1).is_some_and(|next| next.is_numeric()))
+        });
+        if misplaced {
+            self.diagnostics.push(Diagnostic::new_error_simple(
+                DiagnosticKind::Syntax,
+                &format!(
+                    "misplaced `_` in number `{}`, separators can only go between two digits",
+                    number
+                ),
+                &self.position,
+            ));
+        }
+        let digits = number.replace('_', "");
+        if digits.contains('.') {
+            let f = digits.parse().unwrap_or_else(|_| {
+                self.invalid_number(number, "only the digits 0 to 9 can be used");
+                0.0
+            });
+            self.simple_add(Symbol::Float(f), number.chars().count());
+        } else {
+            let n = digits.parse::<i64>().unwrap_or_else(|e| {
+                let reason = match e.kind() {
+                    IntErrorKind::PosOverflow => "it's too large for an `Int`",
+                    _ => "only the digits 0 to 9 can be used",
+                };
+                self.invalid_number(number, reason);
+                0
+            });
+            self.simple_add(Symbol::Integer(n), number.chars().count());
+        }
+    }
+
+    /// Report a number that can't be parsed at its start
+    ///
+    /// A placeholder `0` token is still added by the caller, so the parser stays in step with the source
+    fn invalid_number(&mut self, number: &str, reason: &str) {
+        self.diagnostics.push(Diagnostic::new_error_simple(
+            DiagnosticKind::Syntax,
+            &format!("invalid number `{}`, {}", number, reason),
+            &self.position,
+        ));
+    }
+
+    /// Lex a name wrapped in backticks, so that a keyword can be used as a name (`` `import` `` is the identifier `import`)
+    fn lex_raw_identifier(&mut self, chars: &mut Peekable<Chars>) {
+        let start = self.position.clone();
+        chars.next();
+        let mut word = String::new();
+        while let Some(&ch) = chars.peek() {
+            if ch.is_alphanumeric() || ch == '_' {
+                word.push(ch);
+                chars.next();
+            } else {
+                break;
+            }
+        }
+        let closed = chars.peek() == Some(&'`');
+        if closed {
+            chars.next();
+        }
+        self.position.column += word.chars().count() + 1 + usize::from(closed);
+        if word.is_empty() || word.starts_with(|c: char| c.is_numeric()) {
+            self.diagnostics.push(Diagnostic::new_error_simple(
+                DiagnosticKind::Syntax,
+                "expected a name inside of the backticks, like `match`",
+                &start,
+            ));
+            return;
+        }
+        if !closed {
+            self.diagnostics.push(Diagnostic::new_error_simple(
+                DiagnosticKind::Syntax,
+                &format!(
+                    "unterminated raw identifier, expected a closing backtick after `{}",
+                    word
+                ),
+                &start,
+            ));
+        }
+        self.token_stream
+            .push(Token::new(Symbol::Identifier(word), &start));
+    }
+
+    /// Lex a triple quoted string, escapes aren't processed and line breaks are kept
+    ///
+    /// The token sits where the opening quotes are, and the position afterwards accounts for every line the string spans
+    fn lex_raw_string(&mut self, chars: &mut Peekable<Chars>) {
+        let start = self.position.clone();
+        for _ in 0..3 {
+            chars.next();
+        }
+        self.position.column += 3;
+        let mut content = String::new();
+        let mut closed = false;
+        while let Some(c) = chars.next() {
+            if c == '"' && chars.peek() == Some(&'"') {
+                let mut ahead = chars.clone();
+                ahead.next();
+                if ahead.next() == Some('"') {
+                    chars.next();
+                    chars.next();
+                    self.position.column += 3;
+                    closed = true;
+                    break;
+                }
+            }
+            content.push(c);
+            if c == '\n' {
+                self.position.line += 1;
+                self.position.column = 0;
+            } else {
+                self.position.column += 1;
+            }
+        }
+        if !closed {
+            self.diagnostics.push(Diagnostic::new_error_simple(
+                DiagnosticKind::Syntax,
+                "unterminated raw string, expected a closing `\"\"\"`",
+                &start,
+            ));
+        }
+        self.token_stream
+            .push(Token::new(Symbol::StringLiteral(content), &start));
+    }
+}
+
+/// Tokens pulled from the lexer one at a time, so a file's tokens never all have to be in memory at once
+///
+/// The stream yields the same tokens as `Lexer::lex`, including the trailing newline. Diagnostics are still collected in the lexer, so read them once the stream is used up
+pub struct TokenStream<'a> {
+    lexer: &'a mut Lexer,
+    chars: Peekable<Chars<'a>>,
+    finished: bool,
+}
+
+impl Iterator for TokenStream<'_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        while self.lexer.token_stream.is_empty() {
+            if self.chars.peek().is_some() {
+                self.lexer.lex_next(&mut self.chars);
+            } else if !self.finished {
+                self.lexer.finish();
+                self.finished = true;
+            } else {
+                return None;
+            }
+        }
+        Some(self.lexer.token_stream.remove(0))
+    }
 }
 
 // -------------------- Unit Tests --------------------
@@ -381,7 +661,7 @@ mod tests {
     fn lex_int() {
         let input_int = "64";
         let mut lexer = Lexer::new("test");
-        lexer.lex(&input_int);
+        lexer.lex(input_int);
         assert_eq!(lexer.token_stream[0].symbol, Symbol::Integer(64));
     }
 
@@ -389,7 +669,7 @@ mod tests {
     fn lex_float() {
         let input_int = "3947.2884";
         let mut lexer = Lexer::new("test");
-        lexer.lex(&input_int);
+        lexer.lex(input_int);
         assert_eq!(lexer.token_stream[0].symbol, Symbol::Float(3947.2884));
     }
 
@@ -397,7 +677,7 @@ mod tests {
     fn lex_add_infix() {
         let input_int = "1 + 2";
         let mut lexer = Lexer::new("test");
-        lexer.lex(&input_int);
+        lexer.lex(input_int);
         let symbols = lexer
             .token_stream
             .iter()
@@ -420,7 +700,7 @@ mod tests {
     fn lex_function_call_variables() {
         let input_int = "foo(a, b)";
         let mut lexer = Lexer::new("test");
-        lexer.lex(&input_int);
+        lexer.lex(input_int);
         let symbols = lexer
             .token_stream
             .iter()
@@ -445,7 +725,7 @@ mod tests {
     fn lex_function_call_ints() {
         let input_int = "foo(1, 2)";
         let mut lexer = Lexer::new("test");
-        lexer.lex(&input_int);
+        lexer.lex(input_int);
         let symbols = lexer
             .token_stream
             .iter()
@@ -470,7 +750,7 @@ mod tests {
     fn lex_function_call_floats() {
         let input_int = "sub(1.2, 3.4)";
         let mut lexer = Lexer::new("test");
-        lexer.lex(&input_int);
+        lexer.lex(input_int);
         let symbols = lexer
             .token_stream
             .iter()
@@ -495,7 +775,7 @@ mod tests {
     fn lex_underscores() {
         let input = "variable_name";
         let mut lexer = Lexer::new("test");
-        lexer.lex(&input);
+        lexer.lex(input);
         let symbols = lexer
             .token_stream
             .iter()
@@ -509,4 +789,261 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn lex_leading_underscore() {
+        let input = "_unused _";
+        let mut lexer = Lexer::new("test");
+        lexer.lex(input);
+        let symbols = lexer
+            .token_stream
+            .iter()
+            .map(|t| t.symbol.clone())
+            .collect::<Vec<Symbol>>();
+        assert_eq!(
+            symbols,
+            vec![
+                Symbol::Identifier("_unused".to_string()),
+                Symbol::Space,
+                Symbol::Underscore,
+                Symbol::NewLine
+            ]
+        );
+    }
+
+    #[test]
+    fn lex_tab_inside_string() {
+        let mut lexer = Lexer::new("test");
+        lexer.lex("let s = \"a\tb\"; x");
+        assert!(lexer.diagnostics.is_empty(), "{:#?}", lexer.diagnostics);
+        let positions = lexer
+            .token_stream
+            .iter()
+            .map(|t| (t.symbol.clone(), t.pos.column))
+            .collect::<Vec<(Symbol, usize)>>();
+        // The tab stays a tab in the value, rather than turning into spaces like it does outside of a string
+        assert_eq!(positions[6], (Symbol::StringLiteral("a\tb".to_string()), 8));
+        // But it's still 4 columns wide, and the quotes count too
+        assert_eq!(positions[7], (Symbol::Semicolon, 16));
+        assert_eq!(positions[9], (Symbol::Identifier("x".to_string()), 18));
+    }
+
+    #[test]
+    fn lex_multiline_raw_string() {
+        let input = "let s = \"\"\"line one\n  \"quoted\" \\n\nend\"\"\";";
+        let mut lexer = Lexer::new("test");
+        lexer.lex(input);
+        assert!(lexer.diagnostics.is_empty(), "{:#?}", lexer.diagnostics);
+        let positions = lexer
+            .token_stream
+            .iter()
+            .map(|t| (t.symbol.clone(), t.pos.line, t.pos.column))
+            .collect::<Vec<(Symbol, usize, usize)>>();
+        assert_eq!(
+            positions[6],
+            (
+                Symbol::StringLiteral("line one\n  \"quoted\" \\n\nend".to_string()),
+                0,
+                8
+            )
+        );
+        // The semicolon comes right after the closing quotes, two lines down
+        assert_eq!(positions[7], (Symbol::Semicolon, 2, 6));
+    }
+
+    #[test]
+    fn lex_unterminated_raw_string() {
+        let mut lexer = Lexer::new("test");
+        lexer.lex("let s = \"\"\"never closed\n");
+        assert_eq!(lexer.diagnostics.len(), 1);
+        assert_eq!(lexer.diagnostics[0].position().column, 8);
+    }
+
+    fn lex_numbers(input: &str) -> (Vec<Symbol>, usize) {
+        let mut lexer = Lexer::new("test");
+        lexer.lex(input);
+        let symbols = lexer
+            .token_stream
+            .into_iter()
+            .map(|t| t.symbol)
+            .filter(|s| matches!(s, Symbol::Integer(_) | Symbol::Float(_)))
+            .collect();
+        (symbols, lexer.diagnostics.len())
+    }
+
+    #[test]
+    fn lex_number_separators() {
+        assert_eq!(lex_numbers("1_000"), (vec![Symbol::Integer(1000)], 0));
+        assert_eq!(
+            lex_numbers("1_000_000 + 3_000.000_5"),
+            (vec![Symbol::Integer(1000000), Symbol::Float(3000.0005)], 0)
+        );
+    }
+
+    #[test]
+    fn lex_misplaced_separators() {
+        for input in ["_1000", "1000_", "1__000", "1_.5", "1._5"] {
+            let (symbols, errors) = lex_numbers(input);
+            assert_eq!(errors, 1, "{}", input);
+            // The value is still usable, so parsing can carry on
+            assert_eq!(symbols.len(), 1, "{}", input);
+        }
+    }
+
+    #[test]
+    fn lex_invalid_numbers() {
+        for (input, message, placeholder) in [
+            (
+                "x = 1.٣;",
+                "invalid number `1.٣`, only the digits 0 to 9 can be used",
+                Symbol::Float(0.0),
+            ),
+            (
+                "x = 99999999999999999999;",
+                "invalid number `99999999999999999999`, it's too large for an `Int`",
+                Symbol::Integer(0),
+            ),
+            (
+                "x = 1٣;",
+                "invalid number `1٣`, only the digits 0 to 9 can be used",
+                Symbol::Integer(0),
+            ),
+        ] {
+            let mut lexer = Lexer::new("test");
+            lexer.lex(input);
+            assert_eq!(lexer.diagnostics.len(), 1, "{}", input);
+            assert_eq!(lexer.diagnostics[0].message(), message);
+            assert_eq!(lexer.diagnostics[0].position().column, 4, "{}", input);
+            // A placeholder keeps the tokens after it where they belong
+            let tokens = lexer
+                .token_stream
+                .iter()
+                .map(|t| (t.symbol.clone(), t.pos.column))
+                .collect::<Vec<(Symbol, usize)>>();
+            assert_eq!(tokens[4], (placeholder, 4), "{}", input);
+            assert_eq!(
+                tokens[5],
+                (Symbol::Semicolon, input.chars().count() - 1),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn lex_number_with_two_dots() {
+        let mut lexer = Lexer::new("test");
+        lexer.lex("x = 1.2.3;");
+        assert_eq!(lexer.diagnostics.len(), 1);
+        assert_eq!(
+            lexer.diagnostics[0].message(),
+            "invalid number `1.2.3`, a number can only have one `.`"
+        );
+        assert_eq!(lexer.diagnostics[0].position().column, 4);
+        // The number stops at the second `.`, and what's left is lexed as usual
+        let tokens = lexer
+            .token_stream
+            .iter()
+            .skip(4)
+            .take(4)
+            .map(|t| (t.symbol.clone(), t.pos.column))
+            .collect::<Vec<(Symbol, usize)>>();
+        assert_eq!(
+            tokens,
+            vec![
+                (Symbol::Float(1.2), 4),
+                (Symbol::Dot, 7),
+                (Symbol::Integer(3), 8),
+                (Symbol::Semicolon, 9),
+            ]
+        );
+    }
+
+    #[test]
+    fn lex_unicode_identifier_positions() {
+        let mut lexer = Lexer::new("test");
+        lexer.lex("let größe = _naïve;");
+        assert!(lexer.diagnostics.is_empty(), "{:#?}", lexer.diagnostics);
+        let positions = lexer
+            .token_stream
+            .iter()
+            .map(|t| (t.symbol.clone(), t.pos.line, t.pos.column))
+            .collect::<Vec<(Symbol, usize, usize)>>();
+        assert_eq!(
+            positions[2],
+            (Symbol::Identifier("größe".to_string()), 0, 4)
+        );
+        assert_eq!(positions[4], (Symbol::Equals, 0, 10));
+        assert_eq!(
+            positions[6],
+            (Symbol::Identifier("_naïve".to_string()), 0, 12)
+        );
+        assert_eq!(positions[7], (Symbol::Semicolon, 0, 18));
+    }
+
+    #[test]
+    fn lex_raw_identifiers() {
+        let mut lexer = Lexer::new("test");
+        lexer.lex("let `match` = `import`;");
+        assert!(lexer.diagnostics.is_empty(), "{:#?}", lexer.diagnostics);
+        let positions = lexer
+            .token_stream
+            .iter()
+            .map(|t| (t.symbol.clone(), t.pos.column))
+            .collect::<Vec<(Symbol, usize)>>();
+        assert_eq!(positions[0], (Symbol::Let, 0));
+        assert_eq!(positions[2], (Symbol::Identifier("match".to_string()), 4));
+        assert_eq!(positions[4], (Symbol::Equals, 12));
+        assert_eq!(positions[6], (Symbol::Identifier("import".to_string()), 14));
+        assert_eq!(positions[7], (Symbol::Semicolon, 22));
+
+        let mut lexer = Lexer::new("test");
+        lexer.lex("let `` = `match;");
+        let messages = lexer
+            .diagnostics
+            .iter()
+            .map(|d| (d.message(), d.position().column))
+            .collect::<Vec<(&str, usize)>>();
+        assert_eq!(
+            messages,
+            vec![
+                ("expected a name inside of the backticks, like `match`", 4),
+                (
+                    "unterminated raw identifier, expected a closing backtick after `match",
+                    9
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn lex_trailing_comment_positions() {
+        let input = "let x # the answer\nlet y";
+        let mut lexer = Lexer::new("test");
+        lexer.lex(input);
+        let positions = lexer
+            .token_stream
+            .iter()
+            .map(|t| (t.symbol.clone(), t.pos.line, t.pos.column))
+            .collect::<Vec<(Symbol, usize, usize)>>();
+        println!("{:?}", positions);
+        // The newline comes after the comment
+        assert_eq!(positions[4], (Symbol::NewLine, 0, 18));
+        assert_eq!(positions[5], (Symbol::Let, 1, 0));
+        assert_eq!(positions[7], (Symbol::Identifier("y".to_string()), 1, 4));
+    }
+
+    #[test]
+    fn stream_matches_batch() {
+        for entry in std::fs::read_dir("test_programs").unwrap() {
+            let path = entry.unwrap().path();
+            let source = std::fs::read_to_string(&path).unwrap();
+            let mut batch = Lexer::new("test");
+            batch.lex(&source);
+            let mut lexer = Lexer::new("test");
+            let streamed = lexer.stream(&source).collect::<Vec<Token>>();
+            assert_eq!(streamed, batch.token_stream, "{:?}", path);
+            assert_eq!(lexer.diagnostics, batch.diagnostics, "{:?}", path);
+        }
+    }
 }
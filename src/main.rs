@@ -1,124 +1,460 @@
 #![allow(dead_code)]
 
 mod aggregation;
+mod analysis;
+mod cache;
 mod cli;
 mod codegen_c;
 mod diagnostics;
 mod expression_parser;
+mod json;
 mod lexer;
 mod parser;
 mod pipeline;
+mod scopes;
+mod typecheck;
 
 use std::env;
 use std::error::Error;
 use std::fs;
+use std::path::{Path, PathBuf};
 use std::time::Instant;
 
 use aggregation::ParsingTables;
-use cli::{Flags, Target};
+use cache::AstCache;
+use cli::{Flags, Mode, Target};
+use codegen_c::CodegenOptions;
+use parser::ASTNode;
+use pipeline::SourceMap;
 
-/// Which standard library files should we NOT emit?
-const NO_EMIT_LIST: [&'static str; 1] = ["arrays.iona"];
+/// Parse a file and print whatever went wrong, exiting if parsing couldn't recover
+fn parse_or_exit(
+    file: &Path,
+    verbose: bool,
+    cache: Option<&AstCache>,
+    sources: &mut SourceMap,
+) -> Vec<ASTNode> {
+    let result = match pipeline::parse_file(file, verbose, cache) {
+        Ok(result) => result,
+        Err(e) => {
+            eprint!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    sources.insert(file, &result.source);
+    if let Some(trace) = &result.trace {
+        eprintln!(
+            "Parser stack trace (in code order, top-to-bottom)\n{:#?}",
+            trace
+        );
+    }
+    if result.ast.is_some() && !result.diagnostics.is_empty() {
+        eprintln!("non-fatal errors\n{}", result.render());
+    }
+    match result.into_ast() {
+        Ok(ast) => ast,
+        Err(e) => {
+            eprint!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Print what the pipeline found that didn't stop it, after the parser's stack for each file in verbose mode
+fn print_problems(sources: &SourceMap) {
+    for trace in sources.traces.iter() {
+        eprintln!(
+            "Parser stack trace (in code order, top-to-bottom)\n{:#?}",
+            trace
+        );
+    }
+    if !sources.diagnostics.is_empty() {
+        eprint!(
+            "{}",
+            pipeline::render_diagnostics(&sources.diagnostics, sources)
+        );
+    }
+}
+
+/// Check an entrypoint and everything it imports, printing what was found. Returns false if there were errors
+fn run_check(
+    file: &Path,
+    resolver: &pipeline::ModuleResolver,
+    command: &cli::Command,
+    cache: Option<&AstCache>,
+    t_start: Instant,
+    sources: &mut SourceMap,
+) -> bool {
+    let checked = pipeline::check(
+        file,
+        resolver,
+        command.flags.contains(&Flags::Library),
+        &command.lints,
+        command.flags.contains(&Flags::Verbose),
+        cache,
+        sources,
+    );
+    print_problems(sources);
+    let t_all = Instant::now();
+    match checked {
+        Ok(summary) => {
+            println!(
+                "finished checking {} in {:?}: {}",
+                &file.to_string_lossy(),
+                t_all - t_start,
+                summary
+            );
+            summary.errors == 0
+        }
+        Err(e) => {
+            eprint!("{}", e);
+            println!(
+                "finished checking {} in {:?}",
+                &file.to_string_lossy(),
+                t_all - t_start
+            );
+            false
+        }
+    }
+}
+
+/// Generate C for an entrypoint and everything it imports, then write it to `gen/`. Returns false if it couldn't be compiled
+fn run_build(
+    file: &Path,
+    resolver: &pipeline::ModuleResolver,
+    command: &cli::Command,
+    codegen_options: &CodegenOptions,
+    cache: Option<&AstCache>,
+    t_start: Instant,
+    sources: &mut SourceMap,
+) -> bool {
+    let compiled = pipeline::compile_modules(
+        file,
+        resolver,
+        codegen_options,
+        command.flags.contains(&Flags::Library),
+        &command.lints,
+        command.flags.contains(&Flags::Verbose),
+        cache,
+        sources,
+    );
+    print_problems(sources);
+    let (files, tables) = match compiled {
+        Ok(compiled) => compiled,
+        Err(e) => {
+            eprint!("{}", e);
+            return false;
+        }
+    };
+    if command.flags.contains(&Flags::DumpTypeTable) {
+        print!("{}", tables.types.dump());
+    }
+    // Each unique monomorphization is generated once, no matter how many modules use it
+    let filled_templates = codegen_c::generate_templated_libs(&tables.types);
+    codegen_c::emit_templated_stdlib_files(&filled_templates);
+    // Stdout only gets the entrypoint's C, so it can be piped straight into a C compiler
+    if command.flags.contains(&Flags::EmitCFragment) {
+        if let Some((_, generated_code)) = files.last() {
+            print!("{}", generated_code);
+        }
+        return true;
+    }
+    // Write files
+    for (path, generated_code) in files.iter() {
+        fs::write(path, generated_code).expect("Unable to write file");
+    }
+    let t_all = Instant::now();
+    // Report on code timings
+    println!(
+        "finished compiling {} in {:?}",
+        &file.to_string_lossy(),
+        t_all - t_start
+    );
+    true
+}
+
+/// Run `compile` now and after every change to the entrypoint or its imports, until Ctrl-C stops the process
+///
+/// Each run after the first starts with a separator naming the files that changed. `compile` loads its files into the `SourceMap` it's given, which is how the next run knows what to watch
+fn watch(file: &Path, command: &cli::Command, compile: &mut dyn FnMut(Instant, &mut SourceMap)) {
+    let mut probe = pipeline::PollingProbe {
+        interval: command.watch_interval,
+    };
+    pipeline::watch(file, &mut probe, &mut |changed| {
+        if !changed.is_empty() {
+            let names = changed
+                .iter()
+                .map(|path| path.to_string_lossy())
+                .collect::<Vec<_>>();
+            println!("\n---------- {} changed ----------\n", names.join(", "));
+        }
+        let mut sources = SourceMap::new();
+        compile(Instant::now(), &mut sources);
+        sources
+    });
+}
 
 fn main() -> Result<(), Box<dyn Error>> {
     // Capture command line
     let args: Vec<String> = env::args().collect();
     let command = cli::parse_args(&args)?;
     let t_start = Instant::now();
+    let mut codegen_options = CodegenOptions::new(&command.platform);
+    codegen_options.contracts = command.contracts;
+    // Unchanged files are loaded from the cache instead of being parsed again
+    let cache = command
+        .flags
+        .contains(&Flags::Incremental)
+        .then(|| AstCache::new(Path::new(cache::CACHE_DIR)));
     // Compile a normal target
-    if let Target::Entrypoint(file) = command.target {
-        let maybe_ast = pipeline::file_to_ast(&file, command.flags.contains(&Flags::Verbose));
-        if let Err(e) = maybe_ast {
-            eprint!("{}", e);
+    if let Target::Entrypoint(file) = command.target.clone() {
+        let resolver = pipeline::ModuleResolver::new(&file, &command.include_dirs);
+        let mut sources = SourceMap::new();
+        // Only lex, so problems in the lexer can be told apart from problems in the parser
+        if command.flags.contains(&Flags::EmitTokens) {
+            let (lexer, source) = pipeline::lex_file(&file)?;
+            let tokens = if command.flags.contains(&Flags::Json) {
+                pipeline::render_tokens_json(&lexer)
+            } else {
+                pipeline::render_tokens(&lexer, &source)
+            };
+            match &command.output {
+                Some(path) => fs::write(path, tokens)?,
+                None => print!("{}", tokens),
+            }
+            return Ok(());
+        }
+        // Only parse, and hand the ASTs to whatever tool asked for them
+        if command.flags.contains(&Flags::EmitAst) {
+            let parsed = pipeline::parse_all_reachable(
+                &file,
+                &resolver,
+                command.flags.contains(&Flags::Verbose),
+                cache.as_ref(),
+                &mut sources,
+            );
+            print_problems(&sources);
+            let (asts, _) = match parsed {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    eprint!("{}", e);
+                    std::process::exit(1);
+                }
+            };
+            let json = pipeline::render_ast_json(&asts, &resolver);
+            match &command.output {
+                Some(path) => fs::write(path, json)?,
+                None => print!("{}", json),
+            }
+            return Ok(());
+        }
+        // Only resolve the imports and report how the modules depend on each other, for build systems
+        if command.flags.contains(&Flags::EmitDeps) {
+            let verbose = command.flags.contains(&Flags::Verbose);
+            // The graph is for people, so it still draws the modules that are missing
+            let deps = if command.flags.contains(&Flags::Dot) {
+                pipeline::parse_import_graph(
+                    &file,
+                    &resolver,
+                    verbose,
+                    cache.as_ref(),
+                    &mut sources,
+                )
+                .map(|tables| pipeline::render_dependency_graph(&tables))
+            } else {
+                pipeline::parse_all_reachable(
+                    &file,
+                    &resolver,
+                    verbose,
+                    cache.as_ref(),
+                    &mut sources,
+                )
+                .map(|(_, tables)| pipeline::render_dependencies(&tables, &resolver))
+            };
+            print_problems(&sources);
+            let deps = match deps {
+                Ok(deps) => deps,
+                Err(e) => {
+                    eprint!("{}", e);
+                    std::process::exit(1);
+                }
+            };
+            match &command.output {
+                Some(path) => fs::write(path, deps)?,
+                None => print!("{}", deps),
+            }
+            return Ok(());
+        }
+        // Only look for problems, nothing is generated or written
+        if command.mode == Mode::Check {
+            if command.flags.contains(&Flags::Watch) {
+                watch(&file, &command, &mut |t_start, sources| {
+                    run_check(&file, &resolver, &command, cache.as_ref(), t_start, sources);
+                });
+                return Ok(());
+            }
+            let ok = run_check(
+                &file,
+                &resolver,
+                &command,
+                cache.as_ref(),
+                t_start,
+                &mut sources,
+            );
+            std::process::exit(if ok { 0 } else { 1 });
+        }
+        // Everything the entrypoint imports goes into the same C file
+        if command.flags.contains(&Flags::SingleUnit) {
+            let compiled = pipeline::compile_single_unit(
+                &file,
+                &resolver,
+                &codegen_options,
+                command.flags.contains(&Flags::Library),
+                &command.lints,
+                command.flags.contains(&Flags::Verbose),
+                cache.as_ref(),
+                &mut sources,
+            );
+            print_problems(&sources);
+            let (generated_code, tables) = match compiled {
+                Ok(compiled) => compiled,
+                Err(e) => {
+                    eprint!("{}", e);
+                    std::process::exit(1);
+                }
+            };
+            if command.flags.contains(&Flags::DumpTypeTable) {
+                print!("{}", tables.types.dump());
+            }
+            let filled_templates = codegen_c::generate_templated_libs(&tables.types);
+            codegen_c::emit_templated_stdlib_files(&filled_templates);
+            if command.flags.contains(&Flags::EmitCFragment) {
+                print!("{}", generated_code);
+                return Ok(());
+            }
+            fs::write(pipeline::ENTRYPOINT_OUTPUT, generated_code).expect("Unable to write file");
+            let t_all = Instant::now();
+            println!(
+                "finished compiling {} in {:?}",
+                &file.to_string_lossy(),
+                t_all - t_start
+            );
+            return Ok(());
+        }
+        let build = |t_start, sources: &mut SourceMap| {
+            run_build(
+                &file,
+                &resolver,
+                &command,
+                &codegen_options,
+                cache.as_ref(),
+                t_start,
+                sources,
+            )
+        };
+        if command.flags.contains(&Flags::Watch) {
+            watch(&file, &command, &mut |t_start, sources| {
+                build(t_start, sources);
+            });
+            return Ok(());
+        }
+        if !build(t_start, &mut sources) {
             std::process::exit(1);
         }
-        let ast = maybe_ast.unwrap();
-        // TEMP: handle std lib gen (will use pipeline later)
-        let module_name = file
-            .file_stem()
-            .expect(&format!("unable to get file stem from filename {:?}", file))
-            .to_string_lossy();
-        let mut tables = ParsingTables::new();
-        tables.update(&ast, &module_name);
-        let filled_templates = codegen_c::generate_templated_libs(&tables.types);
-        codegen_c::emit_templated_stdlib_files(&filled_templates);
-        // Write file
-        let generated_code = codegen_c::write_all(
-            ast.iter(),
-            &tables.types,
-            &file.file_stem().unwrap().to_string_lossy(),
-            false,
-        );
-        fs::write("gen/test_case.c", generated_code).expect("Unable to write file");
-        let t_all = Instant::now();
-        // Report on code timings
-        println!(
-            "finished compiling {} in {:?}",
-            &file.to_string_lossy(),
-            t_all - t_start
-        );
         return Ok(());
     }
     // Compile the standard library
     if let Target::StdLib = command.target {
-        let paths = fs::read_dir("stdlib").expect("unable to find /stdlib/ directory in root");
-        for path in paths {
-            let file = path.unwrap();
-            let entrypoint_filepath = &file.path();
-            let maybe_ast = pipeline::file_to_ast(
+        // `read_dir` doesn't promise any order, so sort to keep the output the same between runs
+        let mut paths = fs::read_dir(pipeline::STDLIB_DIR)
+            .expect("unable to find /stdlib/ directory in root")
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<PathBuf>>();
+        paths.sort();
+        // Parse every file up front so all modules share one set of tables
+        let mut tables = ParsingTables::new();
+        let mut modules = Vec::new();
+        let mut sources = SourceMap::new();
+        for entrypoint_filepath in paths {
+            let ast = parse_or_exit(
                 &entrypoint_filepath,
                 command.flags.contains(&Flags::Verbose),
+                cache.as_ref(),
+                &mut sources,
             );
-            if let Err(e) = maybe_ast {
-                eprint!("{}", e);
-                std::process::exit(1);
-            }
-            let ast = maybe_ast.unwrap();
-            // Check if we emit code for this
-            if NO_EMIT_LIST.contains(&file.file_name().to_str().unwrap()) {
-                // Report on code timings
-                let t_all = Instant::now();
-                println!(
-                    "finished compiling {} in {:?}",
-                    &file.file_name().to_str().unwrap(),
-                    t_all - t_start
-                );
-                continue;
-            }
             // TEMP: handle std lib gen (will use pipeline later)
             let module_name = entrypoint_filepath
                 .file_stem()
-                .expect(&format!(
-                    "unable to get file stem from filename {:?}",
-                    entrypoint_filepath
-                ))
-                .to_string_lossy();
-            let mut tables = ParsingTables::new();
+                .unwrap_or_else(|| {
+                    panic!(
+                        "unable to get file stem from filename {:?}",
+                        entrypoint_filepath
+                    )
+                })
+                .to_string_lossy()
+                .to_string();
             tables.update(&ast, &module_name);
+            modules.push((entrypoint_filepath, module_name, ast));
+        }
+        for (_, module_name, ast) in modules.iter_mut() {
+            pipeline::resolve_types(ast, module_name, &mut tables);
+        }
+        if command.flags.contains(&Flags::DumpTypeTable) {
+            print!("{}", tables.types.dump());
+        }
+        // Each unique monomorphization is generated once, no matter how many modules use it
+        // `check` still runs every semantic check, it just doesn't write anything
+        let emit = command.mode != Mode::Check;
+        if emit {
             let filled_templates = codegen_c::generate_templated_libs(&tables.types);
             codegen_c::emit_templated_stdlib_files(&filled_templates);
-            // Write file
-            let generated_code = codegen_c::write_all(
-                ast.iter(),
-                &tables.types,
-                &file.path().file_stem().unwrap().to_string_lossy(),
-                true,
-            );
-            let new_path = format!(
-                "c_libs/gen_{}",
-                file.file_name().to_str().unwrap().replace(".iona", ".h")
+        }
+        for (entrypoint_filepath, module_name, ast) in modules.iter() {
+            let file_name = entrypoint_filepath
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .to_string();
+            // Check if we emit code for this
+            if pipeline::skips_emit(entrypoint_filepath) {
+                // Report on code timings
+                let t_all = Instant::now();
+                println!("finished compiling {} in {:?}", &file_name, t_all - t_start);
+                continue;
+            }
+            // Only this module's problems are printed, the ones before it already were
+            sources.diagnostics.clear();
+            let checked = pipeline::check_ast(
+                entrypoint_filepath,
+                ast,
+                &tables,
+                false,
+                &command.lints,
+                &mut sources,
             );
-            fs::write(new_path, generated_code).expect("Unable to write file");
+            print_problems(&sources);
+            if let Err(e) = checked {
+                eprint!("{}", e);
+                std::process::exit(1);
+            }
+            // Write file
+            if emit {
+                let generated_code = codegen_c::write_all(
+                    ast.iter(),
+                    &tables.types,
+                    module_name,
+                    true,
+                    &codegen_options,
+                );
+                let new_path = format!("c_libs/gen_{}", file_name.replace(".iona", ".h"));
+                fs::write(new_path, generated_code).expect("Unable to write file");
+            }
             let t_all = Instant::now();
             // Report on code timings
-            println!(
-                "finished compiling {} in {:?}",
-                &file.file_name().to_str().unwrap(),
-                t_all - t_start
-            );
+            println!("finished compiling {} in {:?}", &file_name, t_all - t_start);
         }
         Ok(())
     } else {
-        return Err("impossible!".into());
+        Err("impossible!".into())
     }
 }
@@ -1,7 +1,10 @@
 //! Recursive Descent Parser
-use crate::diagnostics::Diagnostic;
+use crate::diagnostics::{Diagnostic, DiagnosticKind};
 use crate::expression_parser::Expr;
-use crate::lexer::{Symbol, Token};
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::lexer::{SourcePosition, Symbol, Token};
 
 // -------------------- Parser Object --------------------
 
@@ -13,14 +16,16 @@ pub struct ParserMetadata {
 
 /// We use a new Parser for each file
 ///
-/// A Parser receives tokens from a lexer, and tracks its index within the token stream using the `offset` variable to allow for easier lookahead/rollbacks (inspired by Apache Kafka)
+/// A Parser receives tokens from a lexer, and tracks its index within the token stream using the `offset` variable (inspired by Apache Kafka)
+///
+/// Tokens are pulled from the `source` as they're needed, and `tokens` only buffers enough of them to look past the whitespace to the next meaningful token. Consumed tokens are dropped, so the parser never holds the whole file
 ///
 /// The `recursion_counter` prevents the parser from getting stuck in certain operations
 ///
 /// The `trace` holds a list of log messages identifying the order of operations (for debugging)
-#[derive(Debug, Clone, PartialEq)]
-pub struct Parser {
-    tokens: Vec<Token>,
+pub struct Parser<'a> {
+    source: Box<dyn Iterator<Item = Token> + 'a>,
+    tokens: VecDeque<Token>,
     offset: usize,
     pub recursion_counter: usize,
     pub trace: Vec<String>, // queue of parsing fn calls to debug state
@@ -113,7 +118,7 @@ impl<T> ParserOutputExt<T> for ParserOutput<T> {
 
 // -------------------- AST --------------------
 
-/// TODO: the inner type of the Map should be a tuple
+/// A `Map<K, V>` holds a tuple of its key and value types
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Type {
     Void,
@@ -125,14 +130,59 @@ pub enum Type {
     Size,
     Byte,
     Auto,
+    #[allow(clippy::enum_variant_names)]
     CType, // special type for certain standard library primitives
     Array(Box<Type>),
     Map(Box<Type>),
     Shared(Box<Type>),
+    Tuple(Vec<Type>),
+    FixedArray(Box<Type>, usize), // `Array<T, N>`, stored inline instead of on the heap
+    Optional(Box<Type>),          // `T?`, a value that might not be there
     Generic(String),
     Custom(String),
 }
 
+/// Write a type the way it's spelled in Iona source code, like `Array<Int>` or `String?`
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Void => write!(f, "Void"),
+            Type::Self_ => write!(f, "Self"),
+            Type::Integer => write!(f, "Int"),
+            Type::Float => write!(f, "Float"),
+            Type::String => write!(f, "String"),
+            Type::Boolean => write!(f, "Bool"),
+            Type::Size => write!(f, "Size"),
+            Type::Byte => write!(f, "Byte"),
+            Type::Auto => write!(f, "Auto"),
+            Type::CType => write!(f, "RawCType"),
+            Type::Array(inner) => write!(f, "Array<{}>", inner),
+            // The key and value were parsed into a tuple, see `Type`
+            Type::Map(inner) => match inner.as_ref() {
+                Type::Tuple(pair) if pair.len() == 2 => {
+                    write!(f, "Map<{}, {}>", pair[0], pair[1])
+                }
+                other => write!(f, "Map<{}>", other),
+            },
+            Type::Shared(inner) => write!(f, "Shared<{}>", inner),
+            Type::Tuple(members) => {
+                write!(f, "(")?;
+                for (i, member) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", member)?;
+                }
+                write!(f, ")")
+            }
+            Type::FixedArray(inner, size) => write!(f, "Array<{}, {}>", inner, size),
+            Type::Optional(inner) => write!(f, "{}?", inner),
+            Type::Generic(name) => write!(f, "Generic<{}>", name),
+            Type::Custom(name) => write!(f, "{}", name),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum DataProperties {
     Public,
@@ -149,15 +199,30 @@ pub enum DataTraits {
 pub struct Field {
     pub name: String,
     pub field_type: Type,
+    /// Where the field's name is written
+    pub pos: SourcePosition,
+}
+
+/// Attributes sit on the line before a declaration, like `@cfg(target = "windows")`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Attribute {
+    /// Only compile the declaration when the build setting `key` is `value`
+    Cfg { key: String, value: String },
+    /// Don't report the named lints (like `unused`) anywhere inside the declaration
+    Allow(Vec<String>),
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Struct {
     pub name: String,
+    /// Generic parameters listed after the name, like `T` in `struct Pair<T> {`
+    pub type_params: Vec<String>,
     pub fields: Vec<Field>,
     pub properties: Vec<DataProperties>,
     pub traits: Vec<DataTraits>,
     pub methods: Vec<Function>,
+    pub attributes: Vec<Attribute>,
+    pub pos: SourcePosition,
 }
 
 /// An enum has the same shape as a struct but different rules
@@ -166,16 +231,24 @@ pub struct Struct {
 #[derive(Debug, Clone, PartialEq)]
 pub struct Enum {
     pub name: String,
-    pub fields: Vec<Field>,
+    pub type_params: Vec<String>,
+    pub fields: Vec<Field>, // A variant without a payload has a `Void` field type (writing `: Void` is an error)
     pub properties: Vec<DataProperties>,
     pub traits: Vec<DataTraits>,
     pub methods: Vec<Function>,
+    pub attributes: Vec<Attribute>,
+    pub pos: SourcePosition,
 }
 
+/// `import file with items;`
+///
+/// `reexport` is set for `export import file with items;`, which also makes the items available to anything importing this module
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Import {
     pub file: String,
     pub items: Vec<String>,
+    pub pos: SourcePosition,
+    pub reexport: bool,
 }
 
 /// Functions can have different properties than Data Types
@@ -201,12 +274,25 @@ pub enum FunctionPermissions {
 #[derive(Debug, Clone, PartialEq)]
 pub struct Function {
     pub name: String,
+    pub pos: SourcePosition,
+    pub type_params: Vec<String>,
     pub args: Vec<Field>,
     pub returns: Type,
     pub properties: Vec<FunctionProperties>,
     pub permissions: Vec<FunctionPermissions>,
     pub contracts: Vec<FunctionContract>,
     pub statements: Vec<Statement>,
+    pub attributes: Vec<Attribute>,
+    pub is_extern: bool, // Defined in C, so there's no body to generate
+}
+
+/// Defaults for every function in a file, like `@module { Uses: ReadFile; }`
+///
+/// A function without its own `Uses` line gets the module's permissions instead
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleMetadata {
+    pub permissions: Vec<FunctionPermissions>,
+    pub pos: SourcePosition,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -215,32 +301,124 @@ pub enum ASTNode {
     EnumDeclaration(Enum),
     ImportStatement(Import),
     FunctionDeclaration(Function),
+    ModuleMetadata(ModuleMetadata),
 }
 
 // -------------------- Parsers --------------------
 
 // -------------------| Parse Top Level Nodes |-------------------
 
-impl Parser {
+impl Parser<'_> {
+    /// Parse every declaration in the file
+    ///
+    /// A declaration that fails to parse is reported and skipped, so one mistake doesn't hide the errors (or the declarations) after it
     pub fn parse_all(&mut self) -> ParserOutput<Vec<ASTNode>> {
         self.add_trace("parse all");
-        self.parse_list_newline_separated(|p| p.parse_top_level_declaration())
+        let mut nodes = Vec::new();
+        let mut diagnostics = Vec::new();
+        loop {
+            self.skip_whitespace();
+            // The lexer always ends the stream with a newline
+            if self.at_end() {
+                break;
+            }
+            let start = self.offset;
+            let node = self.parse_top_level_declaration();
+            diagnostics.extend(node.diagnostics);
+            match node.output {
+                Some(node) => nodes.push(node),
+                None => {
+                    if self.offset == start {
+                        self.consume();
+                    }
+                    self.recover_to_top_level();
+                }
+            }
+        }
+        ParserOutput {
+            output: Some(nodes),
+            diagnostics,
+        }
+    }
+
+    /// Skip ahead to the start of the next top level declaration: a keyword (or attribute) at the very start of a line
+    ///
+    /// Only the first column counts, so that a `fn` inside a broken struct's methods doesn't stop us early
+    pub fn recover_to_top_level(&mut self) {
+        while !self.at_end() {
+            let token = self.peek();
+            let starts_declaration = match &token.symbol {
+                Symbol::Function
+                | Symbol::Extern
+                | Symbol::Struct
+                | Symbol::Enum
+                | Symbol::Import
+                | Symbol::Tag => true,
+                Symbol::Identifier(name) => name == "export",
+                _ => false,
+            };
+            if starts_declaration && token.pos.column == 0 {
+                return;
+            }
+            self.consume();
+        }
     }
 
     fn parse_top_level_declaration(&mut self) -> ParserOutput<ASTNode> {
         self.add_trace("parse top level declaration (statement)");
         self.skip_whitespace();
+        // `module` isn't a keyword, so it can still be used as a name everywhere else
+        if self.peek().symbol == Symbol::Tag
+            && self.tokens[1].symbol == Symbol::Identifier("module".to_string())
+        {
+            return self.parse_module_metadata().map(ASTNode::ModuleMetadata);
+        }
+        if self.peek().symbol == Symbol::Tag {
+            return self.parse_attributes().and_then(|attributes| {
+                self.skip_whitespace();
+                let pos = self.peek().pos.clone();
+                self.parse_declaration().and_then(|node| match node {
+                    ASTNode::FunctionDeclaration(mut f) => {
+                        f.attributes = attributes;
+                        ParserOutput::okay(ASTNode::FunctionDeclaration(f))
+                    }
+                    ASTNode::StructDeclaration(mut s) => {
+                        s.attributes = attributes;
+                        ParserOutput::okay(ASTNode::StructDeclaration(s))
+                    }
+                    ASTNode::EnumDeclaration(mut e) => {
+                        e.attributes = attributes;
+                        ParserOutput::okay(ASTNode::EnumDeclaration(e))
+                    }
+                    ASTNode::ImportStatement(_) | ASTNode::ModuleMetadata(_) => {
+                        ParserOutput::err(vec![Diagnostic::new_error_simple(
+                            DiagnosticKind::Syntax,
+                            "attributes can only be applied to functions, structs, and enums",
+                            &pos,
+                        )])
+                    }
+                })
+            });
+        }
+        self.parse_declaration()
+    }
+
+    fn parse_declaration(&mut self) -> ParserOutput<ASTNode> {
         match self.peek().symbol {
             Symbol::Struct => self.parse_struct().map(ASTNode::StructDeclaration),
             Symbol::Enum => self.parse_enum().map(ASTNode::EnumDeclaration),
             Symbol::Import => self.parse_import().map(ASTNode::ImportStatement),
-            Symbol::Function => {
-                let item = self.parse_function().map(ASTNode::FunctionDeclaration);
-                return item;
+            // Like `module`, `export` isn't a keyword
+            Symbol::Identifier(ref name) if name == "export" => {
+                self.parse_reexport().map(ASTNode::ImportStatement)
             }
+            Symbol::Function => self.parse_function().map(ASTNode::FunctionDeclaration),
+            Symbol::Extern => self
+                .parse_extern_function()
+                .map(ASTNode::FunctionDeclaration),
             _ => {
                 let message = format!(
-                    "error in top level declaration. Expected a keyword such as 'fn', 'struct', 'enum', or 'import', but found {:?}",
+                    "error in top level declaration. Expected a keyword such as 'fn', 'struct', 'enum', or 'import', but found {}",
                     self.peek().symbol
                 );
                 self.single_error(&message)
@@ -249,20 +427,130 @@ impl Parser {
     }
 }
 
+// -------------------| Parse Attributes |--------------------
+
+impl Parser<'_> {
+    /// Parse one or more attributes, each on its own line
+    fn parse_attributes(&mut self) -> ParserOutput<Vec<Attribute>> {
+        self.add_trace("parse attributes");
+        let mut attributes = Vec::new();
+        let mut diagnostics = Vec::new();
+        while self.peek().symbol == Symbol::Tag {
+            let attribute = self.parse_attribute();
+            diagnostics.extend(attribute.diagnostics);
+            match attribute.output {
+                Some(a) => attributes.push(a),
+                None => return ParserOutput::err(diagnostics),
+            }
+            self.skip_whitespace();
+        }
+        ParserOutput {
+            output: Some(attributes),
+            diagnostics,
+        }
+    }
+
+    /// Parse an attribute like `@cfg(target = "windows")` or `@allow(unused)`
+    fn parse_attribute(&mut self) -> ParserOutput<Attribute> {
+        self.add_trace("parse attribute");
+        self.then_ignore(Symbol::Tag)
+            .and_then(|_| match self.peek().symbol {
+                // `cfg` and `allow` aren't keywords, so they can still be used as names everywhere else
+                Symbol::Identifier(ref name) if name == "cfg" => self.parse_cfg_attribute(),
+                Symbol::Identifier(ref name) if name == "allow" => self.parse_allow_attribute(),
+                ref other => {
+                    let message =
+                        format!("expected `cfg` or `allow` after `@`, but found {}", other);
+                    self.single_error(&message)
+                }
+            })
+    }
+
+    fn parse_cfg_attribute(&mut self) -> ParserOutput<Attribute> {
+        self.then_ignore(Symbol::Identifier("cfg".to_string()))
+            .and_then(|_| self.then_ignore(Symbol::ParenOpen))
+            .and_then(|_| self.with_whitespace(|p| p.then_identifier()))
+            .and_then(|key| {
+                self.with_whitespace(|p| p.then_ignore(Symbol::Equals))
+                    .map(|_| key)
+            })
+            .and_then(|key| {
+                self.skip_whitespace();
+                match self.peek().symbol.clone() {
+                    Symbol::StringLiteral(value) => {
+                        self.consume();
+                        ParserOutput::okay(Attribute::Cfg { key, value })
+                    }
+                    other => {
+                        let message = format!(
+                            "expected a string for the value of a cfg attribute, but found {}",
+                            other
+                        );
+                        self.single_error(&message)
+                    }
+                }
+            })
+            .and_then(|attribute| {
+                self.with_whitespace(|p| p.then_ignore(Symbol::ParenClose))
+                    .map(|_| attribute)
+            })
+    }
+
+    /// Parse a list of lint names, like `@allow(unused)`
+    fn parse_allow_attribute(&mut self) -> ParserOutput<Attribute> {
+        self.then_ignore(Symbol::Identifier("allow".to_string()))
+            .and_then(|_| self.then_ignore(Symbol::ParenOpen))
+            .and_then(|_| {
+                self.parse_list_comma_separated(|p| p.with_whitespace(|p| p.then_identifier()))
+            })
+            .and_then(|lints| {
+                self.with_whitespace(|p| p.then_ignore(Symbol::ParenClose))
+                    .map(|_| Attribute::Allow(lints))
+            })
+    }
+}
+
 // -------------------| Parse Types |--------------------
 
-impl Parser {
+impl Parser<'_> {
+    /// Parse a type, where a trailing `?` makes it optional (`String?`)
     fn parse_type(&mut self) -> ParserOutput<Type> {
         self.add_trace("parse type");
+        self.parse_required_type().map(|t| {
+            if self.peek().symbol == Symbol::Question {
+                self.consume();
+                Type::Optional(Box::new(t))
+            } else {
+                t
+            }
+        })
+    }
+
+    fn parse_required_type(&mut self) -> ParserOutput<Type> {
+        self.add_trace("parse required type");
         // Handle generics
         if self.peek().symbol == Symbol::Generic {
-            self.then_ignore(Symbol::Generic);
-            self.then_ignore(Symbol::LeftAngle);
-            let generic = self
-                .then_identifier()
-                .and_then(|name| ParserOutput::okay(Type::Generic(name)));
-            self.then_ignore(Symbol::RightAngle);
-            return generic;
+            return self
+                .then_ignore(Symbol::Generic)
+                .and_then(|_| self.with_whitespace(|p| p.then_ignore(Symbol::LeftAngle)))
+                .and_then(|_| self.then_identifier())
+                .and_then(|name| {
+                    self.skip_whitespace();
+                    self.then_ignore(Symbol::RightAngle)
+                        .map(|_| Type::Generic(name))
+                });
+        }
+        // Handle tuples, like `(Int, String)`
+        if self.peek().symbol == Symbol::ParenOpen {
+            return self
+                .then_ignore(Symbol::ParenOpen)
+                .and_then(|_| {
+                    self.parse_list_comma_separated(|p| p.with_whitespace(|p| p.parse_type()))
+                })
+                .and_then(|types| {
+                    self.with_whitespace(|p| p.then_ignore(Symbol::ParenClose))
+                        .map(|_| Type::Tuple(types))
+                });
         }
         // Handle everything else
         self.then_identifier().and_then(|name| match name.as_str() {
@@ -275,41 +563,101 @@ impl Parser {
             "Byte" => ParserOutput::okay(Type::Byte),
             "Void" => ParserOutput::okay(Type::Void),
             "RawCType" => ParserOutput::okay(Type::CType),
-            // Handle boxed types
-            "Array" | "Map" | "Shared" => {
-                // Expect and consume a left angle bracket
-                self.then_ignore(Symbol::LeftAngle);
-
-                // Recursively parse the inner type
-                let inner_type = self.parse_type();
-                if inner_type.output.is_some() {
-                    // Expect and consume a right angle bracket
-                    self.then_ignore(Symbol::RightAngle);
-                    let unwrapped_inner_type = inner_type.output.unwrap();
-
-                    // Construct the appropriate boxed type
-                    let boxed_type = match name.as_str() {
-                        "Array" => Type::Array(Box::new(unwrapped_inner_type)),
-                        "Map" => Type::Map(Box::new(unwrapped_inner_type)),
-                        "Shared" => Type::Shared(Box::new(unwrapped_inner_type)),
-                        _ => unreachable!(),
-                    };
-
-                    ParserOutput::okay(boxed_type)
-                } else {
-                    return inner_type;
+            // Handle boxed types, allowing whitespace anywhere inside the brackets
+            "Array" | "Map" | "Shared" => self
+                .with_whitespace(|p| p.then_ignore(Symbol::LeftAngle))
+                .and_then(|_| self.with_whitespace(|p| p.parse_type()))
+                .and_then(|inner| match (name.as_str(), &self.peek().symbol) {
+                    // Arrays can optionally have a fixed size, like `Array<Int, 8>`
+                    ("Array", Symbol::Comma) => self.parse_fixed_array_size().and_then(|size| {
+                        self.then_ignore(Symbol::RightAngle)
+                            .map(|_| Type::FixedArray(Box::new(inner), size))
+                    }),
+                    // Maps hold key-value pairs, like `Map<String, Int>`
+                    ("Map", Symbol::Comma) => self
+                        .then_ignore(Symbol::Comma)
+                        .and_then(|_| self.with_whitespace(|p| p.parse_type()))
+                        .and_then(|value| {
+                            self.then_ignore(Symbol::RightAngle)
+                                .map(|_| Type::Map(Box::new(Type::Tuple(vec![inner, value]))))
+                        }),
+                    _ => self
+                        .then_ignore(Symbol::RightAngle)
+                        .map(|_| match name.as_str() {
+                            "Array" => Type::Array(Box::new(inner)),
+                            "Map" => Type::Map(Box::new(inner)),
+                            "Shared" => Type::Shared(Box::new(inner)),
+                            _ => unreachable!(),
+                        }),
+                }),
+            _ => ParserOutput::okay(Type::Custom(name)),
+        })
+    }
+}
+
+impl Parser<'_> {
+    /// Parse the optional generic parameters after a declaration's name, like `<K, V>`
+    fn parse_type_parameters(&mut self) -> ParserOutput<Vec<String>> {
+        self.add_trace("parse type parameters");
+        let mut names = Vec::new();
+        if self.peek().symbol != Symbol::LeftAngle {
+            return ParserOutput::okay(names);
+        }
+        self.consume();
+        loop {
+            let name = self.with_whitespace(|p| p.then_identifier());
+            match name.output {
+                Some(name) => names.push(name),
+                None => return name.transmute_error(),
+            }
+            match self.peek().symbol {
+                Symbol::Comma => {
+                    self.consume();
+                }
+                Symbol::RightAngle => {
+                    self.consume();
+                    return ParserOutput::okay(names);
+                }
+                _ => {
+                    let message = format!(
+                        "expected ',' or '>' in a list of type parameters, but found {}",
+                        self.peek().symbol
+                    );
+                    return self.single_error(&message);
+                }
+            }
+        }
+    }
+
+    /// Parse the `, N` part of a fixed size array type
+    fn parse_fixed_array_size(&mut self) -> ParserOutput<usize> {
+        self.add_trace("parse fixed array size");
+        self.then_ignore(Symbol::Comma).and_then(|_| {
+            self.skip_whitespace();
+            match self.peek().symbol {
+                Symbol::Integer(n) if n > 0 => {
+                    self.consume();
+                    self.skip_whitespace();
+                    ParserOutput::okay(n as usize)
+                }
+                _ => {
+                    let message = format!(
+                        "expected a positive integer for the size of a fixed array, but found {}",
+                        self.peek().symbol
+                    );
+                    self.single_error(&message)
                 }
             }
-            _ => ParserOutput::okay(Type::Custom(name)),
         })
     }
 }
 
 // -------------------| Parser Imports |--------------------
 
-impl Parser {
+impl Parser<'_> {
     fn parse_import(&mut self) -> ParserOutput<Import> {
         self.add_trace("parse import");
+        let pos = self.peek().pos.clone();
         self.then_ignore(Symbol::Import)
             .and_then(|_| self.with_whitespace(|p| p.then_identifier()))
             .and_then(|file| {
@@ -320,16 +668,33 @@ impl Parser {
                         })
                     })
                     .and_then(|items| {
-                        self.then_ignore(Symbol::Semicolon)
-                            .map(|_| Import { file, items })
+                        self.then_ignore(Symbol::Semicolon).map(|_| Import {
+                            file,
+                            items,
+                            pos,
+                            reexport: false,
+                        })
                     })
             })
     }
+
+    /// `export import file with items;`, an import whose items are exported from this module too
+    fn parse_reexport(&mut self) -> ParserOutput<Import> {
+        self.add_trace("parse re-export");
+        let pos = self.peek().pos.clone();
+        self.then_identifier()
+            .and_then(|_| self.with_whitespace(|p| p.parse_import()))
+            .map(|import| Import {
+                pos,
+                reexport: true,
+                ..import
+            })
+    }
 }
 
 // -------------------| Shared Parsers: Structs and Enums |--------------------
 
-impl Parser {
+impl Parser<'_> {
     fn parse_data_properties(&mut self) -> ParserOutput<DataProperties> {
         self.add_trace("parse data properties");
         self.then_identifier().and_then(|name| match name.as_str() {
@@ -400,6 +765,7 @@ impl Parser {
                         Symbol::BraceClose => break,
                         _ => {
                             diagnostics.push(Diagnostic::new_error_simple(
+                                DiagnosticKind::Syntax,
                                 "Unexpected token when parsing function metadata (props and traits/derives)",
                                 &self.peek().pos,
                             ));
@@ -419,23 +785,26 @@ impl Parser {
 
 // -------------------| Struct Parsers |--------------------
 
-impl Parser {
-    fn parse_struct_declaration(&mut self) -> ParserOutput<String> {
+impl Parser<'_> {
+    fn parse_struct_declaration(&mut self) -> ParserOutput<(String, Vec<String>)> {
         self.add_trace("parse struct declaration");
         self.then_ignore(Symbol::Struct)
             .and_then(|_| self.with_whitespace(|p| p.then_identifier()))
-            .and_then(|name| {
-                self.with_whitespace(|p| p.then_ignore(Symbol::BraceOpen).map(|_| name))
+            .and_then(|name| self.parse_type_parameters().map(|params| (name, params)))
+            .and_then(|declaration| {
+                self.with_whitespace(|p| p.then_ignore(Symbol::BraceOpen).map(|_| declaration))
             })
     }
 
     fn parse_field_mandatory_type(&mut self) -> ParserOutput<Field> {
         self.add_trace("parse a field that has a mandatory type");
+        let pos = self.peek().pos.clone();
         self.then_identifier().and_then(|name| {
             if name == "self" {
                 return ParserOutput::okay(Field {
                     name,
                     field_type: Type::Self_,
+                    pos,
                 });
             }
             self.with_whitespace(|p| p.then_ignore(Symbol::Colon))
@@ -443,17 +812,19 @@ impl Parser {
                 .map(|type_| Field {
                     name,
                     field_type: type_,
+                    pos,
                 })
         })
     }
 
     pub fn parse_struct(&mut self) -> ParserOutput<Struct> {
         self.add_trace("parse struct");
+        let pos = self.peek().pos.clone();
         let name = self.parse_struct_declaration();
         if name.output.is_none() {
             return name.transmute_error::<Struct>();
         }
-        let struct_name = name.output.clone().unwrap();
+        let (struct_name, type_params) = name.output.clone().unwrap();
 
         name.and_then(|_| {
             self.parse_list_comma_separated(|p| {
@@ -469,10 +840,13 @@ impl Parser {
             self.parse_list_newline_separated(|p| p.parse_function())
                 .map(|methods| Struct {
                     name: struct_name,
+                    type_params,
                     fields,
                     properties,
                     traits,
                     methods,
+                    attributes: Vec::new(),
+                    pos,
                 })
         })
         .and_then(|struct_| {
@@ -484,37 +858,60 @@ impl Parser {
 
 // -------------------| Enum Parsers |--------------------
 
-impl Parser {
-    fn parse_enum_declaration(&mut self) -> ParserOutput<String> {
+impl Parser<'_> {
+    fn parse_enum_declaration(&mut self) -> ParserOutput<(String, Vec<String>)> {
         self.add_trace("parse enum declaration");
         self.then_ignore(Symbol::Enum)
             .and_then(|_| self.with_whitespace(|p| p.then_identifier()))
-            .and_then(|name| {
-                self.with_whitespace(|p| p.then_ignore(Symbol::BraceOpen).map(|_| name))
+            .and_then(|name| self.parse_type_parameters().map(|params| (name, params)))
+            .and_then(|declaration| {
+                self.with_whitespace(|p| p.then_ignore(Symbol::BraceOpen).map(|_| declaration))
             })
     }
 
     fn parse_field_optional_type(&mut self) -> ParserOutput<Field> {
         self.add_trace("parse enum field optional type");
+        let pos = self.peek().pos.clone();
         self.then_identifier().and_then(|name| {
             self.with_whitespace(|p| {
                 match p.peek().symbol {
                     Symbol::Colon => {
                         // This is a typed field
-                        p.then_ignore(Symbol::Colon)
-                            .and_then(|_| p.with_whitespace(|p| p.parse_type()))
-                            .map(|field_type| Field { name, field_type })
+                        p.then_ignore(Symbol::Colon).and_then(|_| {
+                            p.skip_whitespace();
+                            let type_pos = p.peek().pos.clone();
+                            p.with_whitespace(|p| p.parse_type())
+                                .and_then(|field_type| match field_type {
+                                    // `Void` is how we store a variant with no payload, so it can't be written out
+                                    Type::Void => ParserOutput::err(vec![
+                                        Diagnostic::new_error_simple(
+                                            DiagnosticKind::Syntax,
+                                            &format!(
+                                                "enum variant `{}` can't carry a Void payload, leave off the type to declare it without one",
+                                                name
+                                            ),
+                                            &type_pos,
+                                        ),
+                                    ]),
+                                    field_type => ParserOutput::okay(Field {
+                                        name,
+                                        field_type,
+                                        pos,
+                                    }),
+                                })
+                        })
                     }
                     Symbol::Comma => {
                         // This is a typeless field
                         ParserOutput::okay(Field {
                             name,
                             field_type: Type::Void,
+                            pos,
                         })
                     }
                     _ => {
                         let message = format!(
-                            "expected ':' or ',' after enum field name, but found {:?}",
+                            "expected ':' or ',' after enum field name, but found {}",
                             p.peek().symbol
                         );
                         p.single_error(&message)
@@ -526,11 +923,12 @@ impl Parser {
 
     pub fn parse_enum(&mut self) -> ParserOutput<Enum> {
         self.add_trace("parse enum");
+        let pos = self.peek().pos.clone();
         let name = self.parse_enum_declaration();
         if name.output.is_none() {
             return name.transmute_error::<Enum>();
         }
-        let enum_name = name.output.clone().unwrap();
+        let (enum_name, type_params) = name.output.clone().unwrap();
         name.and_then(|_| {
             self.parse_list_comma_separated(|p| {
                 p.with_whitespace(|p| p.parse_field_optional_type())
@@ -540,10 +938,13 @@ impl Parser {
             let metadata = self.parse_metadata_data_types();
             metadata.map(|(properties, traits)| Enum {
                 name: enum_name,
+                type_params,
                 fields,
                 properties,
                 traits,
                 methods: Vec::new(),
+                attributes: Vec::new(),
+                pos,
             })
         })
         .and_then(|enum_| {
@@ -558,6 +959,7 @@ impl Parser {
 #[derive(Debug, Clone, PartialEq, Eq)]
 struct FunctionDeclaration {
     pub name: String,
+    pub type_params: Vec<String>,
     pub parameters: Vec<Field>,
     pub return_type: Type,
 }
@@ -568,68 +970,148 @@ pub enum ContractType {
     Output,
 }
 
+/// A runtime check on a function's inputs or output
+///
+/// The `pos` is the location of the `In`/`Out` keyword, so that a failed check can point back at the source
 #[derive(Debug, Clone, PartialEq)]
 pub struct FunctionContract {
-    type_: ContractType,
-    condition: Expr,
-    message: String,
+    pub type_: ContractType,
+    pub condition: Expr,
+    pub message: String,
+    pub pos: SourcePosition,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Branch {
-    condition: Option<Expr>, // None is the catch all case (`_` in a match or `else` in a ternary)
+    pub condition: Option<Expr>, // None is the catch all case (`_` in a match or `else` in a ternary)
     pub computations: Vec<Statement>,
+    pub pos: SourcePosition, // The `if`/`elif`/`else` keyword, or the start of a match arm's pattern
+}
+
+/// A shape that a value is checked against, binding parts of it to names when it matches
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    /// An enum variant, optionally binding its payload, like `Some(x)` or `Empty`
+    Variant {
+        name: String,
+        binding: Option<String>,
+    },
 }
 
+/// Each statement records the position of its first token so later passes can point back at it
 #[derive(Debug, Clone, PartialEq)]
 pub enum Statement {
-    FunctionCall(Expr),
+    FunctionCall {
+        call: Expr,
+        pos: SourcePosition,
+    },
+    /// `value` is `None` for `let x: Int;`, which has to be assigned before it's read
     VariableDeclaration {
         name: String,
         type_: Type,
-        value: Expr,
+        value: Option<Expr>,
+        pos: SourcePosition,
     },
     VariableMutation {
         name: String,
         value: Expr,
+        pos: SourcePosition,
+    },
+    Conditional {
+        branches: Vec<Branch>,
+        pos: SourcePosition,
+    },
+    /// The branch conditions of a match are patterns compared against the scrutinee, not boolean expressions
+    Match {
+        scrutinee: Expr,
+        branches: Vec<Branch>,
+        pos: SourcePosition,
+    },
+    /// With a `binding`, the loop runs for as long as `condition` matches the pattern (`while let Some(x) = next()`)
+    WhileLoop {
+        binding: Option<Pattern>,
+        condition: Expr,
+        body: Vec<Statement>,
+        pos: SourcePosition,
+    },
+    Return {
+        value: Expr,
+        pos: SourcePosition,
+    },
+    /// `assert(cond, "message");` stops the program if `cond` is false, unlike a contract it can go anywhere in a body
+    Assert {
+        cond: Expr,
+        message: Option<String>,
+        pos: SourcePosition,
     },
-    Conditional(Vec<Branch>),
-    Return(Expr),
 }
 
-impl Parser {
+impl Statement {
+    pub fn pos(&self) -> &SourcePosition {
+        match self {
+            Statement::FunctionCall { pos, .. }
+            | Statement::VariableDeclaration { pos, .. }
+            | Statement::VariableMutation { pos, .. }
+            | Statement::Conditional { pos, .. }
+            | Statement::Match { pos, .. }
+            | Statement::WhileLoop { pos, .. }
+            | Statement::Return { pos, .. }
+            | Statement::Assert { pos, .. } => pos,
+        }
+    }
+}
+
+impl Parser<'_> {
     /// Returns (Name, Args, ReturnType)
     fn parse_function_declaration(&mut self) -> ParserOutput<FunctionDeclaration> {
         self.add_trace("parse function declaration");
+        self.parse_function_signature().and_then(|declaration| {
+            self.with_whitespace(|p| p.then_ignore(Symbol::BraceOpen))
+                .map(|_| declaration)
+        })
+    }
+
+    /// Parse everything in a declaration up to the body, like `fn add(a: Int, b: Int) -> Int`
+    fn parse_function_signature(&mut self) -> ParserOutput<FunctionDeclaration> {
+        self.add_trace("parse function signature");
         // Parse "fn" keyword and function name
         let fn_and_name = self
             .then_ignore(Symbol::Function)
             .and_then(|_| self.with_whitespace(|p| p.then_identifier()));
 
+        let declaration =
+            fn_and_name.and_then(|name| self.parse_type_parameters().map(|params| (name, params)));
+
         // Parse parameters and return type
-        let declaration = fn_and_name.and_then(|name| {
+        let declaration = declaration.and_then(|(name, type_params)| {
             self.then_ignore(Symbol::ParenOpen)
-                .and_then(|_| self.parse_list_comma_separated(|p| p.parse_field_mandatory_type()))
+                .and_then(|_| {
+                    // `fn f()` has no parameters, so don't try to parse a field out of the `)`
+                    if self.peek().symbol == Symbol::ParenClose {
+                        ParserOutput::okay(Vec::new())
+                    } else {
+                        self.parse_list_comma_separated(|p| p.parse_field_mandatory_type())
+                    }
+                })
                 .and_then(|parameters| {
                     self.then_ignore(Symbol::ParenClose).and_then(|_| {
                         // Parse return type arrow and type
                         self.with_whitespace(|p| p.then_ignore(Symbol::Dash))
                             .and_then(|_| self.then_ignore(Symbol::RightAngle))
                             .and_then(|_| self.with_whitespace(|p| p.parse_type()))
-                            .map(|return_type| (name, parameters, return_type))
+                            .map(|return_type| (name, type_params, parameters, return_type))
                     })
                 })
         });
 
-        // Parse opening brace and construct final result
-        declaration.and_then(|(name, parameters, return_type)| {
-            self.with_whitespace(|p| p.then_ignore(Symbol::BraceOpen))
-                .map(|_| FunctionDeclaration {
-                    name,
-                    parameters,
-                    return_type,
-                })
-        })
+        declaration.map(
+            |(name, type_params, parameters, return_type)| FunctionDeclaration {
+                name,
+                type_params,
+                parameters,
+                return_type,
+            },
+        )
     }
 
     fn parse_fn_properties(&mut self) -> ParserOutput<FunctionProperties> {
@@ -662,8 +1144,8 @@ impl Parser {
         &mut self,
     ) -> ParserOutput<(Vec<FunctionProperties>, Vec<FunctionPermissions>)> {
         self.add_trace("parse fn metadata");
-        // These are optional fields, if we don't see a tag then skip this
-        if self.peek().symbol != Symbol::Tag {
+        // These are optional fields, if we don't see a metadata tag then skip this (it may be the contracts tag instead)
+        if self.peek().symbol != Symbol::Tag || self.tokens[1].symbol != Symbol::Metadata {
             self.add_trace("skipping fn metadata");
             return ParserOutput::okay((
                 Vec::<FunctionProperties>::new(),
@@ -698,7 +1180,8 @@ impl Parser {
                         Symbol::BraceClose => break,
                         other => {
                             diagnostics.push(Diagnostic::new_error_simple(
-                                &format!("encountered an unexpected symbol parsing function metadata: found {:?}, expected `Is` (Properties), `Uses` (Permissions), or `}}`", other),
+                                DiagnosticKind::Syntax,
+                                &format!("encountered an unexpected symbol parsing function metadata: found {}, expected `Is` (Properties), `Uses` (Permissions), or `}}`", other),
                                 &self.peek().pos,
                             ));
                             self.consume(); // Skip the unexpected token
@@ -714,6 +1197,48 @@ impl Parser {
             .and_then(|metadata| self.then_ignore(Symbol::BraceClose).map(|_| metadata))
     }
 
+    /// Parse the defaults for a whole file, like `@module { Uses: ReadFile; }`
+    fn parse_module_metadata(&mut self) -> ParserOutput<ModuleMetadata> {
+        self.add_trace("parse module metadata");
+        let pos = self.peek().pos.clone();
+        self.then_ignore(Symbol::Tag)
+            .and_then(|_| self.then_identifier())
+            .and_then(|_| self.with_whitespace(|p| p.then_ignore(Symbol::BraceOpen)))
+            .and_then(|_| {
+                let mut permissions = Vec::new();
+                let mut diagnostics = Vec::new();
+                loop {
+                    self.skip_whitespace();
+                    match self.peek().symbol.clone() {
+                        Symbol::Permissions => {
+                            let result = self.parse_metadata_list(Symbol::Permissions, |p| {
+                                p.parse_fn_permissions()
+                            });
+                            permissions.extend(result.output.unwrap_or_default());
+                            diagnostics.extend(result.diagnostics);
+                        }
+                        Symbol::BraceClose => break,
+                        other => {
+                            diagnostics.push(Diagnostic::new_error_simple(
+                                DiagnosticKind::Syntax,
+                                &format!("encountered an unexpected symbol parsing module metadata: found {}, expected `Uses` (Permissions) or `}}`", other),
+                                &self.peek().pos,
+                            ));
+                            self.consume(); // Skip the unexpected token
+                        }
+                    }
+                }
+                ParserOutput {
+                    output: Some(permissions),
+                    diagnostics,
+                }
+            })
+            .and_then(|permissions| {
+                self.then_ignore(Symbol::BraceClose)
+                    .map(|_| ModuleMetadata { permissions, pos })
+            })
+    }
+
     fn parse_function_contracts(&mut self) -> ParserOutput<Vec<FunctionContract>> {
         self.add_trace("parse fn contracts");
         // These are optional fields, if we don't see a tag then skip this
@@ -732,6 +1257,7 @@ impl Parser {
                     self.skip_whitespace();
                     match self.peek().symbol.clone() {
                         Symbol::In | Symbol::Out => {
+                            let contract_pos = self.peek().pos.clone();
                             let contract_type = match self.peek().symbol {
                                 Symbol::In => ContractType::Input,
                                 Symbol::Out => ContractType::Output,
@@ -777,6 +1303,7 @@ impl Parser {
                                 }
                                 _ => {
                                     diagnostics.push(Diagnostic::new_error_simple(
+                                        DiagnosticKind::Syntax,
                                         "Expected a string in a contract for the contract error message",
                                         &self.peek().pos,
                                     ));
@@ -799,19 +1326,21 @@ impl Parser {
                                 type_: contract_type,
                                 condition: condition.output.unwrap(),
                                 message,
+                                pos: contract_pos,
                             });
                         }
                         Symbol::BraceClose => break,
                         other => {
                             diagnostics.push(Diagnostic::new_error_simple(
-                                &format!("Unexpected symbol in contract declaration: {:?}", other),
+                                DiagnosticKind::Syntax,
+                                &format!("Unexpected symbol in contract declaration: {}", other),
                                 &self.peek().pos,
                             ));
                             self.consume(); // Skip the unexpected token
                         }
                     }
                 }
-                if contracts.len() > 0 {
+                if !contracts.is_empty() {
                     ParserOutput {
                         output: Some(contracts),
                         diagnostics,
@@ -833,15 +1362,18 @@ impl Parser {
             Symbol::Let => self.parse_variable_declaration(),
             Symbol::If => self.parse_conditional(),
             Symbol::Match => self.parse_match(),
+            Symbol::While => self.parse_while_loop(),
             Symbol::Return => self.parse_return(),
+            Symbol::Assert => self.parse_assert(),
             Symbol::Identifier(_) => {
                 // Could be function call or assignment
+                let pos = self.peek().pos.clone();
                 let expr = self.parse_expr(0);
                 if expr.output.is_none() {
                     return expr.transmute_error();
                 }
 
-                self.skip_whitespace();
+                self.skip_spaces();
                 match &self.peek().symbol {
                     Symbol::Equals => {
                         // It's an assignment
@@ -851,19 +1383,22 @@ impl Parser {
                         if value.output.is_none() {
                             return value.transmute_error();
                         }
-                        self.then_ignore(Symbol::Semicolon)
+                        self.then_end_statement()
                             .map(|_| Statement::VariableMutation {
                                 name: match &expr.output.unwrap() {
                                     Expr::Variable(name) => name.clone(),
                                     _ => panic!("Invalid assignment target"),
                                 },
                                 value: value.output.unwrap(),
+                                pos,
                             })
                     }
-                    Symbol::Semicolon => {
+                    Symbol::Semicolon | Symbol::NewLine | Symbol::BraceClose => {
                         // It's a function call
-                        self.consume(); // consume ;
-                        ParserOutput::okay(Statement::FunctionCall(expr.output.unwrap()))
+                        self.then_end_statement().map(|_| Statement::FunctionCall {
+                            call: expr.output.unwrap(),
+                            pos,
+                        })
                     }
                     _ => self.single_error(
                         "issue parsing a statement, expected '=' or ';' after an expression",
@@ -871,14 +1406,14 @@ impl Parser {
                 }
             }
             _ => self.single_error(
-                "expected a statement keyword ('let', 'if', 'match', 'return', etc.)",
+                "expected a statement keyword ('let', 'if', 'match', 'while', 'return', etc.)",
             ),
         }
     }
 
     fn parse_variable_declaration(&mut self) -> ParserOutput<Statement> {
         self.add_trace("parse variable declaration");
-        self.consume(); // consume let
+        let pos = self.consume().pos.clone(); // consume let
         self.skip_whitespace();
 
         // Parse name
@@ -896,17 +1431,30 @@ impl Parser {
                 self.parse_type()
             })
             .and_then(|type_| {
-                // Parse initializer
-                self.skip_whitespace();
-                self.then_ignore(Symbol::Equals)
-                    .and_then(|_| {
-                        self.skip_whitespace();
-                        self.parse_expr(0)
-                    })
-                    .and_then(|value| {
-                        self.then_ignore(Symbol::Semicolon)
-                            .map(|_| Statement::VariableDeclaration { name, type_, value })
-                    })
+                // Parse the initializer, if there is one
+                self.skip_spaces();
+                let value = if matches!(
+                    self.peek().symbol,
+                    Symbol::Semicolon | Symbol::NewLine | Symbol::BraceClose
+                ) {
+                    ParserOutput::okay(None)
+                } else {
+                    self.then_ignore(Symbol::Equals)
+                        .and_then(|_| {
+                            self.skip_whitespace();
+                            self.parse_expr(0)
+                        })
+                        .map(Some)
+                };
+                value.and_then(|value| {
+                    self.then_end_statement()
+                        .map(|_| Statement::VariableDeclaration {
+                            name,
+                            type_,
+                            value,
+                            pos,
+                        })
+                })
             })
     }
 
@@ -916,7 +1464,7 @@ impl Parser {
         let mut diagnostics = Vec::new();
 
         // Parse if branch
-        self.consume(); // consume if
+        let pos = self.consume().pos.clone(); // consume if
         self.skip_whitespace();
 
         let condition = self.parse_expr(0);
@@ -933,6 +1481,7 @@ impl Parser {
         branches.push(Branch {
             condition: Some(condition.output.unwrap()),
             computations: block_result.output.unwrap(),
+            pos: pos.clone(),
         });
 
         // Parse elif branches
@@ -942,7 +1491,7 @@ impl Parser {
                 break;
             }
 
-            self.consume(); // consume elif
+            let elif_pos = self.consume().pos.clone(); // consume elif
             self.skip_whitespace();
 
             let elif_condition = self.parse_expr(0);
@@ -961,13 +1510,14 @@ impl Parser {
             branches.push(Branch {
                 condition: Some(elif_condition.output.unwrap()),
                 computations: elif_block.output.unwrap(),
+                pos: elif_pos,
             });
         }
 
         // Parse optional else branch
         self.skip_whitespace();
         if self.peek().symbol == Symbol::Else {
-            self.consume();
+            let else_pos = self.consume().pos.clone();
             self.skip_whitespace();
 
             let else_block = self.parse_block();
@@ -979,19 +1529,20 @@ impl Parser {
             branches.push(Branch {
                 condition: None,
                 computations: else_block.output.unwrap(),
+                pos: else_pos,
             });
         }
 
         if !diagnostics.is_empty() {
             ParserOutput::err(diagnostics)
         } else {
-            ParserOutput::okay(Statement::Conditional(branches))
+            ParserOutput::okay(Statement::Conditional { branches, pos })
         }
     }
 
     fn parse_match(&mut self) -> ParserOutput<Statement> {
         self.add_trace("parse match statement");
-        self.consume(); // consume match
+        let pos = self.consume().pos.clone(); // consume match
         self.skip_whitespace();
 
         let match_expr = self.parse_expr(0);
@@ -1016,6 +1567,7 @@ impl Parser {
             }
 
             // Parse match pattern
+            let arm_pos = self.peek().pos.clone();
             let condition = if self.peek().symbol == Symbol::Underscore {
                 self.consume();
                 None
@@ -1049,43 +1601,111 @@ impl Parser {
                 }
                 block_result.output.unwrap()
             } else {
+                let expr_pos = self.peek().pos.clone();
                 let expr = self.parse_expr(0);
                 if expr.output.is_none() {
                     diagnostics.extend(expr.diagnostics);
                     break;
                 }
 
-                let semi_result: ParserOutput<()>;
                 // Expect a comma, unless it's the last item
-                if self.lookahead().symbol == Symbol::BraceClose {
-                    semi_result = ParserOutput::okay(());
+                let semi_result: ParserOutput<()> = if self.lookahead().symbol == Symbol::BraceClose
+                {
+                    ParserOutput::okay(())
                 } else {
-                    semi_result = self.then_ignore(Symbol::Comma);
-                }
+                    self.then_ignore(Symbol::Comma)
+                };
                 if semi_result.output.is_none() {
                     diagnostics.extend(semi_result.diagnostics);
                     break;
                 }
 
-                vec![Statement::Return(expr.output.unwrap())]
+                vec![Statement::Return {
+                    value: expr.output.unwrap(),
+                    pos: expr_pos,
+                }]
             };
 
             branches.push(Branch {
                 condition,
                 computations: computation,
+                pos: arm_pos,
             });
         }
 
         if !diagnostics.is_empty() {
             ParserOutput::err(diagnostics)
         } else {
-            ParserOutput::okay(Statement::Conditional(branches))
+            ParserOutput::okay(Statement::Match {
+                scrutinee: match_expr.output.unwrap(),
+                branches,
+                pos,
+            })
+        }
+    }
+
+    fn parse_while_loop(&mut self) -> ParserOutput<Statement> {
+        self.add_trace("parse while loop");
+        let pos = self.consume().pos.clone(); // consume while
+        self.skip_whitespace();
+
+        // `while let <pattern> = <expr>` binds the payload for the body
+        let binding = if self.peek().symbol == Symbol::Let {
+            self.consume(); // consume let
+            self.skip_whitespace();
+            let pattern = self.parse_pattern();
+            if pattern.output.is_none() {
+                return pattern.transmute_error();
+            }
+            self.skip_whitespace();
+            let equals = self.then_ignore(Symbol::Equals);
+            if equals.output.is_none() {
+                return equals.transmute_error();
+            }
+            self.skip_whitespace();
+            pattern.output
+        } else {
+            None
+        };
+
+        let condition = self.parse_expr(0);
+        if condition.output.is_none() {
+            return condition.transmute_error();
         }
+
+        self.skip_whitespace();
+        self.parse_block().map(|body| Statement::WhileLoop {
+            binding,
+            condition: condition.output.unwrap(),
+            body,
+            pos,
+        })
+    }
+
+    fn parse_pattern(&mut self) -> ParserOutput<Pattern> {
+        self.add_trace("parse pattern");
+        self.then_identifier().and_then(|name| {
+            if self.peek().symbol != Symbol::ParenOpen {
+                return ParserOutput::okay(Pattern::Variant {
+                    name,
+                    binding: None,
+                });
+            }
+            self.then_ignore(Symbol::ParenOpen)
+                .and_then(|_| self.with_whitespace(|p| p.then_identifier()))
+                .and_then(|binding| {
+                    self.then_ignore(Symbol::ParenClose)
+                        .map(|_| Pattern::Variant {
+                            name,
+                            binding: Some(binding),
+                        })
+                })
+        })
     }
 
     fn parse_return(&mut self) -> ParserOutput<Statement> {
         self.add_trace("parse return statement");
-        self.consume(); // consume return
+        let pos = self.consume().pos.clone(); // consume return
         self.skip_whitespace();
 
         let expr = self.parse_expr(0);
@@ -1093,8 +1713,49 @@ impl Parser {
             return expr.transmute_error();
         }
 
-        self.then_ignore(Symbol::Semicolon)
-            .map(|_| Statement::Return(expr.output.unwrap()))
+        self.then_end_statement().map(|_| Statement::Return {
+            value: expr.output.unwrap(),
+            pos,
+        })
+    }
+
+    /// Parse `assert(cond);` or `assert(cond, "message");`
+    fn parse_assert(&mut self) -> ParserOutput<Statement> {
+        self.add_trace("parse assert statement");
+        let pos = self.consume().pos.clone(); // consume assert
+        let cond = self
+            .with_whitespace(|p| p.then_ignore(Symbol::ParenOpen))
+            .and_then(|_| self.parse_expr(0));
+        if cond.output.is_none() {
+            return cond.transmute_error();
+        }
+        self.skip_whitespace();
+        let message = if self.peek().symbol == Symbol::Comma {
+            self.consume();
+            self.skip_whitespace();
+            match self.peek().symbol.clone() {
+                Symbol::StringLiteral(message) => {
+                    self.consume();
+                    Some(message)
+                }
+                other => {
+                    return self.single_error(&format!(
+                        "expected a string message after the condition of an assert, but found {}",
+                        other
+                    ))
+                }
+            }
+        } else {
+            None
+        };
+        self.skip_whitespace();
+        self.then_ignore(Symbol::ParenClose)
+            .and_then(|_| self.then_end_statement())
+            .map(|_| Statement::Assert {
+                cond: cond.output.unwrap(),
+                message,
+                pos,
+            })
     }
 
     /// A block is a collection of statements wrapped in braces {}
@@ -1174,6 +1835,7 @@ impl Parser {
         if self.peek().symbol != Symbol::Function {
             return ParserOutput::err(diagnostics); // no diagnostics, just "not a function"
         }
+        let pos = self.peek().pos.clone();
 
         // Parse the function declaration
         let declaration = match self.parse_function_declaration() {
@@ -1262,12 +1924,16 @@ impl Parser {
         let declaration_inner = declaration.unwrap();
         let function = Function {
             name: declaration_inner.name,
+            pos,
+            type_params: declaration_inner.type_params,
             args: declaration_inner.parameters,
             returns: declaration_inner.return_type,
             properties: properties.unwrap(),
             permissions: permissions.unwrap(),
             contracts: contracts.unwrap(),
             statements: statements.unwrap(),
+            attributes: Vec::new(),
+            is_extern: false,
         };
 
         ParserOutput {
@@ -1275,27 +1941,86 @@ impl Parser {
             diagnostics,
         }
     }
-}
-
-// -------------------- Parsing Utilities --------------------
 
-impl Parser {
+    /// Parse a binding to a C function, like `extern fn c_malloc(n: Size) -> RawCType;`
+    ///
+    /// These have no metadata, contracts, or body, just a signature
+    fn parse_extern_function(&mut self) -> ParserOutput<Function> {
+        self.add_trace("parse an extern function");
+        let pos = self.peek().pos.clone();
+        self.then_ignore(Symbol::Extern)
+            .and_then(|_| self.with_whitespace(|p| p.parse_function_signature()))
+            .and_then(|declaration| {
+                self.with_whitespace(|p| p.then_ignore(Symbol::Semicolon))
+                    .map(|_| Function {
+                        name: declaration.name,
+                        pos,
+                        type_params: declaration.type_params,
+                        args: declaration.parameters,
+                        returns: declaration.return_type,
+                        properties: Vec::new(),
+                        permissions: Vec::new(),
+                        contracts: Vec::new(),
+                        statements: Vec::new(),
+                        attributes: Vec::new(),
+                        is_extern: true,
+                    })
+            })
+    }
+}
+
+// -------------------- Parsing Utilities --------------------
+
+impl Parser<'static> {
+    /// Parse tokens that have already all been lexed
     pub fn new(tokens: Vec<Token>) -> Self {
-        Parser {
+        Parser::from_stream(tokens.into_iter())
+    }
+}
+
+impl<'a> Parser<'a> {
+    /// Parse tokens as they're lexed, see `Lexer::stream`
+    pub fn from_stream(source: impl Iterator<Item = Token> + 'a) -> Self {
+        let mut parser = Parser {
+            source: Box::new(source),
+            tokens: VecDeque::new(),
             offset: 0,
-            tokens,
             recursion_counter: 0,
             trace: Vec::new(),
+        };
+        parser.fill();
+        parser
+    }
+}
+
+impl Parser<'_> {
+    /// Pull tokens from the source until the buffer reaches a token that isn't whitespace (which `lookahead` needs) and holds at least two tokens (which peeking one past the current token needs)
+    fn fill(&mut self) {
+        while self.tokens.len() < 2
+            || self
+                .tokens
+                .iter()
+                .all(|token| matches!(token.symbol, Symbol::Space | Symbol::NewLine))
+        {
+            match self.source.next() {
+                Some(token) => self.tokens.push_back(token),
+                None => break,
+            }
         }
     }
 
+    /// Is the current token the newline the lexer ends every stream with?
+    fn at_end(&self) -> bool {
+        self.tokens.len() < 2
+    }
+
     /// Debug message to build a "stack trace"
     ///
     /// Record the current token, offset, and a message
     pub fn add_trace(&mut self, message: &str) {
         self.trace.push(format!(
             "{}: {} => {}",
-            self.offset, self.tokens[self.offset], message
+            self.offset, self.tokens[0], message
         ));
     }
 
@@ -1322,59 +2047,72 @@ impl Parser {
     ///
     /// (Context) To avoid running out of bounds, the lexer inserts a dummy newline at the end of the input
     pub fn peek(&self) -> &Token {
-        &self.tokens[self.offset]
+        &self.tokens[0]
     }
 
     /// Non-destructively skip whitespace to find the next "meaningful" token
     pub fn lookahead(&self) -> &Token {
-        let mut future_offset = self.offset;
-        // Simulate skipping whitespace
-        while future_offset < self.tokens.len() - 1 {
-            match self.tokens[future_offset].symbol {
-                Symbol::Space | Symbol::NewLine => future_offset += 1,
-                _ => break,
-            }
-        }
-        &self.tokens[future_offset]
+        // Simulate skipping whitespace, `fill` makes sure the buffer goes far enough
+        self.tokens
+            .iter()
+            .find(|token| !matches!(token.symbol, Symbol::Space | Symbol::NewLine))
+            .unwrap_or(&self.tokens[self.tokens.len() - 1])
     }
 
     /// Return the next token and advance the cursor
     ///
     /// (Context) To avoid running out of bounds, the lexer inserts a dummy newline at the end of the input
-    pub fn consume(&mut self) -> &Token {
-        let token = &self.tokens[self.offset];
+    pub fn consume(&mut self) -> Token {
+        let token = self
+            .tokens
+            .pop_front()
+            .expect("the parser ran past the end of the token stream");
         self.offset += 1;
+        self.fill();
         token
     }
 
     /// Helper method to create a single error from a given message
     pub fn single_error<T>(&self, message: &str) -> ParserOutput<T> {
         ParserOutput::err(vec![Diagnostic::new_error_simple(
+            DiagnosticKind::Syntax,
             message,
             &self.peek().pos,
         )])
     }
 
     pub fn skip_whitespace(&mut self) {
-        while matches!(self.peek().symbol, Symbol::Space | Symbol::NewLine)
-            && self.offset < self.tokens.len()
-            && self.offset < self.tokens.len() - 1
-        {
+        while matches!(self.peek().symbol, Symbol::Space | Symbol::NewLine) && !self.at_end() {
+            self.consume();
+        }
+    }
+
+    /// Skip spaces but stop at the end of the line, for when a newline means something
+    pub fn skip_spaces(&mut self) {
+        while self.peek().symbol == Symbol::Space && !self.at_end() {
             self.consume();
         }
     }
 
+    /// End a statement with a `;`, or with the end of the line (or block) when the semicolon is left off
+    ///
+    /// The newline or `}` isn't consumed, the block parser skips or expects it
+    fn then_end_statement(&mut self) -> ParserOutput<()> {
+        self.skip_spaces();
+        match self.peek().symbol {
+            Symbol::NewLine | Symbol::BraceClose => ParserOutput::okay(()),
+            _ => self.then_ignore(Symbol::Semicolon),
+        }
+    }
+
     pub fn then_ignore(&mut self, expected: Symbol) -> ParserOutput<()> {
         if self.peek().symbol == expected {
             self.consume();
             ParserOutput::okay(())
         } else {
-            let message = format!(
-                "expected {:?}, but found {:?}",
-                expected,
-                self.peek().symbol
-            );
+            let message = format!("expected {}, but found {}", expected, self.peek().symbol);
             ParserOutput::err(vec![Diagnostic::new_error_simple(
+                DiagnosticKind::Syntax,
                 &message,
                 &self.peek().pos,
             )])
@@ -1386,8 +2124,12 @@ impl Parser {
         match &next.symbol {
             Symbol::Identifier(name) => ParserOutput::okay(name.to_string()),
             _ => {
-                let message = format!("expected an identifier, but found {:?}", next.symbol);
-                ParserOutput::err(vec![Diagnostic::new_error_simple(&message, &next.pos)])
+                let message = format!("expected an identifier, but found {}", next.symbol);
+                ParserOutput::err(vec![Diagnostic::new_error_simple(
+                    DiagnosticKind::Syntax,
+                    &message,
+                    &next.pos,
+                )])
             }
         }
     }
@@ -1399,7 +2141,7 @@ impl Parser {
         f(self)
     }
 
-    fn with_whitespace<T, F>(&mut self, f: F) -> ParserOutput<T>
+    pub fn with_whitespace<T, F>(&mut self, f: F) -> ParserOutput<T>
     where
         F: FnOnce(&mut Self) -> ParserOutput<T>,
     {
@@ -1474,9 +2216,9 @@ impl Parser {
         let mut items = Vec::new();
         let mut diagnostics = Vec::new();
 
-        while self.offset < self.tokens.len() {
+        while !self.tokens.is_empty() {
             self.skip_whitespace();
-            if self.offset >= self.tokens.len() {
+            if self.tokens.is_empty() {
                 break;
             }
 
@@ -1531,7 +2273,7 @@ mod tests {
         let program_text = "String";
         // Lex
         let mut lexer = Lexer::new("test");
-        lexer.lex(&program_text);
+        lexer.lex(program_text);
         // Parse
         let mut parser = Parser::new(lexer.token_stream);
         let out = parser.parse_type();
@@ -1545,7 +2287,7 @@ mod tests {
         let program_text = "Array<Int>";
         // Lex
         let mut lexer = Lexer::new("test");
-        lexer.lex(&program_text);
+        lexer.lex(program_text);
         // Parse
         let mut parser = Parser::new(lexer.token_stream);
         let out = parser.parse_type();
@@ -1554,12 +2296,26 @@ mod tests {
         assert_eq!(out.output.unwrap(), expected);
     }
 
+    #[test]
+    fn parse_types_optional() {
+        let program_text = "String?";
+        // Lex
+        let mut lexer = Lexer::new("test");
+        lexer.lex(program_text);
+        // Parse
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_type();
+        let expected = Type::Optional(Box::new(Type::String));
+        assert!(out.output.is_some());
+        assert_eq!(out.output.unwrap(), expected);
+    }
+
     #[test]
     fn parse_types_generic() {
         let program_text = "Generic<T>";
         // Lex
         let mut lexer = Lexer::new("test");
-        lexer.lex(&program_text);
+        lexer.lex(program_text);
         // Parse
         let mut parser = Parser::new(lexer.token_stream);
         let out = parser.parse_type();
@@ -1568,6 +2324,127 @@ mod tests {
         assert_eq!(out.output.unwrap(), expected);
     }
 
+    #[test]
+    fn parse_types_tuple() {
+        let program_text = "(Int, Array<String>)";
+        // Lex
+        let mut lexer = Lexer::new("test");
+        lexer.lex(program_text);
+        // Parse
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_type();
+        let expected = Type::Tuple(vec![Type::Integer, Type::Array(Box::new(Type::String))]);
+        assert!(out.output.is_some());
+        assert_eq!(out.output.unwrap(), expected);
+    }
+
+    #[test]
+    fn parse_types_fixed_array() {
+        let program_text = "Array<Int, 8>";
+        // Lex
+        let mut lexer = Lexer::new("test");
+        lexer.lex(program_text);
+        // Parse
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_type();
+        let expected = Type::FixedArray(Box::new(Type::Integer), 8);
+        assert!(out.output.is_some());
+        assert_eq!(out.output.unwrap(), expected);
+    }
+
+    #[test]
+    fn parse_cfg_attribute() {
+        let program_text = r#"@cfg(target = "windows")
+fn open(cfg: String) -> Int {
+    let target: String = cfg;
+    return 0;
+}"#;
+        // Lex
+        let mut lexer = Lexer::new("test");
+        lexer.lex(program_text);
+        // Parse
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+        match &out.output.unwrap()[0] {
+            ASTNode::FunctionDeclaration(f) => {
+                assert_eq!(f.name, "open");
+                assert_eq!(
+                    f.attributes,
+                    vec![Attribute::Cfg {
+                        key: "target".to_string(),
+                        value: "windows".to_string()
+                    }]
+                );
+                // `cfg` is still an ordinary name outside of an attribute
+                assert_eq!(f.args[0].name, "cfg");
+            }
+            other => panic!("expected a function, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn recover_after_broken_declaration() {
+        let program_text = r#"struct Broken {
+    name String,
+    fn inner() -> Int {
+        return 1;
+    }
+}
+
+fn main(argc: Int) -> Int {
+    return 0;
+}
+
+struct Also broken
+
+fn other() -> Int {
+    return 1;
+}"#;
+        let mut lexer = Lexer::new("test");
+        lexer.lex(program_text);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        let nodes = out.output.unwrap();
+        assert_eq!(nodes.len(), 2, "{:#?}", out.diagnostics);
+        assert!(matches!(&nodes[0], ASTNode::FunctionDeclaration(f) if f.name == "main"));
+        assert!(matches!(&nodes[1], ASTNode::FunctionDeclaration(f) if f.name == "other"));
+        // Nothing is reported past the broken structs
+        let lines = out
+            .diagnostics
+            .iter()
+            .map(|d| d.position().line)
+            .collect::<Vec<usize>>();
+        assert_eq!(lines, vec![1, 1, 11], "{:#?}", out.diagnostics);
+    }
+
+    #[test]
+    fn parse_allow_attribute() {
+        let program_text = r#"@allow(unused, unreachable)
+fn open(allow: String) -> Int {
+    return 0;
+}"#;
+        let mut lexer = Lexer::new("test");
+        lexer.lex(program_text);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+        match &out.output.unwrap()[0] {
+            ASTNode::FunctionDeclaration(f) => {
+                assert_eq!(
+                    f.attributes,
+                    vec![Attribute::Allow(vec![
+                        "unused".to_string(),
+                        "unreachable".to_string()
+                    ])]
+                );
+                // `allow` is still an ordinary name outside of an attribute
+                assert_eq!(f.args[0].name, "allow");
+            }
+            other => panic!("expected a function, got {:?}", other),
+        }
+    }
+
     #[test]
     fn parse_struct() {
         let program_text = r#"struct Animal {
@@ -1592,7 +2469,7 @@ mod tests {
         }"#;
         // Lex
         let mut lexer = Lexer::new("test");
-        lexer.lex(&program_text);
+        lexer.lex(program_text);
         // Parse
         let mut parser = Parser::new(lexer.token_stream);
         let out = parser.parse_struct();
@@ -1619,20 +2496,31 @@ mod tests {
         let program_text = "fn foo(a: Int, b: Int) -> Int {";
         // Lex
         let mut lexer = Lexer::new("test");
-        lexer.lex(&program_text);
+        lexer.lex(program_text);
         // Parse
         let mut parser = Parser::new(lexer.token_stream);
         let out = parser.parse_function_declaration();
         let expected = FunctionDeclaration {
             name: "foo".to_string(),
+            type_params: Vec::new(),
             parameters: vec![
                 Field {
                     name: "a".to_string(),
                     field_type: Type::Integer,
+                    pos: SourcePosition {
+                        filename: "test".to_string(),
+                        line: 0,
+                        column: 7,
+                    },
                 },
                 Field {
                     name: "b".to_string(),
                     field_type: Type::Integer,
+                    pos: SourcePosition {
+                        filename: "test".to_string(),
+                        line: 0,
+                        column: 15,
+                    },
                 },
             ],
             return_type: Type::Integer,
@@ -1649,7 +2537,7 @@ mod tests {
 	    }"#;
         // Lex
         let mut lexer = Lexer::new("test");
-        lexer.lex(&program_text);
+        lexer.lex(program_text);
         println!("{:#?}", lexer.token_stream);
         // Parse
         let mut parser = Parser::new(lexer.token_stream);
@@ -1667,6 +2555,63 @@ mod tests {
         assert_eq!(expected_properties, perms);
     }
 
+    #[test]
+    fn parse_module_metadata() {
+        let program_text = "@module {\n    Uses: ReadFile, Audit;\n}\n\nfn module(x: Int) -> Int {\n    return x;\n}\n";
+        let mut lexer = Lexer::new("test");
+        lexer.lex(program_text);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+        let ast = out.output.unwrap();
+        assert_eq!(ast.len(), 2);
+        assert_eq!(
+            ast[0],
+            ASTNode::ModuleMetadata(ModuleMetadata {
+                permissions: vec![
+                    FunctionPermissions::ReadFile,
+                    FunctionPermissions::Custom("Audit".to_string())
+                ],
+                pos: SourcePosition {
+                    filename: "test".to_string(),
+                    line: 0,
+                    column: 0
+                },
+            })
+        );
+        // `module` is still an ordinary name
+        assert!(matches!(&ast[1], ASTNode::FunctionDeclaration(f) if f.name == "module"));
+    }
+
+    #[test]
+    fn parse_reexport() {
+        let program_text =
+            "import shapes with area;\nexport import geometry with Point, Line;\n\nfn export(x: Int) -> Int {\n    return x;\n}\n";
+        let mut lexer = Lexer::new("test");
+        lexer.lex(program_text);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+        let ast = out.output.unwrap();
+        assert_eq!(ast.len(), 3);
+        assert!(matches!(&ast[0], ASTNode::ImportStatement(i) if !i.reexport));
+        assert_eq!(
+            ast[1],
+            ASTNode::ImportStatement(Import {
+                file: "geometry".to_string(),
+                items: vec!["Point".to_string(), "Line".to_string()],
+                pos: SourcePosition {
+                    filename: "test".to_string(),
+                    line: 1,
+                    column: 0
+                },
+                reexport: true,
+            })
+        );
+        // `export` is still an ordinary name
+        assert!(matches!(&ast[2], ASTNode::FunctionDeclaration(f) if f.name == "export"));
+    }
+
     #[test]
     fn parse_fn_contracts() {
         let program_text = r#"@contracts {
@@ -1675,7 +2620,7 @@ mod tests {
 	    }"#;
         // Lex
         let mut lexer = Lexer::new("test");
-        lexer.lex(&program_text);
+        lexer.lex(program_text);
         let symbols = lexer
             .token_stream
             .iter()
@@ -1696,6 +2641,11 @@ mod tests {
                 right: Box::new(Expr::IntegerLiteral(0)),
             },
             message: "a must be greater than 0".to_string(),
+            pos: SourcePosition {
+                filename: "test".to_string(),
+                line: 1,
+                column: 12,
+            },
         };
         let expected_out: FunctionContract = FunctionContract {
             type_: ContractType::Output,
@@ -1705,6 +2655,11 @@ mod tests {
                 right: Box::new(Expr::IntegerLiteral(0)),
             },
             message: "output must be greater than 0".to_string(),
+            pos: SourcePosition {
+                filename: "test".to_string(),
+                line: 2,
+                column: 12,
+            },
         };
         let expected: Vec<FunctionContract> = vec![expected_in, expected_out];
         assert_eq!(expected, out.output.unwrap());
@@ -1721,10 +2676,28 @@ mod tests {
         assert!(result.output.is_some());
 
         match result.output.unwrap() {
-            Statement::VariableDeclaration { name, type_, value } => {
+            Statement::VariableDeclaration {
+                name, type_, value, ..
+            } => {
                 assert_eq!(name, "x");
                 assert_eq!(type_, Type::Integer);
-                assert_eq!(value, Expr::IntegerLiteral(42));
+                assert_eq!(value, Some(Expr::IntegerLiteral(42)));
+            }
+            _ => panic!("Expected VariableDeclaration"),
+        }
+    }
+
+    #[test]
+    fn parse_declaration_without_value() {
+        let mut lexer = Lexer::new("test");
+        lexer.lex("let x: Array<Int>;");
+        let mut parser = Parser::new(lexer.token_stream);
+        let result = parser.parse_statement();
+        assert!(result.diagnostics.is_empty(), "{:#?}", result.diagnostics);
+        match result.output.unwrap() {
+            Statement::VariableDeclaration { name, value, .. } => {
+                assert_eq!(name, "x");
+                assert_eq!(value, None);
             }
             _ => panic!("Expected VariableDeclaration"),
         }
@@ -1748,7 +2721,7 @@ mod tests {
         assert!(result.output.is_some());
 
         match result.output.unwrap() {
-            Statement::Conditional(branches) => {
+            Statement::Conditional { branches, .. } => {
                 assert_eq!(branches.len(), 3);
 
                 // Check if branch
@@ -1784,7 +2757,12 @@ mod tests {
         assert!(result.output.is_some());
 
         match result.output.unwrap() {
-            Statement::Conditional(branches) => {
+            Statement::Match {
+                scrutinee,
+                branches,
+                ..
+            } => {
+                assert_eq!(scrutinee, Expr::Variable("x".to_string()));
                 assert_eq!(branches.len(), 3);
 
                 // Check literal match
@@ -1799,7 +2777,68 @@ mod tests {
                 assert!(branches[2].condition.is_none());
                 assert_eq!(branches[2].computations.len(), 1);
             }
-            _ => panic!("Expected Conditional"),
+            _ => panic!("Expected Match"),
+        }
+    }
+
+    #[test]
+    fn parse_while_let() {
+        let program = r#"while let Some(item) = next(queue) {
+            print(item);
+        }"#;
+
+        let mut lexer = Lexer::new("test");
+        lexer.lex(program);
+        let mut parser = Parser::new(lexer.token_stream);
+
+        let result = parser.parse_statement();
+        assert!(result.diagnostics.is_empty());
+        match result.output.unwrap() {
+            Statement::WhileLoop {
+                binding,
+                condition,
+                body,
+                ..
+            } => {
+                assert_eq!(
+                    binding,
+                    Some(Pattern::Variant {
+                        name: "Some".to_string(),
+                        binding: Some("item".to_string()),
+                    })
+                );
+                assert_eq!(
+                    condition,
+                    Expr::FunctionCall {
+                        name: "next".to_string(),
+                        arguments: vec![Expr::Variable("queue".to_string())],
+                    }
+                );
+                assert_eq!(body.len(), 1);
+                assert!(matches!(body[0], Statement::FunctionCall { .. }));
+            }
+            _ => panic!("Expected WhileLoop"),
+        }
+    }
+
+    #[test]
+    fn parse_while() {
+        let program = r#"while x < 10 {
+            x = x + 1;
+        }"#;
+
+        let mut lexer = Lexer::new("test");
+        lexer.lex(program);
+        let mut parser = Parser::new(lexer.token_stream);
+
+        let result = parser.parse_statement();
+        assert!(result.diagnostics.is_empty());
+        match result.output.unwrap() {
+            Statement::WhileLoop { binding, body, .. } => {
+                assert!(binding.is_none());
+                assert_eq!(body.len(), 1);
+            }
+            _ => panic!("Expected WhileLoop"),
         }
     }
 
@@ -1847,4 +2886,324 @@ mod tests {
         assert_eq!(function.contracts.len(), 3);
         assert_eq!(function.statements.len(), 4);
     }
+
+    #[test]
+    fn parse_function_without_parameters() {
+        let program = "fn f() -> Void {}";
+        let mut lexer = Lexer::new("test");
+        lexer.lex(program);
+        let mut parser = Parser::new(lexer.token_stream);
+
+        let result = parser.parse_function();
+        assert!(
+            result.diagnostics.is_empty(),
+            "Expected no diagnostics, but found: {:?}",
+            result.diagnostics
+        );
+        let function = result.output.unwrap();
+        assert_eq!(function.name, "f");
+        assert!(function.args.is_empty());
+        assert_eq!(function.returns, Type::Void);
+        assert!(function.statements.is_empty());
+    }
+
+    #[test]
+    fn parse_extern_function() {
+        let program = "extern fn c_malloc(n: Size) -> RawCType;\n";
+        let mut lexer = Lexer::new("test");
+        lexer.lex(program);
+        let mut parser = Parser::new(lexer.token_stream);
+
+        let result = parser.parse_all();
+        assert!(
+            result.diagnostics.is_empty(),
+            "Expected no diagnostics, but found: {:?}",
+            result.diagnostics
+        );
+        let ast = result.output.unwrap();
+        let function = match &ast[0] {
+            ASTNode::FunctionDeclaration(f) => f,
+            other => panic!("expected a function, found {:?}", other),
+        };
+        assert!(function.is_extern);
+        assert_eq!(function.name, "c_malloc");
+        assert_eq!(function.args[0].field_type, Type::Size);
+        assert_eq!(function.returns, Type::CType);
+        assert!(function.statements.is_empty());
+    }
+
+    #[test]
+    fn parse_enum_rejects_void_payload() {
+        let program_text = r#"enum Status {
+            Alive: Int,
+            Dead: Void,
+
+            @metadata {
+                Is: Public;
+            }
+        }"#;
+        let mut lexer = Lexer::new("test");
+        lexer.lex(program_text);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_enum();
+        assert!(out.output.is_none());
+        assert_eq!(
+            out.diagnostics[0].message(),
+            "enum variant `Dead` can't carry a Void payload, leave off the type to declare it without one"
+        );
+        assert_eq!(out.diagnostics[0].position().line, 2);
+        assert_eq!(out.diagnostics[0].position().column, 18);
+    }
+
+    #[test]
+    fn parse_enum_bare_variant() {
+        let program_text = r#"enum Status {
+            Alive: Int,
+            Dead,
+
+            @metadata {
+                Is: Public;
+            }
+        }"#;
+        let mut lexer = Lexer::new("test");
+        lexer.lex(program_text);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_enum();
+        assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+        let e = out.output.unwrap();
+        assert_eq!(e.fields[0].field_type, Type::Integer);
+        assert_eq!(e.fields[1].name, "Dead");
+        assert_eq!(e.fields[1].field_type, Type::Void);
+    }
+
+    #[test]
+    fn parse_type_parameters() {
+        let program_text = r#"enum Result<O, E> {
+    Okay: Generic<O>,
+    Error: Generic<E>
+
+    @metadata {
+        Is: Public;
+    }
+}
+
+fn first<T>(items: Array<Generic<T>>) -> Generic<T> {
+    return items[0];
+}
+"#;
+        let mut lexer = Lexer::new("test");
+        lexer.lex(program_text);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+        let ast = out.output.unwrap();
+        match &ast[0] {
+            ASTNode::EnumDeclaration(e) => assert_eq!(e.type_params, vec!["O", "E"]),
+            other => panic!("expected an enum, found {:?}", other),
+        }
+        match &ast[1] {
+            ASTNode::FunctionDeclaration(f) => {
+                assert_eq!(f.type_params, vec!["T"]);
+                assert_eq!(f.returns, Type::Generic("T".to_string()));
+            }
+            other => panic!("expected a function, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_if_expression() {
+        let program = "let m: Int = if a > b then a else b;";
+        let mut lexer = Lexer::new("test");
+        lexer.lex(program);
+        let mut parser = Parser::new(lexer.token_stream);
+
+        let result = parser.parse_statement();
+        assert!(result.diagnostics.is_empty(), "{:#?}", result.diagnostics);
+
+        match result.output.unwrap() {
+            Statement::VariableDeclaration { name, value, .. } => {
+                assert_eq!(name, "m");
+                assert_eq!(
+                    value,
+                    Some(Expr::IfExpr {
+                        cond: Box::new(Expr::BinaryOp {
+                            left: Box::new(Expr::Variable("a".to_string())),
+                            operator: BinaryOperator::GreaterThan,
+                            right: Box::new(Expr::Variable("b".to_string())),
+                        }),
+                        then: Box::new(Expr::Variable("a".to_string())),
+                        els: Box::new(Expr::Variable("b".to_string())),
+                    })
+                );
+            }
+            _ => panic!("Expected VariableDeclaration"),
+        }
+    }
+
+    #[test]
+    fn parse_assert() {
+        let mut lexer = Lexer::new("test");
+        lexer.lex(r#"assert(x > 0, "positive");"#);
+        let mut parser = Parser::new(lexer.token_stream);
+        let result = parser.parse_statement();
+        assert!(result.diagnostics.is_empty(), "{:#?}", result.diagnostics);
+        match result.output.unwrap() {
+            Statement::Assert { cond, message, .. } => {
+                assert_eq!(
+                    cond,
+                    Expr::BinaryOp {
+                        left: Box::new(Expr::Variable("x".to_string())),
+                        operator: BinaryOperator::GreaterThan,
+                        right: Box::new(Expr::IntegerLiteral(0)),
+                    }
+                );
+                assert_eq!(message, Some("positive".to_string()));
+            }
+            other => panic!("expected an assert, found {:?}", other),
+        }
+
+        let mut lexer = Lexer::new("test");
+        lexer.lex("assert(ready);");
+        let mut parser = Parser::new(lexer.token_stream);
+        let result = parser.parse_statement();
+        assert!(result.diagnostics.is_empty(), "{:#?}", result.diagnostics);
+        assert!(matches!(
+            result.output.unwrap(),
+            Statement::Assert { message: None, .. }
+        ));
+    }
+
+    #[test]
+    fn parse_block_without_semicolons() {
+        let mut lexer = Lexer::new("test");
+        lexer.lex(
+            r#"{
+    let total: Int = a
+        + b
+    let empty: Int
+    empty = total * 2
+    print(empty)
+    assert(empty > 0, "positive")
+    if total > 0 { return total }
+    return 0;
+}"#,
+        );
+        let mut parser = Parser::new(lexer.token_stream);
+        let result = parser.parse_block();
+        assert!(result.diagnostics.is_empty(), "{:#?}", result.diagnostics);
+        let statements = result.output.unwrap();
+        assert_eq!(statements.len(), 7);
+        // An operator at the start of the next line continues the expression
+        match &statements[0] {
+            Statement::VariableDeclaration {
+                value: Some(value), ..
+            } => assert_eq!(value.to_string(), "a + b"),
+            other => panic!("expected a declaration, found {:?}", other),
+        }
+        assert!(matches!(
+            statements[1],
+            Statement::VariableDeclaration { value: None, .. }
+        ));
+        assert!(matches!(statements[2], Statement::VariableMutation { .. }));
+        assert!(matches!(statements[3], Statement::FunctionCall { .. }));
+        assert!(matches!(statements[4], Statement::Assert { .. }));
+        assert!(matches!(statements[5], Statement::Conditional { .. }));
+        assert!(matches!(statements[6], Statement::Return { .. }));
+
+        // Two statements on one line still need a semicolon between them
+        let mut lexer = Lexer::new("test");
+        lexer.lex("{ print(a) print(b) }");
+        let mut parser = Parser::new(lexer.token_stream);
+        assert!(!parser.parse_block().diagnostics.is_empty());
+    }
+
+    #[test]
+    fn parse_types_with_whitespace() {
+        fn parse(program_text: &str) -> Type {
+            let mut lexer = Lexer::new("test");
+            lexer.lex(program_text);
+            let mut parser = Parser::new(lexer.token_stream);
+            let out = parser.parse_type();
+            assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+            out.output.unwrap()
+        }
+
+        assert_eq!(parse("Array< Int >"), parse("Array<Int>"));
+        assert_eq!(parse("Map<String , Int>"), parse("Map<String,Int>"));
+        assert_eq!(
+            parse("Map<String , Int>"),
+            Type::Map(Box::new(Type::Tuple(vec![Type::String, Type::Integer])))
+        );
+        assert_eq!(
+            parse("Array< Array< Bool > , 4 >"),
+            parse("Array<Array<Bool>,4>")
+        );
+        assert_eq!(parse("Generic< T >"), Type::Generic("T".to_string()));
+    }
+
+    #[test]
+    fn display_types_as_source() {
+        fn parse(program_text: &str) -> Type {
+            let mut lexer = Lexer::new("test");
+            lexer.lex(program_text);
+            let mut parser = Parser::new(lexer.token_stream);
+            let out = parser.parse_type();
+            assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+            out.output.unwrap()
+        }
+
+        // Each type is written back the way it was spelled
+        for program_text in [
+            "Int",
+            "Bool",
+            "RawCType",
+            "Array<String>",
+            "Array<Float, 4>",
+            "Map<String, Int>",
+            "Shared<Byte>",
+            "(Int, Array<Bool>)",
+            "Size?",
+            "Generic<T>",
+            "Animal",
+        ] {
+            assert_eq!(parse(program_text).to_string(), program_text);
+        }
+    }
+
+    #[test]
+    fn parse_types_unclosed_bracket() {
+        let mut lexer = Lexer::new("test");
+        lexer.lex("Array<Int;");
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_type();
+        assert!(out.output.is_none());
+        assert_eq!(out.diagnostics[0].message(), "expected '>', but found ';'");
+    }
+
+    #[test]
+    fn missing_colon_message() {
+        let mut lexer = Lexer::new("test");
+        lexer.lex("legs Int");
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_field_mandatory_type();
+        assert!(out.output.is_none());
+        assert_eq!(
+            out.diagnostics[0].message(),
+            "expected ':', but found an identifier 'Int'"
+        );
+    }
+
+    #[test]
+    fn parse_streamed_tokens() {
+        for entry in std::fs::read_dir("test_programs").unwrap() {
+            let path = entry.unwrap().path();
+            let source = std::fs::read_to_string(&path).unwrap();
+            let mut lexer = Lexer::new("test");
+            lexer.lex(&source);
+            let batch = Parser::new(lexer.token_stream).parse_all();
+            let mut lexer = Lexer::new("test");
+            let streamed = Parser::from_stream(lexer.stream(&source)).parse_all();
+            assert_eq!(streamed, batch, "{:?}", path);
+        }
+    }
 }
@@ -1,115 +1,1847 @@
 //! Combine the stages of compilation for repeated calls
 
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::error::Error;
+use std::fmt;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
 
-use crate::aggregation::ParsingTables;
+use crate::aggregation::{ParsingTables, SemanticOutput};
+use crate::analysis;
+use crate::cache::AstCache;
+use crate::codegen_c::{self, CodegenOptions};
+use crate::diagnostics::{apply_lint_levels, Diagnostic, IssueLevel, LintLevels};
+use crate::json::{Json, ToJson};
 use crate::lexer::Lexer;
 use crate::parser::{ASTNode, Parser};
+use crate::typecheck;
 
-pub fn file_to_ast(filepath: &Path, verbose: bool) -> Result<Vec<ASTNode>, Box<dyn Error>> {
-    // Try to open linked file
-    let maybe_text = fs::read_to_string(filepath);
-    let program_text: String = if maybe_text.is_err() {
-        return Err(format!("unable to find file {:?}, aborting compilation\n", filepath).into());
-    } else {
-        maybe_text.unwrap()
-    };
-    // Lex
+/// Everything parsing a single file produced, so callers can decide how (or whether) to show it
+///
+/// `ast` is `None` if parsing couldn't recover. `trace` is the parser's stack when something went wrong, and is only kept in verbose mode
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileResult {
+    pub ast: Option<Vec<ASTNode>>,
+    pub diagnostics: Vec<Diagnostic>,
+    pub source: String,
+    pub trace: Option<Vec<String>>,
+}
+
+impl CompileResult {
+    /// Every diagnostic, with the source around it
+    pub fn render(&self) -> String {
+        self.diagnostics
+            .iter()
+            .map(|d| d.display(&self.source))
+            .collect::<String>()
+    }
+
+    /// The AST, or an error carrying every diagnostic if parsing couldn't recover
+    pub fn into_ast(self) -> Result<Vec<ASTNode>, Box<dyn Error>> {
+        let message = self.render();
+        self.ast.ok_or_else(|| {
+            format!("could not compile due to parsing error(s)\n\n{}", message).into()
+        })
+    }
+}
+
+/// Read a file and split it into tokens, for `--emit=tokens`
+pub fn lex_file(filepath: &Path) -> Result<(Lexer, String), Box<dyn Error>> {
+    let program_text = read_source(filepath)?;
     let mut lexer = Lexer::new(&filepath.to_string_lossy());
     lexer.lex(&program_text);
-    // Parse the file
-    let mut parser = Parser::new(lexer.token_stream);
-    let out = parser.parse_all();
-    if !out.diagnostics.is_empty() {
-        // out.output.is_none()
-        let message_buffer = out
-            .diagnostics
-            .iter()
-            .map(|d| d.display(&program_text))
-            .collect::<String>();
-        if verbose {
-            eprintln!(
-                "Parser stack trace (in code order, top-to-bottom)\n{:#?}",
-                parser.unwind_stack()
-            );
+    Ok((lexer, program_text))
+}
+
+/// One token per line as `line:col SYMBOL(detail)`, followed by whatever the lexer complained about
+pub fn render_tokens(lexer: &Lexer, source: &str) -> String {
+    let mut buffer = lexer
+        .token_stream
+        .iter()
+        .map(|token| {
+            format!(
+                "{}:{} {:?}\n",
+                token.pos.line, token.pos.column, token.symbol
+            )
+        })
+        .collect::<String>();
+    for d in lexer.diagnostics.iter() {
+        buffer.push_str(&d.display(source));
+    }
+    buffer
+}
+
+/// The tokens and the lexer's diagnostics as one JSON object
+pub fn render_tokens_json(lexer: &Lexer) -> String {
+    let json = Json::Object(vec![
+        ("tokens".to_string(), lexer.token_stream.to_json()),
+        ("diagnostics".to_string(), lexer.diagnostics.to_json()),
+    ]);
+    format!("{}\n", json)
+}
+
+/// Read a source file, with the error every stage reports when it's missing
+fn read_source(filepath: &Path) -> Result<String, Box<dyn Error>> {
+    fs::read_to_string(filepath)
+        .map_err(|_| format!("unable to find file {:?}, aborting compilation\n", filepath).into())
+}
+
+/// Lex and parse source code that's already in memory, `name` is what its diagnostics point at
+pub fn source_to_ast(name: &str, source: &str, verbose: bool) -> CompileResult {
+    parse_source(Path::new(name), source.to_string(), verbose, None)
+}
+
+/// Lex and parse a file without printing anything, only a file that can't be read is an error
+///
+/// With a `cache`, an unchanged file's AST is loaded from it instead, and a file that parsed cleanly is saved to it
+pub fn parse_file(
+    filepath: &Path,
+    verbose: bool,
+    cache: Option<&AstCache>,
+) -> Result<CompileResult, Box<dyn Error>> {
+    let program_text = read_source(filepath)?;
+    Ok(parse_source(filepath, program_text, verbose, cache))
+}
+
+fn parse_source(
+    filepath: &Path,
+    program_text: String,
+    verbose: bool,
+    cache: Option<&AstCache>,
+) -> CompileResult {
+    if let Some(ast) = cache.and_then(|cache| cache.load(filepath, &program_text)) {
+        return CompileResult {
+            ast: Some(ast),
+            diagnostics: Vec::new(),
+            source: program_text,
+            trace: None,
+        };
+    }
+    // Lex and parse together, so only the tokens the parser is looking at are in memory
+    let mut lexer = Lexer::new(&filepath.to_string_lossy());
+    let (mut out, trace) = {
+        let mut parser = Parser::from_stream(lexer.stream(&program_text));
+        let out = parser.parse_all();
+        (out, verbose.then(|| parser.unwind_stack()))
+    };
+    out.diagnostics.splice(0..0, lexer.diagnostics);
+    let trace = trace.filter(|_| !out.diagnostics.is_empty());
+    // Only clean parses are cached, so a hit never hides a diagnostic
+    if let (Some(cache), Some(ast), true) = (cache, &out.output, out.diagnostics.is_empty()) {
+        cache.store(filepath, &program_text, ast);
+    }
+    CompileResult {
+        ast: out.output,
+        diagnostics: out.diagnostics,
+        source: program_text,
+        trace,
+    }
+}
+
+/// Parse a file, failing if parsing couldn't recover
+///
+/// The file's source, and any problems that didn't stop it, are kept in `sources` for the caller to show
+pub fn file_to_ast(
+    filepath: &Path,
+    verbose: bool,
+    cache: Option<&AstCache>,
+    sources: &mut SourceMap,
+) -> Result<Vec<ASTNode>, Box<dyn Error>> {
+    let result = parse_file(filepath, verbose, cache)?;
+    sources.insert(filepath, &result.source);
+    sources.keep_problems(result)
+}
+
+/// Parse a module from wherever the provider gets it, like `file_to_ast`
+fn module_to_ast(
+    provider: &dyn ModuleProvider,
+    module: &str,
+    verbose: bool,
+    cache: Option<&AstCache>,
+    sources: &mut SourceMap,
+) -> Result<Vec<ASTNode>, Box<dyn Error>> {
+    let filepath = provider.locate(module)?;
+    let source = provider.read(module)?;
+    sources.insert(&filepath, &source);
+    sources.keep_problems(parse_source(&filepath, source, verbose, cache))
+}
+
+/// Resolve enum variant paths, replace `Auto` declarations with their inferred types, then refresh the type table to match
+pub fn resolve_types(ast: &mut Vec<ASTNode>, module_name: &str, tables: &mut ParsingTables) {
+    typecheck::resolve_enum_variants(ast, &tables.types);
+    typecheck::resolve_auto_types(ast, module_name, tables);
+    tables
+        .types
+        .update_with_functions(ast, module_name, &tables.functions);
+}
+
+/// Run the semantic checks (name resolution and type checking) over a parsed file
+///
+/// `entry_point` is set for the file a program is built from, which also has to declare a usable `main`
+///
+/// `lints` adjusts the level of each lint found, see `apply_lint_levels`
+///
+/// Errors stop compilation, anything less severe is kept in `sources` and then we keep going
+pub fn check_ast(
+    filepath: &Path,
+    ast: &[ASTNode],
+    tables: &ParsingTables,
+    entry_point: bool,
+    lints: &LintLevels,
+    sources: &mut SourceMap,
+) -> Result<(), Box<dyn Error>> {
+    report(
+        &semantic_diagnostics(filepath, ast, tables, entry_point, lints),
+        "semantic",
+        sources,
+    )
+}
+
+/// Everything the semantic checks find in a parsed file, without printing any of it
+pub fn semantic_diagnostics(
+    filepath: &Path,
+    ast: &[ASTNode],
+    tables: &ParsingTables,
+    entry_point: bool,
+    lints: &LintLevels,
+) -> SemanticOutput {
+    let module_name = filepath
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_default();
+    let mut out = SemanticOutput::new();
+    if entry_point {
+        out.extend(analysis::check_entry_point(
+            ast,
+            &filepath.to_string_lossy(),
+        ));
+    }
+    out.extend(tables.modules.check_duplicates(&module_name));
+    out.extend(analysis::check_scopes(ast));
+    out.extend(analysis::check_calls(ast, &module_name, tables));
+    out.extend(analysis::check_types(ast, &module_name, tables));
+    out.extend(analysis::check_type_positions(ast));
+    out.extend(analysis::check_field_names(ast));
+    out.extend(analysis::check_generics(ast));
+    out.extend(analysis::check_derives(ast, tables));
+    out.extend(analysis::check_permissions(ast, &module_name, tables));
+    out.extend(analysis::check_unused_permissions(
+        ast,
+        &module_name,
+        tables,
+    ));
+    out.extend(analysis::check_contracts(ast, &module_name, tables));
+    out.extend(analysis::check_match_arms(ast));
+    out.extend(analysis::check_indices(ast));
+    out.extend(analysis::check_returns(ast));
+    out.extend(analysis::check_initialization(ast));
+    out.extend(analysis::check_unused_imports(ast));
+    out.extend(analysis::check_dead_functions(ast, &module_name, tables));
+    out.extend(typecheck::check_program(ast, &module_name, tables));
+    out.diagnostics = apply_lint_levels(out.diagnostics, lints);
+    out
+}
+
+/// Fail if a stage found any errors, otherwise keep what it found in `sources` for the caller to show
+///
+/// `stage` names the kind of error in the message, like "could not compile due to import error(s)"
+fn report(
+    out: &SemanticOutput,
+    stage: &str,
+    sources: &mut SourceMap,
+) -> Result<(), Box<dyn Error>> {
+    if out.has_errors() {
+        let message_buffer = render_diagnostics(&out.diagnostics, sources);
+        return Err(format!(
+            "could not compile due to {} error(s)\n\n{}",
+            stage, message_buffer
+        )
+        .into());
+    }
+    sources.diagnostics.extend(out.diagnostics.iter().cloned());
+    Ok(())
+}
+
+/// Every parsed module's AST, keyed by module name and sorted so iterating it is stable between runs
+pub type ModuleAsts = BTreeMap<String, Vec<ASTNode>>;
+
+/// Generated C, each with the path to write it to
+pub type GeneratedFiles = Vec<(PathBuf, String)>;
+
+/// Where the standard library's source lives, relative to where the compiler is run
+pub const STDLIB_DIR: &str = "stdlib";
+
+/// Which standard library files should we NOT emit?
+const NO_EMIT_LIST: [&str; 1] = ["arrays.iona"];
+
+/// Is this a standard library file whose C is written by hand (in `c_libs/`), so there's nothing to generate?
+pub fn skips_emit(filepath: &Path) -> bool {
+    filepath.parent() == Some(Path::new(STDLIB_DIR))
+        && filepath
+            .file_name()
+            .is_some_and(|name| NO_EMIT_LIST.contains(&name.to_string_lossy().as_ref()))
+}
+
+/// Where the generated C for the entrypoint goes
+pub const ENTRYPOINT_OUTPUT: &str = "gen/test_case.c";
+
+/// The file a module would be in, if it lived in `dir`
+pub fn module_path(dir: &Path, module: &str) -> PathBuf {
+    dir.join(format!("{}.iona", module))
+}
+
+/// Find the file an imported module lives in
+///
+/// The directories are searched in order: the one the entrypoint is in, then any extra include directories (`--include=<dir>`), then the standard library
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleResolver {
+    pub root: PathBuf,
+    pub include_dirs: Vec<PathBuf>,
+    pub stdlib_dir: PathBuf,
+}
+
+impl ModuleResolver {
+    pub fn new(entrypoint_filepath: &Path, include_dirs: &[PathBuf]) -> ModuleResolver {
+        ModuleResolver {
+            root: entrypoint_filepath
+                .parent()
+                .unwrap_or(Path::new(""))
+                .to_path_buf(),
+            include_dirs: include_dirs.to_vec(),
+            stdlib_dir: PathBuf::from(STDLIB_DIR),
         }
-        if out.output.is_none() {
-            return Err(format!(
-                "could not compile due to parsing error(s)\n\n{}",
-                message_buffer
+    }
+
+    /// Every file the module could be in, in the order they're tried
+    pub fn candidates(&self, module: &str) -> Vec<PathBuf> {
+        std::iter::once(&self.root)
+            .chain(self.include_dirs.iter())
+            .chain(std::iter::once(&self.stdlib_dir))
+            .map(|dir| module_path(dir, module))
+            .collect()
+    }
+
+    /// The first candidate that exists
+    pub fn resolve(&self, module: &str) -> Result<PathBuf, Box<dyn Error>> {
+        let candidates = self.candidates(module);
+        match candidates.iter().find(|path| path.is_file()) {
+            Some(path) => Ok(path.clone()),
+            None => Err(format!(
+                "unable to find module `{}`, tried:\n{}",
+                module,
+                candidates
+                    .iter()
+                    .map(|path| format!("  {}\n", path.to_string_lossy()))
+                    .collect::<String>()
             )
-            .into());
-        } else {
-            eprintln!("non-fatal errors\n{}", message_buffer);
-            return Ok(out.output.unwrap());
+            .into()),
         }
-    } else {
-        return Ok(out.output.unwrap());
+    }
+}
+
+/// Where the source of each module comes from, so a program can be compiled from files (a `ModuleResolver`) or entirely from memory (a `HashMap` from module name to source)
+pub trait ModuleProvider {
+    /// The path a module's diagnostics point at, its file stem is the module's name
+    fn locate(&self, module: &str) -> Result<PathBuf, Box<dyn Error>>;
+
+    fn read(&self, module: &str) -> Result<String, Box<dyn Error>>;
+}
+
+impl ModuleProvider for ModuleResolver {
+    fn locate(&self, module: &str) -> Result<PathBuf, Box<dyn Error>> {
+        self.resolve(module)
+    }
+
+    fn read(&self, module: &str) -> Result<String, Box<dyn Error>> {
+        read_source(&self.resolve(module)?)
+    }
+}
+
+/// Modules in memory are named as if they were files next to each other, so `shapes` is at `shapes.iona`
+impl ModuleProvider for HashMap<String, String> {
+    fn locate(&self, module: &str) -> Result<PathBuf, Box<dyn Error>> {
+        match self.contains_key(module) {
+            true => Ok(module_path(Path::new(""), module)),
+            false => Err(format!("unable to find module `{}`", module).into()),
+        }
+    }
+
+    fn read(&self, module: &str) -> Result<String, Box<dyn Error>> {
+        self.get(module)
+            .cloned()
+            .ok_or_else(|| format!("unable to find module `{}`", module).into())
     }
 }
 
 /// Recursively parse a file, check all of the modules it needs (imports), and then parse those modules too
+///
+/// Each module is loaded from the `provider` at most once, even if several modules import it or the imports form a cycle. Modules are loaded in name order, so diagnostics come out in the same order every run
 fn parse_recursively(
-    ast_map_handle: &mut HashMap<String, Vec<ASTNode>>,
+    ast_map_handle: &mut ModuleAsts,
     tables_handle: &mut ParsingTables,
+    provider: &dyn ModuleProvider,
     verbose: bool,
+    cache: Option<&AstCache>,
+    sources: &mut SourceMap,
 ) -> Result<(), Box<dyn Error>> {
     for (module, is_parsed) in tables_handle.modules.parsing_status.clone().iter() {
-        if !*is_parsed {
-            let new_path = Path::new(module);
-            let module_name = new_path
-                .file_stem()
-                .expect(&format!(
-                    "unable to get file stem from filename {:?}",
-                    new_path
-                ))
-                .to_string_lossy();
-            let new_nodes = file_to_ast(new_path, verbose)?;
-            tables_handle.update(&new_nodes, &module_name);
-            ast_map_handle.insert(module.to_string(), new_nodes);
-            parse_recursively(ast_map_handle, tables_handle, verbose)?;
+        if *is_parsed || ast_map_handle.contains_key(module) {
+            continue;
         }
+        let new_nodes = module_to_ast(provider, module, verbose, cache, sources)?;
+        // Mark it before recursing, so an import cycle back to this module doesn't load it again
+        tables_handle
+            .modules
+            .parsing_status
+            .insert(module.to_string(), true);
+        tables_handle.update(&new_nodes, module);
+        ast_map_handle.insert(module.to_string(), new_nodes);
+        parse_recursively(
+            ast_map_handle,
+            tables_handle,
+            provider,
+            verbose,
+            cache,
+            sources,
+        )?;
     }
     Ok(())
 }
 
+/// Parse the entrypoint and every module it (transitively) imports, then check that the imports line up
+///
+/// The tables are returned too, since they describe how the modules fit together. Every file that was read is added to `sources`, so later diagnostics can be rendered against any of them
 pub fn parse_all_reachable(
     entrypoint_filepath: &Path,
+    resolver: &ModuleResolver,
     verbose: bool,
-) -> Result<HashMap<String, Vec<ASTNode>>, Box<dyn Error>> {
-    let mut output: HashMap<String, Vec<ASTNode>> = HashMap::new();
-    let module_name = entrypoint_filepath
-        .file_stem()
-        .expect(&format!(
-            "unable to get file stem from filename {:?}",
-            entrypoint_filepath
-        ))
-        .to_string_lossy();
-    let entrypoint_nodes = file_to_ast(entrypoint_filepath, verbose)?;
+    cache: Option<&AstCache>,
+    sources: &mut SourceMap,
+) -> Result<(ModuleAsts, ParsingTables), Box<dyn Error>> {
+    let entrypoint_nodes = file_to_ast(entrypoint_filepath, verbose, cache, sources)?;
+    parse_imports(
+        &module_name(entrypoint_filepath),
+        entrypoint_nodes,
+        resolver,
+        verbose,
+        cache,
+        sources,
+    )
+}
+
+/// `parse_all_reachable` for an entrypoint that comes from a provider too, like a program that's only in memory
+pub fn parse_reachable_modules(
+    entry_module: &str,
+    provider: &dyn ModuleProvider,
+    verbose: bool,
+    cache: Option<&AstCache>,
+    sources: &mut SourceMap,
+) -> Result<(ModuleAsts, ParsingTables), Box<dyn Error>> {
+    let entrypoint_nodes = module_to_ast(provider, entry_module, verbose, cache, sources)?;
+    parse_imports(
+        entry_module,
+        entrypoint_nodes,
+        provider,
+        verbose,
+        cache,
+        sources,
+    )
+}
+
+/// Parse everything an already parsed entrypoint imports, then check that the imports line up
+fn parse_imports(
+    entry_module: &str,
+    entrypoint_nodes: Vec<ASTNode>,
+    provider: &dyn ModuleProvider,
+    verbose: bool,
+    cache: Option<&AstCache>,
+    sources: &mut SourceMap,
+) -> Result<(ModuleAsts, ParsingTables), Box<dyn Error>> {
+    let mut output: ModuleAsts = BTreeMap::new();
     let mut tables = ParsingTables::new();
-    tables.update(&entrypoint_nodes, &module_name);
+    tables
+        .modules
+        .parsing_status
+        .insert(entry_module.to_string(), true);
+    tables.update(&entrypoint_nodes, entry_module);
     // We don't need these nodes anymore so put them in the table
-    output.insert(
-        entrypoint_filepath.to_string_lossy().to_string(),
-        entrypoint_nodes,
-    );
-    parse_recursively(&mut output, &mut tables, verbose)?;
-    Ok(output)
+    output.insert(entry_module.to_string(), entrypoint_nodes);
+    parse_recursively(&mut output, &mut tables, provider, verbose, cache, sources)?;
+    report(&tables.check(), "import", sources)?;
+    Ok((output, tables))
+}
+
+/// Stands in an empty module for any module the wrapped provider can't find, and remembers which ones those were
+struct AllowMissing<'a> {
+    provider: &'a dyn ModuleProvider,
+    missing: RefCell<BTreeSet<String>>,
+}
+
+impl ModuleProvider for AllowMissing<'_> {
+    fn locate(&self, module: &str) -> Result<PathBuf, Box<dyn Error>> {
+        match self.provider.locate(module) {
+            Ok(path) => Ok(path),
+            Err(_) => {
+                self.missing.borrow_mut().insert(module.to_string());
+                Ok(module_path(Path::new(""), module))
+            }
+        }
+    }
+
+    fn read(&self, module: &str) -> Result<String, Box<dyn Error>> {
+        match self.missing.borrow().contains(module) {
+            true => Ok(String::new()),
+            false => self.provider.read(module),
+        }
+    }
+}
+
+/// Parse the entrypoint and every module it (transitively) imports, only to find out how they depend on each other
+///
+/// Unlike `parse_all_reachable` a module that can't be found isn't an error, it's left unparsed in `parsing_status`. The imports aren't checked either
+pub fn parse_import_graph(
+    entrypoint_filepath: &Path,
+    resolver: &ModuleResolver,
+    verbose: bool,
+    cache: Option<&AstCache>,
+    sources: &mut SourceMap,
+) -> Result<ParsingTables, Box<dyn Error>> {
+    let entry_module = module_name(entrypoint_filepath);
+    let entrypoint_nodes = file_to_ast(entrypoint_filepath, verbose, cache, sources)?;
+    let provider = AllowMissing {
+        provider: resolver,
+        missing: RefCell::new(BTreeSet::new()),
+    };
+    let mut output: ModuleAsts = BTreeMap::new();
+    let mut tables = ParsingTables::new();
+    tables
+        .modules
+        .parsing_status
+        .insert(entry_module.clone(), true);
+    tables.update(&entrypoint_nodes, &entry_module);
+    output.insert(entry_module, entrypoint_nodes);
+    parse_recursively(&mut output, &mut tables, &provider, verbose, cache, sources)?;
+    for module in provider.missing.into_inner() {
+        tables.modules.parsing_status.insert(module, false);
+    }
+    Ok(tables)
+}
+
+/// A module is named after its file, without the extension
+fn module_name(filepath: &Path) -> String {
+    filepath
+        .file_stem()
+        .unwrap_or_else(|| panic!("unable to get file stem from filename {:?}", filepath))
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Parse and check an entrypoint and everything it imports, then generate them as one C translation unit
+///
+/// Modules are written after the ones they import. The tables are returned too, since the caller still has to generate the templated libraries they need
+///
+/// `is_library` skips checking the entrypoint for a `main`. Warnings, and anything else that didn't stop compilation, are kept in `sources`
+#[allow(clippy::too_many_arguments)]
+pub fn compile_single_unit(
+    entrypoint_filepath: &Path,
+    resolver: &ModuleResolver,
+    options: &CodegenOptions,
+    is_library: bool,
+    lints: &LintLevels,
+    verbose: bool,
+    cache: Option<&AstCache>,
+    sources: &mut SourceMap,
+) -> Result<(String, ParsingTables), Box<dyn Error>> {
+    compile_unit(
+        &module_name(entrypoint_filepath),
+        resolver,
+        options,
+        is_library,
+        lints,
+        verbose,
+        cache,
+        sources,
+    )
+}
+
+/// Compile a program that's only in memory into one C translation unit, for tests and for embedding the compiler
+///
+/// `modules` maps each module's name to its source, see the `ModuleProvider` for a `HashMap`
+pub fn compile_str(
+    entry_module: &str,
+    modules: &HashMap<String, String>,
+    options: &CodegenOptions,
+    is_library: bool,
+    lints: &LintLevels,
+    sources: &mut SourceMap,
+) -> Result<(String, ParsingTables), Box<dyn Error>> {
+    compile_unit(
+        entry_module,
+        modules,
+        options,
+        is_library,
+        lints,
+        false,
+        None,
+        sources,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn compile_unit(
+    entry_module: &str,
+    provider: &dyn ModuleProvider,
+    options: &CodegenOptions,
+    is_library: bool,
+    lints: &LintLevels,
+    verbose: bool,
+    cache: Option<&AstCache>,
+    sources: &mut SourceMap,
+) -> Result<(String, ParsingTables), Box<dyn Error>> {
+    let (asts, tables, order) = load_program(entry_module, provider, verbose, cache, sources)?;
+    let entry = order.last().cloned().unwrap_or_default();
+    let mut modules = Vec::new();
+    for module in order.iter() {
+        let ast = asts
+            .get(module)
+            .ok_or_else(|| format!("module `{}` was never parsed", module))?;
+        let entry_point = !is_library && *module == entry;
+        check_ast(
+            &provider.locate(module)?,
+            ast,
+            &tables,
+            entry_point,
+            lints,
+            sources,
+        )?;
+        modules.push((module.as_str(), ast.as_slice()));
+    }
+    let generated_code = codegen_c::write_unit(&modules, &tables.types, options);
+    Ok((generated_code, tables))
+}
+
+/// Parse and check an entrypoint and everything it imports, then generate the C for each of them
+///
+/// Every imported module gets a header and a source file in `gen/`, named after the module so the entrypoint's `#include`s find them. The entrypoint's file comes last, and is the one with `main`. The tables are returned too, since the caller still has to generate the templated libraries they need
+///
+/// `is_library` skips checking the entrypoint for a `main`. Warnings, and anything else that didn't stop compilation, are kept in `sources`
+#[allow(clippy::too_many_arguments)]
+pub fn compile_modules(
+    entrypoint_filepath: &Path,
+    resolver: &ModuleResolver,
+    options: &CodegenOptions,
+    is_library: bool,
+    lints: &LintLevels,
+    verbose: bool,
+    cache: Option<&AstCache>,
+    sources: &mut SourceMap,
+) -> Result<(GeneratedFiles, ParsingTables), Box<dyn Error>> {
+    let (asts, tables, order) = load_program(
+        &module_name(entrypoint_filepath),
+        resolver,
+        verbose,
+        cache,
+        sources,
+    )?;
+    let entry = order.last().cloned().unwrap_or_default();
+    let mut files = Vec::new();
+    for module in order.iter() {
+        let ast = asts
+            .get(module)
+            .ok_or_else(|| format!("module `{}` was never parsed", module))?;
+        let filepath = resolver.resolve(module)?;
+        let entry_point = !is_library && *module == entry;
+        check_ast(&filepath, ast, &tables, entry_point, lints, sources)?;
+        if *module == entry {
+            let source = codegen_c::write_all(ast.iter(), &tables.types, module, false, options);
+            files.push((PathBuf::from(ENTRYPOINT_OUTPUT), source));
+        } else if !skips_emit(&filepath) {
+            let (header, source) =
+                codegen_c::write_module_files(ast, &tables.types, module, options);
+            files.push((PathBuf::from(format!("gen/{}.h", module)), header));
+            files.push((PathBuf::from(format!("gen/{}.c", module)), source));
+        }
+    }
+    Ok((files, tables))
+}
+
+/// Parse an entrypoint and everything it imports, then resolve their types in build order
+///
+/// The build order comes back too, it always ends with the entrypoint
+fn load_program(
+    entry_module: &str,
+    provider: &dyn ModuleProvider,
+    verbose: bool,
+    cache: Option<&AstCache>,
+    sources: &mut SourceMap,
+) -> Result<(ModuleAsts, ParsingTables, Vec<String>), Box<dyn Error>> {
+    let (mut asts, mut tables) =
+        parse_reachable_modules(entry_module, provider, verbose, cache, sources)?;
+    let order = tables.modules.build_order(entry_module);
+    for module in order.iter() {
+        if let Some(ast) = asts.get_mut(module) {
+            resolve_types(ast, module, &mut tables);
+        }
+    }
+    Ok((asts, tables, order))
+}
+
+/// How many problems `check` found
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CheckSummary {
+    pub errors: usize,
+    /// Warnings and lints
+    pub warnings: usize,
+}
+
+impl fmt::Display for CheckSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} error(s), {} warning(s)", self.errors, self.warnings)
+    }
+}
+
+/// Parse and check an entrypoint and everything it imports, without generating or writing anything
+///
+/// Every module is checked even once one has errors, and everything found is kept in `sources` so it can all be shown together. Syntax and import errors still stop it early, since the modules can't be checked without them
+pub fn check(
+    entrypoint_filepath: &Path,
+    resolver: &ModuleResolver,
+    is_library: bool,
+    lints: &LintLevels,
+    verbose: bool,
+    cache: Option<&AstCache>,
+    sources: &mut SourceMap,
+) -> Result<CheckSummary, Box<dyn Error>> {
+    // Only what this check finds is counted, not whatever the caller already had
+    let before = sources.diagnostics.len();
+    let (asts, tables, order) = load_program(
+        &module_name(entrypoint_filepath),
+        resolver,
+        verbose,
+        cache,
+        sources,
+    )?;
+    let entry = order.last().cloned().unwrap_or_default();
+    let mut out = SemanticOutput::new();
+    for module in order.iter() {
+        let ast = asts
+            .get(module)
+            .ok_or_else(|| format!("module `{}` was never parsed", module))?;
+        let entry_point = !is_library && *module == entry;
+        out.extend(
+            semantic_diagnostics(&resolver.resolve(module)?, ast, &tables, entry_point, lints)
+                .diagnostics,
+        );
+    }
+    // Syntax errors the parser recovered from were kept while loading, and count the same as the semantic errors
+    sources.diagnostics.extend(out.diagnostics);
+    let found = &sources.diagnostics[before..];
+    let errors = found
+        .iter()
+        .filter(|d| *d.level() == IssueLevel::Error)
+        .count();
+    Ok(CheckSummary {
+        errors,
+        warnings: found.len() - errors,
+    })
+}
+
+/// How `watch` looks at the files it's watching, so tests can fake the filesystem and the clock
+pub trait WatchProbe {
+    /// When the file last changed, `None` if it can't be read
+    fn modified(&self, path: &Path) -> Option<SystemTime>;
+    /// Wait until it's time to look again, returning false to stop watching
+    fn wait(&mut self) -> bool;
+}
+
+/// Look at each file's modification time every `interval`, until the process is stopped
+pub struct PollingProbe {
+    pub interval: Duration,
+}
+
+impl WatchProbe for PollingProbe {
+    fn modified(&self, path: &Path) -> Option<SystemTime> {
+        fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+
+    fn wait(&mut self) -> bool {
+        thread::sleep(self.interval);
+        true
+    }
+}
+
+/// Run `compile` now, and again whenever the entrypoint or anything it imports changes
+///
+/// `compile` is given the files that changed, which is empty for the first run, and hands back the `SourceMap` of the files it loaded. Those are what's watched next, so adding or removing an import changes what's watched
+pub fn watch(
+    entrypoint_filepath: &Path,
+    probe: &mut dyn WatchProbe,
+    compile: &mut dyn FnMut(&[PathBuf]) -> SourceMap,
+) {
+    let stamp = |probe: &dyn WatchProbe, files: &[PathBuf]| {
+        files
+            .iter()
+            .map(|file| (file.clone(), probe.modified(file)))
+            .collect::<BTreeMap<PathBuf, Option<SystemTime>>>()
+    };
+    let mut current = stamp(probe, &[entrypoint_filepath.to_path_buf()]);
+    let mut changed = Vec::new();
+    loop {
+        let sources = compile(&changed);
+        // Keep the times from before compiling, so a change made while it ran is still noticed next time
+        let stamps = watched_files(entrypoint_filepath, &sources)
+            .into_iter()
+            .map(|file| {
+                let modified = match current.get(&file) {
+                    Some(modified) => *modified,
+                    None => probe.modified(&file),
+                };
+                (file, modified)
+            })
+            .collect::<BTreeMap<PathBuf, Option<SystemTime>>>();
+        loop {
+            if !probe.wait() {
+                return;
+            }
+            current = stamp(probe, &stamps.keys().cloned().collect::<Vec<PathBuf>>());
+            changed = current
+                .iter()
+                .filter(|(file, modified)| stamps.get(*file) != Some(*modified))
+                .map(|(file, _)| file.clone())
+                .collect::<Vec<PathBuf>>();
+            if !changed.is_empty() {
+                break;
+            }
+        }
+    }
+}
+
+/// The entrypoint and every file the compile that produced `sources` loaded
+///
+/// The entrypoint is always watched, so a run that couldn't even read it is tried again once it's fixed
+fn watched_files(entrypoint_filepath: &Path, sources: &SourceMap) -> Vec<PathBuf> {
+    let mut files = BTreeSet::from([entrypoint_filepath.to_path_buf()]);
+    files.extend(sources.files());
+    files.into_iter().collect()
+}
+
+/// Write the import graph as Makefile rules, one `module.iona: dependency.iona ...` line per importing module
+pub fn render_dependencies(tables: &ParsingTables, resolver: &ModuleResolver) -> String {
+    // Everything in the graph was parsed, so it resolves
+    let path = |module: &str| {
+        resolver
+            .resolve(module)
+            .unwrap_or_else(|_| module_path(&resolver.root, module))
+            .to_string_lossy()
+            .to_string()
+    };
+    tables
+        .modules
+        .dependencies()
+        .iter()
+        .map(|(module, imports)| {
+            let imports = imports
+                .iter()
+                .map(|import| path(import))
+                .collect::<Vec<String>>();
+            format!("{}: {}\n", path(module), imports.join(" "))
+        })
+        .collect::<String>()
+}
+
+/// Render how the modules import each other as a Graphviz DOT graph
+///
+/// Each module is a node and each import an edge labelled with the imported items, both sorted so the output is stable. Modules that were never parsed (because they couldn't be found) are dashed
+pub fn render_dependency_graph(tables: &ParsingTables) -> String {
+    let mut dot = String::from("digraph dependencies {\n");
+    for (module, is_parsed) in tables.modules.parsing_status.iter() {
+        match is_parsed {
+            true => dot.push_str(&format!("    {:?};\n", module)),
+            false => dot.push_str(&format!("    {:?} [style=dashed];\n", module)),
+        }
+    }
+    for ((module, import), items) in tables.modules.import_edges().iter() {
+        let items = items.iter().cloned().collect::<Vec<String>>().join(", ");
+        dot.push_str(&format!(
+            "    {:?} -> {:?} [label={:?}];\n",
+            module, import, items
+        ));
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// Write every module's AST as one JSON object, keyed by the path of the module's file
+///
+/// The keys are sorted so the output doesn't depend on the order the modules were parsed in
+pub fn render_ast_json(asts: &ModuleAsts, resolver: &ModuleResolver) -> String {
+    let mut modules = asts
+        .iter()
+        .map(|(module, ast)| {
+            let path = resolver
+                .resolve(module)
+                .unwrap_or_else(|_| module_path(&resolver.root, module));
+            (path.to_string_lossy().to_string(), ast.to_json())
+        })
+        .collect::<Vec<(String, Json)>>();
+    modules.sort_by(|a, b| a.0.cmp(&b.0));
+    format!("{}\n", Json::Object(modules))
+}
+
+/// The source of every file the pipeline has loaded, keyed by the filename diagnostics point at
+///
+/// A diagnostic can point into any module, not just the one being checked, so it's rendered against whichever source its position names
+///
+/// The pipeline doesn't print anything itself, so the problems it found that didn't stop it are kept here too, for the caller to show
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceMap {
+    sources: BTreeMap<String, String>,
+    /// Warnings, and the errors the parser recovered from, in the order they were found
+    pub diagnostics: Vec<Diagnostic>,
+    /// The parser's stack for each file it had problems with, only kept in verbose mode
+    pub traces: Vec<Vec<String>>,
+}
+
+impl SourceMap {
+    pub fn new() -> SourceMap {
+        SourceMap::default()
+    }
+
+    pub fn insert(&mut self, filepath: &Path, source: &str) {
+        self.sources
+            .insert(filepath.to_string_lossy().to_string(), source.to_string());
+    }
+
+    pub fn get(&self, filename: &str) -> Option<&str> {
+        self.sources.get(filename).map(|source| source.as_str())
+    }
+
+    /// The path of every file that was loaded
+    pub fn files(&self) -> Vec<PathBuf> {
+        self.sources.keys().map(PathBuf::from).collect()
+    }
+
+    /// Keep the parser's trace and any problems that didn't stop it, then hand back the AST (or fail if there isn't one)
+    fn keep_problems(&mut self, result: CompileResult) -> Result<Vec<ASTNode>, Box<dyn Error>> {
+        if let Some(trace) = &result.trace {
+            self.traces.push(trace.clone());
+        }
+        if result.ast.is_some() {
+            self.diagnostics.extend(result.diagnostics.iter().cloned());
+        }
+        result.into_ast()
+    }
+}
+
+/// Render diagnostics that may point into several different files
+///
+/// A file that was never loaded renders without its source lines
+pub fn render_diagnostics(diagnostics: &[Diagnostic], sources: &SourceMap) -> String {
+    diagnostics
+        .iter()
+        .map(|d| d.display(sources.get(&d.position().filename).unwrap_or_default()))
+        .collect::<String>()
 }
 
 // -------------------- Unit Tests --------------------
 
-// #[cfg(test)]
-// mod tests {
-//     use super::*;
-//     use crate::lexer::Lexer;
-//     use crate::parser::Parser;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics::{DiagnosticKind, LintLevel};
+    use crate::lexer::SourcePosition;
 
-//     #[test]
-//     fn parse_reachable() {
+    /// Write a project's files into a fresh directory under the system temp dir
+    fn project(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("iona_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        for (file, text) in files.iter() {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        }
+        root
+    }
+
+    fn reachable(entrypoint: &Path) -> Result<(ModuleAsts, ParsingTables), Box<dyn Error>> {
+        parse_all_reachable(
+            entrypoint,
+            &ModuleResolver::new(entrypoint, &[]),
+            false,
+            None,
+            &mut SourceMap::new(),
+        )
+    }
+
+    #[test]
+    fn parse_file_collects_diagnostics() {
+        let source =
+            "fn broken( -> Int {\n    return 1;\n}\n\nfn fine() -> Int {\n    return 2;\n}\n";
+        let root = project("parse_file", &[("main.iona", source)]);
+        let file = root.join("main.iona");
+        let result = parse_file(&file, false, None).unwrap();
+        assert_eq!(result.source, source);
+        assert_eq!(result.trace, None);
+        assert!(!result.diagnostics.is_empty());
+        assert_eq!(result.diagnostics[0].kind(), DiagnosticKind::Syntax);
+        assert_eq!(result.diagnostics[0].position().line, 0);
+        // Syntax errors are recoverable, so the caller still gets an AST
+        assert!(result.ast.is_some());
+        assert!(result.render().starts_with("error[E0001]: "));
+        // Verbose mode keeps the parser's stack for the error
+        assert!(parse_file(&file, true, None).unwrap().trace.is_some());
+        assert!(parse_file(&root.join("missing.iona"), false, None).is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn emit_dependencies() {
+        let root = project(
+            "deps",
+            &[
+                (
+                    "main.iona",
+                    "import shapes with area;\n\nfn main() -> Int {\n    return area(2);\n}\n",
+                ),
+                (
+                    "shapes.iona",
+                    "fn area(side: Int) -> Int {\n    @metadata {\n        Is: Public;\n    }\n    return side * side;\n}\n",
+                ),
+            ],
+        );
+        let (asts, tables) = reachable(&root.join("main.iona")).unwrap();
+        assert_eq!(asts.len(), 2);
+        assert_eq!(
+            render_dependencies(&tables, &ModuleResolver::new(&root.join("main.iona"), &[])),
+            format!(
+                "{}: {}\n",
+                root.join("main.iona").to_string_lossy(),
+                root.join("shapes.iona").to_string_lossy()
+            )
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn emit_dependency_graph() {
+        let root = project(
+            "deps_dot",
+            &[
+                (
+                    "main.iona",
+                    "import shapes with area, Square;\nimport colors with Color;\n\nfn main() -> Int {\n    return area(2);\n}\n",
+                ),
+                (
+                    "shapes.iona",
+                    "import colors with Color;\n\nstruct Square {\n    side: Int,\n}\n\nfn area(side: Int) -> Int {\n    return side * side;\n}\n",
+                ),
+                ("colors.iona", "enum Color {\n    Red,\n    Blue,\n}\n"),
+            ],
+        );
+        let graph = |root: &Path| {
+            let entrypoint = root.join("main.iona");
+            let tables = parse_import_graph(
+                &entrypoint,
+                &ModuleResolver::new(&entrypoint, &[]),
+                false,
+                None,
+                &mut SourceMap::new(),
+            )
+            .unwrap();
+            render_dependency_graph(&tables)
+        };
+        let golden = concat!(
+            "digraph dependencies {\n",
+            "    \"colors\";\n",
+            "    \"main\";\n",
+            "    \"shapes\";\n",
+            "    \"main\" -> \"colors\" [label=\"Color\"];\n",
+            "    \"main\" -> \"shapes\" [label=\"Square, area\"];\n",
+            "    \"shapes\" -> \"colors\" [label=\"Color\"];\n",
+            "}\n",
+        );
+        assert_eq!(graph(&root), golden);
+        // A module that can't be found is still drawn, just dashed
+        fs::remove_file(root.join("colors.iona")).unwrap();
+        assert_eq!(
+            graph(&root),
+            golden.replace("\"colors\";", "\"colors\" [style=dashed];")
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn resolve_module_paths() {
+        let public = |name: &str| {
+            format!(
+                "fn {}() -> Int {{\n    @metadata {{\n        Is: Public;\n    }}\n    return 1;\n}}\n",
+                name
+            )
+        };
+        let root = project(
+            "resolve",
+            &[
+                (
+                    "game/main.iona",
+                    "import npc with talk;\nimport items with equip;\nimport strings with shout;\n",
+                ),
+                ("game/npc.iona", &public("talk")),
+                ("vendor/npc.iona", &public("greet")),
+                ("vendor/items.iona", &public("equip")),
+                ("std/items.iona", &public("drop")),
+                ("std/strings.iona", &public("shout")),
+            ],
+        );
+        let entrypoint = root.join("game/main.iona");
+        let mut resolver = ModuleResolver::new(&entrypoint, &[root.join("vendor")]);
+        resolver.stdlib_dir = root.join("std");
+        // Siblings of the entrypoint come first, then include directories, then the standard library
+        assert_eq!(resolver.resolve("npc").unwrap(), root.join("game/npc.iona"));
+        assert_eq!(
+            resolver.resolve("items").unwrap(),
+            root.join("vendor/items.iona")
+        );
+        assert_eq!(
+            resolver.resolve("strings").unwrap(),
+            root.join("std/strings.iona")
+        );
+        let (asts, _) =
+            parse_all_reachable(&entrypoint, &resolver, false, None, &mut SourceMap::new())
+                .unwrap();
+        assert_eq!(asts.len(), 4);
+
+        let error = resolver.resolve("missing").unwrap_err().to_string();
+        assert_eq!(
+            error,
+            format!(
+                "unable to find module `missing`, tried:\n  {}\n  {}\n  {}\n",
+                root.join("game/missing.iona").to_string_lossy(),
+                root.join("vendor/missing.iona").to_string_lossy(),
+                root.join("std/missing.iona").to_string_lossy()
+            )
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn each_module_is_parsed_once() {
+        // `a` and `b` both import `c`, which imports `a` back
+        struct CountingProvider {
+            sources: HashMap<String, String>,
+            reads: RefCell<HashMap<String, usize>>,
+        }
+        impl ModuleProvider for CountingProvider {
+            fn locate(&self, module: &str) -> Result<PathBuf, Box<dyn Error>> {
+                self.sources.locate(module)
+            }
+            fn read(&self, module: &str) -> Result<String, Box<dyn Error>> {
+                *self
+                    .reads
+                    .borrow_mut()
+                    .entry(module.to_string())
+                    .or_default() += 1;
+                self.sources.read(module)
+            }
+        }
+        let provider = CountingProvider {
+            sources: sources(&[
+                ("a", "import b with f;\nimport c with g;\n"),
+                ("b", "import c with g;\n"),
+                ("c", "import a with h;\n"),
+            ]),
+            reads: RefCell::new(HashMap::new()),
+        };
+        let mut asts = ModuleAsts::new();
+        let mut tables = ParsingTables::new();
+        let entry = module_to_ast(&provider, "a", false, None, &mut SourceMap::new()).unwrap();
+        tables.modules.parsing_status.insert("a".to_string(), true);
+        tables.update(&entry, "a");
+        asts.insert("a".to_string(), entry);
+        parse_recursively(
+            &mut asts,
+            &mut tables,
+            &provider,
+            false,
+            None,
+            &mut SourceMap::new(),
+        )
+        .unwrap();
+        let mut modules = asts.keys().cloned().collect::<Vec<String>>();
+        modules.sort();
+        assert_eq!(modules, vec!["a", "b", "c"]);
+        let reads = provider.reads.borrow();
+        assert_eq!(reads.len(), 3);
+        assert!(reads.values().all(|count| *count == 1), "{:#?}", reads);
+    }
 
-//     }
-// }
+    fn sources(modules: &[(&str, &str)]) -> HashMap<String, String> {
+        modules
+            .iter()
+            .map(|(name, text)| (name.to_string(), text.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parse_a_string() {
+        let result = source_to_ast("inline", "fn main() -> Int {\n    return 0;\n}\n", false);
+        assert!(result.diagnostics.is_empty(), "{:#?}", result.diagnostics);
+        assert_eq!(result.ast.map(|ast| ast.len()), Some(1));
+    }
+
+    #[test]
+    fn compile_modules_from_memory() {
+        let program = sources(&[
+            (
+                "main",
+                "import shapes with area;\n\nfn main() -> Int {\n    return area(3);\n}\n",
+            ),
+            (
+                "shapes",
+                "fn area(side: Int) -> Int {\n    @metadata {\n        Is: Public;\n    }\n    return side * side;\n}\n",
+            ),
+        ]);
+        let (asts, _) =
+            parse_reachable_modules("main", &program, false, None, &mut SourceMap::new()).unwrap();
+        assert_eq!(asts.len(), 2);
+        let (c_code, _) = compile_str(
+            "main",
+            &program,
+            &CodegenOptions::new("linux"),
+            false,
+            &LintLevels::default(),
+            &mut SourceMap::new(),
+        )
+        .unwrap();
+        assert!(
+            c_code.contains("Integer area(Integer side) {"),
+            "{}",
+            c_code
+        );
+        assert!(c_code.contains("Integer iona_main(void) {"), "{}", c_code);
+        assert!(
+            c_code.contains("int main(int argc, char** argv) {"),
+            "{}",
+            c_code
+        );
+    }
+
+    #[test]
+    fn problems_are_kept_for_the_caller() {
+        let program = sources(&[
+            (
+                "main",
+                "import shapes with area;\n\nfn main() -> Int {\n    let unused: Int = 1;\n    return area(3);\n}\n",
+            ),
+            (
+                "shapes",
+                "fn broken( -> Int {\n    return 1;\n}\n\nfn area(side: Int) -> Int {\n    @metadata {\n        Is: Public;\n    }\n    return side * side;\n}\n",
+            ),
+        ]);
+        let mut loaded = SourceMap::new();
+        let parsed = parse_reachable_modules("main", &program, true, None, &mut loaded);
+        assert!(parsed.is_ok());
+        // The syntax error in the imported module was recovered from, so it's kept instead of stopping the load
+        assert!(!loaded.diagnostics.is_empty());
+        assert!(loaded
+            .diagnostics
+            .iter()
+            .all(|d| d.kind() == DiagnosticKind::Syntax && d.position().filename == "shapes.iona"));
+        assert_eq!(loaded.traces.len(), 1);
+        // Warnings from the semantic checks are kept too, rather than printed
+        let mut loaded = SourceMap::new();
+        let program = sources(&[(
+            "main",
+            "fn main() -> Int {\n    let unused: Int = 1;\n    return 0;\n}\n",
+        )]);
+        compile_str(
+            "main",
+            &program,
+            &CodegenOptions::new("linux"),
+            false,
+            &LintLevels::default(),
+            &mut loaded,
+        )
+        .unwrap();
+        assert_eq!(loaded.diagnostics.len(), 1);
+        assert!(render_diagnostics(&loaded.diagnostics, &loaded).starts_with("warning[W0001]"));
+    }
+
+    #[test]
+    fn missing_modules_in_memory() {
+        let program = sources(&[("main", "import shapes with area;\n")]);
+        let error = parse_reachable_modules("main", &program, false, None, &mut SourceMap::new())
+            .unwrap_err();
+        assert!(error.to_string().contains("unable to find module `shapes`"));
+    }
+
+    #[test]
+    fn render_each_diagnostic_against_its_own_file() {
+        let mut sources = SourceMap::new();
+        sources.insert(
+            Path::new("main.iona"),
+            "fn main() -> Int {\n    return 0;\n}\n",
+        );
+        sources.insert(
+            Path::new("shapes.iona"),
+            "fn area() -> Int {\n    return side;\n}\n",
+        );
+        let at = |filename: &str| SourcePosition {
+            filename: filename.to_string(),
+            line: 1,
+            column: 11,
+        };
+        let diagnostics = vec![
+            Diagnostic::new_error_simple(
+                DiagnosticKind::UndefinedVariable,
+                "`side` is not defined",
+                &at("shapes.iona"),
+            ),
+            Diagnostic::new_error_simple(DiagnosticKind::Syntax, "oops", &at("main.iona")),
+            Diagnostic::new_error_simple(DiagnosticKind::Syntax, "gone", &at("missing.iona")),
+        ];
+        let rendered = render_diagnostics(&diagnostics, &sources);
+        assert_eq!(
+            rendered,
+            [
+                diagnostics[0].display("fn area() -> Int {\n    return side;\n}\n"),
+                diagnostics[1].display("fn main() -> Int {\n    return 0;\n}\n"),
+                diagnostics[2].display(""),
+            ]
+            .concat()
+        );
+        assert!(rendered.contains("return side;"), "{}", rendered);
+    }
+
+    #[test]
+    fn render_diagnostics_from_an_imported_module() {
+        // The private import is in `shapes`, which only exists in memory
+        let program = sources(&[
+            (
+                "main",
+                "import shapes with area;\n\nfn main() -> Int {\n    return area(3);\n}\n",
+            ),
+            (
+                "shapes",
+                "import secrets with hidden;\n\nfn area(side: Int) -> Int {\n    @metadata {\n        Is: Public;\n    }\n    return side * hidden();\n}\n",
+            ),
+            ("secrets", "fn hidden() -> Int {\n    return 1;\n}\n"),
+        ]);
+        let mut sources = SourceMap::new();
+        let error = parse_reachable_modules("main", &program, false, None, &mut sources)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("shapes.iona:0:0"), "{}", error);
+        assert!(error.contains("import secrets with hidden;"), "{}", error);
+        assert_eq!(sources.get("shapes.iona"), Some(program["shapes"].as_str()));
+    }
+
+    #[test]
+    fn modules_are_processed_in_the_same_order_every_run() {
+        // Every module but `main` imports something private, so each one adds a diagnostic
+        let public = "    @metadata {\n        Is: Public;\n    }\n";
+        let mut files = vec![(
+            "main.iona".to_string(),
+            "import b with b;\nimport c with c;\nimport d with d;\nimport e with e;\n\nfn main() -> Int {\n    return 0;\n}\n".to_string(),
+        )];
+        for module in ["b", "c", "d", "e"] {
+            files.push((
+                format!("{}.iona", module),
+                format!(
+                    "import z with hidden;\n\nfn {}() -> Int {{\n{}    return 0;\n}}\n",
+                    module, public
+                ),
+            ));
+        }
+        files.push((
+            "z.iona".to_string(),
+            "fn hidden() -> Int {\n    return 0;\n}\n".to_string(),
+        ));
+        let files = files
+            .iter()
+            .map(|(file, text)| (file.as_str(), text.as_str()))
+            .collect::<Vec<(&str, &str)>>();
+        let root = project("ordering", &files);
+        let entrypoint = module_path(&root, "main");
+        let errors = (0..2)
+            .map(|_| reachable(&entrypoint).unwrap_err().to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(errors[0], errors[1]);
+        let modules = errors[0]
+            .lines()
+            .filter(|line| line.contains("is private to module"))
+            .count();
+        assert_eq!(modules, 4, "{}", errors[0]);
+        fs::remove_dir_all(&root).unwrap();
+
+        // With `hidden` made public, the generated files come out in the same order too
+        let files = files
+            .iter()
+            .map(|(file, text)| match *file {
+                "z.iona" => (*file, "fn hidden() -> Int {\n    @metadata {\n        Is: Public;\n    }\n    return 0;\n}\n"),
+                _ => (*file, *text),
+            })
+            .collect::<Vec<(&str, &str)>>();
+        let root = project("ordering_output", &files);
+        let entrypoint = module_path(&root, "main");
+        let outputs = (0..2)
+            .map(|_| {
+                compile_modules(
+                    &entrypoint,
+                    &ModuleResolver::new(&entrypoint, &[]),
+                    &CodegenOptions::new("linux"),
+                    false,
+                    &LintLevels::default(),
+                    false,
+                    None,
+                    &mut SourceMap::new(),
+                )
+                .unwrap()
+                .0
+            })
+            .collect::<Vec<GeneratedFiles>>();
+        assert_eq!(outputs[0], outputs[1]);
+        let paths = outputs[0]
+            .iter()
+            .map(|(path, _)| path.to_string_lossy().to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(
+            paths,
+            vec![
+                "gen/z.h",
+                "gen/z.c",
+                "gen/b.h",
+                "gen/b.c",
+                "gen/c.h",
+                "gen/c.c",
+                "gen/d.h",
+                "gen/d.c",
+                "gen/e.h",
+                "gen/e.c",
+                ENTRYPOINT_OUTPUT
+            ]
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    /// Something that happens while `watch` waits, given each file's fake modification time
+    type Step = Box<dyn FnMut(&mut HashMap<PathBuf, u64>)>;
+
+    /// Stands in for the filesystem's clock, each `wait` runs the next step of a script until there are none left
+    struct FakeProbe {
+        modified: HashMap<PathBuf, u64>,
+        steps: Vec<Step>,
+    }
+
+    impl WatchProbe for FakeProbe {
+        fn modified(&self, path: &Path) -> Option<SystemTime> {
+            let seconds = self.modified.get(path).copied().unwrap_or_default();
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))
+        }
+
+        fn wait(&mut self) -> bool {
+            if self.steps.is_empty() {
+                return false;
+            }
+            let mut step = self.steps.remove(0);
+            step(&mut self.modified);
+            true
+        }
+    }
+
+    fn bump(path: &Path) -> Step {
+        let path = path.to_path_buf();
+        Box::new(move |modified| *modified.entry(path.clone()).or_default() += 1)
+    }
+
+    fn nothing() -> Step {
+        Box::new(|_| {})
+    }
+
+    fn watch_runs(entrypoint: &Path, steps: Vec<Step>) -> Vec<Vec<PathBuf>> {
+        let mut probe = FakeProbe {
+            modified: HashMap::new(),
+            steps,
+        };
+        let resolver = ModuleResolver::new(entrypoint, &[]);
+        let mut runs = Vec::new();
+        watch(entrypoint, &mut probe, &mut |changed| {
+            runs.push(changed.to_vec());
+            let mut sources = SourceMap::new();
+            let _ = check(
+                entrypoint,
+                &resolver,
+                true,
+                &LintLevels::default(),
+                false,
+                None,
+                &mut sources,
+            );
+            sources
+        });
+        runs
+    }
+
+    #[test]
+    fn watch_recompiles_once_per_change() {
+        let root = project(
+            "watch_once",
+            &[
+                ("main.iona", "import util with double;\n"),
+                (
+                    "util.iona",
+                    "fn double(x: Int) -> Int {\n    return x * 2;\n}\n",
+                ),
+                ("unrelated.iona", "fn other() -> Int {\n    return 0;\n}\n"),
+            ],
+        );
+        let entrypoint = module_path(&root, "main");
+        let util = module_path(&root, "util");
+        let runs = watch_runs(
+            &entrypoint,
+            vec![
+                nothing(),
+                bump(&util),
+                nothing(),
+                bump(&module_path(&root, "unrelated")),
+                nothing(),
+            ],
+        );
+        // The first run is the initial compile, then exactly one for the change
+        assert_eq!(runs, vec![vec![], vec![util]]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn watch_follows_new_imports() {
+        let root = project(
+            "watch_imports",
+            &[
+                ("main.iona", "fn main() -> Int {\n    return 0;\n}\n"),
+                (
+                    "util.iona",
+                    "fn double(x: Int) -> Int {\n    return x * 2;\n}\n",
+                ),
+            ],
+        );
+        let entrypoint = module_path(&root, "main");
+        let util = module_path(&root, "util");
+        let add_import = {
+            let entrypoint = entrypoint.clone();
+            let mut bump_main = bump(&entrypoint);
+            Box::new(move |modified: &mut HashMap<PathBuf, u64>| {
+                fs::write(&entrypoint, "import util with double;\n").unwrap();
+                bump_main(modified);
+            })
+        };
+        let runs = watch_runs(
+            &entrypoint,
+            vec![bump(&util), add_import, bump(&util), nothing()],
+        );
+        // `util` isn't watched until `main` imports it
+        assert_eq!(runs, vec![vec![], vec![entrypoint], vec![util]]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn watch_uses_the_files_the_compile_loaded() {
+        let root = project(
+            "watch_loaded",
+            &[
+                ("main.iona", "fn main() -> Int {\n    return 0;\n}\n"),
+                ("loaded.iona", "fn one() -> Int {\n    return 1;\n}\n"),
+                ("ignored.iona", "fn two() -> Int {\n    return 2;\n}\n"),
+            ],
+        );
+        let entrypoint = module_path(&root, "main");
+        let loaded = module_path(&root, "loaded");
+        let mut probe = FakeProbe {
+            modified: HashMap::new(),
+            steps: vec![bump(&module_path(&root, "ignored")), bump(&loaded)],
+        };
+        let mut runs = Vec::new();
+        // Nothing is parsed here, whatever the compile says it loaded is what's watched
+        watch(&entrypoint, &mut probe, &mut |changed| {
+            runs.push(changed.to_vec());
+            let mut sources = SourceMap::new();
+            sources.insert(&loaded, "");
+            sources
+        });
+        assert_eq!(runs, vec![vec![], vec![loaded]]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn unchanged_modules_come_from_the_cache() {
+        let root = project(
+            "incremental",
+            &[
+                (
+                    "main.iona",
+                    "import util with double;\n\nfn main() -> Int {\n    return double(2);\n}\n",
+                ),
+                (
+                    "util.iona",
+                    "fn double(x: Int) -> Int {\n    @metadata {\n        Is: Public;\n    }\n    return x * 2;\n}\n",
+                ),
+            ],
+        );
+        let entrypoint = root.join("main.iona");
+        let resolver = ModuleResolver::new(&entrypoint, &[]);
+        let run = || {
+            let cache = AstCache::new(&root.join(".iona-cache"));
+            let (asts, _) = parse_all_reachable(
+                &entrypoint,
+                &resolver,
+                false,
+                Some(&cache),
+                &mut SourceMap::new(),
+            )
+            .unwrap();
+            (asts, cache.hits(), cache.misses())
+        };
+        let (fresh, hits, misses) = run();
+        assert_eq!((hits, misses), (0, 2));
+        let (cached, hits, misses) = run();
+        assert_eq!((hits, misses), (2, 0));
+        assert_eq!(cached, fresh);
+        // Editing a module only invalidates that module
+        fs::write(
+            root.join("util.iona"),
+            "fn double(x: Int) -> Int {\n    @metadata {\n        Is: Public;\n    }\n    return x + x;\n}\n",
+        )
+        .unwrap();
+        let (edited, hits, misses) = run();
+        assert_eq!((hits, misses), (1, 1));
+        assert_ne!(edited["util"], fresh["util"]);
+        assert_eq!(run().1, 2);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn emit_tokens() {
+        let source = "let x = `y;\n";
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(source);
+        assert_eq!(
+            render_tokens(&lexer, source),
+            r#"0:0 Let
+0:3 Space
+0:4 Identifier("x")
+0:5 Space
+0:6 Equals
+0:7 Space
+0:8 Identifier("y")
+0:10 Semicolon
+0:11 NewLine
+2:0 NewLine
+error[E0001]: test.iona:0:8
+ 0 |let x = `y;
+            ^unterminated raw identifier, expected a closing backtick after `y
+
+"#
+        );
+        let json = render_tokens_json(&lexer);
+        assert!(json.starts_with(
+            r#"{"tokens":[{"symbol":"Let","pos":{"filename":"test.iona","line":0,"column":0}},"#
+        ));
+        assert!(json.ends_with(
+            r#""diagnostics":[{"level":"error","code":"E0001","message":"unterminated raw identifier, expected a closing backtick after `y","position":{"filename":"test.iona","line":0,"column":8},"notes":[]}]}
+"#
+        ));
+    }
+
+    #[test]
+    fn emit_ast_json() {
+        let root = project(
+            "emit_ast",
+            &[
+                ("main.iona", "import util with double;\n"),
+                (
+                    "util.iona",
+                    "fn double(x: Int) -> Int {\n    @metadata {\n        Is: Public;\n    }\n    return x * 2;\n}\n",
+                ),
+            ],
+        );
+        let entrypoint = root.join("main.iona");
+        let resolver = ModuleResolver::new(&entrypoint, &[]);
+        let render = || render_ast_json(&reachable(&entrypoint).unwrap().0, &resolver);
+        let json = render();
+        let main = module_path(&root, "main").to_string_lossy().to_string();
+        let util = module_path(&root, "util").to_string_lossy().to_string();
+        assert!(json.starts_with(&format!("{{\"{}\":[{{\"ImportStatement\":", main)));
+        assert!(json.contains(&format!(
+            "}}}}],\"{}\":[{{\"FunctionDeclaration\":{{\"name\":\"double\",",
+            util
+        )));
+        assert!(json.ends_with("]}\n"));
+        // The modules are parsed into a fresh map each time, but always come out in the same order
+        for _ in 0..4 {
+            assert_eq!(render(), json);
+        }
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn import_diagnostics_from_every_module() {
+        let root = project(
+            "semantic",
+            &[
+                (
+                    "main.iona",
+                    "import shapes with aera, secret;\n\nfn main() -> Int {\n    return 0;\n}\n",
+                ),
+                (
+                    "shapes.iona",
+                    "fn area(side: Int) -> Int {\n    @metadata {\n        Is: Public;\n    }\n    return side * side;\n}\n\nfn secret() -> Int {\n    return 1;\n}\n",
+                ),
+            ],
+        );
+        let mut tables = ParsingTables::new();
+        for module in ["main", "shapes"] {
+            let ast = file_to_ast(
+                &module_path(&root, module),
+                false,
+                None,
+                &mut SourceMap::new(),
+            )
+            .unwrap();
+            tables.update(&ast, module);
+        }
+        let out = tables.check();
+        assert!(out.has_errors());
+        assert_eq!(
+            out.diagnostics
+                .iter()
+                .map(|d| (d.kind(), d.message()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    DiagnosticKind::UnresolvedImport,
+                    "`aera` is not defined in module `shapes` (did you mean `area`?)"
+                ),
+                (
+                    DiagnosticKind::PrivateImport,
+                    "`secret` is private to module `shapes`, mark it as `Public` or `Export` to import it"
+                ),
+            ]
+        );
+
+        // The pipeline renders the same list, and stops
+        let error = reachable(&root.join("main.iona")).unwrap_err().to_string();
+        assert!(error.starts_with("could not compile due to import error(s)"));
+        let undefined = error.find("`aera` is not defined").unwrap();
+        let private = error.find("`secret` is private").unwrap();
+        assert!(undefined < private);
+        assert_eq!(
+            error
+                .matches(&format!("{}:0:0", root.join("main.iona").to_string_lossy()))
+                .count(),
+            2
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn lint_levels_from_the_command_line() {
+        let root = project(
+            "lints",
+            &[(
+                "main.iona",
+                "fn main() -> Int {\n    let unused: Int = 1;\n    return 0;\n}\n",
+            )],
+        );
+        let file = root.join("main.iona");
+        let mut sources = SourceMap::new();
+        let mut ast = file_to_ast(&file, false, None, &mut sources).unwrap();
+        let mut tables = ParsingTables::new();
+        tables.update(&ast, "main");
+        resolve_types(&mut ast, "main", &mut tables);
+        let check = |levels: &[(&str, LintLevel)]| {
+            let lints = levels
+                .iter()
+                .map(|(name, level)| (name.to_string(), *level))
+                .collect::<LintLevels>();
+            check_ast(&file, &ast, &tables, true, &lints, &mut sources.clone())
+        };
+        // Lints alone don't stop compilation
+        assert!(check(&[]).is_ok());
+        assert!(check(&[("unused_imports", LintLevel::Deny)]).is_ok());
+        let error = check(&[("unused_variables", LintLevel::Deny)])
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with("could not compile due to semantic error(s)"));
+        assert!(error.contains("error[W0001]"));
+        // The specific lint wins over its group
+        assert!(check(&[
+            ("unused", LintLevel::Deny),
+            ("unused_variables", LintLevel::Allow)
+        ])
+        .is_ok());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    /// A module that imports `area` from `next`, and exports its own `area`
+    fn chained_module(next: &str) -> String {
+        format!(
+            "import {} with area;\n\nfn area(side: Int) -> Int {{\n    @metadata {{\n        Is: Public;\n    }}\n    return side;\n}}\n",
+            next
+        )
+    }
+
+    #[test]
+    fn two_module_cycle() {
+        let root = project(
+            "cycle2",
+            &[
+                ("a.iona", &chained_module("b")),
+                ("b.iona", &chained_module("a")),
+            ],
+        );
+        let error = reachable(&root.join("a.iona")).unwrap_err().to_string();
+        assert!(
+            error.contains("import cycle between modules: a -> b -> a"),
+            "{}",
+            error
+        );
+        assert_eq!(error.matches("import cycle").count(), 1);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn three_module_cycle() {
+        let root = project(
+            "cycle3",
+            &[
+                ("a.iona", &chained_module("b")),
+                ("b.iona", &chained_module("c")),
+                ("c.iona", &chained_module("a")),
+            ],
+        );
+        let error = reachable(&root.join("a.iona")).unwrap_err().to_string();
+        assert!(
+            error.contains("import cycle between modules: a -> b -> c -> a"),
+            "{}",
+            error
+        );
+        // The closing import in `c` is the error, the other imports in the loop are notes
+        assert!(error.contains(&format!("{}:0:0", root.join("c.iona").to_string_lossy())));
+        assert!(error.contains(&format!(
+            "note: see also {}:0:0",
+            root.join("a.iona").to_string_lossy()
+        )));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn self_import() {
+        let root = project("cycle1", &[("a.iona", &chained_module("a"))]);
+        let error = reachable(&root.join("a.iona")).unwrap_err().to_string();
+        assert!(error.contains("module `a` imports itself"), "{}", error);
+        assert!(!error.contains("import cycle"));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
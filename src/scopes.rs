@@ -0,0 +1,392 @@
+//! Lexical scopes: what a name refers to at a given point in a function body
+//!
+//! `SymbolTable` is the bookkeeping, `walk_function` drives it over a function so that individual checks only need to implement `ScopeVisitor`
+
+use std::collections::HashMap;
+
+use crate::expression_parser::Expr;
+use crate::lexer::SourcePosition;
+use crate::parser::{ContractType, Function, FunctionContract, Pattern, Statement, Type};
+
+/// A name in scope, and whether anything has read it yet
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binding {
+    pub type_: Type,
+    /// Where a `let` declared it, or `None` for names that come from elsewhere (parameters, `result`, patterns)
+    pub declared_at: Option<SourcePosition>,
+    /// For parameters, where the function that takes them is declared
+    pub parameter_of: Option<SourcePosition>,
+    pub read: bool,
+}
+
+/// A stack of scopes, innermost last
+#[derive(Debug, Default)]
+pub struct SymbolTable {
+    scopes: Vec<HashMap<String, Binding>>,
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable::default()
+    }
+
+    /// The outermost scope of a function, holding its parameters
+    pub fn for_function(f: &Function) -> SymbolTable {
+        let mut table = SymbolTable::new();
+        table.push_scope();
+        for arg in f.args.iter() {
+            table.declare_parameter(&arg.name, arg.field_type.clone(), &f.pos);
+        }
+        table
+    }
+
+    pub fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    /// Close the innermost scope, returning the locals it declared that were never read
+    pub fn pop_scope(&mut self) -> Vec<(String, Binding)> {
+        let unused = self
+            .unused()
+            .map(|(name, binding)| (name.to_string(), binding.clone()))
+            .collect();
+        self.scopes.pop();
+        unused
+    }
+
+    /// Add a name to the innermost scope, shadowing anything outside it with the same name
+    pub fn declare(&mut self, name: &str, type_: Type, declared_at: Option<&SourcePosition>) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(
+                name.to_string(),
+                Binding {
+                    type_,
+                    declared_at: declared_at.cloned(),
+                    parameter_of: None,
+                    read: false,
+                },
+            );
+        }
+    }
+
+    /// Like `declare`, but remembers that the name is a parameter of the function at `function_pos`
+    pub fn declare_parameter(&mut self, name: &str, type_: Type, function_pos: &SourcePosition) {
+        self.declare(name, type_, None);
+        if let Some(binding) = self.scopes.last_mut().and_then(|scope| scope.get_mut(name)) {
+            binding.parameter_of = Some(function_pos.clone());
+        }
+    }
+
+    /// Find the innermost binding with this name
+    pub fn lookup(&self, name: &str) -> Option<&Binding> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    pub fn is_declared(&self, name: &str) -> bool {
+        self.lookup(name).is_some()
+    }
+
+    /// Mark the innermost binding with this name as read, returns false if there isn't one
+    pub fn mark_read(&mut self, name: &str) -> bool {
+        match self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name))
+        {
+            Some(binding) => {
+                binding.read = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Locals in the innermost scope that nothing has read, in the order they were declared
+    ///
+    /// Names starting with `_` are deliberately unused and implicit bindings weren't written by the user, so both are skipped
+    pub fn unused(&self) -> impl Iterator<Item = (&str, &Binding)> {
+        let mut unused = self
+            .scopes
+            .last()
+            .into_iter()
+            .flat_map(|scope| scope.iter())
+            .filter(|(name, binding)| {
+                !binding.read && !name.starts_with('_') && binding.declared_at.is_some()
+            })
+            .map(|(name, binding)| (name.as_str(), binding))
+            .collect::<Vec<(&str, &Binding)>>();
+        unused.sort_by_key(|(_, binding)| {
+            binding
+                .declared_at
+                .as_ref()
+                .map(|pos| (pos.line, pos.column))
+        });
+        unused.into_iter()
+    }
+
+    /// Everything visible right now, sorted so suggestions are deterministic
+    pub fn visible_names(&self) -> Vec<&str> {
+        let mut names = self
+            .scopes
+            .iter()
+            .flat_map(|scope| scope.keys().map(|name| name.as_str()))
+            .collect::<Vec<&str>>();
+        names.sort();
+        names.dedup();
+        names
+    }
+}
+
+/// A check that runs with a `SymbolTable` kept up to date by `walk_function`
+pub trait ScopeVisitor {
+    /// Called for each contract, an `Out` contract can also see `result`
+    fn visit_contract(&mut self, _contract: &FunctionContract, _symbols: &mut SymbolTable) {}
+
+    /// Called before the statement's own declarations take effect, nested blocks are walked afterwards
+    fn visit_statement(&mut self, _statement: &Statement, _symbols: &mut SymbolTable) {}
+
+    /// Called with whatever a scope declared but never read, just as it closes
+    fn scope_closed(&mut self, _unused: Vec<(String, Binding)>) {}
+}
+
+/// Walk a function's contracts and statements in order, opening and closing scopes as blocks start and end
+pub fn walk_function<V: ScopeVisitor>(f: &Function, visitor: &mut V) {
+    let mut symbols = SymbolTable::for_function(f);
+    for contract in f.contracts.iter() {
+        match contract.type_ {
+            ContractType::Input => visitor.visit_contract(contract, &mut symbols),
+            ContractType::Output => {
+                symbols.push_scope();
+                symbols.declare("result", f.returns.clone(), None);
+                visitor.visit_contract(contract, &mut symbols);
+                let unused = symbols.pop_scope();
+                visitor.scope_closed(unused);
+            }
+        }
+    }
+    // Locals go in their own scope so that they're reported when the body ends
+    walk_block(&f.statements, &mut symbols, visitor);
+}
+
+/// Walk a block in a child scope, anything it declares is gone once the block ends
+fn walk_block<V: ScopeVisitor>(
+    statements: &[Statement],
+    symbols: &mut SymbolTable,
+    visitor: &mut V,
+) {
+    symbols.push_scope();
+    walk_statements(statements, symbols, visitor);
+    let unused = symbols.pop_scope();
+    visitor.scope_closed(unused);
+}
+
+fn walk_statements<V: ScopeVisitor>(
+    statements: &[Statement],
+    symbols: &mut SymbolTable,
+    visitor: &mut V,
+) {
+    for statement in statements.iter() {
+        visitor.visit_statement(statement, symbols);
+        match statement {
+            Statement::VariableDeclaration {
+                name, type_, pos, ..
+            } => symbols.declare(name, type_.clone(), Some(pos)),
+            Statement::Conditional { branches, .. } | Statement::Match { branches, .. } => {
+                for branch in branches.iter() {
+                    walk_block(&branch.computations, symbols, visitor);
+                }
+            }
+            // A `while let` binding only exists inside the body
+            Statement::WhileLoop { binding, body, .. } => {
+                symbols.push_scope();
+                if let Some(Pattern::Variant {
+                    binding: Some(name),
+                    ..
+                }) = binding
+                {
+                    symbols.declare(name, Type::Auto, None);
+                }
+                walk_statements(body, symbols, visitor);
+                let unused = symbols.pop_scope();
+                visitor.scope_closed(unused);
+            }
+            Statement::VariableMutation { .. }
+            | Statement::FunctionCall { .. }
+            | Statement::Return { .. }
+            | Statement::Assert { .. } => {}
+        }
+    }
+}
+
+/// Call `visit` on every variable an expression reads
+pub fn variables_in<F>(expr: &Expr, visit: &mut F)
+where
+    F: FnMut(&str),
+{
+    match expr {
+        Expr::Variable(name) => visit(name),
+        Expr::IntegerLiteral(_)
+        | Expr::FloatLiteral(_)
+        | Expr::StringLiteral(_)
+        | Expr::EnumVariant { .. } => {}
+        Expr::PropertyAccess { object, .. } => variables_in(object, visit),
+        Expr::FunctionCall { arguments, .. } => {
+            for arg in arguments.iter() {
+                variables_in(arg, visit);
+            }
+        }
+        Expr::MethodCall {
+            object, arguments, ..
+        } => {
+            variables_in(object, visit);
+            for arg in arguments.iter() {
+                variables_in(arg, visit);
+            }
+        }
+        Expr::BinaryOp { left, right, .. } => {
+            variables_in(left, visit);
+            variables_in(right, visit);
+        }
+        Expr::UnaryOp { operand, .. } => variables_in(operand, visit),
+        Expr::IndexAccess { object, index } => {
+            variables_in(object, visit);
+            variables_in(index, visit);
+        }
+        Expr::IfExpr { cond, then, els } => {
+            variables_in(cond, visit);
+            variables_in(then, visit);
+            variables_in(els, visit);
+        }
+    }
+}
+
+// -------------------- Unit Tests --------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::{ASTNode, Parser};
+
+    fn pos(line: usize) -> SourcePosition {
+        SourcePosition {
+            line,
+            column: 0,
+            filename: "test.iona".to_string(),
+        }
+    }
+
+    fn parse_function(program: &str) -> Function {
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(program);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+        match out.output.unwrap().remove(0) {
+            ASTNode::FunctionDeclaration(f) => f,
+            other => panic!("expected a function, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn nested_scopes() {
+        let mut symbols = SymbolTable::new();
+        symbols.push_scope();
+        symbols.declare("outer", Type::Integer, Some(&pos(1)));
+        symbols.push_scope();
+        symbols.declare("inner", Type::Float, Some(&pos(2)));
+        assert!(symbols.is_declared("outer"));
+        assert!(symbols.is_declared("inner"));
+        assert_eq!(symbols.visible_names(), vec!["inner", "outer"]);
+        symbols.pop_scope();
+        assert!(symbols.is_declared("outer"));
+        assert!(!symbols.is_declared("inner"));
+    }
+
+    #[test]
+    fn shadowing_finds_innermost() {
+        let mut symbols = SymbolTable::new();
+        symbols.push_scope();
+        symbols.declare("x", Type::Integer, Some(&pos(1)));
+        symbols.push_scope();
+        symbols.declare("x", Type::String, Some(&pos(2)));
+        assert_eq!(symbols.lookup("x").unwrap().type_, Type::String);
+        // Reading the shadow leaves the outer `x` unread
+        assert!(symbols.mark_read("x"));
+        assert!(symbols.pop_scope().is_empty());
+        assert_eq!(symbols.lookup("x").unwrap().type_, Type::Integer);
+        let unused = symbols.pop_scope();
+        assert_eq!(unused.len(), 1);
+        assert_eq!(unused[0].1.declared_at, Some(pos(1)));
+        assert!(!symbols.mark_read("x"));
+    }
+
+    #[test]
+    fn unused_entries_in_declaration_order() {
+        let mut symbols = SymbolTable::new();
+        symbols.push_scope();
+        symbols.declare("param", Type::Integer, None);
+        symbols.declare("b", Type::Integer, Some(&pos(3)));
+        symbols.declare("_ignored", Type::Integer, Some(&pos(4)));
+        symbols.declare("a", Type::Integer, Some(&pos(2)));
+        symbols.declare("used", Type::Integer, Some(&pos(1)));
+        symbols.mark_read("used");
+        let unused = symbols
+            .unused()
+            .map(|(name, _)| name)
+            .collect::<Vec<&str>>();
+        assert_eq!(unused, vec!["a", "b"]);
+    }
+
+    /// Records what was visible at each return, and what went unused
+    #[derive(Default)]
+    struct Recorder {
+        visible_at_return: Vec<Vec<String>>,
+        unused: Vec<String>,
+    }
+
+    impl ScopeVisitor for Recorder {
+        fn visit_statement(&mut self, statement: &Statement, symbols: &mut SymbolTable) {
+            if let Statement::Return { value, .. } = statement {
+                variables_in(value, &mut |name| {
+                    symbols.mark_read(name);
+                });
+                self.visible_at_return.push(
+                    symbols
+                        .visible_names()
+                        .into_iter()
+                        .map(String::from)
+                        .collect(),
+                );
+            }
+        }
+
+        fn scope_closed(&mut self, unused: Vec<(String, Binding)>) {
+            self.unused.extend(unused.into_iter().map(|(name, _)| name));
+        }
+    }
+
+    #[test]
+    fn walk_function_scopes() {
+        let f = parse_function(
+            r#"
+fn main(argc: Int) -> Int {
+    let total: Int = argc;
+    if argc > 0 {
+        let inner: Int = 1;
+        return argc;
+    }
+    return total;
+}
+"#,
+        );
+        let mut recorder = Recorder::default();
+        walk_function(&f, &mut recorder);
+        assert_eq!(
+            recorder.visible_at_return,
+            vec![vec!["argc", "inner", "total"], vec!["argc", "total"]]
+        );
+        assert_eq!(recorder.unused, vec!["inner"]);
+    }
+}
@@ -0,0 +1,1731 @@
+//! Infer the types of expressions and check them against declarations
+//!
+//! This is deliberately conservative: if we can't work out the type of an expression (method calls, struct fields, etc.) we don't report anything about it
+
+use std::collections::HashMap;
+
+use crate::aggregation::{FunctionSig, FunctionTable, ParsingTables, TypeTable};
+use crate::diagnostics::{did_you_mean, Diagnostic, DiagnosticKind};
+use crate::expression_parser::{BinaryOperator, Expr, UnaryOperator};
+use crate::lexer::SourcePosition;
+use crate::parser::{ASTNode, Branch, ContractType, Enum, Function, Pattern, Statement, Type};
+
+/// The types of everything visible at a given point in a function body
+type Scope = HashMap<String, Type>;
+
+/// Check every function (including struct methods) in a module
+pub fn check_program(
+    ast: &[ASTNode],
+    module_name: &str,
+    tables: &ParsingTables,
+) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for node in ast.iter() {
+        match node {
+            ASTNode::FunctionDeclaration(f) => {
+                diagnostics.extend(check_function(f, module_name, tables));
+            }
+            ASTNode::StructDeclaration(s) => {
+                for method in s.methods.iter() {
+                    diagnostics.extend(check_function(method, module_name, tables));
+                }
+            }
+            ASTNode::EnumDeclaration(e) => {
+                for method in e.methods.iter() {
+                    diagnostics.extend(check_function(method, module_name, tables));
+                }
+            }
+            ASTNode::ImportStatement(_) | ASTNode::ModuleMetadata(_) => {}
+        }
+    }
+    diagnostics
+}
+
+/// Check the statements of a single function, resolving the functions it calls from `module_name`
+pub fn check_function(f: &Function, module_name: &str, tables: &ParsingTables) -> Vec<Diagnostic> {
+    run_checker(f, module_name, tables).diagnostics
+}
+
+/// Replace the type of every `Auto` declaration with the type of its initializer, so later stages never see `Auto`
+///
+/// Declarations whose type can't be inferred are left alone, `check_program` reports them
+pub fn resolve_auto_types(ast: &mut [ASTNode], module_name: &str, tables: &ParsingTables) {
+    for node in ast.iter_mut() {
+        match node {
+            ASTNode::FunctionDeclaration(f) => resolve_function(f, module_name, tables),
+            ASTNode::StructDeclaration(s) => {
+                for method in s.methods.iter_mut() {
+                    resolve_function(method, module_name, tables);
+                }
+            }
+            ASTNode::EnumDeclaration(e) => {
+                for method in e.methods.iter_mut() {
+                    resolve_function(method, module_name, tables);
+                }
+            }
+            ASTNode::ImportStatement(_) | ASTNode::ModuleMetadata(_) => {}
+        }
+    }
+}
+
+/// Rewrite `Status.Alive` into an `Expr::EnumVariant` wherever `Status` names an enum and isn't shadowed by a local
+///
+/// This runs before type inference, so `let s: Auto = Status.Alive;` picks up the enum's type
+pub fn resolve_enum_variants(ast: &mut [ASTNode], types: &TypeTable) {
+    for node in ast.iter_mut() {
+        match node {
+            ASTNode::FunctionDeclaration(f) => resolve_variants_in_function(f, types),
+            ASTNode::StructDeclaration(s) => {
+                for method in s.methods.iter_mut() {
+                    resolve_variants_in_function(method, types);
+                }
+            }
+            ASTNode::EnumDeclaration(e) => {
+                for method in e.methods.iter_mut() {
+                    resolve_variants_in_function(method, types);
+                }
+            }
+            ASTNode::ImportStatement(_) | ASTNode::ModuleMetadata(_) => {}
+        }
+    }
+}
+
+fn resolve_variants_in_function(f: &mut Function, types: &TypeTable) {
+    let mut locals = f
+        .args
+        .iter()
+        .map(|arg| arg.name.clone())
+        .collect::<Vec<String>>();
+    // Postconditions can also see `result`
+    locals.push("result".to_string());
+    for contract in f.contracts.iter_mut() {
+        resolve_variants_in_expr(&mut contract.condition, &locals, types);
+    }
+    locals.pop();
+    resolve_variants_in_block(&mut f.statements, &mut locals, types);
+}
+
+/// `locals` holds every name declared so far, and anything a block declares is forgotten once it ends
+fn resolve_variants_in_block(
+    statements: &mut [Statement],
+    locals: &mut Vec<String>,
+    types: &TypeTable,
+) {
+    let outer = locals.len();
+    for statement in statements.iter_mut() {
+        match statement {
+            Statement::FunctionCall { call: expr, .. }
+            | Statement::VariableMutation { value: expr, .. }
+            | Statement::Return { value: expr, .. }
+            | Statement::Assert { cond: expr, .. } => resolve_variants_in_expr(expr, locals, types),
+            Statement::VariableDeclaration { name, value, .. } => {
+                if let Some(value) = value {
+                    resolve_variants_in_expr(value, locals, types);
+                }
+                locals.push(name.clone());
+            }
+            Statement::Conditional { branches, .. } => {
+                for branch in branches.iter_mut() {
+                    if let Some(condition) = &mut branch.condition {
+                        resolve_variants_in_expr(condition, locals, types);
+                    }
+                    resolve_variants_in_block(&mut branch.computations, locals, types);
+                }
+            }
+            // The arms are patterns, only their payload bindings matter here
+            Statement::Match {
+                scrutinee,
+                branches,
+                ..
+            } => {
+                resolve_variants_in_expr(scrutinee, locals, types);
+                for branch in branches.iter_mut() {
+                    let before = locals.len();
+                    if let Some(Expr::FunctionCall { arguments, .. }) = &branch.condition {
+                        for arg in arguments.iter() {
+                            if let Expr::Variable(binding) = arg {
+                                locals.push(binding.clone());
+                            }
+                        }
+                    }
+                    resolve_variants_in_block(&mut branch.computations, locals, types);
+                    locals.truncate(before);
+                }
+            }
+            Statement::WhileLoop {
+                binding,
+                condition,
+                body,
+                ..
+            } => {
+                resolve_variants_in_expr(condition, locals, types);
+                let before = locals.len();
+                if let Some(Pattern::Variant {
+                    binding: Some(name),
+                    ..
+                }) = binding
+                {
+                    locals.push(name.clone());
+                }
+                resolve_variants_in_block(body, locals, types);
+                locals.truncate(before);
+            }
+        }
+    }
+    locals.truncate(outer);
+}
+
+fn resolve_variants_in_expr(expr: &mut Expr, locals: &[String], types: &TypeTable) {
+    match expr {
+        Expr::PropertyAccess { object, property } => match object.as_ref() {
+            Expr::Variable(name) if !locals.contains(name) && types.is_enum(name) => {
+                *expr = Expr::EnumVariant {
+                    enum_name: name.clone(),
+                    variant: property.clone(),
+                };
+            }
+            _ => resolve_variants_in_expr(object, locals, types),
+        },
+        Expr::IntegerLiteral(_)
+        | Expr::FloatLiteral(_)
+        | Expr::StringLiteral(_)
+        | Expr::Variable(_)
+        | Expr::EnumVariant { .. } => {}
+        Expr::FunctionCall { arguments, .. } => {
+            for arg in arguments.iter_mut() {
+                resolve_variants_in_expr(arg, locals, types);
+            }
+        }
+        Expr::MethodCall {
+            object, arguments, ..
+        } => {
+            resolve_variants_in_expr(object, locals, types);
+            for arg in arguments.iter_mut() {
+                resolve_variants_in_expr(arg, locals, types);
+            }
+        }
+        Expr::BinaryOp { left, right, .. } => {
+            resolve_variants_in_expr(left, locals, types);
+            resolve_variants_in_expr(right, locals, types);
+        }
+        Expr::UnaryOp { operand, .. } => resolve_variants_in_expr(operand, locals, types),
+        Expr::IndexAccess { object, index } => {
+            resolve_variants_in_expr(object, locals, types);
+            resolve_variants_in_expr(index, locals, types);
+        }
+        Expr::IfExpr { cond, then, els } => {
+            resolve_variants_in_expr(cond, locals, types);
+            resolve_variants_in_expr(then, locals, types);
+            resolve_variants_in_expr(els, locals, types);
+        }
+    }
+}
+
+fn resolve_function(f: &mut Function, module_name: &str, tables: &ParsingTables) {
+    let inferred = run_checker(f, module_name, tables).inferred;
+    substitute_auto(&mut f.statements, &inferred);
+}
+
+/// Rewrite `Auto` declarations using the types inferred for them, matched up by position
+fn substitute_auto(statements: &mut [Statement], inferred: &[(SourcePosition, Type)]) {
+    for statement in statements.iter_mut() {
+        match statement {
+            Statement::VariableDeclaration { type_, pos, .. } if *type_ == Type::Auto => {
+                if let Some((_, found)) = inferred.iter().find(|(at, _)| at == pos) {
+                    *type_ = found.clone();
+                }
+            }
+            Statement::Conditional { branches, .. } | Statement::Match { branches, .. } => {
+                for branch in branches.iter_mut() {
+                    substitute_auto(&mut branch.computations, inferred);
+                }
+            }
+            Statement::WhileLoop { body, .. } => substitute_auto(body, inferred),
+            _ => {}
+        }
+    }
+}
+
+fn run_checker<'a>(
+    f: &'a Function,
+    module_name: &'a str,
+    tables: &'a ParsingTables,
+) -> Checker<'a> {
+    let mut checker = Checker {
+        function: f,
+        module: module_name,
+        functions: &tables.functions,
+        types: &tables.types,
+        diagnostics: Vec::new(),
+        inferred: Vec::new(),
+    };
+    let mut scope: Scope = f
+        .args
+        .iter()
+        .map(|arg| (arg.name.clone(), arg.field_type.clone()))
+        .collect();
+    checker.check_contracts(&scope);
+    checker.check_block(&f.statements, &mut scope);
+    checker
+}
+
+/// Can a value of type `found` be stored somewhere that expects `expected`?
+///
+/// Generics accept anything until we monomorphize them
+fn is_assignable(expected: &Type, found: &Type) -> bool {
+    match (expected, found) {
+        (Type::Auto, _) | (_, Type::Auto) => true,
+        (Type::Optional(expected), Type::Optional(found)) => is_assignable(expected, found),
+        (Type::Generic(_), _) | (_, Type::Generic(_)) => true,
+        _ => expected == found,
+    }
+}
+
+/// The type of a value that's stored somewhere expecting `expected`
+///
+/// Integer literals narrow into `Size` and `Byte`, other `Int`s have to be converted explicitly
+fn narrow_literal(expected: &Type, value: &Expr, found: Type) -> Type {
+    match (expected, value, &found) {
+        (Type::Size | Type::Byte, Expr::IntegerLiteral(_), Type::Integer) => expected.clone(),
+        _ => found,
+    }
+}
+
+/// Does a type still have a part that hasn't been worked out, like the `Auto` in the type of `none`?
+fn contains_auto(type_: &Type) -> bool {
+    match type_ {
+        Type::Auto => true,
+        Type::Array(inner) | Type::Optional(inner) | Type::Shared(inner) | Type::Map(inner) => {
+            contains_auto(inner)
+        }
+        Type::Tuple(members) => members.iter().any(contains_auto),
+        _ => false,
+    }
+}
+
+/// A signature as it would be written in Iona, for error messages
+fn describe_signature(sig: &FunctionSig) -> String {
+    let args = sig
+        .args
+        .iter()
+        .map(|arg| format!("{}: {}", arg.name, arg.field_type))
+        .collect::<Vec<String>>();
+    format!("fn {}({}) -> {}", sig.name, args.join(", "), sig.returns)
+}
+
+fn count_arguments(count: usize) -> String {
+    match count {
+        1 => "1 argument".to_string(),
+        n => format!("{} arguments", n),
+    }
+}
+
+struct Checker<'a> {
+    function: &'a Function,
+    /// The module the function belongs to, which is where the functions it calls are resolved from
+    module: &'a str,
+    functions: &'a FunctionTable,
+    types: &'a TypeTable,
+    diagnostics: Vec<Diagnostic>,
+    /// The type each `Auto` declaration resolved to, by the position of the declaration
+    inferred: Vec<(SourcePosition, Type)>,
+}
+
+impl<'a> Checker<'a> {
+    fn error(&mut self, kind: DiagnosticKind, message: &str, pos: &SourcePosition) {
+        self.error_with_notes(kind, message, pos, &[]);
+    }
+
+    /// An error with extra explanation under the caret, see `Diagnostic::with_note`
+    fn error_with_notes(
+        &mut self,
+        kind: DiagnosticKind,
+        message: &str,
+        pos: &SourcePosition,
+        notes: &[String],
+    ) {
+        let diagnostic = notes.iter().fold(
+            Diagnostic::new_error_simple(kind, message, pos),
+            |diagnostic, note| diagnostic.with_note(note),
+        );
+        self.diagnostics.push(diagnostic);
+    }
+
+    /// Each block gets its own scope, so that variables declared in one branch don't leak into the next
+    fn check_block(&mut self, statements: &[Statement], scope: &mut Scope) {
+        for statement in statements.iter() {
+            self.check_statement(statement, scope);
+        }
+    }
+
+    fn check_statement(&mut self, statement: &Statement, scope: &mut Scope) {
+        match statement {
+            Statement::FunctionCall { call, pos } => {
+                self.infer_expr_type(call, scope, pos);
+            }
+            Statement::VariableDeclaration {
+                name,
+                type_,
+                value,
+                pos,
+            } => {
+                let inferred = value.as_ref().and_then(|value| {
+                    self.infer_expr_type(value, scope, pos)
+                        .map(|found| narrow_literal(type_, value, found))
+                });
+                let resolved = match (type_, inferred) {
+                    // `Auto` adopts whatever the initializer is, as long as all of it is known (`none` alone could be any optional)
+                    (Type::Auto, Some(t)) if !contains_auto(&t) => {
+                        self.inferred.push((pos.clone(), t.clone()));
+                        t
+                    }
+                    (Type::Auto, _) => {
+                        self.error(
+                            DiagnosticKind::CannotInferType,
+                            &format!(
+                                "cannot infer the type of `{}`, give it an explicit type instead of Auto",
+                                name
+                            ),
+                            pos,
+                        );
+                        Type::Auto
+                    }
+                    (declared, Some(found)) => {
+                        if !is_assignable(declared, &found) {
+                            self.error(
+                                DiagnosticKind::MismatchedTypes,
+                                &format!(
+                                    "mismatched types: `{}` is declared as {} but its initial value is {}",
+                                    name, declared, found
+                                ),
+                                pos,
+                            );
+                        }
+                        declared.clone()
+                    }
+                    (declared, None) => declared.clone(),
+                };
+                scope.insert(name.clone(), resolved);
+            }
+            Statement::VariableMutation { name, value, pos } => {
+                let inferred = self.infer_expr_type(value, scope, pos);
+                if let (Some(declared), Some(found)) = (scope.get(name).cloned(), inferred) {
+                    let found = narrow_literal(&declared, value, found);
+                    if !is_assignable(&declared, &found) {
+                        self.error(
+                            DiagnosticKind::MismatchedTypes,
+                            &format!(
+                                "mismatched types: cannot assign {} to `{}`, which has type {}",
+                                found, name, declared
+                            ),
+                            pos,
+                        );
+                    }
+                }
+            }
+            Statement::Conditional { branches, pos } => {
+                for branch in branches.iter() {
+                    if let Some(condition) = &branch.condition {
+                        self.check_condition(condition, scope, pos);
+                    }
+                    let mut branch_scope = scope.clone();
+                    self.check_block(&branch.computations, &mut branch_scope);
+                }
+            }
+            Statement::Match {
+                scrutinee,
+                branches,
+                pos,
+            } => {
+                // Patterns are compared against the scrutinee, they aren't conditions themselves
+                let scrutinee_type = self.infer_expr_type(scrutinee, scope, pos);
+                if let Some(enum_) = self.enum_of(&scrutinee_type) {
+                    for branch in branches.iter() {
+                        match &branch.condition {
+                            Some(Expr::Variable(variant)) => {
+                                self.check_variant(enum_, variant, None, &branch.pos)
+                            }
+                            Some(Expr::FunctionCall { name, arguments }) => {
+                                self.check_variant(enum_, name, Some(arguments.len()), &branch.pos)
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                if let Some(Type::Optional(_)) = scrutinee_type {
+                    for branch in branches.iter() {
+                        match &branch.condition {
+                            Some(Expr::Variable(name)) => {
+                                self.check_optional_pattern(name, false, &branch.pos)
+                            }
+                            Some(Expr::FunctionCall { name, .. }) => {
+                                self.check_optional_pattern(name, true, &branch.pos)
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                self.check_exhaustive(scrutinee_type, branches, pos);
+                for branch in branches.iter() {
+                    let mut branch_scope = scope.clone();
+                    self.check_block(&branch.computations, &mut branch_scope);
+                }
+            }
+            Statement::WhileLoop {
+                binding,
+                condition,
+                body,
+                pos,
+            } => {
+                let mut body_scope = scope.clone();
+                match binding {
+                    // The payload's type isn't known until patterns are resolved against enums
+                    Some(Pattern::Variant { name, binding }) => {
+                        let condition_type = self.infer_expr_type(condition, scope, pos);
+                        if let Some(enum_) = self.enum_of(&condition_type) {
+                            self.check_variant(enum_, name, binding.as_ref().map(|_| 1), pos);
+                        }
+                        let payload = match &condition_type {
+                            Some(Type::Optional(inner)) => {
+                                self.check_optional_pattern(name, binding.is_some(), pos);
+                                inner.as_ref().clone()
+                            }
+                            _ => Type::Auto,
+                        };
+                        if let Some(binding) = binding {
+                            body_scope.insert(binding.clone(), payload);
+                        }
+                    }
+                    None => self.check_condition(condition, scope, pos),
+                }
+                self.check_block(body, &mut body_scope);
+            }
+            Statement::Return { value, pos } => {
+                if let Some(found) = self.infer_expr_type(value, scope, pos) {
+                    let expected = &self.function.returns;
+                    let found = narrow_literal(expected, value, found);
+                    if !is_assignable(expected, &found) {
+                        self.error(
+                            DiagnosticKind::MismatchedTypes,
+                            &format!(
+                                "wrong return type: `{}` returns {}, but this returns {}",
+                                self.function.name, expected, found
+                            ),
+                            pos,
+                        );
+                    }
+                }
+            }
+            Statement::Assert { cond, pos, .. } => self.check_condition(cond, scope, pos),
+        }
+    }
+
+    fn enum_of(&self, type_: &Option<Type>) -> Option<&'a Enum> {
+        match type_ {
+            Some(Type::Custom(name)) => self.types.get_enum(name),
+            _ => None,
+        }
+    }
+
+    /// Make sure a pattern names a variant of the enum, with a payload binding only if the variant has a payload
+    ///
+    /// `payload` is the number of names the pattern binds, or `None` for a bare variant. A bare variant can always match, it just ignores any payload
+    fn check_variant(
+        &mut self,
+        enum_: &Enum,
+        variant: &str,
+        payload: Option<usize>,
+        pos: &SourcePosition,
+    ) {
+        let Some(field) = enum_.fields.iter().find(|f| f.name == variant) else {
+            let message = format!("enum `{}` has no variant `{}`", enum_.name, variant);
+            let message = match did_you_mean(variant, enum_.fields.iter().map(|f| f.name.as_str()))
+            {
+                Some(suggestion) => format!("{} (did you mean `{}`?)", message, suggestion),
+                None => message,
+            };
+            self.error(DiagnosticKind::UndefinedVariant, &message, pos);
+            return;
+        };
+        match (payload, &field.field_type) {
+            (None, _) => {}
+            (Some(_), Type::Void) => self.error(
+                DiagnosticKind::VariantPayload,
+                &format!(
+                    "variant `{}` of enum `{}` doesn't carry a payload, match it as `{}`",
+                    variant, enum_.name, variant
+                ),
+                pos,
+            ),
+            (Some(1), _) => {}
+            (Some(count), payload_type) => self.error(
+                DiagnosticKind::VariantPayload,
+                &format!(
+                    "variant `{}` of enum `{}` carries one {} payload, but this pattern binds {}",
+                    variant, enum_.name, payload_type, count
+                ),
+                pos,
+            ),
+        }
+    }
+
+    /// Optionals aren't enums, but they can be matched as if they had a `Some` variant carrying the value and an empty `None`
+    fn check_optional_pattern(&mut self, variant: &str, payload: bool, pos: &SourcePosition) {
+        match (variant, payload) {
+            ("Some", _) | ("None", false) => {}
+            ("None", true) => self.error(
+                DiagnosticKind::VariantPayload,
+                "`None` doesn't carry a payload, match it as `None`",
+                pos,
+            ),
+            _ => self.error(
+                DiagnosticKind::UndefinedVariant,
+                &format!("an optional is either `Some` or `None`, not `{}`", variant),
+                pos,
+            ),
+        }
+    }
+
+    /// Make sure a match covers every value of its scrutinee
+    ///
+    /// Enums need an arm for each variant (or a `_`), anything else always needs a `_`. Arms after a `_` are reported by `analysis::check_match_arms`
+    fn check_exhaustive(
+        &mut self,
+        scrutinee_type: Option<Type>,
+        branches: &[Branch],
+        pos: &SourcePosition,
+    ) {
+        let has_catch_all = branches.iter().any(|b| b.condition.is_none());
+        let enum_ = self.enum_of(&scrutinee_type);
+        match (enum_, scrutinee_type) {
+            (Some(enum_), _) => {
+                let mut covered: Vec<&str> = Vec::new();
+                for branch in branches.iter() {
+                    let variant = match &branch.condition {
+                        Some(Expr::Variable(variant))
+                        | Some(Expr::FunctionCall { name: variant, .. }) => variant,
+                        Some(_) => continue,
+                        None => break,
+                    };
+                    if covered.contains(&variant.as_str()) {
+                        self.diagnostics.push(Diagnostic::new_warning_simple(
+                            DiagnosticKind::UnreachableCode,
+                            &format!(
+                                "unreachable match arm, `{}` is already matched above",
+                                variant
+                            ),
+                            &branch.pos,
+                        ));
+                    } else {
+                        covered.push(variant);
+                    }
+                }
+                let missing = enum_
+                    .fields
+                    .iter()
+                    .filter(|variant| !covered.contains(&variant.name.as_str()))
+                    .map(|variant| format!("`{}`", variant.name))
+                    .collect::<Vec<String>>();
+                if !has_catch_all && !missing.is_empty() {
+                    self.error(
+                        DiagnosticKind::NonExhaustiveMatch,
+                        &format!(
+                            "non-exhaustive match: {} of enum `{}` not covered",
+                            missing.join(", "),
+                            enum_.name
+                        ),
+                        pos,
+                    );
+                }
+            }
+            // `Some` and `None` between them cover an optional
+            (None, Some(Type::Optional(_)))
+                if ["Some", "None"].iter().all(|variant| {
+                    branches.iter().any(|b| match &b.condition {
+                        Some(Expr::Variable(name)) | Some(Expr::FunctionCall { name, .. }) => {
+                            name == variant
+                        }
+                        _ => false,
+                    })
+                }) => {}
+            (None, Some(found)) if !has_catch_all => self.error(
+                DiagnosticKind::NonExhaustiveMatch,
+                &format!(
+                    "non-exhaustive match: matching on {} needs a catch all `_` arm",
+                    found
+                ),
+                pos,
+            ),
+            _ => {}
+        }
+    }
+
+    /// Every contract condition has to be a Boolean, where `Out` contracts can also see the return value as `result`
+    fn check_contracts(&mut self, scope: &Scope) {
+        for contract in self.function.contracts.iter() {
+            let (keyword, scope) = match contract.type_ {
+                ContractType::Input => ("In", scope.clone()),
+                ContractType::Output => {
+                    let mut scope = scope.clone();
+                    scope.insert("result".to_string(), self.function.returns.clone());
+                    ("Out", scope)
+                }
+            };
+            let found = self.infer_expr_type(&contract.condition, &scope, &contract.pos);
+            if let Some(found) = found.filter(|found| *found != Type::Boolean) {
+                self.error(
+                    DiagnosticKind::MismatchedTypes,
+                    &format!(
+                        "mismatched types: an `{}` contract must be a Bool, but found {}",
+                        keyword, found
+                    ),
+                    &contract.pos,
+                );
+            }
+        }
+    }
+
+    fn check_condition(&mut self, condition: &Expr, scope: &Scope, pos: &SourcePosition) {
+        if let Some(found) = self.infer_expr_type(condition, scope, pos) {
+            if found != Type::Boolean {
+                self.error(
+                    DiagnosticKind::MismatchedTypes,
+                    &format!(
+                        "mismatched types: a condition must be a Bool, but found {}",
+                        found
+                    ),
+                    pos,
+                );
+            }
+        }
+    }
+
+    /// Make sure a call passes the right number of arguments, each with a type the parameter accepts
+    fn check_arguments(
+        &mut self,
+        sig: &FunctionSig,
+        arguments: &[Expr],
+        found: &[Option<Type>],
+        pos: &SourcePosition,
+    ) {
+        if found.len() != sig.args.len() {
+            let note = if found.len() < sig.args.len() {
+                format!(
+                    "nothing was passed for {}",
+                    sig.args[found.len()..]
+                        .iter()
+                        .map(|arg| format!("`{}: {}`", arg.name, arg.field_type))
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                )
+            } else {
+                format!(
+                    "the last {} ha{} no parameter to go to",
+                    count_arguments(found.len() - sig.args.len()),
+                    if found.len() - sig.args.len() == 1 {
+                        "s"
+                    } else {
+                        "ve"
+                    }
+                )
+            };
+            self.error_with_notes(
+                DiagnosticKind::WrongArgumentCount,
+                &format!(
+                    "wrong number of arguments: `{}` takes {} but was given {} (expected `{}`)",
+                    sig.name,
+                    count_arguments(sig.args.len()),
+                    found.len(),
+                    describe_signature(sig)
+                ),
+                pos,
+                &[note],
+            );
+            return;
+        }
+        for (i, ((param, found), argument)) in
+            sig.args.iter().zip(found.iter()).zip(arguments).enumerate()
+        {
+            if let Some(found) = found {
+                let found = &narrow_literal(&param.field_type, argument, found.clone());
+                if !is_assignable(&param.field_type, found) {
+                    let note = format!(
+                        "because parameter `{}` is declared as {} at {}:{}:{}",
+                        param.name,
+                        param.field_type,
+                        param.pos.filename,
+                        param.pos.line,
+                        param.pos.column
+                    );
+                    self.error_with_notes(
+                        DiagnosticKind::MismatchedTypes,
+                        &format!(
+                            "mismatched types: argument {} of `{}` should be {}, but found {} (expected `{}`)",
+                            i + 1,
+                            sig.name,
+                            param.field_type,
+                            found,
+                            describe_signature(sig)
+                        ),
+                        pos,
+                        &[note],
+                    );
+                }
+            }
+        }
+    }
+
+    /// Work out the type of an expression, reporting any type errors inside of it
+    ///
+    /// Returns `None` if the type can't be determined yet
+    fn infer_expr_type(
+        &mut self,
+        expr: &Expr,
+        scope: &Scope,
+        pos: &SourcePosition,
+    ) -> Option<Type> {
+        match expr {
+            Expr::IntegerLiteral(_) => Some(Type::Integer),
+            Expr::FloatLiteral(_) => Some(Type::Float),
+            Expr::StringLiteral(_) => Some(Type::String),
+            Expr::Variable(name) => match scope.get(name) {
+                Some(Type::Auto) => None,
+                Some(t) => Some(t.clone()),
+                // The lexer doesn't have boolean literals, so they show up as variables
+                None if name == "true" || name == "false" => Some(Type::Boolean),
+                // Nothing says what kind of optional `none` is, so it fits any of them
+                None if name == "none" => Some(Type::Optional(Box::new(Type::Auto))),
+                None => None,
+            },
+            Expr::FunctionCall { name, arguments } => {
+                let found = arguments
+                    .iter()
+                    .map(|arg| self.infer_expr_type(arg, scope, pos))
+                    .collect::<Vec<Option<Type>>>();
+                if name == "some" && found.len() == 1 {
+                    let inner = found[0].clone().unwrap_or(Type::Auto);
+                    return Some(Type::Optional(Box::new(inner)));
+                }
+                let sig = self.functions.resolve(self.module, name)?;
+                self.check_arguments(sig, arguments, &found, pos);
+                Some(sig.returns.clone())
+            }
+            Expr::MethodCall {
+                object,
+                method,
+                arguments,
+            } => {
+                let object_type = self.infer_expr_type(object, scope, pos);
+                for arg in arguments.iter() {
+                    self.infer_expr_type(arg, scope, pos);
+                }
+                // Lengths are `Size`, like the indices they're compared against
+                match object_type {
+                    Some(Type::Array(_) | Type::FixedArray(..))
+                        if method == "len" && arguments.is_empty() =>
+                    {
+                        Some(Type::Size)
+                    }
+                    _ => None,
+                }
+            }
+            Expr::EnumVariant { enum_name, variant } => {
+                let enum_ = self.types.get_enum(enum_name)?;
+                self.check_variant(enum_, variant, None, pos);
+                Some(Type::Custom(enum_.name.clone()))
+            }
+            Expr::PropertyAccess { object, .. } => {
+                self.infer_expr_type(object, scope, pos);
+                None
+            }
+            Expr::IndexAccess { object, index } => {
+                let object_type = self.infer_expr_type(object, scope, pos);
+                if let Some(found) = self.infer_expr_type(index, scope, pos) {
+                    if found != Type::Integer && !is_assignable(&Type::Size, &found) {
+                        self.error(
+                            DiagnosticKind::MismatchedTypes,
+                            &format!(
+                                "mismatched types: arrays must be indexed with a Size (or an Int), but found {}",
+                                found
+                            ),
+                            pos,
+                        );
+                    }
+                }
+                match object_type {
+                    Some(Type::Array(inner)) | Some(Type::FixedArray(inner, _)) => Some(*inner),
+                    _ => None,
+                }
+            }
+            Expr::UnaryOp { operator, operand } => match operator {
+                UnaryOperator::Negate => self.infer_expr_type(operand, scope, pos),
+            },
+            Expr::IfExpr { cond, then, els } => {
+                self.check_condition(cond, scope, pos);
+                let then_type = self.infer_expr_type(then, scope, pos);
+                let els_type = self.infer_expr_type(els, scope, pos);
+                match (then_type, els_type) {
+                    (Some(then_type), Some(els_type)) => {
+                        if then_type != els_type {
+                            self.error(
+                                DiagnosticKind::MismatchedTypes,
+                                &format!(
+                                    "mismatched types: both branches of an `if` expression must have the same type, but found {} and {}",
+                                    then_type, els_type
+                                ),
+                                pos,
+                            );
+                        }
+                        Some(then_type)
+                    }
+                    (then_type, els_type) => then_type.or(els_type),
+                }
+            }
+            Expr::BinaryOp {
+                left,
+                operator,
+                right,
+            } => {
+                let left_type = self.infer_expr_type(left, scope, pos);
+                let right_type = self.infer_expr_type(right, scope, pos);
+                let (left_type, right_type) = match (left_type, right_type) {
+                    (Some(l), Some(r)) => (l, r),
+                    // Comparisons are always Booleans, even if we don't know what's being compared
+                    _ => {
+                        return match operator {
+                            BinaryOperator::LessThan
+                            | BinaryOperator::GreaterThan
+                            | BinaryOperator::And
+                            | BinaryOperator::Or => Some(Type::Boolean),
+                            _ => None,
+                        }
+                    }
+                };
+                match operator {
+                    BinaryOperator::And | BinaryOperator::Or => {
+                        if left_type != Type::Boolean || right_type != Type::Boolean {
+                            self.error(
+                                DiagnosticKind::MismatchedTypes,
+                                &format!(
+                                    "mismatched types: `{}` needs two Bools, but found {} and {}",
+                                    operator, left_type, right_type
+                                ),
+                                pos,
+                            );
+                        }
+                        Some(Type::Boolean)
+                    }
+                    _ => {
+                        // An integer literal takes on the type of the Size it's used with, like it does in a `let`
+                        let left_type = match (left.as_ref(), &right_type) {
+                            (Expr::IntegerLiteral(_), Type::Size) => Type::Size,
+                            _ => left_type,
+                        };
+                        let right_type = match (right.as_ref(), &left_type) {
+                            (Expr::IntegerLiteral(_), Type::Size) => Type::Size,
+                            _ => right_type,
+                        };
+                        if left_type != right_type {
+                            self.error(
+                                DiagnosticKind::MismatchedTypes,
+                                &format!(
+                                    "mismatched types: cannot apply `{}` to {} and {}",
+                                    operator, left_type, right_type
+                                ),
+                                pos,
+                            );
+                        }
+                        match operator {
+                            BinaryOperator::LessThan | BinaryOperator::GreaterThan => {
+                                Some(Type::Boolean)
+                            }
+                            _ => Some(left_type),
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// -------------------- Unit Tests --------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics::IssueLevel;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn check(program: &str) -> Vec<Diagnostic> {
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(program);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+        let mut ast = out.output.unwrap();
+        let mut tables = ParsingTables::new();
+        tables.update(&ast, "test.iona");
+        resolve_enum_variants(&mut ast, &tables.types);
+        check_program(&ast, "test.iona", &tables)
+    }
+
+    #[test]
+    fn accept_well_typed_program() {
+        const PROGRAM: &str = r#"
+fn double(a: Int) -> Int {
+    return a * 2;
+}
+
+fn main(argc: Int) -> Int {
+    let x: Int = double(argc);
+    let y: Auto = x + 1;
+    let name: String = "iona";
+    if y > 3 {
+        x = y;
+    }
+    return x;
+}
+"#;
+        let diagnostics = check(PROGRAM);
+        assert!(diagnostics.is_empty(), "{:#?}", diagnostics);
+    }
+
+    #[test]
+    fn reject_bad_initializer() {
+        let diagnostics = check(
+            r#"
+fn main(argc: Int) -> Int {
+    let x: Int = "hello";
+    return x;
+}
+"#,
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0]
+            .message()
+            .contains("`x` is declared as Int but its initial value is String"));
+        assert_eq!(diagnostics[0].position().line, 2);
+        assert_eq!(diagnostics[0].kind(), DiagnosticKind::MismatchedTypes);
+        assert_eq!(diagnostics[0].code(), "E0009");
+    }
+
+    const ADD: &str = r#"
+fn add(a: Int, b: Int) -> Int {
+    return a + b;
+}
+"#;
+
+    #[test]
+    fn correct_call_arguments() {
+        let diagnostics = check(&format!(
+            "{}{}",
+            ADD,
+            r#"
+fn main(argc: Int) -> Int {
+    return add(argc, 2);
+}
+"#
+        ));
+        assert!(diagnostics.is_empty(), "{:#?}", diagnostics);
+    }
+
+    #[test]
+    fn too_few_arguments() {
+        let diagnostics = check(&format!(
+            "{}{}",
+            ADD,
+            r#"
+fn main(argc: Int) -> Int {
+    return add(1);
+}
+"#
+        ));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message(),
+            "wrong number of arguments: `add` takes 2 arguments but was given 1 (expected `fn add(a: Int, b: Int) -> Int`)"
+        );
+        assert_eq!(diagnostics[0].kind(), DiagnosticKind::WrongArgumentCount);
+        assert_eq!(diagnostics[0].position().line, 6);
+        assert_eq!(diagnostics[0].notes(), ["nothing was passed for `b: Int`"]);
+    }
+
+    #[test]
+    fn too_many_arguments() {
+        let diagnostics = check(&format!(
+            "{}{}",
+            ADD,
+            r#"
+fn main(argc: Int) -> Int {
+    let x: Int = add(1, 2, argc);
+    return x;
+}
+"#
+        ));
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0]
+            .message()
+            .starts_with("wrong number of arguments: `add` takes 2 arguments but was given 3"));
+        assert_eq!(
+            diagnostics[0].notes(),
+            ["the last 1 argument has no parameter to go to"]
+        );
+    }
+
+    #[test]
+    fn calls_resolve_in_the_calling_module() {
+        let mut tables = ParsingTables::new();
+        let mut asts = Vec::new();
+        for (name, program) in [
+            (
+                "a",
+                "fn helper(x: Int, y: Int) -> Int {\n    return x;\n}\n",
+            ),
+            (
+                "main",
+                "import a with helper;\n\nfn helper(name: String) -> Int {\n    return 0;\n}\n\nfn main() -> Int {\n    return helper(\"x\");\n}\n",
+            ),
+            (
+                "b",
+                "import a with helper;\n\nfn main() -> Int {\n    return helper(1, 2);\n}\n",
+            ),
+        ] {
+            let mut lexer = Lexer::new(&format!("{}.iona", name));
+            lexer.lex(program);
+            let mut parser = Parser::new(lexer.token_stream);
+            let ast = parser.parse_all().output.unwrap();
+            tables.update(&ast, name);
+            asts.push((name, ast));
+        }
+        // `main` calls its own one-argument `helper`, `b` calls the two-argument one it imports
+        for (name, ast) in asts.iter() {
+            assert!(check_program(ast, name, &tables).is_empty());
+        }
+    }
+
+    #[test]
+    fn mismatched_argument_type() {
+        let diagnostics = check(&format!(
+            "{}{}",
+            ADD,
+            r#"
+fn main(argc: Int) -> Int {
+    return add(argc, "two");
+}
+"#
+        ));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message(),
+            "mismatched types: argument 2 of `add` should be Int, but found String (expected `fn add(a: Int, b: Int) -> Int`)"
+        );
+        assert_eq!(
+            diagnostics[0].notes(),
+            ["because parameter `b` is declared as Int at test.iona:1:15"]
+        );
+    }
+
+    #[test]
+    fn optionals_hold_their_inner_type() {
+        let diagnostics = check(
+            r#"
+fn main(argc: Int) -> Int {
+    let x: Int? = none;
+    let y: Int? = some(argc);
+    let z: Int? = some("three");
+    let w: Auto = none;
+    return argc;
+}
+"#,
+        );
+        assert_eq!(diagnostics.len(), 2, "{:#?}", diagnostics);
+        assert_eq!(diagnostics[0].kind(), DiagnosticKind::MismatchedTypes);
+        assert_eq!(diagnostics[0].position().line, 4);
+        assert_eq!(diagnostics[1].kind(), DiagnosticKind::CannotInferType);
+        assert_eq!(diagnostics[1].position().line, 5);
+    }
+
+    #[test]
+    fn boolean_contracts() {
+        let diagnostics = check(
+            r#"
+fn double(a: Int) -> Int {
+    @contracts {
+        In: (a > 0, "a must be positive")
+        Out: (result > a, "output must grow")
+    }
+    return a * 2;
+}
+"#,
+        );
+        assert!(diagnostics.is_empty(), "{:#?}", diagnostics);
+    }
+
+    #[test]
+    fn reject_non_boolean_contracts() {
+        let diagnostics = check(
+            r#"
+fn double(a: Int) -> Int {
+    @contracts {
+        In: (a + 1, "a must be positive")
+        Out: (result, "output must grow")
+    }
+    return a * 2;
+}
+"#,
+        );
+        assert_eq!(diagnostics.len(), 2, "{:#?}", diagnostics);
+        assert_eq!(
+            diagnostics[0].message(),
+            "mismatched types: an `In` contract must be a Bool, but found Int"
+        );
+        assert_eq!(diagnostics[0].position().line, 3);
+        assert_eq!(
+            diagnostics[1].message(),
+            "mismatched types: an `Out` contract must be a Bool, but found Int"
+        );
+    }
+
+    #[test]
+    fn reject_mismatched_operands() {
+        let diagnostics = check(
+            r#"
+fn main(argc: Int, ratio: Float) -> Int {
+    let x: Auto = argc + ratio;
+    return argc;
+}
+"#,
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0]
+            .message()
+            .contains("cannot apply `+` to Int and Float"));
+    }
+
+    #[test]
+    fn reject_wrong_return_type() {
+        let diagnostics = check(
+            r#"
+fn main(argc: Int) -> Int {
+    return "done";
+}
+"#,
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0]
+            .message()
+            .contains("`main` returns Int, but this returns String"));
+    }
+
+    #[test]
+    fn reject_bad_mutation() {
+        let diagnostics = check(
+            r#"
+fn main(argc: Int) -> Int {
+    let x: Int = 1;
+    x = 2.5;
+    return x;
+}
+"#,
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0]
+            .message()
+            .contains("cannot assign Float to `x`, which has type Int"));
+    }
+
+    #[test]
+    fn reject_non_boolean_condition() {
+        let diagnostics = check(
+            r#"
+fn main(argc: Int) -> Int {
+    if argc + 1 {
+        return 1;
+    }
+    return 0;
+}
+"#,
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0]
+            .message()
+            .contains("a condition must be a Bool, but found Int"));
+    }
+
+    #[test]
+    fn auto_adopts_inferred_type() {
+        let diagnostics = check(
+            r#"
+fn main(argc: Int) -> Int {
+    let x: Auto = "text";
+    return x;
+}
+"#,
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0]
+            .message()
+            .contains("`main` returns Int, but this returns String"));
+    }
+
+    fn resolve(program: &str) -> Vec<Statement> {
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(program);
+        let mut parser = Parser::new(lexer.token_stream);
+        let out = parser.parse_all();
+        assert!(out.diagnostics.is_empty(), "{:#?}", out.diagnostics);
+        let mut ast = out.output.unwrap();
+        let mut tables = ParsingTables::new();
+        tables.update(&ast, "test.iona");
+        resolve_auto_types(&mut ast, "test.iona", &tables);
+        match ast.pop() {
+            Some(ASTNode::FunctionDeclaration(f)) => f.statements,
+            other => panic!("expected a function, got {:?}", other),
+        }
+    }
+
+    fn declared_type(statement: &Statement) -> &Type {
+        match statement {
+            Statement::VariableDeclaration { type_, .. } => type_,
+            other => panic!("expected a declaration, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn resolve_auto_from_literal() {
+        let statements = resolve(
+            r#"
+fn main(argc: Int) -> Int {
+    let x: Auto = 5;
+    if argc > 0 {
+        let y: Auto = 2.5;
+    }
+    return x;
+}
+"#,
+        );
+        assert_eq!(declared_type(&statements[0]), &Type::Integer);
+        match &statements[1] {
+            Statement::Conditional { branches, .. } => {
+                assert_eq!(declared_type(&branches[0].computations[0]), &Type::Float);
+            }
+            other => panic!("expected a conditional, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn resolve_auto_from_function_return() {
+        let statements = resolve(
+            r#"
+fn name(id: Int) -> String {
+    return "iona";
+}
+
+fn main(argc: Int) -> Int {
+    let x: Auto = name(argc);
+    let y: Auto = x;
+    return argc;
+}
+"#,
+        );
+        assert_eq!(declared_type(&statements[0]), &Type::String);
+        assert_eq!(declared_type(&statements[1]), &Type::String);
+    }
+
+    #[test]
+    fn reject_uninferable_auto() {
+        let diagnostics = check(
+            r#"
+fn main(argc: Int) -> Int {
+    let x: Auto = mystery(argc);
+    return argc;
+}
+"#,
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message(),
+            "cannot infer the type of `x`, give it an explicit type instead of Auto"
+        );
+        assert_eq!(diagnostics[0].position().line, 2);
+    }
+
+    const STATUS: &str = r#"
+enum Status {
+    Alive,
+    Dead,
+    Unknown,
+
+    @metadata {
+        Is: Public;
+    }
+}
+"#;
+
+    #[test]
+    fn reject_missing_variant() {
+        let diagnostics = check(&format!(
+            "{}{}",
+            STATUS,
+            r#"
+fn describe(status: Status) -> Int {
+    match status {
+        Alive => 1,
+        Unknown => 2
+    }
+    return 0;
+}
+"#
+        ));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message(),
+            "non-exhaustive match: `Dead` of enum `Status` not covered"
+        );
+        assert_eq!(diagnostics[0].position().line, 12);
+    }
+
+    #[test]
+    fn misspelled_variant() {
+        let diagnostics = check(&format!(
+            "{}{}",
+            STATUS,
+            r#"
+fn describe(status: Status) -> Int {
+    match status {
+        Alvie => 1,
+        _ => 2
+    }
+    return 0;
+}
+"#
+        ));
+        assert_eq!(diagnostics.len(), 1, "{:#?}", diagnostics);
+        assert_eq!(
+            diagnostics[0].message(),
+            "enum `Status` has no variant `Alvie` (did you mean `Alive`?)"
+        );
+        assert_eq!(diagnostics[0].kind(), DiagnosticKind::UndefinedVariant);
+        // Reported at the arm
+        assert_eq!(diagnostics[0].position().line, 13);
+    }
+
+    #[test]
+    fn variant_expressions() {
+        let diagnostics = check(&format!(
+            "{}{}",
+            STATUS,
+            r#"
+fn main(argc: Int) -> Int {
+    let good: Status = Status.Alive;
+    let bad: Status = Status.Alvie;
+    return argc;
+}
+"#
+        ));
+        assert_eq!(diagnostics.len(), 1, "{:#?}", diagnostics);
+        assert_eq!(
+            diagnostics[0].message(),
+            "enum `Status` has no variant `Alvie` (did you mean `Alive`?)"
+        );
+        assert_eq!(diagnostics[0].position().line, 13);
+    }
+
+    #[test]
+    fn resolve_variant_paths() {
+        let mut lexer = Lexer::new("test.iona");
+        lexer.lex(&format!(
+            "{}{}",
+            STATUS,
+            r#"
+fn main(point: Point) -> Int {
+    let s: Status = Status.Alive;
+    let x: Int = point.x;
+    return x;
+}
+"#
+        ));
+        let mut parser = Parser::new(lexer.token_stream);
+        let mut ast = parser.parse_all().output.unwrap();
+        let mut tables = ParsingTables::new();
+        tables.update(&ast, "test.iona");
+        resolve_enum_variants(&mut ast, &tables.types);
+        let ASTNode::FunctionDeclaration(f) = &ast[1] else {
+            panic!("expected a function, found {:#?}", ast[1]);
+        };
+        let values = f
+            .statements
+            .iter()
+            .filter_map(|statement| match statement {
+                Statement::VariableDeclaration { value, .. } => value.clone(),
+                _ => None,
+            })
+            .collect::<Vec<Expr>>();
+        assert_eq!(
+            values,
+            vec![
+                Expr::EnumVariant {
+                    enum_name: "Status".to_string(),
+                    variant: "Alive".to_string(),
+                },
+                // Field access on a local is left alone
+                Expr::PropertyAccess {
+                    object: Box::new(Expr::Variable("point".to_string())),
+                    property: "x".to_string(),
+                },
+            ]
+        );
+    }
+
+    const PETS: &str = r#"
+enum Pets {
+    Dog,
+    Cat: Int,
+
+    @metadata {
+        Is: Public;
+    }
+}
+"#;
+
+    #[test]
+    fn wrong_payload_patterns() {
+        let diagnostics = check(&format!(
+            "{}{}",
+            PETS,
+            r#"
+fn describe(pet: Pets) -> Int {
+    match pet {
+        Dog(x) => 1,
+        Cat(a, b) => 2
+    }
+    return 0;
+}
+"#
+        ));
+        let messages = diagnostics
+            .iter()
+            .map(|d| d.message())
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            messages,
+            vec![
+                "variant `Dog` of enum `Pets` doesn't carry a payload, match it as `Dog`",
+                "variant `Cat` of enum `Pets` carries one Int payload, but this pattern binds 2"
+            ]
+        );
+    }
+
+    #[test]
+    fn payload_patterns() {
+        // A bare variant ignores its payload
+        let diagnostics = check(&format!(
+            "{}{}",
+            PETS,
+            r#"
+fn describe(pet: Pets) -> Int {
+    match pet {
+        Dog => 1,
+        Cat(lives) => 2
+    }
+    match pet {
+        Dog => 1,
+        Cat => 2
+    }
+    return 0;
+}
+"#
+        ));
+        assert!(diagnostics.is_empty(), "{:#?}", diagnostics);
+    }
+
+    #[test]
+    fn array_lengths_and_indices_are_sizes() {
+        let diagnostics = check(
+            r#"
+fn first(xs: Array<Int>) -> Int {
+    let n: Size = xs.len();
+    let empty: Bool = xs.len();
+    let i: Size = 0;
+    let count: Int = 3;
+    let j: Size = count;
+    let bad: Int = xs["0"];
+    return xs[i];
+}
+"#,
+        );
+        let messages = diagnostics
+            .iter()
+            .map(|d| d.message())
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            messages,
+            vec![
+                "mismatched types: `empty` is declared as Bool but its initial value is Size",
+                "mismatched types: `j` is declared as Size but its initial value is Int",
+                "mismatched types: arrays must be indexed with a Size (or an Int), but found String",
+            ]
+        );
+    }
+
+    #[test]
+    fn size_arithmetic_and_comparisons() {
+        let diagnostics = check(
+            r#"
+fn last(xs: Array<Int>, n: Size) -> Int {
+    if xs.len() > 0 {
+        return xs[xs.len() - 1];
+    }
+    let m: Size = n - 1;
+    let same: Bool = m < n;
+    let bad: Bool = n > 1.5;
+    return 0;
+}
+"#,
+        );
+        let messages = diagnostics
+            .iter()
+            .map(|d| d.message())
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            messages,
+            vec!["mismatched types: cannot apply `>` to Size and Float"]
+        );
+    }
+
+    #[test]
+    fn optional_patterns() {
+        let diagnostics = check(
+            r#"
+fn first(x: Int?, names: String?) -> Int {
+    match x {
+        Some => 1,
+        None => 0
+    }
+    while let Some(v) = x {
+        let doubled: Int = v * 2;
+    }
+    while let Some(name) = names {
+        let wrong: Int = name;
+    }
+    while let None(v) = x {
+    }
+    match x {
+        Some => 1,
+        Nothing => 0
+    }
+    return 0;
+}
+"#,
+        );
+        let messages = diagnostics
+            .iter()
+            .map(|d| d.message())
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            messages,
+            vec![
+                "mismatched types: `wrong` is declared as Int but its initial value is String",
+                "`None` doesn't carry a payload, match it as `None`",
+                "an optional is either `Some` or `None`, not `Nothing`",
+                "non-exhaustive match: matching on Int? needs a catch all `_` arm",
+            ]
+        );
+    }
+
+    #[test]
+    fn accept_exhaustive_match() {
+        let diagnostics = check(&format!(
+            "{}{}",
+            STATUS,
+            r#"
+fn describe(status: Status) -> Int {
+    match status {
+        Alive => 1,
+        Dead => 2,
+        Unknown => 3
+    }
+    match status {
+        Alive => 1,
+        _ => 2
+    }
+    return 0;
+}
+"#
+        ));
+        assert!(diagnostics.is_empty(), "{:#?}", diagnostics);
+    }
+
+    #[test]
+    fn warn_about_repeated_variant() {
+        let diagnostics = check(&format!(
+            "{}{}",
+            STATUS,
+            r#"
+fn describe(status: Status) -> Int {
+    match status {
+        Alive => 1,
+        Alive => 2,
+        _ => 3
+    }
+    return 0;
+}
+"#
+        ));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(*diagnostics[0].level(), IssueLevel::Warning);
+        assert_eq!(
+            diagnostics[0].message(),
+            "unreachable match arm, `Alive` is already matched above"
+        );
+        assert_eq!(diagnostics[0].position().line, 14);
+    }
+
+    #[test]
+    fn integer_match_needs_catch_all() {
+        let diagnostics = check(
+            r#"
+fn describe(count: Int) -> Int {
+    match count {
+        0 => 1,
+        1 => 2
+    }
+    match count {
+        0 => 1,
+        _ => 2
+    }
+    return 0;
+}
+"#,
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message(),
+            "non-exhaustive match: matching on Int needs a catch all `_` arm"
+        );
+        assert_eq!(diagnostics[0].position().line, 2);
+    }
+
+    #[test]
+    fn if_expression_branches_must_match() {
+        let diagnostics = check(
+            r#"
+fn pick(a: Int, b: Int) -> Int {
+    let m: Int = if a > b then a else "b";
+    let n: Int = if a then a else b;
+    return m + n;
+}
+"#,
+        );
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(
+            diagnostics[0].message(),
+            "mismatched types: both branches of an `if` expression must have the same type, but found Int and String"
+        );
+        assert_eq!(
+            diagnostics[1].message(),
+            "mismatched types: a condition must be a Bool, but found Int"
+        );
+    }
+}
@@ -0,0 +1,295 @@
+//! Run the compiler binary end to end and check what it prints
+
+use std::fs;
+use std::process::Command;
+
+#[test]
+fn emit_c_fragment_to_stdout() {
+    let before = fs::read_to_string("gen/test_case.c").unwrap_or_default();
+    let output = Command::new(env!("CARGO_BIN_EXE_iona"))
+        .args([
+            "build",
+            "test_programs/comprehensive.iona",
+            "--emit=c-fragment",
+            "--lib",
+        ])
+        .output()
+        .expect("unable to run the compiler");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.starts_with("// source: comprehensive\n"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("void print_pet(Pets pet) {"), "{}", stdout);
+    // `print` is a builtin, so its header comes along with the call
+    assert!(
+        stdout.contains("#include \"../c_libs/console_io.h\"\n"),
+        "{}",
+        stdout
+    );
+    assert!(!stdout.contains("finished compiling"));
+    // Nothing is written to the usual output file
+    let after = fs::read_to_string("gen/test_case.c").unwrap_or_default();
+    assert_eq!(before, after);
+}
+
+#[test]
+fn single_unit_combines_modules() {
+    // The compiler writes to `gen/` relative to where it runs, so give it a directory of its own
+    let root = std::env::temp_dir().join(format!("iona_single_unit_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("gen")).unwrap();
+    fs::write(
+        root.join("main.iona"),
+        "import shapes with area;\n\nfn main() -> Int {\n    return area(2);\n}\n",
+    )
+    .unwrap();
+    fs::write(
+        root.join("shapes.iona"),
+        "fn area(side: Int) -> Int {\n    @metadata {\n        Is: Public;\n    }\n    return side * side;\n}\n",
+    )
+    .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_iona"))
+        .current_dir(&root)
+        .args(["build", "main.iona", "--single-unit"])
+        .output()
+        .expect("unable to run the compiler");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let generated = fs::read_to_string(root.join("gen/test_case.c")).unwrap();
+    assert!(
+        generated.starts_with("// source: shapes, main\n"),
+        "{}",
+        generated
+    );
+    // Imported modules come first, and their internals stay internal
+    let area = generated
+        .find("static Integer area(Integer side) {")
+        .expect(&generated);
+    let main = generated
+        .find("\nInteger iona_main(void) {")
+        .expect(&generated);
+    assert!(area < main);
+    assert!(!generated.contains("#include \"shapes.h\""));
+    assert_eq!(fs::read_dir(root.join("gen")).unwrap().count(), 1);
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn imported_modules_get_their_own_files() {
+    let root = std::env::temp_dir().join(format!("iona_modules_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("gen")).unwrap();
+    fs::create_dir_all(root.join("c_libs")).unwrap();
+    fs::write(
+        root.join("main.iona"),
+        "import shapes with area;\n\nfn main() -> Int {\n    return area(2);\n}\n",
+    )
+    .unwrap();
+    fs::write(
+        root.join("shapes.iona"),
+        "fn area(side: Int) -> Int {\n    @metadata {\n        Is: Public;\n    }\n    return side * side;\n}\n",
+    )
+    .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_iona"))
+        .current_dir(&root)
+        .args(["build", "main.iona"])
+        .output()
+        .expect("unable to run the compiler");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let main = fs::read_to_string(root.join("gen/test_case.c")).unwrap();
+    let header = fs::read_to_string(root.join("gen/shapes.h")).unwrap();
+    let source = fs::read_to_string(root.join("gen/shapes.c")).unwrap();
+    // The entrypoint includes the header the imported module was written to
+    assert!(main.contains("#include \"shapes.h\""), "{}", main);
+    assert!(main.contains("\nInteger iona_main(void) {"), "{}", main);
+    assert!(header.starts_with("#pragma once\n"), "{}", header);
+    assert!(header.contains("Integer area(Integer side);"), "{}", header);
+    assert!(
+        source.starts_with("// source: shapes\n\n#include \"shapes.h\"\n"),
+        "{}",
+        source
+    );
+    assert!(
+        source.contains("Integer area(Integer side) {"),
+        "{}",
+        source
+    );
+    assert_eq!(fs::read_dir(root.join("gen")).unwrap().count(), 3);
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn module_files_link_together() {
+    let root = std::env::temp_dir().join(format!("iona_link_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("gen")).unwrap();
+    fs::create_dir_all(root.join("c_libs")).unwrap();
+    for entry in fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/c_libs")).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|ext| ext == "h") {
+            fs::copy(&path, root.join("c_libs").join(path.file_name().unwrap())).unwrap();
+        }
+    }
+    fs::write(
+        root.join("main.iona"),
+        "import shapes with area;\n\nfn main() -> Int {\n    return area(2) + 1;\n}\n",
+    )
+    .unwrap();
+    fs::write(
+        root.join("shapes.iona"),
+        "fn area(side: Int) -> Int {\n    @metadata {\n        Is: Public;\n    }\n    return side * side;\n}\n",
+    )
+    .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_iona"))
+        .current_dir(&root)
+        .args(["build", "main.iona"])
+        .output()
+        .expect("unable to run the compiler");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    // Both files include the runtime headers, so anything defined in them can't clash at link time
+    let output = Command::new("gcc")
+        .current_dir(&root)
+        .args(["gen/test_case.c", "gen/shapes.c", "-o", "program"])
+        .output()
+        .expect("unable to run gcc");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    // The Iona `main`'s result is the exit code
+    let status = Command::new(root.join("program"))
+        .status()
+        .expect("unable to run the program");
+    assert_eq!(status.code(), Some(5));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn check_reports_without_writing() {
+    let root = std::env::temp_dir().join(format!("iona_check_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("gen")).unwrap();
+    fs::create_dir_all(root.join("c_libs")).unwrap();
+    fs::write(
+        root.join("main.iona"),
+        "fn main() -> Int {\n    return missing;\n}\n",
+    )
+    .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_iona"))
+        .current_dir(&root)
+        .args(["check", "main.iona"])
+        .output()
+        .expect("unable to run the compiler");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("`missing` is not defined"), "{}", stderr);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.starts_with("finished checking main.iona in "),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.ends_with(": 1 error(s), 0 warning(s)\n"),
+        "{}",
+        stdout
+    );
+    // Nothing was generated
+    assert_eq!(fs::read_dir(root.join("gen")).unwrap().count(), 0);
+    assert_eq!(fs::read_dir(root.join("c_libs")).unwrap().count(), 0);
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn check_fails_on_syntax_errors() {
+    let root = std::env::temp_dir().join(format!("iona_check_syntax_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    // The parser recovers from the broken signature, and nothing else is wrong, so only the syntax errors can fail the check
+    fs::write(
+        root.join("main.iona"),
+        "fn broken( -> Int {\n    return 1;\n}\n\nfn main() -> Int {\n    return 0;\n}\n",
+    )
+    .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_iona"))
+        .current_dir(&root)
+        .args(["check", "main.iona"])
+        .output()
+        .expect("unable to run the compiler");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("error[E0001]"), "{}", stderr);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.starts_with("finished checking main.iona in "),
+        "{}",
+        stdout
+    );
+    assert!(!stdout.contains(": 0 error(s)"), "{}", stdout);
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn check_emits_dependency_graph() {
+    let root = std::env::temp_dir().join(format!("iona_deps_dot_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    fs::write(
+        root.join("main.iona"),
+        "import shapes with area;\nimport missing with nothing;\n\nfn main() -> Int {\n    return area(2);\n}\n",
+    )
+    .unwrap();
+    fs::write(
+        root.join("shapes.iona"),
+        "fn area(side: Int) -> Int {\n    @metadata {\n        Is: Public;\n    }\n    return side * side;\n}\n",
+    )
+    .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_iona"))
+        .current_dir(&root)
+        .args([
+            "check",
+            "main.iona",
+            "--emit=deps",
+            "--dot",
+            "-o",
+            "deps.dot",
+        ])
+        .output()
+        .expect("unable to run the compiler");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let dot = fs::read_to_string(root.join("deps.dot")).unwrap();
+    assert!(dot.starts_with("digraph dependencies {\n"), "{}", dot);
+    assert!(
+        dot.contains("    \"main\" -> \"shapes\" [label=\"area\"];\n"),
+        "{}",
+        dot
+    );
+    // A module that can't be found is drawn instead of failing the check
+    assert!(dot.contains("    \"missing\" [style=dashed];\n"), "{}", dot);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!stdout.contains("finished checking"), "{}", stdout);
+    fs::remove_dir_all(&root).unwrap();
+}